@@ -0,0 +1,183 @@
+use crate::errors::AppError;
+use crate::models::{DeduplicationReport, DuplicatesGroup};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the groups accepted as intentional duplicates are recorded,
+/// relative to the directory holding the report.
+pub const ACCEPTED_FILE: &str = ".idar/accepted.json";
+
+/// A group of files accepted with `idar ignore-group`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct AcceptedGroup {
+    id: String,
+    #[serde(
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    paths: Vec<PathBuf>,
+}
+
+/// Groups of a directory whose files are duplicates on purpose, which later
+/// scans leave out of the report and `idar apply` leaves alone.
+///
+/// Paths under the directory are recorded relative to it, so that the list
+/// still holds once the directory is moved. A group found again is only
+/// left out while every one of its items was accepted together; a new copy
+/// of them makes it reported again.
+#[derive(Debug, Clone, Default)]
+pub struct Accepted {
+    /// Absolute path of the directory holding the list.
+    directory: PathBuf,
+    groups: Vec<AcceptedGroup>,
+}
+
+impl Accepted {
+    /// Read the groups accepted in `directory`, which has none if it has no
+    /// list.
+    pub fn load(directory: &Path) -> Result<Accepted, AppError> {
+        let directory = std::path::absolute(directory)?;
+        let groups = match fs::read_to_string(directory.join(ACCEPTED_FILE)) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Accepted { directory, groups })
+    }
+
+    /// Write the list back to the directory it was read from.
+    pub fn save(&self) -> Result<(), AppError> {
+        let path = self.directory.join(ACCEPTED_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&self.groups)?)?;
+        Ok(())
+    }
+
+    /// Record the items of `group` as intentional duplicates, returning
+    /// `false` if they already were.
+    pub fn accept(&mut self, group: &DuplicatesGroup) -> bool {
+        if self.contains(group) {
+            return false;
+        }
+        let mut paths: Vec<PathBuf> = group
+            .items
+            .iter()
+            .map(|item| self.key(&item.path))
+            .collect();
+        paths.sort();
+        self.groups.push(AcceptedGroup {
+            id: group.id.clone(),
+            paths,
+        });
+        true
+    }
+
+    /// Whether every item of `group` was accepted in one group.
+    pub fn contains(&self, group: &DuplicatesGroup) -> bool {
+        let keys: Vec<PathBuf> = group
+            .items
+            .iter()
+            .map(|item| self.key(&item.path))
+            .collect();
+        self.groups
+            .iter()
+            .any(|accepted| keys.iter().all(|key| accepted.paths.contains(key)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// How `path` is recorded: relative to the directory if it is under it,
+    /// or else absolute.
+    fn key(&self, path: &Path) -> PathBuf {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        match path.strip_prefix(&self.directory) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        }
+    }
+}
+
+/// Accept the groups of the report at `report_path` with the ids
+/// `group_ids`, recording them in the directory of the report.
+pub fn run(report_path: &Path, group_ids: &[String]) -> Result<(), AppError> {
+    let report: DeduplicationReport = serde_json::from_str(&fs::read_to_string(report_path)?)?;
+    let mut accepted = Accepted::load(report_path.parent().unwrap_or(Path::new(".")))?;
+    let groups = group_ids
+        .iter()
+        .map(|id| {
+            report
+                .groups
+                .iter()
+                .find(|group| &group.id == id)
+                .ok_or_else(|| AppError::UnknownGroup {
+                    id: id.clone(),
+                    report: report_path.to_path_buf(),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for group in groups {
+        if accepted.accept(group) {
+            println!(
+                "Accepted group {} of {} files as intentional duplicates.",
+                group.id,
+                group.items.len()
+            );
+        } else {
+            println!("Group {} was already accepted.", group.id);
+        }
+    }
+    accepted.save()?;
+    println!(
+        "Accepted groups recorded in {:?}",
+        accepted.directory.join(ACCEPTED_FILE)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+    use image_hasher::ImageHash;
+    use tempfile::tempdir;
+
+    fn group(paths: &[PathBuf]) -> DuplicatesGroup {
+        let items = paths
+            .iter()
+            .map(|path| {
+                ImageInfo::new(path.clone(), ImageHash::from_base64("DAIDBwMHAf8").unwrap())
+            })
+            .collect();
+        DuplicatesGroup::new(items)
+    }
+
+    #[test]
+    fn test_accepted_groups_are_left_out_until_a_new_copy_appears() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.jpg");
+        let b = dir.path().join("sub").join("b.jpg");
+        let c = dir.path().join("c.jpg");
+        let pair = group(&[a.clone(), b.clone()]);
+        assert_eq!(pair.id, group(&[b.clone(), a.clone()]).id);
+
+        let mut accepted = Accepted::load(dir.path()).unwrap();
+        assert!(accepted.is_empty());
+        assert!(accepted.accept(&pair));
+        assert!(!accepted.accept(&pair));
+        accepted.save().unwrap();
+
+        let accepted = Accepted::load(dir.path()).unwrap();
+        assert_eq!(
+            accepted.groups[0].paths,
+            [Path::new("a.jpg"), Path::new("sub/b.jpg")]
+        );
+        assert!(accepted.contains(&group(&[b.clone(), a.clone()])));
+        assert!(!accepted.contains(&group(&[a, b, c])));
+    }
+}
@@ -0,0 +1,1418 @@
+use crate::accepted::Accepted;
+use crate::archive;
+use crate::cache::FileStamp;
+use crate::decode::DecodeOptions;
+use crate::errors::AppError;
+use crate::exact;
+use crate::interactive::{Answer, Prompter};
+use crate::metadata;
+use crate::models::{DeduplicationReport, DuplicatesGroup, SuggestedAction};
+use crate::pages;
+use crate::pattern::Pattern;
+use crate::plan::{Plan, PLAN_FILE};
+use crate::quality;
+use crate::reflink;
+use crate::source;
+use crate::trash;
+use crate::undo;
+use clap::ValueEnum;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// Which file of a duplicate group is kept when the others are acted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Keep {
+    /// The largest file, in bytes.
+    #[value(alias = "keep-largest")]
+    Largest,
+    /// The smallest file, in bytes.
+    #[value(alias = "keep-smallest")]
+    Smallest,
+    /// The file modified longest ago.
+    #[value(alias = "keep-oldest")]
+    Oldest,
+    /// The file modified last.
+    #[value(alias = "keep-newest")]
+    Newest,
+    /// The image with the most pixels.
+    #[value(alias = "keep-highest-resolution")]
+    HighestResolution,
+    /// The file whose path comes first alphabetically.
+    #[value(alias = "keep-first-alphabetical")]
+    FirstAlphabetical,
+    /// The best copy by resolution, then lossless formats over lossy ones,
+    /// then the score of `--quality`, then file size.
+    #[value(alias = "keep-best-quality")]
+    BestQuality,
+}
+
+/// What is done to the duplicates of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionKind {
+    /// Move to the trash of the user.
+    Trash,
+    /// Delete for good, with `--permanent`.
+    Delete,
+    /// Move to the quarantine directory, under the same relative path.
+    Move,
+    /// Replace with a hard link to the kept file.
+    Hardlink,
+    /// Replace with a copy-on-write clone of the kept file.
+    Reflink,
+    /// Replace with a relative symbolic link to the kept file.
+    Symlink,
+    /// Move with every other file of the group, the kept one included, to
+    /// a folder of its own in the consolidation directory.
+    Consolidate,
+    /// Rename after the kept file, as `name.dup1.jpg`, `name.dup2.jpg` and
+    /// so on, in the same directory.
+    Rename,
+}
+
+impl ActionKind {
+    /// Whether the action does away with duplicates, or replaces them,
+    /// rather than only gathering them for review.
+    pub fn removes(self) -> bool {
+        !matches!(self, ActionKind::Consolidate | ActionKind::Rename)
+    }
+
+    /// The action in the past tense, to report it done.
+    fn past(&self) -> &'static str {
+        match self {
+            ActionKind::Trash => "Trashed",
+            ActionKind::Delete => "Deleted",
+            ActionKind::Move => "Moved",
+            ActionKind::Hardlink => "Hard linked",
+            ActionKind::Reflink => "Reflinked",
+            ActionKind::Symlink => "Symlinked",
+            ActionKind::Consolidate => "Consolidated",
+            ActionKind::Rename => "Renamed",
+        }
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionKind::Trash => write!(f, "trash"),
+            ActionKind::Delete => write!(f, "delete"),
+            ActionKind::Move => write!(f, "move"),
+            ActionKind::Hardlink => write!(f, "hard link"),
+            ActionKind::Reflink => write!(f, "reflink"),
+            ActionKind::Symlink => write!(f, "symlink"),
+            ActionKind::Consolidate => write!(f, "consolidate"),
+            ActionKind::Rename => write!(f, "rename"),
+        }
+    }
+}
+
+/// How the duplicates of every group are acted on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionOptions {
+    pub kind: ActionKind,
+    pub keep: Keep,
+    /// Only record what would be done, leaving every file untouched.
+    pub dry_run: bool,
+    /// Quarantine directory duplicates are moved to by [`ActionKind::Move`],
+    /// or where [`ActionKind::Consolidate`] makes the folders of groups.
+    pub move_to: Option<PathBuf>,
+    /// Directories whose files are kept over those of the next ones and of
+    /// the rest, whatever `keep` says.
+    pub prefer_dirs: Vec<PathBuf>,
+    /// Ask which files of every group to keep, suggesting the one `keep`
+    /// picks.
+    pub interactive: bool,
+    /// Take the actions without asking to confirm the plan first.
+    pub yes: bool,
+    /// Copy the metadata the kept file lacks from the duplicates done away
+    /// with, before they are.
+    pub merge_metadata: bool,
+    /// Take the sidecar files of the duplicates deleted or moved along
+    /// with them.
+    pub sidecars: bool,
+    /// How much the action may do in one run.
+    pub limits: Limits,
+    /// Paths never acted on, relative to their scanned directory, kept
+    /// whatever `keep` says.
+    pub protect: Vec<Pattern>,
+}
+
+/// Limits on how many duplicates an action that does away with them may
+/// take in one run, past which it takes none, to guard against a threshold
+/// set too loose.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Most files acted on.
+    pub max_files: Option<usize>,
+    /// Most bytes acted on.
+    pub max_bytes: Option<u64>,
+    /// Largest share of the scanned files acted on, between 0 and 1.
+    pub max_share: Option<f64>,
+}
+
+impl Limits {
+    /// Why acting on `files` files of `bytes` bytes in all, out of
+    /// `scanned` files when known, is over the limits, if it is.
+    pub fn exceeded(&self, files: usize, bytes: u64, scanned: Option<usize>) -> Option<String> {
+        if let Some(max) = self.max_files.filter(|&max| files > max) {
+            return Some(format!(
+                "{} files are more than --max-delete {}",
+                files, max
+            ));
+        }
+        if let Some(max) = self.max_bytes.filter(|&max| bytes > max) {
+            return Some(format!(
+                "{} are more than --max-delete-bytes {}",
+                HumanBytes(bytes),
+                HumanBytes(max)
+            ));
+        }
+        let share = scanned
+            .filter(|&scanned| scanned > 0)
+            .map(|scanned| files as f64 / scanned as f64);
+        match (share, self.max_share) {
+            (Some(share), Some(max)) if share > max => Some(format!(
+                "{} files are {:.0}% of those scanned, more than --max-delete-pct {}; \
+                 pass --force to go ahead",
+                files,
+                share * 100.0,
+                max
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ActionOptions {
+    /// Trash the duplicates of every group but its first file, taking their
+    /// sidecars along, after confirming the plan.
+    fn default() -> Self {
+        ActionOptions {
+            kind: ActionKind::Trash,
+            keep: Keep::FirstAlphabetical,
+            dry_run: false,
+            move_to: None,
+            prefer_dirs: Vec::new(),
+            interactive: false,
+            yes: false,
+            merge_metadata: false,
+            sidecars: true,
+            limits: Limits::default(),
+            protect: Vec::new(),
+        }
+    }
+}
+
+impl ActionOptions {
+    /// The same options for a dry run, which plans the actions.
+    pub fn planning(&self) -> ActionOptions {
+        ActionOptions {
+            dry_run: true,
+            ..self.clone()
+        }
+    }
+
+    /// The pattern of `protect` that `path`, found under the scanned
+    /// directory `root`, matches, if any.
+    pub fn protection(&self, path: &Path, root: Option<&Path>) -> Option<&Pattern> {
+        let relative = root
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        self.protect
+            .iter()
+            .find(|pattern| pattern.matches(relative))
+    }
+
+    /// The items of `group` to keep: the one picked by `keep`, then the
+    /// protected ones.
+    pub fn kept(&self, group: &DuplicatesGroup) -> Vec<usize> {
+        let kept = keeper(group, self.keep, &self.prefer_dirs);
+        let protected = group.items.iter().enumerate().filter(|&(index, item)| {
+            index != kept && self.protection(&item.path, item.root.as_deref()).is_some()
+        });
+        std::iter::once(kept)
+            .chain(protected.map(|(index, _)| index))
+            .collect()
+    }
+}
+
+/// An action taken on a duplicate, recorded in the report.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Action {
+    pub kind: ActionKind,
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub path: PathBuf,
+    /// File of the group that was kept.
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub kept: PathBuf,
+    /// Where the file was moved to, in the quarantine directory or the
+    /// trash.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::serialization::optional_path_to_json",
+        deserialize_with = "crate::serialization::optional_path_from_json"
+    )]
+    pub destination: Option<PathBuf>,
+    /// Bytes freed by acting on the file: its size, or 0 for a hard link
+    /// to a file of the group, whose data is counted once with that file.
+    #[serde(default)]
+    pub bytes: u64,
+    /// CRC32 checksum of the file before it was acted on, and of the kept
+    /// file, with which [`undo`](crate::undo) checks what it restores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kept_checksum: Option<u32>,
+    /// Whether the action was only planned, with `--dry-run`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// Why the action failed or was not taken; the file is untouched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Sidecar files of the file that followed it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sidecars: Vec<Sidecar>,
+    /// Sidecar files of the file left behind, with `--no-sidecars` or
+    /// because they could not follow it.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    pub orphaned: Vec<PathBuf>,
+}
+
+/// A sidecar file that followed the file it belongs to.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Sidecar {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub path: PathBuf,
+    /// Where it went, unless it was deleted for good.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::serialization::optional_path_to_json",
+        deserialize_with = "crate::serialization::optional_path_from_json"
+    )]
+    pub destination: Option<PathBuf>,
+}
+
+impl Action {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Whether the file was actually acted on, rather than in a dry run.
+    pub fn taken(&self) -> bool {
+        self.succeeded() && !self.dry_run
+    }
+}
+
+/// Index of the item of `group` to keep, by `keep`, among those in the
+/// first of `prefer_dirs` holding any.
+///
+/// Ties, and items whose size or modification time cannot be read, fall
+/// back to the first path alphabetically, so the choice is the same from
+/// one run to the next.
+pub fn keeper(group: &DuplicatesGroup, keep: Keep, prefer_dirs: &[PathBuf]) -> usize {
+    let items = &group.items;
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let prefer_dirs: Vec<PathBuf> = prefer_dirs.iter().map(|dir| absolute(dir)).collect();
+    let priorities: Vec<usize> = items
+        .iter()
+        .map(|item| {
+            let path = absolute(&archive::backing_file(&item.path));
+            prefer_dirs
+                .iter()
+                .position(|dir| path.starts_with(dir))
+                .unwrap_or(prefer_dirs.len())
+        })
+        .collect();
+    let sizes: Vec<Option<u64>> = match keep {
+        Keep::Largest | Keep::Smallest => items
+            .iter()
+            .map(|item| archive::size(&item.path).ok())
+            .collect(),
+        _ => Vec::new(),
+    };
+    let times: Vec<Option<SystemTime>> = match keep {
+        Keep::Oldest | Keep::Newest => items.iter().map(|item| modified(&item.path).ok()).collect(),
+        _ => Vec::new(),
+    };
+    let ranking: Vec<PathBuf> = match keep {
+        Keep::BestQuality => quality::rank(group)
+            .into_iter()
+            .map(|rank| rank.path)
+            .collect(),
+        _ => Vec::new(),
+    };
+    let place = |index: usize| ranking.iter().position(|path| *path == items[index].path);
+    let pixels = |index: usize| {
+        items[index]
+            .dimensions
+            .map(|d| d.width as u64 * d.height as u64)
+    };
+    // Orders the preferred item first, and unknown values last.
+    let prefer = |a: usize, b: usize| match keep {
+        Keep::Largest => sizes[b].cmp(&sizes[a]),
+        Keep::Smallest => (sizes[a].is_none(), sizes[a]).cmp(&(sizes[b].is_none(), sizes[b])),
+        Keep::Oldest => (times[a].is_none(), times[a]).cmp(&(times[b].is_none(), times[b])),
+        Keep::Newest => times[b].cmp(&times[a]),
+        Keep::HighestResolution => pixels(b).cmp(&pixels(a)),
+        Keep::FirstAlphabetical => Ordering::Equal,
+        Keep::BestQuality => place(a).cmp(&place(b)),
+    };
+    (0..items.len())
+        .min_by(|&a, &b| {
+            priorities[a]
+                .cmp(&priorities[b])
+                .then_with(|| prefer(a, b))
+                .then_with(|| items[a].path.cmp(&items[b].path))
+        })
+        .unwrap_or(0)
+}
+
+/// Bytes freed by removing every item of `group` but the `kept` ones, with
+/// the paths hard linked to them. Archive members, pages of multi-page files
+/// and remote files, which are never removed, free nothing.
+pub fn reclaimable(group: &DuplicatesGroup, kept: &[usize]) -> u64 {
+    group
+        .items
+        .iter()
+        .enumerate()
+        .filter(|&(index, item)| {
+            !kept.contains(&index)
+                && !archive::is_member(&item.path)
+                && !pages::is_page(&item.path)
+                && !source::is_remote(&item.path)
+        })
+        .filter_map(|(_, item)| archive::size(&item.path).ok())
+        .sum()
+}
+
+/// Suggest keeping the `kept` items of `group` and removing the others.
+pub fn suggest(group: &mut DuplicatesGroup, kept: &[usize]) {
+    for (index, item) in group.items.iter_mut().enumerate() {
+        item.suggested_action = Some(if kept.contains(&index) {
+            SuggestedAction::Keep
+        } else {
+            SuggestedAction::Remove
+        });
+    }
+}
+
+/// Record in `group` how its items rank by quality when `keep` picks the
+/// best one, so that the report says why it was kept.
+pub fn record_ranking(group: &mut DuplicatesGroup, keep: Keep) {
+    if keep == Keep::BestQuality {
+        group.ranking = quality::rank(group);
+    }
+}
+
+/// Keep one item of `group`, picked by `options.keep`, and act on the
+/// others and the paths hard linked to them, returning what was done.
+///
+/// Archive members, pages of multi-page files and paths matching
+/// `options.protect` are never modified, and nothing is done when the kept
+/// file cannot be found. With
+/// `options.dry_run` the actions are checked and returned but not taken.
+pub fn apply(group: &DuplicatesGroup, options: &ActionOptions) -> Vec<Action> {
+    let kept = keeper(group, options.keep, &options.prefer_dirs);
+    act_on(group, options, &[kept], None)
+}
+
+/// Like [`apply`], asking `prompter` which items to keep if
+/// `options.interactive`. Skipped groups are left untouched.
+pub fn apply_with<R: BufRead, W: Write>(
+    group: &DuplicatesGroup,
+    options: &ActionOptions,
+    prompter: &mut Prompter<R, W>,
+) -> Vec<Action> {
+    apply_asking(group, options, prompter, None)
+}
+
+/// Like [`apply_with`], leaving every file untouched if there is a
+/// `refusal`.
+fn apply_asking<R: BufRead, W: Write>(
+    group: &DuplicatesGroup,
+    options: &ActionOptions,
+    prompter: &mut Prompter<R, W>,
+    refusal: Option<String>,
+) -> Vec<Action> {
+    let suggested = keeper(group, options.keep, &options.prefer_dirs);
+    if !options.interactive || refusal.is_some() {
+        return act_on(group, options, &[suggested], refusal);
+    }
+    match prompter.ask(group, suggested) {
+        Ok(Answer::Keep(kept)) if !kept.is_empty() => act_on(group, options, &kept, None),
+        Ok(Answer::Keep(_)) => {
+            let refusal = "no file was picked to keep".to_string();
+            act_on(group, options, &[suggested], Some(refusal))
+        }
+        Ok(Answer::Skip) => {
+            let refusal = "skipped at the prompt".to_string();
+            act_on(group, options, &[suggested], Some(refusal))
+        }
+        Err(err) => act_on(group, options, &[suggested], Some(err.to_string())),
+    }
+}
+
+/// Act on the items of `group` but those of `kept`, the first of which
+/// links point to and other files are renamed after. Consolidating acts on
+/// every item.
+fn act_on(
+    group: &DuplicatesGroup,
+    options: &ActionOptions,
+    kept: &[usize],
+    refusal: Option<String>,
+) -> Vec<Action> {
+    let kept_path = &group.items[kept[0]].path;
+    let missing = refusal.or_else(|| {
+        archive::size(kept_path)
+            .err()
+            .map(|err| format!("kept file cannot be read: {}", err))
+    });
+    let missing = missing.or_else(|| {
+        let removed: Vec<&Path> = group
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(index, item)| {
+                !kept.contains(index)
+                    && options
+                        .protection(&item.path, item.root.as_deref())
+                        .is_none()
+            })
+            .map(|(_, item)| item.path.as_path())
+            .collect();
+        merge_metadata(options, kept_path, &removed).err()
+    });
+    let checksums = !options.dry_run && missing.is_none();
+    let kept_checksum = checksums.then(|| exact::checksum(kept_path).ok()).flatten();
+    let consolidate = options.kind == ActionKind::Consolidate;
+    let folder = match (options.kind, &options.move_to) {
+        (ActionKind::Consolidate, Some(move_to)) => {
+            let name = kept_path.file_stem().unwrap_or(kept_path.as_os_str());
+            Some(trash::free_name(move_to, Path::new(name), |candidate| {
+                fs::symlink_metadata(candidate).is_err()
+            }))
+        }
+        _ => None,
+    };
+    // Destinations already given to files of the group, and the number of
+    // the next renamed one.
+    let mut taken: Vec<PathBuf> = Vec::new();
+    let mut number = 1;
+    group
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| consolidate || !kept.contains(index))
+        .flat_map(|(_, item)| {
+            std::iter::once((&item.path, item.sidecars.as_slice(), false))
+                .chain(item.links.iter().map(|link| (link, &[][..], true)))
+                .map(|(path, sidecars, link)| (path, item.root.as_deref(), sidecars, link))
+        })
+        .map(|(path, root, sidecars, link)| {
+            let bytes = if link {
+                0
+            } else {
+                archive::size(path).unwrap_or(0)
+            };
+            let free = |candidate: &Path| {
+                !taken.iter().any(|path| path == candidate)
+                    && fs::symlink_metadata(candidate).is_err()
+            };
+            // Protected files are kept, whatever `keep` picked.
+            let protection = options
+                .protection(path, root)
+                .map(|pattern| format!("protected by --protect `{}`", pattern.as_str()));
+            let destination = match (options.kind, &options.move_to, &folder) {
+                _ if protection.is_some() => None,
+                (ActionKind::Move, Some(move_to), _) => Some(quarantine_path(move_to, root, path)),
+                (ActionKind::Consolidate, _, Some(folder)) => {
+                    let name = path.file_name().unwrap_or(path.as_os_str());
+                    Some(trash::free_name(folder, Path::new(name), free))
+                }
+                (ActionKind::Rename, _, _) => {
+                    let (name, n) = duplicate_name(kept_path, path, number, free);
+                    number = n + 1;
+                    Some(name)
+                }
+                _ => None,
+            };
+            taken.extend(destination.clone());
+            let checksum = checksums.then(|| exact::checksum(path).ok()).flatten();
+            let (destination, error) = match protection.as_ref().or(missing.as_ref()) {
+                Some(reason) => (destination, Some(reason.clone())),
+                None => match act(options, path, kept_path, destination.as_deref()) {
+                    Ok(moved) => (moved.or(destination), None),
+                    Err(err) => (destination, Some(err)),
+                },
+            };
+            let (sidecars, orphaned) = match error {
+                None => follow(options, path, destination.as_deref(), sidecars),
+                Some(_) => (Vec::new(), Vec::new()),
+            };
+            Action {
+                kind: options.kind,
+                path: path.clone(),
+                kept: kept_path.clone(),
+                destination,
+                bytes,
+                checksum,
+                kept_checksum,
+                dry_run: options.dry_run,
+                error,
+                sidecars,
+                orphaned,
+            }
+        })
+        .collect()
+}
+
+/// Take the `planned` actions of a dry run that can be, as `options` says,
+/// returning what was done.
+pub fn execute(planned: &[Action], options: &ActionOptions) -> Vec<Action> {
+    let options = ActionOptions {
+        dry_run: false,
+        ..options.clone()
+    };
+    let mut kept_checksums: HashMap<&Path, Result<Option<u32>, String>> = HashMap::new();
+    planned
+        .iter()
+        .map(|action| {
+            if !action.succeeded() {
+                return Action {
+                    dry_run: false,
+                    ..action.clone()
+                };
+            }
+            let kept_checksum = kept_checksums.entry(&action.kept).or_insert_with(|| {
+                let removed: Vec<&Path> = planned
+                    .iter()
+                    .filter(|other| other.kept == action.kept && other.succeeded())
+                    .map(|other| other.path.as_path())
+                    .collect();
+                merge_metadata(&options, &action.kept, &removed)?;
+                Ok(exact::checksum(&action.kept).ok())
+            });
+            let kept_checksum = match kept_checksum {
+                Ok(checksum) => *checksum,
+                Err(err) => {
+                    return Action {
+                        dry_run: false,
+                        error: Some(err.clone()),
+                        ..action.clone()
+                    }
+                }
+            };
+            let checksum = exact::checksum(&action.path).ok();
+            let destination = action.destination.as_deref();
+            let (destination, error) = match act(&options, &action.path, &action.kept, destination)
+            {
+                Ok(moved) => (moved.or(action.destination.clone()), None),
+                Err(err) => (action.destination.clone(), Some(err)),
+            };
+            let planned: Vec<PathBuf> = action.sidecars.iter().map(|s| s.path.clone()).collect();
+            let (sidecars, orphaned) = match error {
+                None => {
+                    let (sidecars, orphaned) =
+                        follow(&options, &action.path, destination.as_deref(), &planned);
+                    (sidecars, [&action.orphaned[..], &orphaned].concat())
+                }
+                Some(_) => (Vec::new(), Vec::new()),
+            };
+            Action {
+                destination,
+                checksum,
+                kept_checksum,
+                dry_run: false,
+                error,
+                sidecars,
+                orphaned,
+                ..action.clone()
+            }
+        })
+        .collect()
+}
+
+/// The first free name of `name.dupN.ext`, `N` counting from `first`, in
+/// the directory of `path`, where `name` is the stem of `kept` and `ext`
+/// the extension of `path`, with the `N` it has.
+fn duplicate_name(
+    kept: &Path,
+    path: &Path,
+    first: usize,
+    free: impl Fn(&Path) -> bool,
+) -> (PathBuf, usize) {
+    let stem = kept.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    (first..)
+        .map(|n| {
+            let name = match &extension {
+                Some(ext) => format!("{}.dup{}.{}", stem, n, ext),
+                None => format!("{}.dup{}", stem, n),
+            };
+            (path.with_file_name(name), n)
+        })
+        .find(|(candidate, _)| free(candidate))
+        .expect("some name is free")
+}
+
+/// Act on the duplicates of the report at `report_path`, as [`apply`]
+/// does, and add the actions to the report.
+///
+/// Groups with a file whose size or modification time differs from those
+/// recorded in the report, or that has disappeared, are left alone, since
+/// the file may no longer be the duplicate it was, and so are the groups
+/// accepted as intentional duplicates since. The share of the files acted
+/// on is checked against the number of images the report's run hashed.
+pub fn run(report_path: &Path, options: &ActionOptions) -> Result<(), AppError> {
+    let mut report: DeduplicationReport = serde_json::from_str(&fs::read_to_string(report_path)?)?;
+    if options.keep == Keep::BestQuality {
+        for group in report.groups.iter_mut().filter(|g| g.quality.is_empty()) {
+            quality::score(group, &DecodeOptions::default());
+        }
+    }
+    for group in &mut report.groups {
+        record_ranking(group, options.keep);
+    }
+    let mut prompter = Prompter::terminal();
+    let planning = options.planning();
+    let mut plan = Plan::default();
+    let accepted = Accepted::load(report_path.parent().unwrap_or(Path::new(".")))?;
+    for group in &report.groups {
+        let refusal = if accepted.contains(group) {
+            Some("the group was accepted with `idar ignore-group`".to_string())
+        } else {
+            changed_since_report(group)
+        };
+        plan.add(
+            group,
+            apply_asking(group, &planning, &mut prompter, refusal),
+        );
+    }
+    let scanned = report.metadata.scanned;
+    if scanned.is_none() && options.limits.max_share.is_some() {
+        eprintln!(
+            "warning: the report does not record how many images were scanned, \
+             so --max-delete-pct is not checked"
+        );
+    }
+    let plan_path = report_path.with_file_name(PLAN_FILE);
+    let actions = plan.carry_out(options, &mut prompter, &plan_path, scanned)?;
+    print_summary(&actions);
+    undo::record(&actions, report_path.parent().unwrap_or(Path::new(".")))?;
+    report.actions.extend(actions);
+    fs::write(report_path, serde_json::to_string_pretty(&report)?)?;
+    println!("Actions recorded in {:?}", report_path);
+    Ok(())
+}
+
+/// Why the files of `group` may have changed since its report was written.
+fn changed_since_report(group: &DuplicatesGroup) -> Option<String> {
+    group.items.iter().find_map(|item| {
+        let Some(recorded) = group.files.iter().find(|file| file.path == item.path) else {
+            return Some(format!(
+                "the report does not record the size and time of {:?}",
+                item.path
+            ));
+        };
+        (FileStamp::of(&item.path) != Some(recorded.stamp))
+            .then(|| format!("{:?} changed since the report was written", item.path))
+    })
+}
+
+/// Where `path`, found under `root`, is moved to in the quarantine
+/// directory `move_to`: under its path relative to `root`, or under its
+/// whole path without a root.
+pub fn quarantine_path(move_to: &Path, root: Option<&Path>, path: &Path) -> PathBuf {
+    let relative = root
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path);
+    let parts = relative
+        .components()
+        .filter(|part| matches!(part, Component::Normal(_)));
+    move_to.join(parts.collect::<PathBuf>())
+}
+
+/// Take the action of `options` on `path`, moving it to `destination` or
+/// linking it to `kept`, or only check that it could be in a dry run, or say
+/// why it could not be. Returns where the file ended up in the trash.
+fn act(
+    options: &ActionOptions,
+    path: &Path,
+    kept: &Path,
+    destination: Option<&Path>,
+) -> Result<Option<PathBuf>, String> {
+    if archive::is_member(path) {
+        return Err("archive members are never modified".to_string());
+    }
+    if pages::is_page(path) {
+        return Err("pages of multi-page files are never modified".to_string());
+    }
+    if source::is_remote(path) {
+        return Err("remote files are never modified".to_string());
+    }
+    fs::symlink_metadata(path).map_err(|err| err.to_string())?;
+    if let Some(destination) = destination {
+        if fs::symlink_metadata(destination).is_ok() {
+            return Err(format!("{:?} already exists", destination));
+        }
+    }
+    match options.kind {
+        ActionKind::Hardlink | ActionKind::Reflink => check_linkable(kept, path, true)?,
+        ActionKind::Symlink => check_linkable(kept, path, false)?,
+        _ => {}
+    }
+    if options.dry_run {
+        return Ok(None);
+    }
+    let result = match (options.kind, destination) {
+        (ActionKind::Trash, _) => trash::trash(path).map(Some),
+        (ActionKind::Delete, _) => fs::remove_file(path).map(|_| None),
+        (ActionKind::Move | ActionKind::Consolidate | ActionKind::Rename, Some(destination)) => {
+            move_file(path, destination).map(|_| None)
+        }
+        (ActionKind::Move | ActionKind::Consolidate | ActionKind::Rename, None) => {
+            Err(io::Error::other("nowhere to move the file to"))
+        }
+        (ActionKind::Hardlink, _) => replace(path, |link| fs::hard_link(kept, link)).map(|_| None),
+        (ActionKind::Reflink, _) => replace(path, |clone| {
+            reflink::clone_file(kept, clone)?;
+            fs::set_permissions(clone, fs::metadata(path)?.permissions())
+        })
+        .map(|_| None),
+        (ActionKind::Symlink, _) => replace(path, |link| symlink_to(kept, link)).map(|_| None),
+    };
+    result.map_err(|err| err.to_string())
+}
+
+/// Take the `sidecars` of `path` along with it now that the action has
+/// deleted it or moved it to `destination`, or only check that they could
+/// be followed in a dry run. Returns the sidecars that followed and those
+/// left behind, orphaned; sidecars that are gone already are neither.
+fn follow(
+    options: &ActionOptions,
+    path: &Path,
+    destination: Option<&Path>,
+    sidecars: &[PathBuf],
+) -> (Vec<Sidecar>, Vec<PathBuf>) {
+    let moves = matches!(
+        options.kind,
+        ActionKind::Move | ActionKind::Consolidate | ActionKind::Rename
+    );
+    if !moves && !matches!(options.kind, ActionKind::Trash | ActionKind::Delete) {
+        return (Vec::new(), Vec::new());
+    }
+    let sidecars = sidecars
+        .iter()
+        .filter(|sidecar| fs::symlink_metadata(sidecar).is_ok());
+    if !options.sidecars {
+        return (Vec::new(), sidecars.cloned().collect());
+    }
+    let mut followed = Vec::new();
+    let mut orphaned = Vec::new();
+    for sidecar in sidecars {
+        let target = destination
+            .filter(|_| moves)
+            .map(|destination| sidecar_destination(path, destination, sidecar));
+        let result = match (options.kind, target) {
+            (_, Some(target)) if fs::symlink_metadata(&target).is_ok() => None,
+            (_, target) if options.dry_run => Some(target),
+            (ActionKind::Trash, _) => trash::trash(sidecar).ok().map(Some),
+            (ActionKind::Delete, _) => fs::remove_file(sidecar).ok().map(|_| None),
+            (_, Some(target)) => move_file(sidecar, &target).ok().map(|_| Some(target)),
+            (_, None) => None,
+        };
+        match result {
+            Some(destination) => followed.push(Sidecar {
+                path: sidecar.clone(),
+                destination,
+            }),
+            None => orphaned.push(sidecar.clone()),
+        }
+    }
+    (followed, orphaned)
+}
+
+/// Where the sidecar `sidecar` of `path` goes when `path` goes to
+/// `destination`: next to it, named after its new name as it was after the
+/// old one, as `IMG_1.jpg.xmp` or `IMG_1.xmp`.
+fn sidecar_destination(path: &Path, destination: &Path, sidecar: &Path) -> PathBuf {
+    let name = sidecar.file_name().unwrap_or_default().to_string_lossy();
+    let renamed = |old: Option<&std::ffi::OsStr>, new: Option<&std::ffi::OsStr>| {
+        let (old, new) = (old?.to_str()?, new?.to_str()?);
+        name.get(..old.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(old))
+            .map(|_| format!("{}{}", new, &name[old.len()..]))
+    };
+    let name = renamed(path.file_name(), destination.file_name())
+        .or_else(|| renamed(path.file_stem(), destination.file_stem()))
+        .unwrap_or_else(|| name.to_string());
+    destination.with_file_name(name)
+}
+
+/// Copy the metadata `kept` lacks from the `removed` files when
+/// `options.merge_metadata` says to and the action does away with them,
+/// or say why it could not be.
+fn merge_metadata(options: &ActionOptions, kept: &Path, removed: &[&Path]) -> Result<(), String> {
+    if !options.merge_metadata || options.dry_run || !options.kind.removes() {
+        return Ok(());
+    }
+    metadata::merge(kept, removed)
+        .map(|_| ())
+        .map_err(|err| format!("metadata cannot be merged into the kept file: {}", err))
+}
+
+/// Check that `path` can be replaced by a link or clone of `kept`: that `kept`
+/// is a regular file of its own with the same extension, so that the
+/// format of `path` does not change, on the same filesystem if
+/// `same_filesystem`, and not already the same file.
+fn check_linkable(kept: &Path, path: &Path, same_filesystem: bool) -> Result<(), String> {
+    if archive::is_member(kept) || pages::is_page(kept) {
+        return Err("the kept file is inside another file and cannot be linked to".to_string());
+    }
+    let extension = |path: &Path| {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+    };
+    if extension(kept) != extension(path) {
+        return Err("the kept file has another extension".to_string());
+    }
+    let kept_metadata = fs::metadata(kept).map_err(|err| err.to_string())?;
+    let metadata = fs::symlink_metadata(path).map_err(|err| err.to_string())?;
+    if !kept_metadata.is_file() || !metadata.is_file() {
+        return Err("only regular files are linked".to_string());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if same_filesystem && kept_metadata.dev() != metadata.dev() {
+            return Err("the kept file is on another filesystem".to_string());
+        }
+        if kept_metadata.ino() == metadata.ino() {
+            return Err("already a hard link to the kept file".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Replace `path` with the file `create` makes at a temporary path next to
+/// it, so that `path` is never missing if creating fails.
+pub(crate) fn replace(path: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".idar-tmp");
+    let temporary = path.with_file_name(name);
+    create(&temporary)
+        .and_then(|_| fs::rename(&temporary, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })
+}
+
+/// Create `link` as a symbolic link to `kept`, relative to the directory
+/// of `link`, and check that it leads to `kept`.
+fn symlink_to(kept: &Path, link: &Path) -> io::Result<()> {
+    let kept = std::path::absolute(kept)?;
+    let link_dir = std::path::absolute(link)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    create_symlink(&relative_path(&link_dir, &kept), link)?;
+    if fs::canonicalize(link)? != fs::canonicalize(&kept)? {
+        return Err(io::Error::other(format!(
+            "the link to {:?} does not lead to it",
+            kept
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
+
+/// Path of `to` relative to the directory `from`, both absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    std::iter::repeat_n(Component::ParentDir, from.len() - common)
+        .chain(to[common..].iter().copied())
+        .collect()
+}
+
+/// Move `path` to `destination`, creating its directory, and copying it
+/// over when the two are on different filesystems.
+pub(crate) fn move_file(path: &Path, destination: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(path, destination) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(path, destination)?;
+            fs::remove_file(path)
+        }
+        result => result,
+    }
+}
+
+/// Print the `actions` taken, every one of them for a dry run, and how
+/// many bytes they free.
+pub fn print_summary(actions: &[Action]) {
+    let Some(first) = actions.first() else {
+        return;
+    };
+    let taken: Vec<&Action> = actions.iter().filter(|a| a.succeeded()).collect();
+    let bytes = HumanBytes(taken.iter().map(|a| a.bytes).sum());
+    if first.dry_run {
+        for action in &taken {
+            match &action.destination {
+                Some(destination) => println!(
+                    "Would {} {:?} ({}) to {:?}, keeping {:?}",
+                    action.kind,
+                    action.path,
+                    HumanBytes(action.bytes),
+                    destination,
+                    action.kept
+                ),
+                None => println!(
+                    "Would {} {:?} ({}), keeping {:?}",
+                    action.kind,
+                    action.path,
+                    HumanBytes(action.bytes),
+                    action.kept
+                ),
+            }
+        }
+        println!(
+            "Dry run: would {} {} duplicates ({}).",
+            first.kind,
+            taken.len(),
+            bytes
+        );
+    } else {
+        println!(
+            "{} {} duplicates ({}).",
+            first.kind.past(),
+            taken.len(),
+            bytes
+        );
+    }
+    let skipped = actions.len() - taken.len();
+    if skipped > 0 {
+        println!("Left {} duplicates untouched; see the report.", skipped);
+    }
+    let orphaned: Vec<&PathBuf> = taken.iter().flat_map(|a| &a.orphaned).collect();
+    if !orphaned.is_empty() {
+        let left = if first.dry_run { "Would leave" } else { "Left" };
+        println!("{} {} sidecar files behind:", left, orphaned.len());
+        for sidecar in orphaned {
+            println!("  {:?}", sidecar);
+        }
+    }
+}
+
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(archive::backing_file(path))?.modified()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deduplicate::DeduplicateOptions;
+    use crate::models::{DeduplicationMetadata, Dimensions, FileState, ImageInfo};
+    use tempfile::tempdir;
+
+    fn group(paths: &[PathBuf], dimensions: &[(u32, u32)]) -> DuplicatesGroup {
+        let items = paths
+            .iter()
+            .zip(dimensions)
+            .map(|(path, &(width, height))| ImageInfo {
+                dimensions: Some(Dimensions { width, height }),
+                ..ImageInfo::at(path)
+            })
+            .collect();
+        DuplicatesGroup::new(items)
+    }
+
+    #[test]
+    fn test_keeper_follows_strategy() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["b.jpg", "a.jpg", "originals/c.jpg"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        fs::create_dir(dir.path().join("originals")).unwrap();
+        fs::write(&paths[0], vec![0u8; 300]).unwrap();
+        fs::write(&paths[1], vec![0u8; 100]).unwrap();
+        fs::write(&paths[2], vec![0u8; 300]).unwrap();
+        let group = group(&paths, &[(10, 10), (40, 30), (20, 20)]);
+
+        assert_eq!(keeper(&group, Keep::Largest, &[]), 0);
+        assert_eq!(keeper(&group, Keep::Smallest, &[]), 1);
+        assert_eq!(keeper(&group, Keep::HighestResolution, &[]), 1);
+        assert_eq!(keeper(&group, Keep::FirstAlphabetical, &[]), 1);
+        let prefer_dirs = [dir.path().join("downloads"), dir.path().join("originals")];
+        assert_eq!(keeper(&group, Keep::Smallest, &prefer_dirs), 2);
+        assert_eq!(reclaimable(&group, &[1]), 600);
+
+        let mut group = group;
+        suggest(&mut group, &[1]);
+        let suggested: Vec<_> = group.items.iter().map(|i| i.suggested_action).collect();
+        let (keep, remove) = (Some(SuggestedAction::Keep), Some(SuggestedAction::Remove));
+        assert_eq!(suggested, [remove, keep, remove]);
+        let json = serde_json::to_string(&group.items[1]).unwrap();
+        assert!(json.contains(r#""suggested_action":"keep""#));
+    }
+
+    #[test]
+    fn test_delete_removes_all_but_the_kept_file() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.jpg", "b.jpg", "c.jpg"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        for path in &paths {
+            fs::write(path, b"image").unwrap();
+        }
+        let mut group = group(&paths, &[(10, 10), (20, 20), (10, 10)]);
+        let zip = dir.path().join("x.zip");
+        fs::write(&zip, b"").unwrap();
+        group.items[2].path = archive::member_path(&zip, "c.jpg");
+        let options = ActionOptions {
+            kind: ActionKind::Delete,
+            keep: Keep::HighestResolution,
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let planned = apply(&group, &options);
+        assert!(planned[0].succeeded() && planned[0].dry_run);
+        assert_eq!(planned[0].bytes, 5);
+        assert!(paths[0].exists());
+
+        let protected = ActionOptions {
+            protect: vec![Pattern::new("a.*").unwrap()],
+            ..options.clone()
+        };
+        let planned = apply(&group, &protected);
+        let reason = "protected by --protect `a.*`";
+        assert_eq!(planned[0].error.as_deref(), Some(reason));
+        assert_eq!(protected.kept(&group), [1, 0]);
+
+        let options = ActionOptions {
+            dry_run: false,
+            ..options
+        };
+        let actions = apply(&group, &options);
+        assert_eq!(actions.len(), 2);
+        assert!(actions.iter().all(|action| action.kept == paths[1]));
+        assert!(actions[0].succeeded());
+        assert!(!actions[1].succeeded());
+        assert!(!paths[0].exists());
+        assert!(paths[1].exists());
+    }
+
+    #[test]
+    fn test_hard_links_of_a_removed_file_free_nothing_more() {
+        let dir = tempdir().unwrap();
+        let paths = vec![dir.path().join("a.jpg"), dir.path().join("b.jpg")];
+        for path in &paths {
+            fs::write(path, b"image").unwrap();
+        }
+        let link = dir.path().join("b_link.jpg");
+        fs::hard_link(&paths[1], &link).unwrap();
+        let mut group = group(&paths, &[(10, 10), (10, 10)]);
+        group.items[1].links = vec![link.clone()];
+        let options = ActionOptions {
+            kind: ActionKind::Delete,
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let planned = apply(&group, &options);
+        let removed: Vec<_> = planned.iter().map(|a| (&a.path, a.bytes)).collect();
+        assert_eq!(removed, [(&paths[1], 5), (&link, 0)]);
+        assert_eq!(reclaimable(&group, &[0]), 5);
+    }
+
+    #[test]
+    fn test_move_mirrors_paths_under_the_quarantine_directory() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("photos");
+        let paths = vec![root.join("a.jpg"), root.join("2020").join("a.jpg")];
+        fs::create_dir_all(root.join("2020")).unwrap();
+        fs::write(&paths[0], b"original").unwrap();
+        fs::write(&paths[1], b"copy").unwrap();
+        let mut group = group(&paths, &[(10, 10), (10, 10)]);
+        for item in &mut group.items {
+            item.root = Some(root.clone());
+        }
+        let quarantine = dir.path().join("idar_quarantine");
+        let options = ActionOptions {
+            kind: ActionKind::Move,
+            keep: Keep::Largest,
+            move_to: Some(quarantine.clone()),
+            ..Default::default()
+        };
+
+        let actions = apply(&group, &options);
+        let moved = quarantine.join("2020").join("a.jpg");
+        assert_eq!(actions[0].path, paths[1]);
+        assert_eq!(actions[0].destination.as_ref(), Some(&moved));
+        assert!(moved.exists() && !paths[1].exists());
+        assert_eq!(
+            quarantine_path(&quarantine, None, Path::new("/srv/pics/b.jpg")),
+            quarantine.join("srv").join("pics").join("b.jpg")
+        );
+    }
+
+    #[test]
+    fn test_hardlink_replaces_copies_with_links_to_the_kept_file() {
+        let dir = tempdir().unwrap();
+        let paths = vec![dir.path().join("a.jpg"), dir.path().join("b.jpg")];
+        fs::write(&paths[0], b"original").unwrap();
+        fs::write(&paths[1], b"copy").unwrap();
+        let group = group(&paths, &[(10, 10), (10, 10)]);
+        let options = ActionOptions {
+            kind: ActionKind::Hardlink,
+            keep: Keep::Largest,
+            ..Default::default()
+        };
+
+        let actions = apply(&group, &options);
+        assert!(actions[0].succeeded());
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"original");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+            assert_eq!(inode(&paths[0]), inode(&paths[1]));
+            let again = apply(&group, &options);
+            assert!(!again[0].succeeded());
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_groups_changed_since_the_report_are_left_alone() {
+        let dir = tempdir().unwrap();
+        let paths = vec![dir.path().join("a.jpg"), dir.path().join("b.jpg")];
+        fs::write(&paths[0], b"original").unwrap();
+        fs::write(&paths[1], b"copy").unwrap();
+        let mut group = group(&paths, &[(10, 10), (10, 10)]);
+        assert!(changed_since_report(&group).is_some());
+        group.files = paths
+            .iter()
+            .map(|path| FileState {
+                path: path.clone(),
+                stamp: FileStamp::of(path).unwrap(),
+            })
+            .collect();
+        assert_eq!(changed_since_report(&group), None);
+
+        fs::write(&paths[1], b"edited copy").unwrap();
+        let options = ActionOptions {
+            kind: ActionKind::Delete,
+            ..Default::default()
+        };
+        let actions = act_on(&group, &options, &[0], changed_since_report(&group));
+        assert!(!actions[0].succeeded());
+        assert!(paths[1].exists());
+    }
+
+    #[test]
+    fn test_apply_refuses_to_delete_too_large_a_share_of_the_scanned_files() {
+        let dir = tempdir().unwrap();
+        let paths = vec![dir.path().join("a.jpg"), dir.path().join("b.jpg")];
+        for path in &paths {
+            fs::write(path, b"image").unwrap();
+        }
+        let mut group = group(&paths, &[(10, 10), (10, 10)]);
+        group.files = paths
+            .iter()
+            .map(|path| FileState {
+                path: path.clone(),
+                stamp: FileStamp::of(path).unwrap(),
+            })
+            .collect();
+        let metadata = DeduplicationMetadata {
+            scanned: Some(2),
+            ..DeduplicateOptions::default().metadata(&[dir.path().to_path_buf()])
+        };
+        let report_path = dir.path().join("dedup_report.json");
+        let report = DeduplicationReport::new(metadata, vec![group]);
+        fs::write(&report_path, serde_json::to_string(&report).unwrap()).unwrap();
+
+        let options = ActionOptions {
+            kind: ActionKind::Delete,
+            yes: true,
+            limits: Limits {
+                max_share: Some(0.4),
+                ..Limits::default()
+            },
+            ..Default::default()
+        };
+        run(&report_path, &options).unwrap();
+        assert!(paths.iter().all(|path| path.exists()));
+        let report: DeduplicationReport =
+            serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+        let error = report.actions[0].error.as_deref().unwrap();
+        assert!(error.contains("--max-delete-pct"), "{}", error);
+    }
+
+    #[test]
+    fn test_consolidate_and_rename_gather_groups_for_review() {
+        let dir = tempdir().unwrap();
+        let paths = vec![
+            dir.path().join("a.jpg"),
+            dir.path().join("x").join("a.jpg"),
+            dir.path().join("b.png"),
+        ];
+        fs::create_dir(dir.path().join("x")).unwrap();
+        for path in &paths {
+            fs::write(path, b"image").unwrap();
+        }
+        let group = group(&paths, &[(10, 10), (10, 10), (10, 10)]);
+        let options = ActionOptions {
+            kind: ActionKind::Rename,
+            dry_run: true,
+            ..Default::default()
+        };
+        let renamed: Vec<Option<PathBuf>> = apply(&group, &options)
+            .into_iter()
+            .map(|action| action.destination)
+            .collect();
+        assert_eq!(
+            renamed,
+            [
+                Some(dir.path().join("x").join("a.dup1.jpg")),
+                Some(dir.path().join("a.dup2.png")),
+            ]
+        );
+
+        let review = dir.path().join("review");
+        let options = ActionOptions {
+            kind: ActionKind::Consolidate,
+            dry_run: false,
+            move_to: Some(review.clone()),
+            ..options
+        };
+        let actions = apply(&group, &options);
+        assert_eq!(actions.len(), 3);
+        assert!(actions.iter().all(Action::taken));
+        let mut names: Vec<String> = fs::read_dir(review.join("a"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a 2.jpg", "a.jpg", "b.png"]);
+    }
+
+    #[test]
+    fn test_sidecars_follow_the_files_they_belong_to() {
+        let dir = tempdir().unwrap();
+        let paths = vec![dir.path().join("a.jpg"), dir.path().join("b.JPG")];
+        let sidecars = vec![dir.path().join("b.JPG.xmp"), dir.path().join("b.json")];
+        for path in paths.iter().chain(&sidecars) {
+            fs::write(path, b"data").unwrap();
+        }
+        let mut group = group(&paths, &[(10, 10), (10, 10)]);
+        group.items[1].sidecars = sidecars.clone();
+        let options = ActionOptions {
+            kind: ActionKind::Rename,
+            sidecars: false,
+            ..Default::default()
+        };
+        let planned = apply(&group, &options.planning());
+        assert_eq!(planned[0].orphaned, sidecars);
+
+        let options = ActionOptions {
+            sidecars: true,
+            ..options
+        };
+        let actions = execute(&apply(&group, &options.planning()), &options);
+        let moved: Vec<Option<PathBuf>> = actions[0]
+            .sidecars
+            .iter()
+            .map(|sidecar| sidecar.destination.clone())
+            .collect();
+        assert_eq!(
+            moved,
+            [
+                Some(dir.path().join("a.dup1.JPG.xmp")),
+                Some(dir.path().join("a.dup1.json")),
+            ]
+        );
+        assert!(actions[0].orphaned.is_empty());
+        assert!(sidecars.iter().all(|sidecar| !sidecar.exists()));
+        assert!(dir.path().join("a.dup1.JPG.xmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_replaces_copies_with_relative_links() {
+        let dir = tempdir().unwrap();
+        let paths = vec![
+            dir.path().join("kept").join("a.jpg"),
+            dir.path().join("other").join("deeper").join("a.jpg"),
+        ];
+        fs::create_dir_all(dir.path().join("kept")).unwrap();
+        fs::create_dir_all(dir.path().join("other").join("deeper")).unwrap();
+        fs::write(&paths[0], b"original").unwrap();
+        fs::write(&paths[1], b"copy").unwrap();
+        let group = group(&paths, &[(10, 10), (10, 10)]);
+        let options = ActionOptions {
+            kind: ActionKind::Symlink,
+            keep: Keep::Largest,
+            ..Default::default()
+        };
+
+        let actions = apply(&group, &options);
+        assert!(actions[0].succeeded());
+        assert_eq!(
+            fs::read_link(&paths[1]).unwrap(),
+            Path::new("../../kept/a.jpg")
+        );
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"original");
+    }
+}
@@ -0,0 +1,221 @@
+use crate::archive;
+use crate::decode;
+use crate::hashing::PerceptualHasher;
+use crate::store::{Hashes, ImageSource};
+#[cfg(feature = "video")]
+use crate::video;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frames, ImageFormat, ImageResult};
+use image_hasher::ImageHash;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Extensions of the files that may hold an animation.
+const EXTENSIONS: &[&str] = &["apng", "gif", "png", "webp"];
+
+/// Largest number of frames decoded per animation.
+const MAX_FRAMES: usize = 1000;
+
+/// Largest number of keyframes kept per animation, spread evenly over the
+/// ones found.
+const MAX_KEYFRAMES: usize = 32;
+
+/// A frame starts a new keyframe when it is further than this fraction of
+/// the hash bits from the last keyframe.
+const KEYFRAME_CHANGE: u32 = 16;
+
+/// Hash the keyframes of the animated GIF, WebP or APNG at `path`, or of
+/// the video with the `video` feature, or return nothing for still images
+/// and animations of a single scene.
+///
+/// A frame is a keyframe when it differs noticeably from the keyframe
+/// before it, so the same animation has the same keyframes whatever its
+/// frame rate, and a trimmed copy has a run of them.
+pub fn hash_keyframes(hasher: &dyn PerceptualHasher, path: &Path) -> Vec<ImageHash> {
+    let Ok(Some(frames)) = decode_frames(path) else {
+        return Vec::new();
+    };
+    let change = hasher.hash_bits() / KEYFRAME_CHANGE;
+    let mut keyframes: Vec<ImageHash> = Vec::new();
+    for frame in frames.take(MAX_FRAMES) {
+        let Ok(frame) = frame else {
+            break;
+        };
+        let hash = hasher.hash_image(&DynamicImage::ImageRgba8(frame.into_buffer()));
+        let last = keyframes.last();
+        if last.is_none_or(|last| hasher.distance(last.as_bytes(), hash.as_bytes()) > change) {
+            keyframes.push(hash);
+        }
+    }
+    if keyframes.len() < 2 {
+        return Vec::new();
+    }
+    if keyframes.len() > MAX_KEYFRAMES {
+        let count = keyframes.len();
+        keyframes = (0..MAX_KEYFRAMES)
+            .map(|i| keyframes[i * count / MAX_KEYFRAMES].clone())
+            .collect();
+    }
+    keyframes
+}
+
+/// The frames of the image at `path` if it is animated.
+fn decode_frames(path: &Path) -> ImageResult<Option<Frames<'static>>> {
+    #[cfg(feature = "video")]
+    if video::is_video(path) {
+        return video::keyframes(path).map(Some);
+    }
+    let named = path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy();
+        EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    });
+    if !named {
+        return Ok(None);
+    }
+    let source = archive::open(path)?;
+    let frames = match decode::format_of(path) {
+        Some(ImageFormat::Gif) => GifDecoder::new(source)?.into_frames(),
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(source)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(source)?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            decoder.apng()?.into_frames()
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(frames))
+}
+
+/// Distance between two sequences of keyframes: the mean distance from
+/// the keyframes of one to the closest keyframe of the other, taking
+/// whichever sequence is closer to the other.
+///
+/// Measuring from the shorter side lets a trimmed copy match the whole
+/// animation it was cut from.
+pub fn sequence_distance(
+    first: Hashes,
+    second: Hashes,
+    distance: impl Fn(&[u8], &[u8]) -> u32,
+) -> u32 {
+    let directed = |from: Hashes, to: Hashes| {
+        let total: u64 = from
+            .iter()
+            .map(|a| to.iter().map(|b| distance(a, b)).min().unwrap_or(u32::MAX) as u64)
+            .sum();
+        (total / from.len().max(1) as u64) as u32
+    };
+    directed(first, second).min(directed(second, first))
+}
+
+/// Replace the verdict of `pairs` on pairs of animations by the distance
+/// between their keyframes, adding the pairs of animations within
+/// `threshold` that were not found by their first frames.
+///
+/// Pairs involving a still image are kept as they are. The result is
+/// sorted.
+pub fn match_sequences<S: ImageSource + ?Sized>(
+    images: &S,
+    pairs: Vec<(usize, usize)>,
+    threshold: u32,
+    distance: impl Fn(&[u8], &[u8]) -> u32,
+) -> Vec<(usize, usize)> {
+    let animated: Vec<usize> = (0..images.len())
+        .filter(|&index| !images.frames(index).is_empty())
+        .collect();
+    if animated.is_empty() {
+        return pairs;
+    }
+    let is_animated: HashSet<usize> = animated.iter().copied().collect();
+    let mut matched: Vec<(usize, usize)> = pairs
+        .into_iter()
+        .filter(|(i, j)| !is_animated.contains(i) || !is_animated.contains(j))
+        .collect();
+    for (position, &i) in animated.iter().enumerate() {
+        for &j in &animated[position + 1..] {
+            if sequence_distance(images.frames(i), images.frames(j), &distance) <= threshold {
+                matched.push((i, j));
+            }
+        }
+    }
+    matched.sort_unstable();
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance;
+    use crate::hashing::{self, HashAlgorithm};
+    use crate::models::ImageInfo;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+    use std::fs::File;
+
+    use tempfile::tempdir;
+
+    /// A frame with bright bars at `code` of eight positions, or from 8 on
+    /// a checkerboard.
+    fn frame(code: u32) -> Frame {
+        let img = RgbaImage::from_fn(64, 64, |x, y| {
+            let lit = match code {
+                0..=7 => x / 8 == code || y / 8 == 7 - code,
+                _ => (x / (code - 6) + y / (code - 6)).is_multiple_of(2),
+            };
+            Rgba(if lit {
+                [255, 255, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            })
+        });
+        Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1))
+    }
+
+    fn write_gif(path: &Path, codes: &[u32]) {
+        let mut encoder = GifEncoder::new(File::create(path).unwrap());
+        encoder
+            .encode_frames(codes.iter().map(|&code| frame(code)))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_trimmed_animation_matches_by_keyframes() {
+        let dir = tempdir().unwrap();
+        let hasher = hashing::build_hasher(HashAlgorithm::Gradient, 16);
+        let animations = [
+            ("full.gif", vec![0, 0, 1, 2, 3, 4, 5, 6, 7]),
+            ("trimmed.gif", vec![3, 4, 4, 5, 6]),
+            ("other.gif", vec![0, 8, 9, 10, 11, 12]),
+            ("still.gif", vec![2]),
+        ];
+        let images: Vec<ImageInfo> = animations
+            .iter()
+            .map(|(name, codes)| {
+                let path = dir.path().join(name);
+                write_gif(&path, codes);
+                ImageInfo {
+                    frames: hash_keyframes(&hasher, &path),
+                    ..ImageInfo::at(name)
+                }
+            })
+            .collect();
+        assert_eq!(images[0].frames.len(), 8);
+        assert!(images[3].frames.is_empty());
+
+        // The other animation starts like the full one, and the pair found
+        // by first frames is dropped; the still image keeps its pair.
+        let pairs = match_sequences(&images[..], vec![(0, 2), (0, 3)], 4, distance::hamming);
+        assert_eq!(pairs, vec![(0, 1), (0, 3)]);
+    }
+}
@@ -0,0 +1,505 @@
+use crate::pages;
+use crate::source;
+use flate2::read::DeflateDecoder;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Separates the path of an archive from the name of one of its members,
+/// as in `photos.zip!/2024/beach.jpg`.
+pub const SEPARATOR: &str = "!/";
+
+/// Extensions of the archives that can be scanned.
+const EXTENSIONS: &[&str] = &["zip", "cbz", "tar"];
+
+const ZIP_END_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP_CENTRAL_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP_LOCAL_SIGNATURE: u32 = 0x0403_4b50;
+const ZIP_END_LEN: usize = 22;
+const TAR_BLOCK: u64 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Storage {
+    /// Zip member stored as is, or deflated, behind a local header at `offset`.
+    Zip { deflated: bool },
+    /// Tar member whose data starts at `offset`.
+    Tar,
+}
+
+#[derive(Debug, Clone)]
+struct Member {
+    name: String,
+    storage: Storage,
+    offset: u64,
+    /// Size of the member as stored in the archive.
+    stored_size: u64,
+    size: u64,
+}
+
+/// Whether `path` names an archive that can be scanned, by its extension.
+pub fn is_archive(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy();
+        EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    })
+}
+
+/// Path of the member `name` of `archive`.
+pub fn member_path(archive: &Path, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.display(), SEPARATOR, name))
+}
+
+/// Split the path of an archive member into the archive and member name,
+/// or return `None` for a path on disk.
+pub fn split(path: &Path) -> Option<(PathBuf, &str)> {
+    let text = path.to_str()?;
+    text.match_indices(SEPARATOR).find_map(|(at, _)| {
+        let archive = Path::new(&text[..at]);
+        (is_archive(archive) && archive.is_file())
+            .then(|| (archive.to_path_buf(), &text[at + SEPARATOR.len()..]))
+    })
+}
+
+/// Whether `path` names a member of an archive.
+pub fn is_member(path: &Path) -> bool {
+    split(path).is_some()
+}
+
+/// The file on disk holding `path`: the archive of a member, the file of a
+/// page, or the path itself.
+pub fn backing_file(path: &Path) -> PathBuf {
+    let path = &pages::file_of(path);
+    match split(path) {
+        Some((archive, _)) => archive,
+        None => path.to_path_buf(),
+    }
+}
+
+/// Names of the regular files in `archive`, in archive order.
+pub fn list(archive: &Path) -> io::Result<Vec<String>> {
+    Ok(index(archive)?
+        .members
+        .iter()
+        .map(|member| member.name.clone())
+        .collect())
+}
+
+/// Size in bytes of the file, remote file or archive member at `path`, or
+/// of the file holding it for a page.
+pub fn size(path: &Path) -> io::Result<u64> {
+    let path = &pages::file_of(path);
+    if source::is_remote(path) {
+        return source::remote_size(path);
+    }
+    match split(path) {
+        Some((archive, name)) => Ok(find(&archive, name)?.size),
+        None => Ok(fs::metadata(path)?.len()),
+    }
+}
+
+/// Readable contents of a file on disk or of an archive member.
+pub enum Source {
+    File(BufReader<File>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+/// Open the file, remote file or archive member at `path` for reading.
+/// Remote files and members are read into memory in full. A page opens the
+/// whole file holding it.
+pub fn open(path: &Path) -> io::Result<Source> {
+    let path = &pages::file_of(path);
+    if source::is_remote(path) {
+        return Ok(Source::Memory(Cursor::new(source::read_remote(
+            path, None,
+        )?)));
+    }
+    match split(path) {
+        Some((archive, name)) => Ok(Source::Memory(Cursor::new(read_member(&archive, name)?))),
+        None => Ok(Source::File(BufReader::new(File::open(path)?))),
+    }
+}
+
+/// The first `len` bytes of what [`open`] reads from `path`, fewer if it is
+/// shorter. Only those are downloaded from a remote file.
+pub fn read_start(path: &Path, len: usize) -> io::Result<Vec<u8>> {
+    if source::is_remote(path) {
+        return source::read_remote(path, Some(len as u64));
+    }
+    let mut start = Vec::with_capacity(len);
+    open(path)?.take(len as u64).read_to_end(&mut start)?;
+    Ok(start)
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(file) => file.read(buf),
+            Source::Memory(bytes) => bytes.read(buf),
+        }
+    }
+}
+
+impl BufRead for Source {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Source::File(file) => file.fill_buf(),
+            Source::Memory(bytes) => bytes.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Source::File(file) => file.consume(amount),
+            Source::Memory(bytes) => bytes.consume(amount),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(file) => file.seek(position),
+            Source::Memory(bytes) => bytes.seek(position),
+        }
+    }
+}
+
+/// Regular files of an archive, by name.
+#[derive(Debug, Default)]
+struct Index {
+    members: Vec<Member>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Index {
+    fn new(members: Vec<Member>) -> Index {
+        let by_name = members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| (member.name.clone(), i))
+            .collect();
+        Index { members, by_name }
+    }
+
+    fn get(&self, name: &str) -> Option<&Member> {
+        self.by_name.get(name).map(|&i| &self.members[i])
+    }
+}
+
+/// Members of `archive`, read once per run and shared between threads.
+fn index(archive: &Path) -> io::Result<Arc<Index>> {
+    static INDEXES: OnceLock<Mutex<HashMap<PathBuf, Arc<Index>>>> = OnceLock::new();
+    let indexes = INDEXES.get_or_init(Default::default);
+    if let Some(index) = indexes.lock().unwrap().get(archive) {
+        return Ok(index.clone());
+    }
+
+    let mut file = BufReader::new(File::open(archive)?);
+    let is_tar = archive
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("tar"));
+    let members = if is_tar {
+        read_tar_index(&mut file)?
+    } else {
+        read_zip_index(&mut file)?
+    };
+    let index = Arc::new(Index::new(members));
+    indexes
+        .lock()
+        .unwrap()
+        .insert(archive.to_path_buf(), index.clone());
+    Ok(index)
+}
+
+fn find(archive: &Path, name: &str) -> io::Result<Member> {
+    index(archive)?
+        .get(name)
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no member `{}`", name)))
+}
+
+/// Read the member `name` of `archive` into memory.
+fn read_member(archive: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let member = find(archive, name)?;
+    let mut file = BufReader::new(File::open(archive)?);
+    let start = match member.storage {
+        Storage::Tar => member.offset,
+        Storage::Zip { .. } => {
+            file.seek(SeekFrom::Start(member.offset))?;
+            let header = read_array::<30>(&mut file)?;
+            if u32_at(&header, 0) != ZIP_LOCAL_SIGNATURE {
+                return Err(invalid("corrupt zip local header"));
+            }
+            let name_len = u16_at(&header, 26) as u64;
+            let extra_len = u16_at(&header, 28) as u64;
+            member.offset + 30 + name_len + extra_len
+        }
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let stored = file.take(member.stored_size);
+
+    let mut bytes = Vec::with_capacity(member.size as usize);
+    match member.storage {
+        Storage::Zip { deflated: true } => DeflateDecoder::new(stored).read_to_end(&mut bytes)?,
+        _ => stored.take(member.size).read_to_end(&mut bytes)?,
+    };
+    Ok(bytes)
+}
+
+fn read_zip_index<R: Read + Seek>(file: &mut R) -> io::Result<Vec<Member>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // The end record is followed by a comment of at most 64 KiB.
+    let tail_len = len.min(ZIP_END_LEN as u64 + u16::MAX as u64);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    if tail.len() < ZIP_END_LEN {
+        return Err(invalid("no zip end of central directory"));
+    }
+    let end = (0..=tail.len() - ZIP_END_LEN)
+        .rev()
+        .find(|&at| u32_at(&tail, at) == ZIP_END_SIGNATURE)
+        .ok_or_else(|| invalid("no zip end of central directory"))?;
+    let count = u16_at(&tail, end + 10) as usize;
+    let directory_offset = u32_at(&tail, end + 16) as u64;
+
+    file.seek(SeekFrom::Start(directory_offset))?;
+    let mut members = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = read_array::<46>(file)?;
+        if u32_at(&header, 0) != ZIP_CENTRAL_SIGNATURE {
+            return Err(invalid("corrupt zip central directory"));
+        }
+        let flags = u16_at(&header, 8);
+        let method = u16_at(&header, 10);
+        let stored_size = u32_at(&header, 20);
+        let size = u32_at(&header, 24);
+        let name_len = u16_at(&header, 28) as usize;
+        let skip = u16_at(&header, 30) as i64 + u16_at(&header, 32) as i64;
+        let offset = u32_at(&header, 42);
+        let mut name = vec![0u8; name_len];
+        file.read_exact(&mut name)?;
+        file.seek(SeekFrom::Current(skip))?;
+
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let encrypted = flags & 1 != 0;
+        let zip64 = [stored_size, size, offset].contains(&u32::MAX);
+        if name.ends_with('/') || encrypted || zip64 || !matches!(method, 0 | 8) {
+            continue;
+        }
+        members.push(Member {
+            name,
+            storage: Storage::Zip {
+                deflated: method == 8,
+            },
+            offset: offset as u64,
+            stored_size: stored_size as u64,
+            size: size as u64,
+        });
+    }
+    Ok(members)
+}
+
+fn read_tar_index<R: Read + Seek>(file: &mut R) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+    let mut long_name: Option<String> = None;
+    let mut offset = 0u64;
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        let header = match read_array::<512>(file) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        let size = parse_octal(&header[124..136]).ok_or_else(|| invalid("corrupt tar size"))?;
+        let data = offset + TAR_BLOCK;
+        offset = data + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+        match header[156] {
+            b'0' | 0 | b'7' => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = text(&header[0..100]);
+                    let prefix = text(&header[345..500]);
+                    match &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        true => format!("{}/{}", prefix, name),
+                        false => name,
+                    }
+                });
+                members.push(Member {
+                    name,
+                    storage: Storage::Tar,
+                    offset: data,
+                    stored_size: size,
+                    size,
+                });
+            }
+            b'L' => {
+                let mut name = vec![0u8; size as usize];
+                file.read_exact(&mut name)?;
+                long_name = Some(text(&name));
+            }
+            _ => long_name = None,
+        }
+    }
+    Ok(members)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = text(field);
+    let digits = digits.trim();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// Text of a NUL-padded header field.
+fn text(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    /// A zip of `files`, deflating those marked so. Checksums are left
+    /// empty since they are not verified.
+    fn zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, data, deflated) in files {
+            let stored = if deflated {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                data.to_vec()
+            };
+            let method: u16 = if deflated { 8 } else { 0 };
+            let sizes = [stored.len() as u32, data.len() as u32];
+            let offset = bytes.len() as u32;
+
+            bytes.extend(ZIP_LOCAL_SIGNATURE.to_le_bytes());
+            bytes.extend([0u8; 4]);
+            bytes.extend(method.to_le_bytes());
+            bytes.extend([0u8; 8]);
+            sizes
+                .iter()
+                .for_each(|size| bytes.extend(size.to_le_bytes()));
+            bytes.extend((name.len() as u16).to_le_bytes());
+            bytes.extend([0u8; 2]);
+            bytes.extend(name.as_bytes());
+            bytes.extend(&stored);
+
+            directory.extend(ZIP_CENTRAL_SIGNATURE.to_le_bytes());
+            directory.extend([0u8; 6]);
+            directory.extend(method.to_le_bytes());
+            directory.extend([0u8; 8]);
+            sizes
+                .iter()
+                .for_each(|size| directory.extend(size.to_le_bytes()));
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0u8; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = bytes.len() as u32;
+        bytes.extend(&directory);
+        bytes.extend(ZIP_END_SIGNATURE.to_le_bytes());
+        bytes.extend([0u8; 4]);
+        bytes.extend((files.len() as u16).to_le_bytes());
+        bytes.extend((files.len() as u16).to_le_bytes());
+        bytes.extend((directory.len() as u32).to_le_bytes());
+        bytes.extend(directory_offset.to_le_bytes());
+        bytes.extend([0u8; 2]);
+        bytes
+    }
+
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &(name, data) in files {
+            let mut header = [0u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+            header[156] = b'0';
+            bytes.extend(header);
+            bytes.extend(data);
+            bytes.resize(bytes.len().next_multiple_of(512), 0);
+        }
+        bytes.extend([0u8; 1024]);
+        bytes
+    }
+
+    #[test]
+    fn test_read_archive_members() {
+        let dir = tempdir().unwrap();
+        let photos = dir.path().join("photos.zip");
+        fs::write(
+            &photos,
+            zip(&[
+                ("2024/", b"", false),
+                ("2024/a.jpg", b"stored image", false),
+                ("b.png", &[7u8; 300], true),
+            ]),
+        )
+        .unwrap();
+        let scans = dir.path().join("scans.tar");
+        fs::write(
+            &scans,
+            tar(&[("c.png", b"tarred"), ("d/e.jpg", &[1u8; 600])]),
+        )
+        .unwrap();
+
+        assert_eq!(list(&photos).unwrap(), vec!["2024/a.jpg", "b.png"]);
+        assert_eq!(list(&scans).unwrap(), vec!["c.png", "d/e.jpg"]);
+
+        let member = member_path(&photos, "b.png");
+        assert_eq!(split(&member), Some((photos.clone(), "b.png")));
+        assert_eq!(backing_file(&member), photos);
+        assert_eq!(size(&member).unwrap(), 300);
+
+        let read = |path: &Path| {
+            let mut bytes = Vec::new();
+            open(path).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+        assert_eq!(read(&member_path(&photos, "2024/a.jpg")), b"stored image");
+        assert_eq!(read(&member), vec![7u8; 300]);
+        assert_eq!(read(&member_path(&scans, "d/e.jpg")), vec![1u8; 600]);
+        assert!(open(&member_path(&scans, "missing.png")).is_err());
+        assert!(split(&dir.path().join("missing.zip!/b.png")).is_none());
+    }
+}
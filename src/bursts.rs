@@ -0,0 +1,108 @@
+use crate::exif::Exif;
+use crate::store::ImageSource;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Photos taken in quick succession by the same camera body.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Burst {
+    /// Make, model and serial number of the camera, as recorded.
+    pub camera: String,
+    /// Photos of the burst, in the order they were taken.
+    #[serde(
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    pub paths: Vec<PathBuf>,
+    /// Time from the first photo to the last, in milliseconds.
+    pub duration_ms: u64,
+}
+
+/// Find the bursts among `images`: runs of photos from the same camera
+/// each taken at most `gap` after the one before, by their EXIF capture
+/// time and sub-second digits.
+///
+/// Images without a capture time or camera are left out.
+pub fn find_bursts<S: ImageSource + ?Sized>(images: &S, gap: Duration) -> Vec<Burst> {
+    let shots: Vec<(String, SystemTime, usize)> = (0..images.len())
+        .into_par_iter()
+        .filter_map(|index| {
+            let exif = Exif::read(images.path(index))?;
+            Some((exif.camera()?, exif.shot_time()?, index))
+        })
+        .collect();
+    group_shots(shots, gap)
+        .into_iter()
+        .map(|(camera, run)| {
+            let first = run[0].0;
+            let last = run[run.len() - 1].0;
+            Burst {
+                camera,
+                paths: run
+                    .iter()
+                    .map(|&(_, index)| images.path(index).to_path_buf())
+                    .collect(),
+                duration_ms: last.duration_since(first).unwrap_or_default().as_millis() as u64,
+            }
+        })
+        .collect()
+}
+
+/// Split the `shots` of every camera into runs of at least two shots no
+/// more than `gap` apart, sorted by camera and time.
+fn group_shots(
+    mut shots: Vec<(String, SystemTime, usize)>,
+    gap: Duration,
+) -> Vec<(String, Vec<(SystemTime, usize)>)> {
+    shots.sort();
+    let mut runs: Vec<(String, Vec<(SystemTime, usize)>)> = Vec::new();
+    let mut current: Option<(String, Vec<(SystemTime, usize)>)> = None;
+    for (camera, time, index) in shots {
+        match current.as_mut() {
+            Some((run_camera, run))
+                if *run_camera == camera
+                    && time
+                        .duration_since(run[run.len() - 1].0)
+                        .unwrap_or_default()
+                        <= gap =>
+            {
+                run.push((time, index));
+            }
+            _ => {
+                runs.extend(current.take().filter(|(_, run)| run.len() > 1));
+                current = Some((camera, vec![(time, index)]));
+            }
+        }
+    }
+    runs.extend(current.filter(|(_, run)| run.len() > 1));
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_close_shots_of_one_camera_form_bursts() {
+        let at = |ms: u64| UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + ms);
+        let shots = vec![
+            ("Canon EOS R5 0123".to_string(), at(300), 2),
+            ("Canon EOS R5 0123".to_string(), at(0), 0),
+            ("Canon EOS R5 0123".to_string(), at(150), 1),
+            ("Canon EOS R5 0123".to_string(), at(5_000), 3),
+            ("Canon EOS R5 0123".to_string(), at(5_100), 4),
+            // Another body of the same model shooting at the same time.
+            ("Canon EOS R5 9876".to_string(), at(100), 5),
+            ("Canon EOS R5 9876".to_string(), at(9_000), 6),
+        ];
+        let runs = group_shots(shots, Duration::from_millis(200));
+        let indices: Vec<Vec<usize>> = runs
+            .iter()
+            .map(|(_, run)| run.iter().map(|&(_, index)| index).collect())
+            .collect();
+        assert_eq!(indices, vec![vec![0, 1, 2], vec![3, 4]]);
+    }
+}
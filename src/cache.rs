@@ -0,0 +1,493 @@
+use crate::archive;
+use crate::content::ContentKind;
+use crate::decode::Colorspace;
+use crate::errors::AppError;
+use crate::models::{Dimensions, ImageInfo};
+use image_hasher::ImageHash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CACHE_VERSION: u32 = 1;
+
+/// Size and modification time of a file, used to detect changes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+impl FileStamp {
+    /// Stamp of the file at `path`. Archive members take the modification
+    /// time of their archive.
+    pub fn of(path: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(archive::backing_file(path)).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(FileStamp {
+            size: archive::size(path).ok()?,
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    stamp: FileStamp,
+    /// Base64 encoded hash, or `None` if the file could not be decoded as an image.
+    hash: Option<String>,
+    /// Dimensions of the image, missing from entries written by older
+    /// versions, which are hashed again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dimensions: Option<Dimensions>,
+    /// Base64 encoded variant hashes, stored under settings that include
+    /// `--invariant`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    variants: Vec<String>,
+    /// Base64 encoded region hashes, stored under settings that include
+    /// `--crop-resistant`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<String>,
+    /// Base64 encoded window hashes, stored under settings that include
+    /// `--find-crops`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    windows: Vec<String>,
+    /// Base64 encoded keyframe hashes of animated images, stored under
+    /// settings that include frames.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    frames: Vec<String>,
+    /// Base64 encoded masked hash, stored under settings that include
+    /// `--watermarks`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    masked: Vec<String>,
+    /// Colour model the image was converted to RGB from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    colorspace: Option<Colorspace>,
+    /// What the image shows, stored under settings that include
+    /// `--tag-content`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<ContentKind>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheData {
+    version: u32,
+    /// Entries keyed by hasher settings, then by absolute file path.
+    entries: HashMap<String, HashMap<PathBuf, CacheEntry>>,
+    /// Start time of the last run over each directory, in seconds since the
+    /// Unix epoch, keyed by absolute directory path.
+    #[serde(default)]
+    runs: HashMap<PathBuf, u64>,
+}
+
+/// An entry added to the cache, as appended to its journal.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    settings: String,
+    path: PathBuf,
+    entry: CacheEntry,
+}
+
+/// Result of looking up a file in the cache.
+pub enum Lookup {
+    /// The file changed since it was cached, or was never seen.
+    Miss,
+    /// The file is unchanged and was previously found not to be an image.
+    NotAnImage,
+    /// The file is unchanged and was hashed as this image, which has no
+    /// root, links or sidecars yet.
+    Hit(Box<ImageInfo>),
+}
+
+/// Hashes computed by previous runs, stored as a JSON file.
+///
+/// Entries added while a run hashes are appended to a journal next to the
+/// file by [`HashCache::flush`], one JSON line each, and merged into the
+/// file once the run is over, so that the whole cache is not rewritten
+/// after every chunk. A plain file is kept rather than SQLite or sled, as
+/// the cache is read whole at the start of a run and those would add a
+/// native or large dependency for it.
+pub struct HashCache {
+    path: PathBuf,
+    data: CacheData,
+    /// Entries added since the journal was last written.
+    pending: Vec<Record>,
+    dirty: bool,
+}
+
+impl HashCache {
+    /// Load the cache stored at `path`, starting empty if it does not exist.
+    ///
+    /// A cache that cannot be parsed (e.g. written by an incompatible
+    /// version) is discarded with a warning rather than failing the run.
+    /// The entries of its journal are added back, up to the first line cut
+    /// short by an interrupted run.
+    pub fn load(path: &Path) -> Result<HashCache, AppError> {
+        let mut readable = true;
+        let mut data = match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str::<CacheData>(&contents) {
+                Ok(data) if data.version == CACHE_VERSION => data,
+                _ => {
+                    eprintln!("warning: ignoring unreadable hash cache {:?}", path);
+                    readable = false;
+                    CacheData::default()
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => CacheData::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut dirty = false;
+        match fs::read_to_string(journal_path(path)) {
+            Ok(journal) if readable => {
+                let records = journal
+                    .lines()
+                    .map_while(|line| serde_json::from_str::<Record>(line).ok());
+                for Record {
+                    settings,
+                    path,
+                    entry,
+                } in records
+                {
+                    data.entries
+                        .entry(settings)
+                        .or_default()
+                        .insert(path, entry);
+                    dirty = true;
+                }
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(HashCache {
+            path: path.to_path_buf(),
+            data,
+            pending: Vec::new(),
+            dirty,
+        })
+    }
+
+    pub fn lookup(&self, settings: &str, path: &Path, stamp: &FileStamp) -> Lookup {
+        let entry = self
+            .data
+            .entries
+            .get(settings)
+            .and_then(|entries| entries.get(&absolute(path)));
+
+        match entry {
+            Some(entry) if entry.stamp == *stamp => match (&entry.hash, entry.dimensions) {
+                (Some(hash), Some(dimensions)) => {
+                    match (
+                        ImageHash::from_base64(hash),
+                        decode_hashes(&entry.variants),
+                        decode_hashes(&entry.segments),
+                        decode_hashes(&entry.windows),
+                        decode_hashes(&entry.frames),
+                        decode_hashes(&entry.masked),
+                    ) {
+                        (
+                            Ok(hash),
+                            Some(variants),
+                            Some(segments),
+                            Some(windows),
+                            Some(frames),
+                            Some(masked),
+                        ) => Lookup::Hit(Box::new(ImageInfo {
+                            dimensions: Some(dimensions),
+                            variants,
+                            segments,
+                            windows,
+                            frames,
+                            masked,
+                            colorspace: entry.colorspace,
+                            content: entry.content,
+                            ..ImageInfo::new(path.to_path_buf(), hash)
+                        })),
+                        _ => Lookup::Miss,
+                    }
+                }
+                (Some(_), None) => Lookup::Miss,
+                (None, _) => Lookup::NotAnImage,
+            },
+            _ => Lookup::Miss,
+        }
+    }
+
+    /// Record the hash and dimensions of a file, or `None` if it is not an
+    /// image.
+    pub fn insert(
+        &mut self,
+        settings: &str,
+        path: &Path,
+        stamp: FileStamp,
+        image: Option<&ImageInfo>,
+    ) {
+        let path = absolute(path);
+        // Paths that are not valid UTF-8 cannot be stored as JSON keys.
+        if path.to_str().is_none() {
+            return;
+        }
+
+        let entry = CacheEntry {
+            stamp,
+            hash: image.map(|image| image.hash.to_base64()),
+            dimensions: image.and_then(|image| image.dimensions),
+            variants: image.map_or_else(Vec::new, |image| encode_hashes(&image.variants)),
+            segments: image.map_or_else(Vec::new, |image| encode_hashes(&image.segments)),
+            windows: image.map_or_else(Vec::new, |image| encode_hashes(&image.windows)),
+            frames: image.map_or_else(Vec::new, |image| encode_hashes(&image.frames)),
+            masked: image.map_or_else(Vec::new, |image| encode_hashes(&image.masked)),
+            colorspace: image.and_then(|image| image.colorspace),
+            content: image.and_then(|image| image.content),
+        };
+        self.pending.push(Record {
+            settings: settings.to_string(),
+            path: path.clone(),
+            entry: entry.clone(),
+        });
+        self.data
+            .entries
+            .entry(settings.to_string())
+            .or_default()
+            .insert(path, entry);
+        self.dirty = true;
+    }
+
+    /// When the last run over `directory` started, if one was recorded.
+    pub fn last_run(&self, directory: &Path) -> Option<SystemTime> {
+        let secs = self.data.runs.get(&absolute(directory))?;
+        Some(UNIX_EPOCH + Duration::from_secs(*secs))
+    }
+
+    /// Record that a run over `directory` started at `started`.
+    pub fn record_run(&mut self, directory: &Path, started: SystemTime) {
+        let directory = absolute(directory);
+        if directory.to_str().is_none() {
+            return;
+        }
+        let secs = started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.data.runs.insert(directory, secs);
+        self.dirty = true;
+    }
+
+    /// Append the entries added since the last flush to the journal, so
+    /// that they survive an interrupted run.
+    pub fn flush(&mut self) -> Result<(), AppError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path(&self.path))?;
+        let mut journal = BufWriter::new(file);
+        for record in &self.pending {
+            writeln!(journal, "{}", serde_json::to_string(record)?)?;
+        }
+        journal.flush()?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write the cache back to disk if it changed, merging its journal into
+    /// it.
+    pub fn save(&mut self) -> Result<(), AppError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        self.data.version = CACHE_VERSION;
+        let contents = serde_json::to_string(&self.data)?;
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &self.path)?;
+        remove_if_present(&journal_path(&self.path))?;
+        self.pending.clear();
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Where the journal of the cache at `path` is kept.
+fn journal_path(path: &Path) -> PathBuf {
+    path.with_extension("journal")
+}
+
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Delete the cache at `path` along with its journal, if any.
+pub fn remove(path: &Path) -> Result<(), AppError> {
+    remove_if_present(path)?;
+    remove_if_present(&journal_path(path))?;
+    Ok(())
+}
+
+fn encode_hashes(hashes: &[ImageHash]) -> Vec<String> {
+    hashes.iter().map(ImageHash::to_base64).collect()
+}
+
+fn decode_hashes(hashes: &[String]) -> Option<Vec<ImageHash>> {
+    hashes
+        .iter()
+        .map(|hash| ImageHash::from_base64(hash).ok())
+        .collect()
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Location of the cache when `--cache-path` is not given.
+///
+/// Follows the platform convention for per-user cache directories.
+pub fn default_cache_path() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.map(|dir| dir.join("idar").join("hashes.json"))
+}
+
+/// Delete the cache file at `path`, if any.
+pub fn clear(path: &Path) -> Result<(), AppError> {
+    remove_if_present(&journal_path(path))?;
+    match fs::remove_file(path) {
+        Ok(()) => {
+            println!("Removed hash cache {:?}", path);
+            Ok(())
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            println!("No hash cache found at {:?}", path);
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_round_trip_and_invalidation() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("cache.json");
+        let file = dir.path().join("image.png");
+        fs::write(&file, b"first").unwrap();
+        let stamp = FileStamp::of(&file).unwrap();
+        let hash = ImageHash::from_base64("DAIDBwMHAf8").unwrap();
+        let dimensions = Dimensions {
+            width: 8,
+            height: 8,
+        };
+        let image = ImageInfo {
+            dimensions: Some(dimensions),
+            ..ImageInfo::new(file.clone(), hash.clone())
+        };
+
+        let mut cache = HashCache::load(&cache_path).unwrap();
+        cache.insert("gradient-8", &file, stamp, Some(&image));
+        cache.insert(
+            "mean-8",
+            &file,
+            stamp,
+            Some(&ImageInfo {
+                dimensions: None,
+                ..image.clone()
+            }),
+        );
+        cache.save().unwrap();
+
+        let cache = HashCache::load(&cache_path).unwrap();
+        assert!(matches!(
+            cache.lookup("gradient-8", &file, &stamp),
+            Lookup::Hit(cached) if *cached == image
+        ));
+        assert!(matches!(
+            cache.lookup("mean-8", &file, &stamp),
+            Lookup::Miss
+        ));
+
+        let changed = FileStamp { size: 6, ..stamp };
+        assert!(matches!(
+            cache.lookup("gradient-8", &file, &changed),
+            Lookup::Miss
+        ));
+    }
+
+    #[test]
+    fn test_flushed_entries_are_journaled_until_saved() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("cache.json");
+        let journal = journal_path(&cache_path);
+        let stamp = |size| FileStamp {
+            size,
+            modified_secs: 0,
+            modified_nanos: 0,
+        };
+        let image = |name: &str| ImageInfo {
+            dimensions: Some(Dimensions {
+                width: 8,
+                height: 8,
+            }),
+            ..ImageInfo::new(
+                dir.path().join(name),
+                ImageHash::from_base64("DAIDBwMHAf8").unwrap(),
+            )
+        };
+        let (first, second) = (image("first.png"), image("second.png"));
+
+        let mut cache = HashCache::load(&cache_path).unwrap();
+        cache.insert("mean-8", &first.path, stamp(1), Some(&first));
+        cache.flush().unwrap();
+        cache.insert("mean-8", &second.path, stamp(2), Some(&second));
+        cache.flush().unwrap();
+        assert!(!cache_path.exists());
+        // An interrupted run may leave the last line cut short.
+        let mut lines = fs::read_to_string(&journal).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        lines.push_str("{\"settings\": \"mean-");
+        fs::write(&journal, lines).unwrap();
+
+        let mut cache = HashCache::load(&cache_path).unwrap();
+        for (image, size) in [(&first, 1), (&second, 2)] {
+            assert!(matches!(
+                cache.lookup("mean-8", &image.path, &stamp(size)),
+                Lookup::Hit(cached) if *cached == *image
+            ));
+        }
+        cache.save().unwrap();
+        assert!(cache_path.exists() && !journal.exists());
+        let cache = HashCache::load(&cache_path).unwrap();
+        assert!(matches!(
+            cache.lookup("mean-8", &second.path, &stamp(2)),
+            Lookup::Hit(_)
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::cache::{self, FileStamp, HashCache, Lookup};
+use crate::errors::AppError;
+use crate::models::ImageInfo;
+use std::path::{Path, PathBuf};
+
+/// Name of the checkpoint file written into the scanned directory.
+pub const CHECKPOINT_FILE: &str = ".idar_checkpoint.json";
+
+/// Number of files hashed between two checkpoints when `--chunk-size` is
+/// not given.
+pub const CHECKPOINT_INTERVAL: usize = 1000;
+
+/// Hashes computed so far by a run, saved periodically so that an
+/// interrupted run can be resumed.
+///
+/// Uses the hash cache format, so files changed since the checkpoint are
+/// hashed again.
+pub struct Checkpoint {
+    cache: HashCache,
+    settings: String,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint at `path`, continuing from it if `resume` is set
+    /// and discarding it otherwise.
+    pub fn open(path: &Path, settings: &str, resume: bool) -> Result<Checkpoint, AppError> {
+        if !resume && path.is_file() {
+            println!(
+                "Discarding the checkpoint of an interrupted run; pass --resume to continue it."
+            );
+            cache::remove(path)?;
+        }
+        let cache = HashCache::load(path)?;
+        Ok(Checkpoint {
+            cache,
+            settings: settings.to_string(),
+        })
+    }
+
+    /// Split `entries` into the images already hashed by the interrupted
+    /// run and the files still to hash.
+    pub fn resume(&self, entries: &[PathBuf]) -> (Vec<ImageInfo>, Vec<PathBuf>) {
+        let mut done = Vec::new();
+        let mut pending = Vec::new();
+        for path in entries {
+            let lookup = match FileStamp::of(path) {
+                Some(stamp) => self.cache.lookup(&self.settings, path, &stamp),
+                None => Lookup::Miss,
+            };
+            match lookup {
+                Lookup::Hit(image) => done.push(*image),
+                Lookup::NotAnImage => {}
+                Lookup::Miss => pending.push(path.clone()),
+            }
+        }
+        (done, pending)
+    }
+
+    /// Record the outcome of hashing `entries`, of which `images` are the
+    /// ones that could be decoded, and write the checkpoint to disk.
+    pub fn record(&mut self, entries: &[PathBuf], images: &[ImageInfo]) -> Result<(), AppError> {
+        let mut images = images.iter().peekable();
+        for path in entries {
+            let Some(stamp) = FileStamp::of(path) else {
+                continue;
+            };
+            let image = images.next_if(|image| &image.path == path);
+            self.cache.insert(&self.settings, path, stamp, image);
+        }
+        self.cache.flush()
+    }
+
+    /// Delete the checkpoint after a run completed.
+    pub fn finish(self, path: &Path) -> Result<(), AppError> {
+        cache::remove(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Dimensions;
+    use image_hasher::ImageHash;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resume_skips_recorded_files() {
+        let dir = tempdir().unwrap();
+        let checkpoint_path = dir.path().join(CHECKPOINT_FILE);
+        let entries: Vec<PathBuf> = ["a.png", "b.txt", "c.png"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        for path in &entries {
+            fs::write(path, b"data").unwrap();
+        }
+        let image = ImageInfo {
+            dimensions: Some(Dimensions {
+                width: 8,
+                height: 8,
+            }),
+            ..ImageInfo::new(
+                entries[0].clone(),
+                ImageHash::from_base64("DAIDBwMHAf8").unwrap(),
+            )
+        };
+
+        let mut checkpoint = Checkpoint::open(&checkpoint_path, "gradient-8", false).unwrap();
+        checkpoint
+            .record(&entries[..2], std::slice::from_ref(&image))
+            .unwrap();
+
+        let checkpoint = Checkpoint::open(&checkpoint_path, "gradient-8", true).unwrap();
+        let (done, pending) = checkpoint.resume(&entries);
+        assert_eq!(done, vec![image]);
+        assert_eq!(pending, vec![entries[2].clone()]);
+
+        checkpoint.finish(&checkpoint_path).unwrap();
+        assert!(!checkpoint_path.exists());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::exif::Exif;
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Side of the square images are fitted in before they are analysed. They
+/// are sampled without filtering, so flat areas keep their exact colour.
+const SAMPLE_SIZE: u32 = 256;
+
+/// Words in the names screenshot tools give their files.
+const SCREENSHOT_NAMES: &[&str] = &[
+    "screenshot",
+    "screen shot",
+    "screen_shot",
+    "bildschirmfoto",
+    "captura de pantalla",
+    "capture d'écran",
+];
+
+/// Fraction of neighbouring pixels of the exact same colour above which an
+/// image without camera metadata is taken for a screenshot. Photos, with
+/// their sensor noise and JPEG artifacts, come nowhere near it.
+const FLAT_FRACTION: f64 = 0.5;
+
+/// Least fraction of near-white pixels of a document page.
+const PAPER_FRACTION: f64 = 0.6;
+
+/// Range of the fraction of dark pixels of a document page: the ink.
+const INK_FRACTION: (f64, f64) = (0.005, 0.3);
+
+/// Largest mean difference between the colour channels of a document,
+/// which is mostly black on white.
+const MAX_COLORFULNESS: f64 = 24.0;
+
+/// What an image most likely shows, tagged with `--tag-content`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+    /// Photos and any image that is neither of the others.
+    Photo,
+    /// Captures of a screen, with flat areas of solid colour.
+    Screenshot,
+    /// Scanned or photographed pages of text, mostly ink on white paper.
+    Document,
+}
+
+impl fmt::Display for ContentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentKind::Photo => write!(f, "photo"),
+            ContentKind::Screenshot => write!(f, "screenshot"),
+            ContentKind::Document => write!(f, "document"),
+        }
+    }
+}
+
+/// Guess what the image `img` decoded from `path` shows, from its name, its
+/// camera metadata and how its colours are spread.
+pub fn classify(path: &Path, img: &DynamicImage) -> ContentKind {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if SCREENSHOT_NAMES.iter().any(|word| name.contains(word)) {
+        return ContentKind::Screenshot;
+    }
+    let (width, height) = img.dimensions();
+    let sample = if width > SAMPLE_SIZE || height > SAMPLE_SIZE {
+        img.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Nearest)
+    } else {
+        img.clone()
+    }
+    .to_rgb8();
+    let stats = Stats::of(&sample);
+    // Pages rendered straight from a file are as flat as a screen, and are
+    // taken for screenshots; scanned pages have the noise of the sensor.
+    if stats.flat >= FLAT_FRACTION && Exif::read(path).and_then(|e| e.camera()).is_none() {
+        return ContentKind::Screenshot;
+    }
+    if stats.paper >= PAPER_FRACTION
+        && (INK_FRACTION.0..=INK_FRACTION.1).contains(&stats.ink)
+        && stats.colorfulness <= MAX_COLORFULNESS
+    {
+        return ContentKind::Document;
+    }
+    ContentKind::Photo
+}
+
+/// Colour statistics of a sampled image, as fractions of its pixels.
+struct Stats {
+    /// Pixels of the same colour as the one to their right.
+    flat: f64,
+    /// Near-white pixels.
+    paper: f64,
+    /// Dark pixels.
+    ink: f64,
+    /// Mean difference between the colour channels of a pixel.
+    colorfulness: f64,
+}
+
+impl Stats {
+    fn of(img: &image::RgbImage) -> Stats {
+        let (width, height) = img.dimensions();
+        let mut flat = 0u64;
+        let mut paper = 0u64;
+        let mut ink = 0u64;
+        let mut colorfulness = 0u64;
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let [r, g, b] = pixel.0;
+            if x + 1 < width && img.get_pixel(x + 1, y) == pixel {
+                flat += 1;
+            }
+            let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+            if luma >= 200 {
+                paper += 1;
+            } else if luma < 100 {
+                ink += 1;
+            }
+            colorfulness += (r.abs_diff(g) as u64 + g.abs_diff(b) as u64) / 2;
+        }
+        let pixels = (width as u64 * height as u64).max(1) as f64;
+        let pairs = ((width.saturating_sub(1)) as u64 * height as u64).max(1) as f64;
+        Stats {
+            flat: flat as f64 / pairs,
+            paper: paper as f64 / pixels,
+            ink: ink as f64 / pixels,
+            colorfulness: colorfulness as f64 / pixels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_screenshots_documents_and_photos_are_told_apart() {
+        // A window with a title bar and buttons on a flat background.
+        let screenshot = RgbImage::from_fn(400, 300, |x, y| match (x, y) {
+            (_, 0..=29) => Rgb([40, 40, 60]),
+            (20..=120, 200..=230) => Rgb([30, 120, 220]),
+            _ => Rgb([236, 236, 236]),
+        });
+        // Lines of text on slightly uneven paper.
+        let page = RgbImage::from_fn(300, 420, |x, y| {
+            let noise = ((x * 7 + y * 13) % 9) as u8;
+            if y % 20 < 4 && (30..270).contains(&x) {
+                Rgb([20 + noise, 20 + noise, 25 + noise])
+            } else {
+                Rgb([240 + noise, 238 + noise, 232 + noise])
+            }
+        });
+        // A colourful gradient with noise everywhere.
+        let photo = RgbImage::from_fn(300, 200, |x, y| {
+            let noise = ((x * 31 + y * 17) % 11) as u8;
+            Rgb([(x % 200) as u8 + noise, (y % 200) as u8, 90 + noise])
+        });
+
+        let path = Path::new("IMG_0001.png");
+        let classify = |img: RgbImage, path: &Path| classify(path, &DynamicImage::ImageRgb8(img));
+        assert_eq!(classify(screenshot, path), ContentKind::Screenshot);
+        assert_eq!(classify(page, path), ContentKind::Document);
+        assert_eq!(classify(photo.clone(), path), ContentKind::Photo);
+        let named = Path::new("Screenshot 2024-05-01 at 10.00.00.png");
+        assert_eq!(classify(photo, named), ContentKind::Screenshot);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::content::ContentKind;
+use crate::decode::Colorspace;
+use crate::distance;
+use crate::hashing::PerceptualHasher;
+use crate::models::{Dimensions, ImageInfo};
+use crate::store::{Hashes, ImageSource};
+use image::{DynamicImage, GenericImageView};
+use image_hasher::ImageHash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Windows are placed on a grid dividing each side into this many steps.
+const STEPS: u32 = 8;
+
+/// Side lengths of the windows, in steps: half and three quarters of the
+/// image.
+const SIZES: [u32; 2] = [4, 6];
+
+/// Size and offsets, in steps, of every window, in the order their hashes
+/// are stored.
+fn windows() -> Vec<(u32, u32, u32)> {
+    SIZES
+        .iter()
+        .flat_map(|&size| {
+            (0..=STEPS - size).flat_map(move |y| (0..=STEPS - size).map(move |x| (size, x, y)))
+        })
+        .collect()
+}
+
+/// Hash the windows of `img`, squares of its grid at several scales, so
+/// that a crop of it can be found by its whole-image hash.
+pub fn hash_windows(hasher: &dyn PerceptualHasher, img: &DynamicImage) -> Vec<ImageHash> {
+    let (width, height) = img.dimensions();
+    if width < STEPS || height < STEPS {
+        return Vec::new();
+    }
+    windows()
+        .into_iter()
+        .map(|(size, x, y)| {
+            let window = img.crop_imm(
+                x * width / STEPS,
+                y * height / STEPS,
+                size * width / STEPS,
+                size * height / STEPS,
+            );
+            hasher.hash_image(&window)
+        })
+        .collect()
+}
+
+/// Rectangle of an image, in pixels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// An image that appears to be a crop of another one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Crop {
+    /// The cropped image.
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub crop: PathBuf,
+    /// The image it was cut from.
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub source: PathBuf,
+    /// Approximate part of `source` shown by `crop`, unless the size of
+    /// `source` is unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// Distance between the hash of `crop` and that of the region.
+    pub distance: u32,
+}
+
+/// The images of a source followed by the window hashes of each, so that
+/// matching pairs images with windows of other images.
+pub struct Windows<'a, S: ?Sized> {
+    images: &'a S,
+    /// Image and window of every entry, `None` for the whole image.
+    entries: Vec<(usize, Option<usize>)>,
+}
+
+impl<'a, S: ImageSource + ?Sized> Windows<'a, S> {
+    pub fn new(images: &'a S) -> Windows<'a, S> {
+        let mut entries: Vec<(usize, Option<usize>)> =
+            (0..images.len()).map(|index| (index, None)).collect();
+        for index in 0..images.len() {
+            entries.extend((0..images.windows(index).len()).map(|window| (index, Some(window))));
+        }
+        Windows { images, entries }
+    }
+
+    /// The crops shown by the pairs of matching entries, sorted by path.
+    ///
+    /// Only pairs of a whole image and a window of another image count.
+    /// Images in the same group of `groups` are already duplicates and are
+    /// not reported as crops of each other.
+    pub fn crops(&self, pairs: Vec<(usize, usize)>, groups: &[Vec<usize>]) -> Vec<Crop> {
+        let grouped: HashSet<(usize, usize)> = groups
+            .iter()
+            .flat_map(|group| {
+                group
+                    .iter()
+                    .flat_map(move |&i| group.iter().map(move |&j| (i, j)))
+            })
+            .collect();
+        let windows = windows();
+
+        // Closest window of each source, by crop and source.
+        let mut closest: BTreeMap<(usize, usize), (u32, usize)> = BTreeMap::new();
+        for (i, j) in pairs {
+            let (crop, (source, window)) = match (self.entries[i], self.entries[j]) {
+                ((crop, None), (source, Some(window))) => (crop, (source, window)),
+                ((source, Some(window)), (crop, None)) => (crop, (source, window)),
+                _ => continue,
+            };
+            if crop == source || grouped.contains(&(crop, source)) {
+                continue;
+            }
+            let dist = distance::hamming(self.hash_bytes(i), self.hash_bytes(j));
+            let best = closest.entry((crop, source)).or_insert((dist, window));
+            if dist < best.0 {
+                *best = (dist, window);
+            }
+        }
+
+        let mut crops: Vec<Crop> = closest
+            .into_iter()
+            .map(|((crop, source), (distance, window))| {
+                let (size, x, y) = windows[window];
+                Crop {
+                    crop: self.images.path(crop).to_path_buf(),
+                    source: self.images.path(source).to_path_buf(),
+                    region: self.images.dimensions(source).map(|dims| Region {
+                        x: x * dims.width / STEPS,
+                        y: y * dims.height / STEPS,
+                        width: size * dims.width / STEPS,
+                        height: size * dims.height / STEPS,
+                    }),
+                    distance,
+                }
+            })
+            .collect();
+        crops.sort_by(|a, b| (&a.crop, &a.source).cmp(&(&b.crop, &b.source)));
+        crops
+    }
+}
+
+impl<S: ImageSource + ?Sized> ImageSource for Windows<'_, S> {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn path(&self, index: usize) -> &Path {
+        self.images.path(self.entries[index].0)
+    }
+
+    fn hash_bytes(&self, index: usize) -> &[u8] {
+        match self.entries[index] {
+            (owner, None) => self.images.hash_bytes(owner),
+            (owner, Some(window)) => self.images.windows(owner).bytes(window),
+        }
+    }
+
+    fn root(&self, index: usize) -> Option<&Path> {
+        self.images.root(self.entries[index].0)
+    }
+
+    fn links(&self, index: usize) -> Vec<PathBuf> {
+        self.images.links(self.entries[index].0)
+    }
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf> {
+        self.images.sidecars(self.entries[index].0)
+    }
+
+    fn variants(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn segments(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn windows(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn frames(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn masked(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions> {
+        self.images.dimensions(self.entries[index].0)
+    }
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace> {
+        self.images.colorspace(self.entries[index].0)
+    }
+
+    fn content(&self, index: usize) -> Option<ContentKind> {
+        self.images.content(self.entries[index].0)
+    }
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String> {
+        self.images.columns(self.entries[index].0)
+    }
+
+    fn image(&self, index: usize) -> ImageInfo {
+        self.images.image(self.entries[index].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::{self, HashAlgorithm};
+    use crate::matching;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn test_window_of_image_is_found_as_crop() {
+        let hasher = hashing::build_hasher(HashAlgorithm::Gradient, 8);
+        let original = DynamicImage::ImageLuma8(GrayImage::from_fn(320, 240, |x, y| {
+            let cell = (x / 12 * 31 + y / 12 * 17).wrapping_mul(2_654_435_761);
+            Luma([(cell >> 24) as u8])
+        }));
+        // The right half of the middle, as in window (4, 4, 2).
+        let crop = original.crop_imm(160, 60, 160, 120);
+        let images: Vec<ImageInfo> = [("original.png", &original), ("crop.png", &crop)]
+            .iter()
+            .map(|&(name, img)| ImageInfo {
+                dimensions: Some(Dimensions {
+                    width: img.width(),
+                    height: img.height(),
+                }),
+                windows: hash_windows(&hasher, img),
+                ..ImageInfo::new(PathBuf::from(name), hasher.hash_image(img))
+            })
+            .collect();
+        assert_eq!(images[0].windows.len(), 34);
+
+        let windows = Windows::new(&images[..]);
+        let crops = windows.crops(matching::candidate_pairs(&windows, 5), &[]);
+        assert_eq!(crops.len(), 1);
+        assert_eq!(crops[0].crop, PathBuf::from("crop.png"));
+        assert_eq!(
+            crops[0].region,
+            Some(Region {
+                x: 160,
+                y: 60,
+                width: 160,
+                height: 120
+            })
+        );
+        assert!(windows
+            .crops(matching::candidate_pairs(&windows, 5), &[vec![0, 1]])
+            .is_empty());
+    }
+}
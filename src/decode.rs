@@ -0,0 +1,659 @@
+use crate::archive;
+use crate::exif::Exif;
+#[cfg(feature = "heif")]
+use crate::heif;
+use crate::icc;
+use crate::models::Dimensions;
+use crate::pages;
+use crate::raw;
+#[cfg(feature = "svg")]
+use crate::svg;
+#[cfg(feature = "video")]
+use crate::video;
+use image::codecs::tiff::TiffDecoder;
+use image::metadata::Orientation;
+use image::{
+    DynamicImage, ExtendedColorType, GrayImage, ImageDecoder, ImageFormat, ImageReader,
+    ImageResult, RgbImage, RgbaImage,
+};
+use jpeg_decoder::PixelFormat;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Number of leading bytes read to detect an image format.
+const SIGNATURE_LEN: usize = 16;
+
+/// Default cap on decoded pixels, far more than any fingerprint needs.
+pub const DEFAULT_MAX_PIXELS: u64 = 4_000_000;
+
+/// Settings for decoding images before they are fingerprinted.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Largest number of pixels kept after decoding, or `None` to always
+    /// decode at full resolution.
+    pub max_pixels: Option<u64>,
+    /// Shared limit on simultaneous decodes, or `None` to decode on every
+    /// thread at once.
+    pub limiter: Option<Arc<DecodeLimiter>>,
+    /// Turn images upright according to their EXIF orientation, so that a
+    /// photo and its rotated copy are decoded the same.
+    pub orientation: bool,
+    /// Convert images with an embedded ICC profile to sRGB, so that a
+    /// wide-gamut export and its sRGB copy are decoded the same.
+    pub color_profiles: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            max_pixels: Some(DEFAULT_MAX_PIXELS),
+            limiter: None,
+            orientation: true,
+            color_profiles: false,
+        }
+    }
+}
+
+/// Counting semaphore bounding how many images are decoded at the same time.
+///
+/// Useful on slow disks, where many concurrent reads thrash, and to bound
+/// the memory used by full resolution images in flight.
+#[derive(Debug)]
+pub struct DecodeLimiter {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl DecodeLimiter {
+    pub fn new(limit: usize) -> DecodeLimiter {
+        DecodeLimiter {
+            available: Mutex::new(limit.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn acquire(&self) -> DecodePermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        DecodePermit(self)
+    }
+}
+
+pub(crate) struct DecodePermit<'a>(&'a DecodeLimiter);
+
+impl Drop for DecodePermit<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Format of the image at `path`, detected from its first bytes.
+///
+/// The extension is only used for formats without a signature, such as
+/// TGA, so misnamed files are recognised and files that are not images are
+/// rejected without a decode attempt. `path` may name an archive member.
+pub fn format_of(path: &Path) -> Option<ImageFormat> {
+    let start = archive::read_start(path, SIGNATURE_LEN).ok()?;
+    match image::guess_format(&start) {
+        Ok(format) => Some(format),
+        Err(_) => ImageFormat::from_path(path)
+            .ok()
+            .filter(|format| *format == ImageFormat::Tga),
+    }
+}
+
+/// Colour model of print images, which are converted to RGB when decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Colorspace {
+    Cmyk,
+    /// CMYK whose colour channels are stored as YCbCr, as Adobe
+    /// applications write JPEGs.
+    Ycck,
+}
+
+impl fmt::Display for Colorspace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Colorspace::Cmyk => write!(f, "CMYK"),
+            Colorspace::Ycck => write!(f, "YCCK"),
+        }
+    }
+}
+
+/// Colour model of the JPEG or TIFF at `path` if it is stored as CMYK or
+/// YCCK and is converted to RGB when decoded, or `None` for RGB and
+/// greyscale images.
+pub fn colorspace_of(path: &Path) -> Option<Colorspace> {
+    let source = archive::open(path).ok()?;
+    match format_of(path)? {
+        ImageFormat::Jpeg => jpeg_colorspace(io::BufReader::new(source)).ok()?,
+        ImageFormat::Tiff => {
+            let decoder = TiffDecoder::new(source).ok()?;
+            (decoder.original_color_type() == ExtendedColorType::Cmyk8).then_some(Colorspace::Cmyk)
+        }
+        _ => None,
+    }
+}
+
+/// Whether `marker` starts the frame header of a JPEG, for the baseline,
+/// progressive and lossless processes.
+fn is_frame_header(marker: u8) -> bool {
+    matches!(marker, 0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF)
+}
+
+/// Read the segments of a JPEG before its scan data, passing the marker
+/// and payload of each to `visit` until it returns `false`.
+pub fn read_jpeg_segments(
+    mut reader: impl Read,
+    mut visit: impl FnMut(u8, &[u8]) -> bool,
+) -> io::Result<()> {
+    let mut marker = [0u8; 4];
+    reader.read_exact(&mut marker[..2])?;
+    if marker[..2] != [0xFF, 0xD8] {
+        return Ok(());
+    }
+    let mut payload = Vec::new();
+    loop {
+        reader.read_exact(&mut marker)?;
+        if marker[0] != 0xFF || matches!(marker[1], 0xDA | 0xD9) {
+            return Ok(());
+        }
+        let len = (u16::from_be_bytes([marker[2], marker[3]]) as u64).saturating_sub(2);
+        payload.clear();
+        reader.by_ref().take(len).read_to_end(&mut payload)?;
+        if !visit(marker[1], &payload) {
+            return Ok(());
+        }
+    }
+}
+
+/// Read the segments of a JPEG up to its frame header to find whether it
+/// has four colour components, and whether an Adobe segment declares them
+/// as YCCK.
+fn jpeg_colorspace(reader: impl Read) -> io::Result<Option<Colorspace>> {
+    let mut transform = None;
+    let mut colorspace = None;
+    read_jpeg_segments(reader, |marker, payload| match marker {
+        // The last byte of the Adobe segment gives the colour transform.
+        0xEE if payload.len() >= 12 && payload.starts_with(b"Adobe") => {
+            transform = Some(payload[11]);
+            true
+        }
+        // The number of components is at byte 5 of the frame header.
+        _ if is_frame_header(marker) => {
+            colorspace = match (payload.get(5), transform) {
+                (Some(4), Some(2)) => Some(Colorspace::Ycck),
+                (Some(4), _) => Some(Colorspace::Cmyk),
+                _ => None,
+            };
+            false
+        }
+        _ => true,
+    })?;
+    Ok(colorspace)
+}
+
+/// Brands of ISO base media files holding HEIF or AVIF images, by the name
+/// of the format.
+const HEIF_BRANDS: [(&[u8; 4], &str); 10] = [
+    (b"heic", "HEIC"),
+    (b"heix", "HEIC"),
+    (b"heim", "HEIC"),
+    (b"heis", "HEIC"),
+    (b"hevc", "HEIC"),
+    (b"hevx", "HEIC"),
+    (b"mif1", "HEIF"),
+    (b"msf1", "HEIF"),
+    (b"avif", "AVIF"),
+    (b"avis", "AVIF"),
+];
+
+/// Name of the format of the image at `path` if it is one this build
+/// recognises but cannot decode, such as HEIC photos from phones.
+///
+/// Decoding HEIF and AVIF needs the libheif and dav1d libraries, which are
+/// not linked in, so without the `heif` feature these files are reported
+/// as errors rather than skipped like files that are not images.
+pub fn undecodable_format(path: &Path) -> Option<&'static str> {
+    if cfg!(feature = "heif") {
+        return None;
+    }
+    heif_format(path)
+}
+
+/// Whether `path` holds a HEIF or AVIF image this build decodes through an
+/// external program, by the brand of its file.
+#[cfg(feature = "heif")]
+pub fn is_heif(path: &Path) -> bool {
+    heif_format(path).is_some()
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn is_heif(_path: &Path) -> bool {
+    false
+}
+
+/// Name of the HEIF or AVIF format of the image at `path`, by the brand
+/// its file starts with.
+fn heif_format(path: &Path) -> Option<&'static str> {
+    let start = archive::read_start(path, SIGNATURE_LEN).ok()?;
+    if start.get(4..8) != Some(b"ftyp".as_slice()) {
+        return None;
+    }
+    let brand = start.get(8..12)?;
+    HEIF_BRANDS
+        .iter()
+        .find(|(known, _)| known.as_slice() == brand)
+        .map(|&(_, name)| name)
+}
+
+/// Whether `path` names a vector drawing this build rasterizes, which is
+/// recognised by its extension rather than a signature.
+#[cfg(feature = "svg")]
+pub fn is_vector(path: &Path) -> bool {
+    svg::is_svg(path)
+}
+
+#[cfg(not(feature = "svg"))]
+pub fn is_vector(_path: &Path) -> bool {
+    false
+}
+
+/// Whether `path` names a video this build samples frames from, by its
+/// extension. Videos are decoded through their first frame.
+#[cfg(feature = "video")]
+pub fn is_video(path: &Path) -> bool {
+    video::is_video(path)
+}
+
+#[cfg(not(feature = "video"))]
+pub fn is_video(_path: &Path) -> bool {
+    false
+}
+
+/// Decode the image at `path`, reducing it to at most `options.max_pixels`.
+///
+/// Large JPEGs are scaled while decoding, so the full resolution image is
+/// never held in memory. Other formats are decoded in full and shrunk right
+/// away, which keeps the later resizing done by the hashers cheap.
+pub fn open(path: &Path, options: &DecodeOptions) -> ImageResult<DynamicImage> {
+    open_sized(path, options).map(|(img, _)| img)
+}
+
+/// Like [`open`], also returning the dimensions of the image before it was
+/// reduced, after turning it upright.
+pub fn open_sized(path: &Path, options: &DecodeOptions) -> ImageResult<(DynamicImage, Dimensions)> {
+    let _permit = options.limiter.as_ref().map(|limiter| limiter.acquire());
+    if let Some((file, page)) = pages::split(path) {
+        let img = to_eight_bit(pages::decode(&file, page)?);
+        let dimensions = dimensions_of(&img);
+        return match options.max_pixels {
+            Some(max_pixels) => Ok((shrink(img, max_pixels), dimensions)),
+            None => Ok((img, dimensions)),
+        };
+    }
+    #[cfg(feature = "heif")]
+    if is_heif(path) {
+        let img = heif::decode(path)?;
+        let dimensions = dimensions_of(&img);
+        return match options.max_pixels {
+            Some(max_pixels) => Ok((shrink(img, max_pixels), dimensions)),
+            None => Ok((img, dimensions)),
+        };
+    }
+    #[cfg(feature = "svg")]
+    if svg::is_svg(path) {
+        let img = svg::rasterize(path)?;
+        let dimensions = dimensions_of(&img);
+        return match options.max_pixels {
+            Some(max_pixels) => Ok((shrink(img, max_pixels), dimensions)),
+            None => Ok((img, dimensions)),
+        };
+    }
+    #[cfg(feature = "video")]
+    if video::is_video(path) {
+        let img = video::first_frame(path)?;
+        let dimensions = dimensions_of(&img);
+        return match options.max_pixels {
+            Some(max_pixels) => Ok((shrink(img, max_pixels), dimensions)),
+            None => Ok((img, dimensions)),
+        };
+    }
+    if raw::is_raw(path) {
+        let (mut img, orientation) = raw::decode_preview(path)?;
+        if options.orientation {
+            img.apply_orientation(orientation.unwrap_or(Orientation::NoTransforms));
+        }
+        let dimensions = dimensions_of(&img);
+        return match options.max_pixels {
+            Some(max_pixels) => Ok((shrink(img, max_pixels), dimensions)),
+            None => Ok((img, dimensions)),
+        };
+    }
+    let reader = ImageReader::new(archive::open(path)?).with_guessed_format()?;
+    if let (Some(max_pixels), Some(ImageFormat::Jpeg)) = (options.max_pixels, reader.format()) {
+        if let Some((img, dimensions)) = open_jpeg_scaled(path, max_pixels, options) {
+            return Ok((shrink(img, max_pixels), dimensions));
+        }
+    }
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = match options.orientation {
+        true => decoder.orientation().unwrap_or(Orientation::NoTransforms),
+        false => Orientation::NoTransforms,
+    };
+    let profile = match options.color_profiles {
+        true => decoder.icc_profile().ok().flatten(),
+        false => None,
+    };
+    let mut img = to_eight_bit(DynamicImage::from_decoder(decoder)?);
+    if let Some(profile) = profile {
+        img = icc::to_srgb(img, &profile);
+    }
+    img.apply_orientation(orientation);
+    let dimensions = dimensions_of(&img);
+    match options.max_pixels {
+        Some(max_pixels) => Ok((shrink(img, max_pixels), dimensions)),
+        None => Ok((img, dimensions)),
+    }
+}
+
+fn dimensions_of(img: &DynamicImage) -> Dimensions {
+    Dimensions {
+        width: img.width(),
+        height: img.height(),
+    }
+}
+
+/// Decode a JPEG larger than `max_pixels` at a reduced DCT scale, returning
+/// it with its full dimensions, turned upright and converted to sRGB as
+/// `options` say.
+///
+/// Returns `None` when the image is small enough to decode normally, or
+/// uses a pixel format left to the `image` crate. CMYK and YCCK images are
+/// converted to RGB the way the `image` crate converts them in full, so
+/// both decodes hash alike.
+fn open_jpeg_scaled(
+    path: &Path,
+    max_pixels: u64,
+    options: &DecodeOptions,
+) -> Option<(DynamicImage, Dimensions)> {
+    let mut decoder = jpeg_decoder::Decoder::new(archive::open(path).ok()?);
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let (full_width, full_height) = (info.width as u32, info.height as u32);
+    if (full_width as u64) * (full_height as u64) <= max_pixels {
+        return None;
+    }
+    if !matches!(
+        info.pixel_format,
+        PixelFormat::L8 | PixelFormat::RGB24 | PixelFormat::CMYK32
+    ) {
+        return None;
+    }
+
+    let (target_width, target_height) = fit(full_width, full_height, max_pixels);
+    decoder
+        .scale(target_width as u16, target_height as u16)
+        .ok()?;
+    let pixels = decoder.decode().ok()?;
+    let info = decoder.info()?;
+    let (width, height) = (info.width as u32, info.height as u32);
+    let mut img = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::from),
+        PixelFormat::RGB24 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::from),
+        PixelFormat::CMYK32 => {
+            let rgb = pixels.chunks_exact(4).flat_map(cmyk_to_rgb).collect();
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::from)
+        }
+        _ => None,
+    }?;
+    if let Some(profile) = decoder.icc_profile().filter(|_| options.color_profiles) {
+        img = icc::to_srgb(img, &profile);
+    }
+    let orientation = decoder
+        .exif_data()
+        .filter(|_| options.orientation)
+        .and_then(|data| Exif::parse(data.to_vec()))
+        .and_then(|exif| exif.orientation())
+        .unwrap_or(Orientation::NoTransforms);
+    img.apply_orientation(orientation);
+    let dimensions = match orientation {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => Dimensions {
+            width: full_height,
+            height: full_width,
+        },
+        _ => Dimensions {
+            width: full_width,
+            height: full_height,
+        },
+    };
+    Some((img, dimensions))
+}
+
+/// Convert the ink amounts of a CMYK pixel to RGB by multiplying out the
+/// black ink, each product rounded as the `image` crate's JPEG decoder does.
+fn cmyk_to_rgb(cmyk: &[u8]) -> [u8; 3] {
+    let blend = |ink: u8| {
+        let t = (255 - ink) as u32 * (255 - cmyk[3]) as u32 + 128;
+        (((t >> 8) + t) >> 8) as u8
+    };
+    [blend(cmyk[0]), blend(cmyk[1]), blend(cmyk[2])]
+}
+
+/// Largest dimensions with the aspect ratio of `width` x `height` that fit
+/// in `max_pixels`.
+fn fit(width: u32, height: u32, max_pixels: u64) -> (u32, u32) {
+    let scale = (max_pixels as f64 / (width as f64 * height as f64)).sqrt();
+    let scaled = |side: u32| ((side as f64 * scale) as u32).max(1);
+    (scaled(width), scaled(height))
+}
+
+/// Reduce `img` to eight bits per sample, so that a 16-bit master and its
+/// 8-bit export decode to the same pixels.
+///
+/// Floating point HDR images are tone mapped with the extended Reinhard
+/// curve, whose white point is the brightest sample, and encoded as sRGB.
+/// Images within the displayable range come out as a plain sRGB export
+/// would, and brighter ones have their highlights compressed instead of
+/// clipped.
+pub fn to_eight_bit(img: DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma16(_) => DynamicImage::ImageLuma8(img.to_luma8()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        DynamicImage::ImageRgb16(_) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        DynamicImage::ImageRgb32F(hdr) => {
+            let white = hdr.iter().fold(1.0f32, |white, &value| white.max(value));
+            let (width, height) = hdr.dimensions();
+            let samples = hdr.into_raw().into_iter().map(|v| tone_map(v, white));
+            DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, samples.collect()).unwrap())
+        }
+        DynamicImage::ImageRgba32F(hdr) => {
+            let white = hdr
+                .pixels()
+                .flat_map(|pixel| &pixel.0[..3])
+                .fold(1.0f32, |white, &value| white.max(value));
+            let (width, height) = hdr.dimensions();
+            let mut img = RgbaImage::new(width, height);
+            for (out, pixel) in img.pixels_mut().zip(hdr.pixels()) {
+                let [r, g, b, a] = pixel.0;
+                let alpha = (a.clamp(0.0, 1.0) * 255.0).round() as u8;
+                out.0 = [
+                    tone_map(r, white),
+                    tone_map(g, white),
+                    tone_map(b, white),
+                    alpha,
+                ];
+            }
+            DynamicImage::ImageRgba8(img)
+        }
+        img => img,
+    }
+}
+
+/// Tone map the linear sample `value` of an image whose brightest sample
+/// is `white`, at least 1, to an 8-bit sRGB sample.
+fn tone_map(value: f32, white: f32) -> u8 {
+    let value = value.max(0.0);
+    let mapped = (value * (1.0 + value / (white * white)) / (1.0 + value)).min(1.0);
+    let encoded = if mapped <= 0.003_130_8 {
+        12.92 * mapped
+    } else {
+        1.055 * mapped.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+fn shrink(img: DynamicImage, max_pixels: u64) -> DynamicImage {
+    if (img.width() as u64) * (img.height() as u64) <= max_pixels {
+        return img;
+    }
+    let (width, height) = fit(img.width(), img.height(), max_pixels);
+    img.thumbnail(width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_caps_decoded_pixels() {
+        let dir = tempdir().unwrap();
+        let image = RgbImage::from_fn(640, 480, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        for name in ["large.jpg", "large.png"] {
+            let path = dir.path().join(name);
+            image.save(&path).unwrap();
+
+            let options = DecodeOptions {
+                max_pixels: Some(100 * 100),
+                ..DecodeOptions::default()
+            };
+            let (img, dimensions) = open_sized(&path, &options).unwrap();
+            let (width, height) = img.dimensions();
+            assert!(width * height <= 100 * 100, "{name}: {width}x{height}");
+            assert!(width >= 100, "{name}: {width}x{height}");
+            assert_eq!((dimensions.width, dimensions.height), (640, 480));
+
+            let options = DecodeOptions {
+                max_pixels: None,
+                ..DecodeOptions::default()
+            };
+            let full = open(&path, &options).unwrap();
+            assert_eq!(full.dimensions(), (640, 480));
+        }
+    }
+
+    #[test]
+    fn test_sixteen_bit_and_hdr_images_are_reduced_to_eight_bits() {
+        let dir = tempdir().unwrap();
+        let master = image::ImageBuffer::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 2000) as u16, (y * 2000) as u16, 65535])
+        });
+        let master_path = dir.path().join("master.png");
+        DynamicImage::ImageRgb16(master.clone())
+            .save(&master_path)
+            .unwrap();
+        let export_path = dir.path().join("export.png");
+        DynamicImage::ImageRgb16(master)
+            .to_rgb8()
+            .save(&export_path)
+            .unwrap();
+
+        let master = open(&master_path, &DecodeOptions::default()).unwrap();
+        let export = open(&export_path, &DecodeOptions::default()).unwrap();
+        assert_eq!(master.as_rgb8(), export.as_rgb8());
+        assert!(master.as_rgb8().is_some());
+
+        // Within the displayable range the curve is a plain sRGB encoding,
+        // and brighter images have their brightest sample at full white.
+        let hdr = |values: [f32; 3]| {
+            let img = image::Rgb32FImage::from_pixel(1, 1, image::Rgb(values));
+            to_eight_bit(DynamicImage::ImageRgb32F(img))
+                .to_rgb8()
+                .get_pixel(0, 0)
+                .0
+        };
+        assert_eq!(hdr([0.5, 0.0, 1.0]), [188, 0, 255]);
+        assert_eq!(hdr([4.0, 0.5, 0.0])[0], 255);
+        assert!(hdr([4.0, 0.5, 0.0])[1] < 188);
+    }
+
+    #[test]
+    fn test_cmyk_and_ycck_jpegs_are_recognised() {
+        let jpeg = |adobe_transform: Option<u8>, components: u8| {
+            let mut bytes = vec![0xFF, 0xD8];
+            bytes.extend([0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+            if let Some(transform) = adobe_transform {
+                bytes.extend([0xFF, 0xEE, 0x00, 0x0E]);
+                bytes.extend(b"Adobe\x00\x64\x00\x00\x00\x00");
+                bytes.push(transform);
+            }
+            bytes.extend([
+                0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x10, 0x00, 0x10, components,
+            ]);
+            bytes
+        };
+        let colorspace = |bytes: Vec<u8>| jpeg_colorspace(bytes.as_slice()).unwrap();
+        assert_eq!(colorspace(jpeg(None, 4)), Some(Colorspace::Cmyk));
+        assert_eq!(colorspace(jpeg(Some(0), 4)), Some(Colorspace::Cmyk));
+        assert_eq!(colorspace(jpeg(Some(2), 4)), Some(Colorspace::Ycck));
+        assert_eq!(colorspace(jpeg(Some(1), 3)), None);
+
+        // No ink is white, full black ink is black whatever the colours.
+        assert_eq!(cmyk_to_rgb(&[0, 0, 0, 0]), [255, 255, 255]);
+        assert_eq!(cmyk_to_rgb(&[0, 255, 255, 0]), [255, 0, 0]);
+        assert_eq!(cmyk_to_rgb(&[0, 0, 0, 255]), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_heic_is_recognised() {
+        let dir = tempdir().unwrap();
+        let heic = dir.path().join("IMG_0001.HEIC");
+        let mut contents = vec![0, 0, 0, 24];
+        contents.extend_from_slice(b"ftypheic\0\0\0\0mif1heic");
+        std::fs::write(&heic, &contents).unwrap();
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, "not an image").unwrap();
+
+        assert!(open(&heic, &DecodeOptions::default()).is_err());
+        let undecodable = (!cfg!(feature = "heif")).then_some("HEIC");
+        assert_eq!(undecodable_format(&heic), undecodable);
+        assert_eq!(is_heif(&heic), cfg!(feature = "heif"));
+        assert_eq!(undecodable_format(&text), None);
+        assert!(!is_heif(&text));
+    }
+
+    #[test]
+    fn test_limiter_bounds_concurrent_permits() {
+        let limiter = DecodeLimiter::new(2);
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permit = limiter.acquire();
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+}
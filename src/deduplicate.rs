@@ -1,122 +1,1241 @@
+use crate::accepted::Accepted;
+use crate::actions::{self, Action, ActionOptions, Keep};
+use crate::animation;
+use crate::archive;
+use crate::bursts::{self, Burst};
+use crate::cache::{FileStamp, HashCache, Lookup};
+use crate::checkpoint::{Checkpoint, CHECKPOINT_FILE, CHECKPOINT_INTERVAL};
+use crate::content::{self, ContentKind};
+use crate::crops::{self, Crop, Windows};
+use crate::decode::{self, DecodeOptions};
+use crate::distance::{self, Metric};
 use crate::errors::AppError;
-use crate::models::{DeduplicationReport, DuplicatesGroup, ImageInfo};
-use image_hasher::Hasher;
+use crate::exact::{self, ExactMarker};
+use crate::filter::{self, FilterOptions};
+use crate::grouping::{self, Grouping};
+use crate::hashing::{self, Channels, HashAlgorithm, ImageHasher, PerceptualHasher, Preprocessing};
+use crate::html;
+use crate::identity;
+use crate::interactive::Prompter;
+use crate::invariance::{self, Expanded, Invariance};
+use crate::manifest::Manifest;
+use crate::matching::{self, LshOptions, Matcher};
+use crate::models::{
+    DeduplicationMetadata, DeduplicationReport, Dimensions, DuplicatesGroup, EntryError, FileState,
+    ImageInfo, MatchKind, PairScore, ReportSummary, Sample, VariantPair,
+};
+use crate::pipeline;
+use crate::plan::{Plan, PLAN_FILE};
+use crate::quality;
+use crate::report::{self, ReportFormat, ReportWriter};
+use crate::scan::{self, ScanOptions, ScanStats};
+use crate::segments::{self, Segments};
+use crate::semantic::{self, Embedder, Embeddings};
+use crate::shard::Part;
+use crate::sidecar;
+use crate::source;
+use crate::store::{HashStore, ImageSource};
+use crate::timestamp::Since;
+use crate::undo;
+use crate::verify::{self, Scores, VerifyOptions};
+use crate::watermark::{self, Masked};
+use image::ImageError;
 use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
 use serde_json;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self};
+use std::io::{self, BufReader};
 use std::path::{Path, PathBuf};
-use rayon::prelude::*;
-
-fn get_image_hashes(directory: &Path, hasher: &Hasher) -> Result<Vec<ImageInfo>, AppError> {
-    if !directory.is_dir() {
-        return Err(AppError::InvalidDirectory(directory.to_path_buf()));
-    }
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-    let entries: Vec<PathBuf> = fs::read_dir(directory)?
-        .filter_map(|x| Result::ok(x))
-        .map(|x| x.path())
-        .collect();
-
-    let bar = ProgressBar::new(entries.len() as u64);
+fn progress_bar(len: usize) -> ProgressBar {
+    let bar = ProgressBar::new(len as u64);
     bar.set_style(
         ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} files")
             .unwrap(),
     );
+    bar
+}
 
-    let image_hashes: Vec<ImageInfo> = entries
-        .par_iter()
-        .filter_map(|path| {
-            if let Ok(img) = image::open(path) {
-                let hash = hasher.hash_image(&img);
-                bar.inc(1);
-                Some(ImageInfo {
-                    path: path.clone(),
-                    hash: hash,
+/// Decode and hash `entries` on a pool of worker threads fed through a
+/// bounded queue, so only a few decoded images are held at any time.
+///
+/// The returned images keep the order of `entries`; files that are not
+/// images are skipped, and those that cannot be read are returned as errors.
+/// Images are decoded and hashed as set by `options`, with the variant and
+/// region hashes it asks for.
+fn get_image_hashes(
+    entries: &[PathBuf],
+    hasher: &dyn PerceptualHasher,
+    options: &DeduplicateOptions,
+    bar: &ProgressBar,
+) -> (Vec<ImageInfo>, Vec<EntryError>) {
+    let results = pipeline::process(entries, rayon::current_num_threads(), |path| {
+        let decoded = decode::open_sized(path, &options.decode);
+        bar.inc(1);
+        let (img, dimensions) = match decoded {
+            Ok(decoded) => decoded,
+            Err(ImageError::IoError(err)) => return Some(Err(EntryError::new(path, err))),
+            Err(_) => {
+                return decode::undecodable_format(path).map(|format| {
+                    let cause = format!("{} images cannot be decoded by this build", format);
+                    Err(EntryError::new(path, cause))
                 })
+            }
+        };
+        Some(Ok(ImageInfo {
+            dimensions: Some(dimensions),
+            variants: invariance::hash_variants(hasher, &img, &options.invariance),
+            segments: if options.crop_resistant {
+                segments::hash_segments(hasher, &img)
+            } else {
+                Vec::new()
+            },
+            windows: if options.find_crops {
+                crops::hash_windows(hasher, &img)
+            } else {
+                Vec::new()
+            },
+            frames: if options.first_frame {
+                Vec::new()
+            } else {
+                animation::hash_keyframes(hasher, path)
+            },
+            masked: if options.watermarks {
+                vec![watermark::hash_masked(hasher, &img)]
             } else {
-                None
+                Vec::new()
+            },
+            colorspace: decode::colorspace_of(path),
+            content: options.tag_content.then(|| content::classify(path, &img)),
+            ..ImageInfo::new(path.clone(), hasher.hash_image(&img))
+        }))
+    });
+    let mut images = Vec::with_capacity(results.len());
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(image) => images.push(image),
+            Err(error) => errors.push(error),
+        }
+    }
+    (images, errors)
+}
+
+/// Hash `entries`, reusing hashes of unchanged files from `cache`.
+///
+/// The returned images keep the order of `entries`. Also returns the number
+/// of hashes found in the cache and the files that could not be read, which
+/// are left out of the cache so they are retried next time.
+fn get_image_hashes_cached(
+    entries: &[PathBuf],
+    hasher: &dyn PerceptualHasher,
+    options: &DeduplicateOptions,
+    bar: &ProgressBar,
+    cache: &mut HashCache,
+    settings: &str,
+) -> (Vec<ImageInfo>, usize, Vec<EntryError>) {
+    let mut cached: HashMap<&Path, ImageInfo> = HashMap::new();
+    let mut misses: Vec<(PathBuf, FileStamp)> = Vec::new();
+    for path in entries {
+        let Some(stamp) = FileStamp::of(path) else {
+            continue;
+        };
+        match cache.lookup(settings, path, &stamp) {
+            Lookup::Hit(image) => {
+                cached.insert(path.as_path(), *image);
             }
+            Lookup::NotAnImage => {}
+            Lookup::Miss => misses.push((path.clone(), stamp)),
+        }
+    }
+    let hits = cached.len();
+    bar.inc(hits as u64);
+
+    let miss_paths: Vec<PathBuf> = misses.iter().map(|(path, _)| path.clone()).collect();
+    let (hashed, errors) = get_image_hashes(&miss_paths, hasher, options, bar);
+    let hashed: HashMap<PathBuf, ImageInfo> = hashed
+        .into_iter()
+        .map(|image| (image.path.clone(), image))
+        .collect();
+    let unreadable: HashSet<&PathBuf> = errors.iter().map(|error| &error.path).collect();
+    for (path, stamp) in misses.iter().filter(|(path, _)| !unreadable.contains(path)) {
+        cache.insert(settings, path, *stamp, hashed.get(path));
+    }
+
+    let images = entries
+        .iter()
+        .filter_map(|path| match cached.remove(path.as_path()) {
+            Some(image) => Some(image),
+            None => hashed.get(path).cloned(),
         })
         .collect();
+    (images, hits, errors)
+}
+
+/// Keep the files of `entries` modified at or after `since`, and the older
+/// ones already recorded in `cache`.
+fn changed_or_cached(
+    entries: Vec<PathBuf>,
+    since: SystemTime,
+    cache: Option<&HashCache>,
+    settings: &str,
+) -> Vec<PathBuf> {
+    let total = entries.len();
+    let kept: Vec<PathBuf> = entries
+        .into_iter()
+        .filter(|path| {
+            let changed = fs::metadata(archive::backing_file(path))
+                .and_then(|metadata| metadata.modified())
+                .map_or(true, |modified| modified >= since);
+            changed
+                || cache.is_some_and(|cache| {
+                    FileStamp::of(path).is_some_and(|stamp| {
+                        !matches!(cache.lookup(settings, path, &stamp), Lookup::Miss)
+                    })
+                })
+        })
+        .collect();
+    if kept.len() < total {
+        println!(
+            "Skipped {} unchanged files without cached hashes.",
+            total - kept.len()
+        );
+    }
+    kept
+}
+
+/// Match images and group them, returning groups as indices into `images`
+/// along with the verification scores of the matched pairs and the pairs
+/// only matched once their corners were masked.
+///
+/// Indices within a group are sorted, and groups are sorted by their first
+/// index, so that sorted `images` give a report that is stable between runs.
+fn match_groups<S: ImageSource + ?Sized>(
+    images: &S,
+    options: &DeduplicateOptions,
+) -> (Vec<Vec<usize>>, Scores, HashSet<(usize, usize)>) {
+    let mut pairs = if let Some(embeddings) = &options.embeddings {
+        semantic::similar_pairs(images, embeddings, options.min_similarity)
+    } else if options.invariance.is_empty() {
+        find_pairs(images, options)
+    } else {
+        let expanded = Expanded::new(images);
+        expanded.owner_pairs(find_pairs(&expanded, options))
+    };
+    if options.crop_resistant {
+        let segments = Segments::new(images);
+        pairs.extend(
+            segments.owner_pairs(find_pairs(&segments, options), options.min_segment_matches),
+        );
+        pairs.sort_unstable();
+        pairs.dedup();
+    }
+    let mut watermarked = HashSet::new();
+    if options.watermarks {
+        let found: HashSet<(usize, usize)> = pairs.iter().copied().collect();
+        let masked = Masked::new(images);
+        watermarked.extend(masked.owner_pairs(find_pairs(&masked, options), &found));
+        pairs.extend(watermarked.iter().copied());
+        pairs.sort_unstable();
+    }
+    if !options.first_frame && options.embeddings.is_none() {
+        pairs = animation::match_sequences(images, pairs, options.threshold(), |a, b| {
+            options.distance(a, b)
+        });
+    }
+    let pairs = if options.cross_root {
+        pairs
+            .into_iter()
+            .filter(|&(i, j)| images.root(i) != images.root(j))
+            .collect()
+    } else {
+        pairs
+    };
+    let (pairs, scores) = verify::verify_pairs(
+        images,
+        pairs,
+        &options.verify,
+        options.algorithm,
+        options.hash_size,
+        &options.decode,
+        &options.invariance,
+    );
 
-    Ok(image_hashes)
+    if !watermarked.is_empty() {
+        let verified: HashSet<&(usize, usize)> = pairs.iter().collect();
+        watermarked.retain(|pair| verified.contains(pair));
+    }
+
+    let mut groups = match options.grouping {
+        Grouping::Greedy => grouping::greedy(images, &pairs),
+        Grouping::Connected => grouping::connected(images, &pairs),
+        Grouping::Complete => grouping::complete(images, &pairs),
+    };
+    for group in &mut groups {
+        group.sort_unstable();
+    }
+    groups.sort_unstable_by_key(|group| group[0]);
+    (groups, scores, watermarked)
 }
 
-fn find_duplicates(images: Vec<ImageInfo>, duplicate_threshold: u32) -> Vec<DuplicatesGroup> {
-    let mut groups: Vec<DuplicatesGroup> = Vec::new();
-    let mut processed: HashSet<PathBuf> = HashSet::new();
+/// Pairs of images closer than the threshold, found by the configured
+/// matcher.
+fn find_pairs<S: ImageSource + ?Sized>(
+    images: &S,
+    options: &DeduplicateOptions,
+) -> Vec<(usize, usize)> {
+    if !options.is_hamming() {
+        return matching::candidate_pairs_by(images, options.threshold(), |a, b| {
+            options.distance(a, b)
+        });
+    }
+    match &options.approx {
+        Some(lsh) => matching::approximate_pairs(images, options.threshold(), lsh),
+        None => match options.matcher {
+            Matcher::Exhaustive => matching::candidate_pairs(images, options.threshold()),
+            Matcher::Mih => matching::multi_index_pairs(images, options.threshold()),
+        },
+    }
+}
 
-    for (i, image) in images.iter().enumerate() {
-        if processed.contains(&image.path) {
-            continue;
-        }
+/// The images of `images` that appear to be crops of others, leaving out
+/// images already grouped together in `groups`.
+fn find_crops<S: ImageSource + ?Sized>(
+    images: &S,
+    groups: &[Vec<usize>],
+    options: &DeduplicateOptions,
+) -> Vec<Crop> {
+    if !options.find_crops {
+        return Vec::new();
+    }
+    let windows = Windows::new(images);
+    let crops = windows.crops(find_pairs(&windows, options), groups);
+    if !crops.is_empty() {
+        println!("Found {} crops of other images.", crops.len());
+    }
+    crops
+}
 
-        let mut current_group: Vec<ImageInfo> = vec![image.clone()];
+/// The bursts among `images`, if `options.bursts` asks for them.
+fn find_bursts<S: ImageSource + ?Sized>(images: &S, options: &DeduplicateOptions) -> Vec<Burst> {
+    let Some(gap) = options.bursts else {
+        return Vec::new();
+    };
+    let bursts = bursts::find_bursts(images, gap);
+    if !bursts.is_empty() {
+        println!("Found {} bursts of photos.", bursts.len());
+    }
+    bursts
+}
 
-        for other_image in images.iter().skip(i + 1) {
-            if processed.contains(&other_image.path) {
-                continue;
+/// The duplicate groups described by the `groups` of indices into `images`.
+fn find_duplicates<S: ImageSource + ?Sized>(
+    images: &S,
+    groups: &[Vec<usize>],
+    scores: &Scores,
+    watermarked: &HashSet<(usize, usize)>,
+    options: &DeduplicateOptions,
+) -> Vec<DuplicatesGroup> {
+    let manifest = options.manifest.as_deref();
+    groups
+        .iter()
+        .map(|indices| to_group(images, indices, scores, watermarked, manifest))
+        .collect()
+}
+
+fn to_group<S: ImageSource + ?Sized>(
+    images: &S,
+    indices: &[usize],
+    scores: &Scores,
+    watermarked: &HashSet<(usize, usize)>,
+    manifest: Option<&Manifest>,
+) -> DuplicatesGroup {
+    let mut items: Vec<ImageInfo> = indices
+        .iter()
+        .map(|&i| {
+            let mut image = images.image(i);
+            image.clear_match_hashes();
+            image
+        })
+        .collect();
+    if let Some(manifest) = manifest {
+        items.iter_mut().for_each(|image| manifest.annotate(image));
+    }
+    let mut group = DuplicatesGroup::new(items);
+    group.files = group
+        .items
+        .iter()
+        .filter_map(|item| {
+            FileStamp::of(&item.path).map(|stamp| FileState {
+                path: item.path.clone(),
+                stamp,
+            })
+        })
+        .collect();
+    for (a, &i) in indices.iter().enumerate() {
+        for &j in &indices[a + 1..] {
+            if let Some(&ssim) = scores.get(&(i.min(j), i.max(j))) {
+                group.scores.push(PairScore {
+                    first: images.path(i.min(j)).to_path_buf(),
+                    second: images.path(i.max(j)).to_path_buf(),
+                    ssim,
+                });
             }
+            if watermarked.contains(&(i.min(j), i.max(j))) {
+                group.watermarked.push(VariantPair {
+                    first: images.path(i.min(j)).to_path_buf(),
+                    second: images.path(i.max(j)).to_path_buf(),
+                });
+            }
+        }
+    }
+    if !group.watermarked.is_empty() {
+        group.kind = MatchKind::Watermarked;
+    }
+    group
+}
+
+fn save_results(
+    report: &DeduplicationReport,
+    path: &Path,
+    format: ReportFormat,
+) -> Result<(), AppError> {
+    match format {
+        ReportFormat::Json => fs::write(path, serde_json::to_string_pretty(report)?)?,
+        ReportFormat::Csv => report::save_csv(&report.groups, path)?,
+        ReportFormat::Html => html::save(report, path)?,
+    }
+    println!("Deduplication report saved to {:?}", path);
+    Ok(())
+}
+
+/// Settings for a deduplication run.
+#[derive(Debug, Clone)]
+pub struct DeduplicateOptions {
+    pub duplicate_threshold: u32,
+    /// Threshold as a fraction of the hash bits, used instead of
+    /// `duplicate_threshold` when set.
+    pub threshold_pct: Option<f64>,
+    pub hash_size: u32,
+    pub algorithm: HashAlgorithm,
+    /// Color planes hashed on their own, their distances adding up.
+    pub channels: Channels,
+    pub preprocessing: Preprocessing,
+    /// Hash images with this algorithm instead of the one configured by
+    /// `algorithm`, `hash_size`, `channels` and `preprocessing`. Its pairs
+    /// are found by comparing every image with every other through its
+    /// distance.
+    pub hasher: Option<Arc<dyn PerceptualHasher>>,
+    /// Compare hashes by this metric instead of the distance of the hasher,
+    /// checking every pair of images.
+    pub metric: Option<Arc<dyn Metric>>,
+    pub grouping: Grouping,
+    pub scan: ScanOptions,
+    pub filter: FilterOptions,
+    /// Group byte-identical files before perceptual hashing, so that copies
+    /// are never decoded.
+    pub exact_pass: bool,
+    pub verify: VerifyOptions,
+    pub matcher: Matcher,
+    /// Use locality-sensitive hashing instead of `matcher`.
+    pub approx: Option<LshOptions>,
+    /// Location of the persistent hash cache, or `None` to disable caching.
+    pub cache_path: Option<PathBuf>,
+    /// Hash files in batches of this size, saving the cache after each
+    /// batch, and stream groups to the report instead of keeping them in
+    /// memory.
+    pub chunk_size: Option<usize>,
+    pub decode: DecodeOptions,
+    /// Only hash files modified after this time. Older files take part in
+    /// matching only if their hash is already cached.
+    pub since: Option<Since>,
+    /// Periodically save the hashes computed so far to a checkpoint file in
+    /// the scanned directory.
+    pub checkpoint: bool,
+    /// Continue from the checkpoint left by an interrupted run.
+    pub resume: bool,
+    /// Move the hashes and paths of the images to a memory-mapped file at
+    /// this path before matching, removing it once the report is written.
+    pub hash_store: Option<PathBuf>,
+    /// Only hash this part of the scanned files.
+    pub part: Option<Part>,
+    /// Only match images found under different roots, ignoring duplicates
+    /// within a single input directory.
+    pub cross_root: bool,
+    /// Only hash a sample of this many files, to estimate how many
+    /// duplicates a large collection holds.
+    pub limit: Option<usize>,
+    /// Leave images smaller than this out of matching, listing them in a
+    /// separate section of the report instead.
+    pub min_resolution: Option<Dimensions>,
+    /// Scan the images listed by this manifest instead of walking the
+    /// directories, copying its columns to the report.
+    pub manifest: Option<Arc<Manifest>>,
+    /// Also hash the rotated and mirrored versions of every image, and
+    /// match images on the smallest distance between any of them.
+    pub invariance: Vec<Invariance>,
+    /// Also hash the regions of every image, and match images sharing at
+    /// least `min_segment_matches` of them, so that cropped copies are found.
+    pub crop_resistant: bool,
+    pub min_segment_matches: usize,
+    /// Also hash windows of every image, and report images that match a
+    /// window of another one as crops of it.
+    pub find_crops: bool,
+    /// Hash animated images by their first frame only, instead of matching
+    /// animations by the distance between their keyframes.
+    pub first_frame: bool,
+    /// Also hash every image with its corners masked, and match images
+    /// that only differ by a logo, watermark or date in a corner.
+    pub watermarks: bool,
+    /// Tag every image as a photo, screenshot or document.
+    pub tag_content: bool,
+    /// Only match images tagged with one of these kinds, if any are given.
+    pub only_content: Vec<ContentKind>,
+    /// Compare the items of every group byte for byte and pixel for pixel,
+    /// listing those that are identical.
+    pub classify: bool,
+    /// Measure the sharpness, exposure and compression of the items of
+    /// every group, scoring them against each other.
+    pub quality: bool,
+    /// Report photos taken by the same camera at most this long after one
+    /// another as bursts, apart from the duplicate groups.
+    pub bursts: Option<Duration>,
+    /// Keep one file of every group and act on the others, recording the
+    /// actions in the report.
+    pub action: Option<ActionOptions>,
+    /// Which file of every group is kept when estimating the space its
+    /// duplicates take, when there is no `action` to say.
+    pub keep: Keep,
+    /// Match images by the cosine similarity of these embeddings instead of
+    /// by their perceptual hashes.
+    pub embeddings: Option<Arc<Embeddings>>,
+    /// Compute the `embeddings` of the scanned images with this model.
+    pub embedder: Option<Embedder>,
+    pub min_similarity: f32,
+    pub format: ReportFormat,
+}
+
+impl Default for DeduplicateOptions {
+    fn default() -> Self {
+        DeduplicateOptions {
+            duplicate_threshold: 10,
+            threshold_pct: None,
+            hash_size: 16,
+            algorithm: HashAlgorithm::default(),
+            channels: Channels::default(),
+            preprocessing: Preprocessing::default(),
+            hasher: None,
+            metric: None,
+            grouping: Grouping::default(),
+            scan: ScanOptions::default(),
+            filter: FilterOptions::default(),
+            exact_pass: true,
+            verify: VerifyOptions::default(),
+            matcher: Matcher::default(),
+            approx: None,
+            cache_path: None,
+            chunk_size: None,
+            decode: DecodeOptions::default(),
+            since: None,
+            checkpoint: false,
+            resume: false,
+            hash_store: None,
+            part: None,
+            cross_root: false,
+            limit: None,
+            min_resolution: None,
+            manifest: None,
+            invariance: Vec::new(),
+            crop_resistant: false,
+            min_segment_matches: segments::DEFAULT_MIN_MATCHES,
+            find_crops: false,
+            first_frame: false,
+            watermarks: false,
+            tag_content: false,
+            only_content: Vec::new(),
+            classify: false,
+            quality: false,
+            bursts: None,
+            action: None,
+            keep: Keep::FirstAlphabetical,
+            embeddings: None,
+            embedder: None,
+            min_similarity: semantic::DEFAULT_MIN_SIMILARITY,
+            format: ReportFormat::default(),
+        }
+    }
+}
+
+impl DeduplicateOptions {
+    /// Hamming distance threshold, resolving `threshold_pct` against the
+    /// size of the configured hashes.
+    pub fn threshold(&self) -> u32 {
+        match self.threshold_pct {
+            Some(fraction) => hashing::threshold_from_pct(fraction, self.hash_bits()),
+            None => self.duplicate_threshold,
+        }
+    }
+
+    /// Number of bits in the configured hashes.
+    fn hash_bits(&self) -> u32 {
+        match &self.hasher {
+            Some(hasher) => hasher.hash_bits(),
+            None => hashing::hash_bits(self.algorithm, self.hash_size, self.channels),
+        }
+    }
+
+    /// Distance between two hashes: by `metric` if set, or else by the
+    /// distance of the hasher.
+    pub fn distance(&self, a: &[u8], b: &[u8]) -> u32 {
+        match (&self.metric, &self.hasher) {
+            (Some(metric), _) => metric.distance(a, b),
+            (None, Some(hasher)) => hasher.distance(a, b),
+            (None, None) => distance::hamming(a, b),
+        }
+    }
+
+    /// Whether hashes are compared by the built-in Hamming distance, which
+    /// the indexed matchers rely on.
+    fn is_hamming(&self) -> bool {
+        self.metric.is_none() && self.hasher.is_none()
+    }
+
+    /// The hasher images are hashed with: `hasher` if set, or else the
+    /// built-in algorithm.
+    pub fn build_hasher(&self) -> Arc<dyn PerceptualHasher> {
+        match &self.hasher {
+            Some(hasher) => hasher.clone(),
+            None => Arc::new(ImageHasher::with_preprocessing(
+                self.algorithm,
+                self.hash_size,
+                self.channels,
+                self.preprocessing,
+            )),
+        }
+    }
+
+    /// Mark which items of `group` the action would keep and which it would
+    /// remove, and record the bytes removing them frees.
+    fn suggest(&self, group: &mut DuplicatesGroup) {
+        let kept = match &self.action {
+            Some(action) => action.kept(group),
+            None => vec![actions::keeper(group, self.keep, &[])],
+        };
+        actions::suggest(group, &kept);
+        group.reclaimable_bytes = actions::reclaimable(group, &kept);
+    }
+
+    /// Report metadata describing a run over `directories` with these options.
+    pub(crate) fn metadata(&self, directories: &[PathBuf]) -> DeduplicationMetadata {
+        let hash_bits = self.hash_bits();
+        DeduplicationMetadata {
+            directory_path: directories[0].clone(),
+            roots: directories.to_vec(),
+            cross_root: self.cross_root,
+            threshold: self.threshold(),
+            threshold_pct: self.threshold() as f64 / hash_bits.max(1) as f64,
+            hash_bits,
+            algorithm: self.algorithm,
+            channels: self.channels,
+            preprocessing: self.preprocessing,
+            verification: self.verify.method,
+            sample: None,
+            min_resolution: self.min_resolution,
+            scanned: None,
+        }
+    }
+}
+
+/// Images found by [`hash_directories`].
+pub struct HashedImages {
+    /// Hashed images, sorted by path.
+    pub images: Vec<ImageInfo>,
+    /// Sets of byte-identical files found by the exact pass.
+    pub identical: Vec<Vec<PathBuf>>,
+    /// Set when only a sample of the files was hashed.
+    pub sample: Option<Sample>,
+    /// Files and directories that could not be read, in the order found.
+    pub errors: Vec<EntryError>,
+}
+
+/// A pseudo-random sample of `limit` files of `paths`, in input order.
+///
+/// Files are picked by a checksum of their path rather than by position, so
+/// the sample spreads over every directory and is the same on every run.
+fn sample(paths: Vec<PathBuf>, limit: usize) -> Vec<PathBuf> {
+    let mut keys: Vec<(u32, usize)> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| (crc32fast::hash(path.as_os_str().as_encoded_bytes()), i))
+        .collect();
+    keys.sort_unstable();
+    let picked: HashSet<usize> = keys.into_iter().take(limit).map(|(_, i)| i).collect();
+    paths
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| picked.contains(i))
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// The directory of `roots` containing `path`, preferring the innermost
+/// one when roots are nested.
+///
+/// Roots are compared in the extended-length form the walk gives paths on
+/// Windows.
+fn root_of<'a>(path: &Path, roots: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(root) || path.starts_with(scan::extended_length(root)))
+        .max_by_key(|root| root.components().count())
+}
 
-            if image.hash.dist(&other_image.hash) < duplicate_threshold {
-                current_group.push(other_image.clone());
-                processed.insert(other_image.path.clone());
+/// Key of the hashes made by `hasher` with `options` in the hash cache,
+/// which differs whenever a setting changes the hashes.
+fn cache_settings(hasher: &dyn PerceptualHasher, options: &DeduplicateOptions) -> String {
+    let mut settings = hasher.name();
+    // Hashes cached before orientation was applied stay valid without it.
+    if options.decode.orientation {
+        settings.push_str("-upright");
+    }
+    if options.decode.color_profiles {
+        settings.push_str("-srgb");
+    }
+    // Hashes cached under the default decode limit keep their key.
+    match options.decode.max_pixels {
+        Some(decode::DEFAULT_MAX_PIXELS) => {}
+        Some(max_pixels) => settings.push_str(&format!("-max{}", max_pixels)),
+        None => settings.push_str("-full"),
+    }
+    settings.push_str(&invariance::settings_suffix(&options.invariance));
+    if options.crop_resistant {
+        settings.push_str("-segments");
+    }
+    if options.find_crops {
+        settings.push_str("-windows");
+    }
+    if !options.first_frame {
+        settings.push_str("-frames");
+    }
+    if options.watermarks {
+        settings.push_str("-masked");
+    }
+    if options.tag_content {
+        settings.push_str("-content");
+    }
+    settings
+}
+
+/// Scan `dirs` and hash every image found, according to `options`.
+///
+/// Images from every directory are pooled together, and each records the
+/// directory it was found under as its root. Checkpoints are kept in the
+/// first directory.
+pub fn hash_directories(
+    dirs: &[PathBuf],
+    options: &DeduplicateOptions,
+) -> Result<HashedImages, AppError> {
+    let started = SystemTime::now();
+    let hasher = options.build_hasher();
+    let mut entries: Vec<PathBuf> = Vec::new();
+    let mut scanned = ScanStats::default();
+    match (&options.manifest, &options.scan.files_from) {
+        (Some(manifest), _) => (entries, scanned) = scan::listed_files(manifest.paths().to_vec()),
+        (None, Some(list)) => (entries, scanned) = scan::read_file_list(list)?,
+        (None, None) => {
+            for dir in dirs {
+                let (files, stats) = source::for_location(dir)?.list(&options.scan)?;
+                entries.extend(files);
+                scanned += stats;
             }
         }
+    }
+    print!("{}", scanned);
+    // Nested roots list the same files twice.
+    entries.sort();
+    entries.dedup();
+    if let Some(part) = &options.part {
+        entries = part.select(entries);
+    }
+    let (entries, filtered) = filter::apply(entries, &options.filter);
+    print!("{}", filtered);
+    let (entries, mut hardlinks) = identity::collapse_hardlinks(entries);
+    if !hardlinks.is_empty() {
+        let count: usize = hardlinks.values().map(Vec::len).sum();
+        println!("Collapsed {} hard links to files already scanned.", count);
+    }
+    let (entries, sampled) = match options.limit {
+        Some(limit) if entries.len() > limit => {
+            let total = entries.len();
+            println!("Hashing a sample of {} of {} files.", limit, total);
+            let sampled = Sample {
+                sampled: limit,
+                total,
+            };
+            (sample(entries, limit), Some(sampled))
+        }
+        _ => (entries, None),
+    };
+
+    let mut cache = match &options.cache_path {
+        Some(cache_path) => Some(HashCache::load(cache_path)?),
+        None => None,
+    };
+    let settings = cache_settings(&*hasher, options);
 
-        if current_group.len() > 1 {
-            groups.push(DuplicatesGroup {
-                items: current_group,
+    let since = match options.since {
+        Some(Since::Time(time)) => Some(time),
+        Some(Since::LastRun) => {
+            // Files are only skipped if every directory was scanned before.
+            let last = cache.as_ref().and_then(|cache| {
+                dirs.iter()
+                    .map(|dir| cache.last_run(dir))
+                    .collect::<Option<Vec<SystemTime>>>()
+                    .and_then(|runs| runs.into_iter().min())
             });
-            processed.insert(image.path.clone());
+            if last.is_none() {
+                println!("No previous run recorded, hashing every file.");
+            }
+            last
         }
+        None => None,
+    };
+    let entries = match since {
+        Some(since) => changed_or_cached(entries, since, cache.as_ref(), &settings),
+        None => entries,
+    };
+
+    let identical = if options.exact_pass {
+        exact::find_identical(&entries)
+    } else {
+        Vec::new()
+    };
+    let copies: HashSet<&PathBuf> = identical.iter().flat_map(|set| &set[1..]).collect();
+    if !copies.is_empty() {
+        println!("Found {} byte-identical copies.", copies.len());
     }
+    let originals: Vec<PathBuf> = entries
+        .iter()
+        .filter(|path| !copies.contains(path))
+        .cloned()
+        .collect();
 
-    groups
+    let checkpoint_path = source::local_dir(&dirs[0]).join(match &options.part {
+        Some(part) => format!(".idar_checkpoint.{}-of-{}.json", part.index, part.count),
+        None => CHECKPOINT_FILE.to_string(),
+    });
+    let mut checkpoint = if options.checkpoint {
+        Some(Checkpoint::open(
+            &checkpoint_path,
+            &settings,
+            options.resume,
+        )?)
+    } else {
+        None
+    };
+    let (mut images, originals) = match &checkpoint {
+        Some(checkpoint) if options.resume => checkpoint.resume(&originals),
+        _ => (Vec::new(), originals),
+    };
+    if !images.is_empty() {
+        println!("Resumed {} hashes from checkpoint.", images.len());
+    }
+
+    let chunk_size = options
+        .chunk_size
+        .or(checkpoint.as_ref().map(|_| CHECKPOINT_INTERVAL))
+        .unwrap_or(originals.len())
+        .max(1);
+
+    let bar = progress_bar(originals.len());
+    let mut cache_hits = 0;
+    let mut errors = std::mem::take(&mut scanned.errors);
+    for chunk in originals.chunks(chunk_size) {
+        let hashed = match cache.as_mut() {
+            Some(cache) => {
+                let (hashed, hits, unreadable) =
+                    get_image_hashes_cached(chunk, &*hasher, options, &bar, cache, &settings);
+                cache_hits += hits;
+                cache.flush()?;
+                errors.extend(unreadable);
+                hashed
+            }
+            None => {
+                let (hashed, unreadable) = get_image_hashes(chunk, &*hasher, options, &bar);
+                errors.extend(unreadable);
+                hashed
+            }
+        };
+        if let Some(checkpoint) = checkpoint.as_mut() {
+            checkpoint.record(chunk, &hashed)?;
+        }
+        images.extend(hashed);
+    }
+    if cache_hits > 0 {
+        println!("Loaded {} hashes from cache.", cache_hits);
+    }
+    let converted = images
+        .iter()
+        .filter(|image| image.colorspace.is_some())
+        .count();
+    if converted > 0 {
+        println!("Converted {} CMYK or YCCK images to RGB.", converted);
+    }
+    exact::propagate_hashes(&mut images, &identical);
+    images.sort_by(|a, b| a.path.cmp(&b.path));
+    for image in &mut images {
+        image.root = root_of(&image.path, dirs).cloned();
+        image.links = hardlinks.remove(&image.path).unwrap_or_default();
+    }
+    sidecar::attach(&mut images);
+
+    if let Some(cache) = cache.as_mut() {
+        // Copies are never hashed, but `--since` needs to know them.
+        for image in images.iter().filter(|image| copies.contains(&image.path)) {
+            if let Some(stamp) = FileStamp::of(&image.path) {
+                cache.insert(&settings, &image.path, stamp, Some(image));
+            }
+        }
+        // A sampled run leaves most files unseen, so `--since last` must not
+        // skip them next time.
+        if sampled.is_none() {
+            for dir in dirs {
+                cache.record_run(dir, started);
+            }
+        }
+        cache.save()?;
+    }
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.finish(&checkpoint_path)?;
+    }
+
+    Ok(HashedImages {
+        images,
+        identical,
+        sample: sampled,
+        errors,
+    })
 }
 
-fn save_results(report: &DeduplicationReport, path: &Path) -> Result<(), AppError> {
-    let contents = serde_json::to_string_pretty(report)?;
-    fs::write(path, contents)?;
-    println!("Deduplication report saved to {:?}", path);
-    Ok(())
+/// Where the action plan is saved, next to the report at `output_path`.
+fn plan_path(output_path: &Path) -> PathBuf {
+    output_path.with_file_name(PLAN_FILE)
+}
+
+fn print_accepted(count: usize) {
+    if count > 0 {
+        println!(
+            "Left out {} groups accepted with `idar ignore-group`.",
+            count
+        );
+    }
+}
+
+/// Mark `group` as exact if its files are byte-identical, then classify,
+/// score and rank it as `options` ask.
+fn complete_group(group: &mut DuplicatesGroup, marker: &ExactMarker, options: &DeduplicateOptions) {
+    marker.mark(group);
+    if options.classify {
+        exact::classify(group, &options.decode);
+    }
+    if options.quality {
+        quality::score(group, &options.decode);
+    }
+    options.suggest(group);
+}
+
+/// The action of a run planned group by group as the report is written, and
+/// carried out once every group is known.
+struct Planner<'a> {
+    action: Option<(&'a ActionOptions, ActionOptions)>,
+    plan: Plan,
+    prompter: Prompter<BufReader<io::Stdin>, io::Stdout>,
+    /// Why the plan was not carried out, reported once the report is saved.
+    refused: Option<AppError>,
+}
+
+impl<'a> Planner<'a> {
+    fn new(action: Option<&'a ActionOptions>) -> Self {
+        Planner {
+            action: action.map(|action| (action, action.planning())),
+            plan: Plan::default(),
+            prompter: Prompter::terminal(),
+            refused: None,
+        }
+    }
+
+    /// Rank the items of `group` and add what the action would do to them
+    /// to the plan.
+    fn add(&mut self, group: &mut DuplicatesGroup) {
+        if let Some((action, planning)) = &self.action {
+            actions::record_ranking(group, action.keep);
+            self.plan.add(
+                group,
+                actions::apply_with(group, planning, &mut self.prompter),
+            );
+        }
+    }
+
+    /// Carry out the plan, saving it next to the report at `output_path`,
+    /// and log what was done for `idar undo`.
+    fn finish(&mut self, output_path: &Path, scanned: usize) -> Result<Vec<Action>, AppError> {
+        let actions = match self.action {
+            Some((action, _)) => match self.plan.carry_out(
+                action,
+                &mut self.prompter,
+                &plan_path(output_path),
+                Some(scanned),
+            ) {
+                Err(err @ AppError::ConfirmationRequired { .. }) => {
+                    self.refused = Some(err);
+                    Vec::new()
+                }
+                result => result?,
+            },
+            None => Vec::new(),
+        };
+        actions::print_summary(&actions);
+        undo::record(&actions, output_path.parent().unwrap_or(Path::new(".")))?;
+        Ok(actions)
+    }
+
+    /// Fail if the plan could not be confirmed, so that the run does not
+    /// pass for one that took its actions.
+    fn refused(self) -> Result<(), AppError> {
+        self.refused.map_or(Ok(()), Err)
+    }
+}
+
+/// Group `images` and save the report to `output_path`, leaving out the
+/// groups accepted in its directory.
+fn write_report<S: ImageSource + ?Sized>(
+    images: &S,
+    identical: &[Vec<PathBuf>],
+    mut below_resolution: Vec<ImageInfo>,
+    errors: Vec<EntryError>,
+    metadata: DeduplicationMetadata,
+    output_path: &Path,
+    options: &DeduplicateOptions,
+) -> Result<ReportSummary, AppError> {
+    if let Some(manifest) = &options.manifest {
+        below_resolution
+            .iter_mut()
+            .for_each(|image| manifest.annotate(image));
+    }
+    for image in &mut below_resolution {
+        image.clear_match_hashes();
+    }
+    let scanned = images.len() + below_resolution.len();
+    let metadata = DeduplicationMetadata {
+        scanned: Some(scanned),
+        ..metadata
+    };
+    let accepted = Accepted::load(output_path.parent().unwrap_or(Path::new(".")))?;
+    let marker = ExactMarker::new(identical);
+    let mut planner = Planner::new(options.action.as_ref());
+    if options.chunk_size.is_some() {
+        let (groups, scores, watermarked) = match_groups(images, options);
+        println!("Found {} duplicate groups.", groups.len());
+        let crops = find_crops(images, &groups, options);
+        let bursts = find_bursts(images, options);
+
+        println!("Saving deduplication report...");
+        let mut writer = ReportWriter::create(output_path, metadata, options.format)?;
+        let mut left_out = 0;
+        for indices in &groups {
+            let mut group = to_group(
+                images,
+                indices,
+                &scores,
+                &watermarked,
+                options.manifest.as_deref(),
+            );
+            if accepted.contains(&group) {
+                left_out += 1;
+                continue;
+            }
+            complete_group(&mut group, &marker, options);
+            planner.add(&mut group);
+            writer.write_group(&group)?;
+        }
+        print_accepted(left_out);
+        let actions = planner.finish(output_path, scanned)?;
+        let summary = writer.finish(&below_resolution, &errors, &crops, &bursts, &actions)?;
+        println!("Deduplication report saved to {:?}", output_path);
+        planner.refused()?;
+        Ok(summary)
+    } else {
+        let (groups, scores, watermarked) = match_groups(images, options);
+        let mut duplicates = find_duplicates(images, &groups, &scores, &watermarked, options);
+        duplicates.retain(|group| !accepted.contains(group));
+        print_accepted(groups.len() - duplicates.len());
+        duplicates
+            .par_iter_mut()
+            .for_each(|group| complete_group(group, &marker, options));
+        println!("Found {} duplicate groups.", duplicates.len());
+        let crops = find_crops(images, &groups, options);
+        let bursts = find_bursts(images, options);
+        for group in &mut duplicates {
+            planner.add(group);
+        }
+        let actions = planner.finish(output_path, scanned)?;
+
+        let mut report = DeduplicationReport::new(metadata, duplicates);
+        report.below_resolution = below_resolution;
+        report.errors = errors;
+        report.crops = crops;
+        report.bursts = bursts;
+        report.actions = actions;
+        println!("Saving deduplication report...");
+        save_results(&report, output_path, options.format)?;
+        planner.refused()?;
+        Ok(report.summary())
+    }
+}
+
+/// Like [`write_report`], first setting aside the images below
+/// `options.min_resolution` and moving the hashes of the others to a
+/// [`HashStore`] if `options.hash_store` is set.
+pub(crate) fn report_images(
+    images: Vec<ImageInfo>,
+    identical: &[Vec<PathBuf>],
+    errors: Vec<EntryError>,
+    metadata: DeduplicationMetadata,
+    output_path: &Path,
+    options: &DeduplicateOptions,
+) -> Result<ReportSummary, AppError> {
+    let images = if options.only_content.is_empty() {
+        images
+    } else {
+        let count = images.len();
+        let kept: Vec<ImageInfo> = images
+            .into_iter()
+            .filter(|image| {
+                image
+                    .content
+                    .is_some_and(|kind| options.only_content.contains(&kind))
+            })
+            .collect();
+        println!(
+            "Left {} images of other content out of matching.",
+            count - kept.len()
+        );
+        kept
+    };
+    let (images, below) = match options.min_resolution {
+        Some(minimum) => images.into_iter().partition(|image| {
+            !image
+                .dimensions
+                .is_some_and(|dimensions| dimensions.is_below(minimum))
+        }),
+        None => (images, Vec::new()),
+    };
+    if let (Some(minimum), false) = (options.min_resolution, below.is_empty()) {
+        println!(
+            "Left {} images below {} out of matching.",
+            below.len(),
+            minimum
+        );
+    }
+
+    match &options.hash_store {
+        Some(store_path) => {
+            let store = HashStore::create(store_path, images)?;
+            let summary = write_report(
+                &store,
+                identical,
+                below,
+                errors,
+                metadata,
+                output_path,
+                options,
+            );
+            store.remove()?;
+            summary
+        }
+        None => write_report(
+            &images[..],
+            identical,
+            below,
+            errors,
+            metadata,
+            output_path,
+            options,
+        ),
+    }
 }
 
+/// Deduplicate the images of `directories` together, saving the report in
+/// the first one, and return its totals.
 pub fn run(
-    directory: String,
-    duplicate_threshold: u32,
-    hash_size: u32, 
+    directories: &[PathBuf],
     report_filename: &str,
-) -> Result<(), AppError> {
-    let dir = Path::new(&directory);
+    options: &DeduplicateOptions,
+) -> Result<ReportSummary, AppError> {
+    match directories {
+        [dir] => println!("Starting deduplication in directory: {:?}", dir),
+        _ => println!("Starting deduplication in directories: {:?}", directories),
+    }
 
-    let hasher = image_hasher::HasherConfig::new().hash_size(hash_size, hash_size).to_hasher();
-    println!("Starting deduplication in directory: {:?}", dir);
+    if options.cross_root && directories.len() < 2 {
+        eprintln!(
+            "warning: --cross-root needs at least two directories; no duplicates will be found"
+        );
+    }
 
-    let image_hashes = get_image_hashes(dir, &hasher)?;
-    println!("Found {} images.", image_hashes.len());
+    let scanned = hash_directories(directories, options)?;
+    println!("Found {} images.", scanned.images.len());
 
-    let duplicates = find_duplicates(image_hashes, duplicate_threshold);
-    println!("Found {} duplicate groups.", duplicates.len());
+    let embedded;
+    let options = match &options.embedder {
+        Some(embedder) => {
+            println!("Embedding images with {:?}...", embedder.model);
+            let paths = scanned.images.iter().map(|image| image.path.clone());
+            let embeddings = embedder.embed(paths.collect())?;
+            println!("Embedded {} images.", embeddings.len());
+            embedded = DeduplicateOptions {
+                embeddings: Some(Arc::new(embeddings)),
+                ..options.clone()
+            };
+            &embedded
+        }
+        None => options,
+    };
 
-    let output_path = dir.join(report_filename);
-    let report = DeduplicationReport::new(dir.to_path_buf(), duplicates, duplicate_threshold);
+    let output_path = source::local_dir(&directories[0]).join(report_filename);
+    let metadata = DeduplicationMetadata {
+        sample: scanned.sample,
+        ..options.metadata(directories)
+    };
 
-    println!("Saving deduplication report...");
-    save_results(&report, &output_path)?;
+    let summary = report_images(
+        scanned.images,
+        &scanned.identical,
+        scanned.errors,
+        metadata,
+        &output_path,
+        options,
+    )?;
 
     println!("Process completed successfully.\n");
-    println!("{}", report);
+    println!("{}", summary);
+    if summary.errors > 0 {
+        eprintln!(
+            "warning: {} files or directories could not be read; see \"errors\" in {:?}",
+            summary.errors, output_path
+        );
+    }
 
-    Ok(())
+    Ok(summary)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use image::RgbImage;
-    use image_hasher::HasherConfig;
     use image_hasher::ImageHash;
     use tempfile::tempdir;
 
@@ -129,15 +1248,101 @@ mod tests {
         *image.get_pixel_mut(5, 5) = image::Rgb([255, 255, 255]);
         image.save(&image_path).unwrap();
 
-        let hasher = HasherConfig::new()
-            .hash_size(16, 16)
-            .to_hasher();
-        let result = get_image_hashes(dir.path(), &hasher);
+        fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+
+        let hasher = hashing::build_hasher(HashAlgorithm::Gradient, 16);
+        let (mut entries, _) = scan::collect_files(dir.path(), &ScanOptions::default()).unwrap();
+        let missing = dir.path().join("missing.png");
+        entries.push(missing.clone());
+        let (image_hashes, errors) = get_image_hashes(
+            &entries,
+            &hasher,
+            &DeduplicateOptions::default(),
+            &ProgressBar::hidden(),
+        );
 
-        assert!(result.is_ok());
-        let image_hashes = result.unwrap();
         assert_eq!(image_hashes.len(), 1);
         assert_eq!(image_hashes[0].path, image_path);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, missing);
+    }
+
+    #[test]
+    fn test_hash_directories_records_roots() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        let mut image: RgbImage = RgbImage::new(64, 64);
+        *image.get_pixel_mut(5, 5) = image::Rgb([255, 255, 255]);
+        image.save(first.path().join("a.png")).unwrap();
+        image.save(second.path().join("b.png")).unwrap();
+
+        let roots = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+        let scanned = hash_directories(&roots, &DeduplicateOptions::default()).unwrap();
+
+        assert_eq!(scanned.images.len(), 2);
+        for image in &scanned.images {
+            let root = image.root.as_ref().unwrap();
+            assert!(image.path.starts_with(root));
+        }
+        assert_ne!(scanned.images[0].root, scanned.images[1].root);
+        let dimensions = Dimensions {
+            width: 64,
+            height: 64,
+        };
+        assert_eq!(scanned.images[0].dimensions, Some(dimensions));
+    }
+
+    #[test]
+    fn test_cache_settings_follow_the_decode_limit() {
+        let options = DeduplicateOptions::default();
+        let hasher = options.build_hasher();
+        let settings = |max_pixels| {
+            let options = DeduplicateOptions {
+                decode: DecodeOptions {
+                    max_pixels,
+                    ..DecodeOptions::default()
+                },
+                ..options.clone()
+            };
+            cache_settings(&*hasher, &options)
+        };
+        let default = cache_settings(&*hasher, &options);
+        assert_eq!(settings(Some(decode::DEFAULT_MAX_PIXELS)), default);
+        assert_ne!(settings(Some(1_000_000)), default);
+        assert_ne!(settings(None), default);
+        assert_ne!(settings(None), settings(Some(1_000_000)));
+    }
+
+    #[test]
+    fn test_min_resolution_sets_small_images_aside() {
+        let dir = tempdir().unwrap();
+        let hash = ImageHash::from_base64("DAIDBwMHAf8").unwrap();
+        let image = |name: &str, side: u32| ImageInfo {
+            dimensions: Some(Dimensions {
+                width: side,
+                height: side,
+            }),
+            ..ImageInfo::new(PathBuf::from(name), hash.clone())
+        };
+        let images = vec![image("a.png", 600), image("b.png", 800), image("c.png", 64)];
+        let options = DeduplicateOptions {
+            min_resolution: Some(Dimensions {
+                width: 512,
+                height: 512,
+            }),
+            ..DeduplicateOptions::default()
+        };
+
+        let output_path = dir.path().join("report.json");
+        let metadata = options.metadata(&[dir.path().to_path_buf()]);
+        let summary =
+            report_images(images, &[], Vec::new(), metadata, &output_path, &options).unwrap();
+        assert_eq!((summary.total_duplicates, summary.below_resolution), (1, 1));
+
+        let report: DeduplicationReport =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        assert_eq!(report.groups[0].items.len(), 2);
+        assert_eq!(report.below_resolution[0].path, PathBuf::from("c.png"));
     }
 
     #[test]
@@ -145,32 +1350,25 @@ mod tests {
         let hash1: ImageHash = ImageHash::from_base64("DAIDBwMHAf8").unwrap();
         let hash2: ImageHash = ImageHash::from_base64("8/JwVtbOVy4").unwrap();
         let hash3: ImageHash = hash1.clone();
-        let hash4: ImageHash = ImageHash::from_base64("DwcHBwcHBwc").unwrap(); 
+        let hash4: ImageHash = ImageHash::from_base64("DwcHBwcHBwc").unwrap();
         let hash5: ImageHash = ImageHash::from_base64("HxcXB4cGBgc").unwrap();
 
-        let image1 = ImageInfo {
-            path: PathBuf::from("image1.png"),
-            hash: hash1,
-        };
-        let image2 = ImageInfo {
-            path: PathBuf::from("image2.png"),
-            hash: hash2,
-        };
-        let image3 = ImageInfo {
-            path: PathBuf::from("image3.png"),
-            hash: hash3, // Duplicate of image1
-        };
-        let image4 = ImageInfo {
-            path: PathBuf::from("image4.png"),
-            hash: hash4,
-        };
-        let image5 = ImageInfo {
-            path: PathBuf::from("image5.png"),
-            hash: hash5,
-        };
+        let image1 = ImageInfo::new(PathBuf::from("image1.png"), hash1);
+        let image2 = ImageInfo::new(PathBuf::from("image2.png"), hash2);
+        let image3 = ImageInfo::new(PathBuf::from("image3.png"), hash3); // Duplicate of image1
+        let image4 = ImageInfo::new(PathBuf::from("image4.png"), hash4);
+        let image5 = ImageInfo::new(PathBuf::from("image5.png"), hash5);
 
-        let images = vec![image1.clone(), image2.clone(), image3.clone(), image4.clone(), image5.clone()];
-        let groups = find_duplicates(images, 10u32);
+        let images = [
+            image1.clone(),
+            image2.clone(),
+            image3.clone(),
+            image4.clone(),
+            image5.clone(),
+        ];
+        let options = DeduplicateOptions::default();
+        let (groups, scores, watermarked) = match_groups(&images[..], &options);
+        let groups = find_duplicates(&images[..], &groups, &scores, &watermarked, &options);
 
         assert_eq!(groups.len(), 2, "Expected two groups of duplicates");
         assert_eq!(
@@ -181,4 +1379,71 @@ mod tests {
         assert!(groups[0].items.contains(&image1));
         assert!(groups[0].items.contains(&image3));
     }
+
+    #[test]
+    fn test_custom_hasher_distance_is_used_for_matching() {
+        /// Mean brightness in a single byte, compared by difference.
+        #[derive(Debug)]
+        struct Brightness;
+
+        impl PerceptualHasher for Brightness {
+            fn name(&self) -> String {
+                "brightness".to_string()
+            }
+
+            fn hash_image(&self, img: &image::DynamicImage) -> ImageHash {
+                let luma = img.to_luma8();
+                let total: u64 = luma.pixels().map(|pixel| pixel.0[0] as u64).sum();
+                let mean = total / luma.pixels().len().max(1) as u64;
+                ImageHash::from_bytes(&[mean as u8]).unwrap()
+            }
+
+            fn distance(&self, a: &[u8], b: &[u8]) -> u32 {
+                a[0].abs_diff(b[0]) as u32
+            }
+        }
+
+        // 127 and 128 differ in every bit, but are one level apart, and 7 is
+        // four bits away from both.
+        let images: Vec<ImageInfo> = [127u8, 128, 7]
+            .iter()
+            .map(|&level| {
+                ImageInfo::new(
+                    PathBuf::from(format!("{}.png", level)),
+                    ImageHash::from_bytes(&[level]).unwrap(),
+                )
+            })
+            .collect();
+        let mut options = DeduplicateOptions {
+            duplicate_threshold: 4,
+            ..DeduplicateOptions::default()
+        };
+        assert!(match_groups(&images[..], &options).0.is_empty());
+
+        options.hasher = Some(Arc::new(Brightness));
+        assert_eq!(match_groups(&images[..], &options).0, vec![vec![0, 1]]);
+        assert_eq!(options.build_hasher().name(), "brightness");
+    }
+
+    #[test]
+    fn test_cross_root_ignores_duplicates_within_a_root() {
+        let hash = ImageHash::from_base64("DAIDBwMHAf8").unwrap();
+        let image = |path: &str, root: &str| ImageInfo {
+            root: Some(PathBuf::from(root)),
+            ..ImageInfo::new(PathBuf::from(root).join(path), hash.clone())
+        };
+        let images = [
+            image("a.png", "test"),
+            image("a.png", "train"),
+            image("b.png", "train"),
+        ];
+        let options = DeduplicateOptions {
+            grouping: Grouping::Complete,
+            cross_root: true,
+            ..DeduplicateOptions::default()
+        };
+
+        assert_eq!(match_groups(&images[..], &options).0, vec![vec![0, 1]]);
+        assert!(match_groups(&images[1..], &options).0.is_empty());
+    }
 }
@@ -0,0 +1,200 @@
+use clap::ValueEnum;
+use std::fmt;
+
+/// Scale of [`Cosine`] distances: a distance of `COSINE_SCALE` is a
+/// similarity of 0, and 0 is a similarity of 1.
+pub const COSINE_SCALE: f32 = 1000.0;
+
+/// A distance between two hashes given as raw bytes, compared against the
+/// duplicate threshold.
+///
+/// Setting `DeduplicateOptions::metric` makes matching compare every pair
+/// of images by it, so new similarity measures only need an implementation
+/// of this trait.
+pub trait Metric: Send + Sync + fmt::Debug {
+    fn distance(&self, a: &[u8], b: &[u8]) -> u32;
+}
+
+/// Built-in metrics, as chosen on the command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MetricKind {
+    /// Number of differing bits.
+    #[default]
+    Hamming,
+    /// Number of differing bits, counting the bits of each part of the
+    /// hash with its own weight; see [`WeightedHamming`].
+    WeightedHamming,
+    /// Cosine distance between hashes holding vectors of little-endian
+    /// `f32` values, such as embeddings; see [`Cosine`].
+    Cosine,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hamming;
+
+impl Metric for Hamming {
+    fn distance(&self, a: &[u8], b: &[u8]) -> u32 {
+        hamming(a, b)
+    }
+}
+
+/// Hamming distance where the hash is split into as many equal parts as
+/// there are weights, and the differing bits of each part count as many
+/// times as its weight.
+///
+/// Hashes of several planes, as built with `--channels`, have one part per
+/// plane, so weights `2,1,1` make luma count twice as much as chroma.
+#[derive(Debug, Clone)]
+pub struct WeightedHamming {
+    weights: Vec<u32>,
+}
+
+impl WeightedHamming {
+    pub fn new(weights: Vec<u32>) -> WeightedHamming {
+        WeightedHamming { weights }
+    }
+}
+
+impl Metric for WeightedHamming {
+    fn distance(&self, a: &[u8], b: &[u8]) -> u32 {
+        let len = a.len().min(b.len());
+        let parts = self.weights.len().max(1);
+        let part_len = len.div_ceil(parts).max(1);
+        a[..len]
+            .chunks(part_len)
+            .zip(b[..len].chunks(part_len))
+            .zip(self.weights.iter().chain(std::iter::repeat(&1)))
+            .map(|((a, b), weight)| hamming(a, b) * weight)
+            .sum()
+    }
+}
+
+/// `1 - cosine similarity` of two vectors of little-endian `f32` values,
+/// scaled by [`COSINE_SCALE`] and rounded. Vectors of zero length are at
+/// a distance of `COSINE_SCALE` from everything, and vectors holding NaN
+/// or infinite values at the largest distance, `2 * COSINE_SCALE`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cosine;
+
+impl Metric for Cosine {
+    fn distance(&self, a: &[u8], b: &[u8]) -> u32 {
+        let values = |bytes: &[u8]| -> Vec<f32> {
+            bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect()
+        };
+        let (a, b) = (values(a), values(b));
+        let dot: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+        let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt()
+            * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norms == 0.0 {
+            return COSINE_SCALE as u32;
+        }
+        let similarity = dot / norms;
+        if !similarity.is_finite() {
+            return (2.0 * COSINE_SCALE) as u32;
+        }
+        ((1.0 - similarity).clamp(0.0, 2.0) * COSINE_SCALE).round() as u32
+    }
+}
+
+/// Number of differing bits between two hashes given as raw bytes.
+///
+/// Like [`image_hasher::ImageHash::dist`], only the common prefix of the two
+/// slices is compared. Bytes are processed as 64-bit words, using the
+/// hardware `popcnt` instruction when the CPU supports it. The default
+/// x86_64 target does not enable it, so its support is checked at run time
+/// rather than left to `-C target-cpu=native`; other targets use the
+/// portable count.
+pub fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if std::arch::is_x86_feature_detected!("popcnt") {
+            // SAFETY: the CPU supports the `popcnt` instruction.
+            return unsafe { hamming_popcnt(a, b) };
+        }
+    }
+    hamming_words(a, b)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "popcnt")]
+unsafe fn hamming_popcnt(a: &[u8], b: &[u8]) -> u32 {
+    hamming_words(a, b)
+}
+
+/// Portable Hamming distance, which the `popcnt` path above compiles
+/// again with the instruction enabled.
+#[inline(always)]
+fn hamming_words(a: &[u8], b: &[u8]) -> u32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    let mut words_a = a.chunks_exact(8);
+    let mut words_b = b.chunks_exact(8);
+    let mut distance: u32 = words_a
+        .by_ref()
+        .zip(words_b.by_ref())
+        .map(|(x, y)| {
+            let x = u64::from_le_bytes(x.try_into().unwrap());
+            let y = u64::from_le_bytes(y.try_into().unwrap());
+            (x ^ y).count_ones()
+        })
+        .sum();
+
+    distance += words_a
+        .remainder()
+        .iter()
+        .zip(words_b.remainder())
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum::<u32>();
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hamming_matches_bytewise_count() {
+        let a: Vec<u8> = (0..37u32).map(|i| (i * 37 % 251) as u8).collect();
+        let b: Vec<u8> = (0..37u32).map(|i| (i * 91 % 241) as u8).collect();
+        for len in 0..a.len() {
+            let expected: u32 = a[..len]
+                .iter()
+                .zip(&b[..len])
+                .map(|(x, y)| (x ^ y).count_ones())
+                .sum();
+            assert_eq!(hamming(&a[..len], &b[..len]), expected);
+            assert_eq!(hamming_words(&a[..len], &b[..len]), expected);
+        }
+        assert_eq!(hamming(&a, &b[..3]), hamming(&a[..3], &b[..3]));
+    }
+
+    #[test]
+    fn test_weighted_and_cosine_metrics() {
+        let weighted = WeightedHamming::new(vec![2, 1, 1]);
+        // One bit differs in the first part and one in the last.
+        assert_eq!(weighted.distance(&[0, 0, 0], &[1, 0, 1]), 3);
+        assert_eq!(Hamming.distance(&[0, 0, 0], &[1, 0, 1]), 2);
+
+        let bytes = |values: &[f32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        };
+        let (a, b, c) = (bytes(&[1.0, 0.0]), bytes(&[3.0, 0.0]), bytes(&[0.0, 2.0]));
+        assert_eq!(Cosine.distance(&a, &b), 0);
+        assert_eq!(Cosine.distance(&a, &c), COSINE_SCALE as u32);
+        assert_eq!(
+            Cosine.distance(&a, &bytes(&[0.0, 0.0])),
+            COSINE_SCALE as u32
+        );
+        for broken in [bytes(&[f32::NAN, 0.0]), bytes(&[f32::INFINITY, 1.0])] {
+            assert_eq!(Cosine.distance(&a, &broken), 2 * COSINE_SCALE as u32);
+            assert_eq!(Cosine.distance(&broken, &broken), 2 * COSINE_SCALE as u32);
+        }
+    }
+}
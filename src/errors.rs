@@ -9,6 +9,11 @@ pub enum AppError {
     SerdeError(serde_json::Error),
     InvalidDirectory(PathBuf),
     FileNotFound(String),
+    InvalidImage(PathBuf),
+    IncompatibleShard(PathBuf),
+    UnknownGroup { id: String, report: PathBuf },
+    ConfirmationRequired { action: String },
+    IncompatibleMetric { metric: String, hasher: String },
 }
 
 impl From<io::Error> for AppError {
@@ -38,6 +43,42 @@ impl fmt::Display for AppError {
             AppError::FileNotFound(dir) => {
                 write!(f, "{} File `{}` not found", error_prefix, dir)
             }
+            AppError::InvalidImage(path) => {
+                write!(
+                    f,
+                    "{} File `{:?}` is not a supported image",
+                    error_prefix, path
+                )
+            }
+            AppError::IncompatibleShard(path) => {
+                write!(
+                    f,
+                    "{} Partial hashes `{:?}` use a different algorithm or hash size",
+                    error_prefix, path
+                )
+            }
+            AppError::UnknownGroup { id, report } => {
+                write!(
+                    f,
+                    "{} The report `{:?}` has no group `{}`",
+                    error_prefix, report, id
+                )
+            }
+            AppError::ConfirmationRequired { action } => {
+                write!(
+                    f,
+                    "{} No terminal to confirm the plan at; pass --yes to {} the files",
+                    error_prefix, action
+                )
+            }
+            AppError::IncompatibleMetric { metric, hasher } => {
+                write!(
+                    f,
+                    "{} The {} metric compares vectors of numbers, but `{}` hashes \
+                     are bits; use --embeddings to match images by embeddings",
+                    error_prefix, metric, hasher
+                )
+            }
         }
     }
 }
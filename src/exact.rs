@@ -0,0 +1,293 @@
+use crate::archive;
+use crate::decode::{self, DecodeOptions};
+use crate::models::{DuplicatesGroup, IdenticalSet, ImageInfo, MatchKind};
+use crate::pages;
+use crc32fast::Hasher as Crc32;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Find sets of byte-identical files among `paths`.
+///
+/// Files are bucketed by size, then by a CRC32 checksum of their contents,
+/// and every candidate is finally compared byte-for-byte with the first file
+/// of its set, so a returned set never contains a checksum collision.
+/// Each set has at least two files, listed in input order. Files that cannot
+/// be read are ignored, as are pages of multi-page files, which share the
+/// bytes of the file holding them.
+pub fn find_identical(paths: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, path) in paths
+        .iter()
+        .enumerate()
+        .filter(|(_, path)| !pages::is_page(path))
+    {
+        if let Ok(size) = archive::size(path) {
+            by_size.entry(size).or_default().push(index);
+        }
+    }
+
+    let candidates: Vec<(usize, u64)> = by_size
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .flat_map(|(size, indices)| indices.into_iter().map(move |index| (index, size)))
+        .collect();
+
+    let checksums: Vec<(usize, u64, u32)> = candidates
+        .par_iter()
+        .filter_map(|&(index, size)| {
+            let crc = checksum(&paths[index]).ok()?;
+            Some((index, size, crc))
+        })
+        .collect();
+
+    let mut by_content: HashMap<(u64, u32), Vec<usize>> = HashMap::new();
+    for (index, size, crc) in checksums {
+        by_content.entry((size, crc)).or_default().push(index);
+    }
+
+    let mut sets: Vec<Vec<usize>> = Vec::new();
+    for mut indices in by_content.into_values().filter(|i| i.len() > 1) {
+        indices.sort_unstable();
+        let mut verified: Vec<Vec<usize>> = Vec::new();
+        for index in indices {
+            let existing = verified
+                .iter_mut()
+                .find(|set| files_equal(&paths[set[0]], &paths[index]).unwrap_or(false));
+            match existing {
+                Some(set) => set.push(index),
+                None => verified.push(vec![index]),
+            }
+        }
+        sets.extend(verified.into_iter().filter(|set| set.len() > 1));
+    }
+
+    sets.sort_unstable_by_key(|set| set[0]);
+    sets.into_iter()
+        .map(|set| set.into_iter().map(|i| paths[i].clone()).collect())
+        .collect()
+}
+
+/// Give every copy in `sets` the hash already computed for the first file of its set.
+///
+/// Sets whose first file was not hashed (e.g. because it is not an image) are skipped.
+pub fn propagate_hashes(images: &mut Vec<ImageInfo>, sets: &[Vec<PathBuf>]) {
+    let hashed: HashMap<&Path, &ImageInfo> = images
+        .iter()
+        .map(|image| (image.path.as_path(), image))
+        .collect();
+
+    let mut copies: Vec<ImageInfo> = Vec::new();
+    for set in sets {
+        if let Some(&original) = hashed.get(set[0].as_path()) {
+            copies.extend(set[1..].iter().map(|path| ImageInfo {
+                path: path.clone(),
+                ..original.clone()
+            }));
+        }
+    }
+    images.extend(copies);
+}
+
+/// Marks groups made up solely of byte-identical files as exact matches.
+pub struct ExactMarker<'a> {
+    set_of: HashMap<&'a Path, usize>,
+}
+
+impl<'a> ExactMarker<'a> {
+    pub fn new(sets: &'a [Vec<PathBuf>]) -> Self {
+        let set_of = sets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, set)| set.iter().map(move |path| (path.as_path(), i)))
+            .collect();
+        ExactMarker { set_of }
+    }
+
+    pub fn mark(&self, group: &mut DuplicatesGroup) {
+        let ids: HashSet<Option<&usize>> = group
+            .items
+            .iter()
+            .map(|item| self.set_of.get(item.path.as_path()))
+            .collect();
+        if ids.len() == 1 && !ids.contains(&None) {
+            group.kind = MatchKind::Exact;
+        }
+    }
+}
+
+/// List the items of `group` that are byte-identical, and those that decode
+/// to the same pixels, and mark the group as such if all its items are.
+///
+/// Pixels are compared at full resolution, after turning images upright as
+/// `decode` says, so a lossless conversion to another format counts as
+/// pixel-identical. Checksums only pick candidates, which are then decoded
+/// again and compared in full. Items that cannot be read are left out.
+pub fn classify(group: &mut DuplicatesGroup, decode: &DecodeOptions) {
+    let paths: Vec<PathBuf> = group.items.iter().map(|item| item.path.clone()).collect();
+    let decode = DecodeOptions {
+        max_pixels: None,
+        ..decode.clone()
+    };
+    let bytes = find_identical(&paths);
+
+    let checksums: Vec<(usize, (u32, u32, u32))> = paths
+        .par_iter()
+        .enumerate()
+        .filter_map(|(index, path)| {
+            let pixels = decode::open(path, &decode).ok()?.into_rgba8();
+            let mut hasher = Crc32::new();
+            hasher.update(pixels.as_raw());
+            Some((index, (pixels.width(), pixels.height(), hasher.finalize())))
+        })
+        .collect();
+    let mut by_pixels: HashMap<(u32, u32, u32), Vec<usize>> = HashMap::new();
+    for (index, key) in checksums {
+        by_pixels.entry(key).or_default().push(index);
+    }
+    let mut pixels: Vec<Vec<usize>> = Vec::new();
+    for mut indices in by_pixels.into_values().filter(|i| i.len() > 1) {
+        indices.sort_unstable();
+        let leader = match decode::open(&paths[indices[0]], &decode) {
+            Ok(img) => img.into_rgba8(),
+            Err(_) => continue,
+        };
+        let set: Vec<usize> = indices
+            .into_iter()
+            .enumerate()
+            .filter(|&(position, index)| {
+                position == 0
+                    || decode::open(&paths[index], &decode)
+                        .is_ok_and(|img| img.into_rgba8() == leader)
+            })
+            .map(|(_, index)| index)
+            .collect();
+        if set.len() > 1 {
+            pixels.push(set);
+        }
+    }
+    pixels.sort_unstable_by_key(|set| set[0]);
+
+    let pixel_sets = pixels
+        .into_iter()
+        .map(|set| -> Vec<PathBuf> { set.into_iter().map(|index| paths[index].clone()).collect() });
+    group.identical = bytes
+        .iter()
+        .map(|set| IdenticalSet {
+            kind: MatchKind::Exact,
+            paths: set.clone(),
+        })
+        .chain(
+            pixel_sets
+                // A set of byte-identical files is already listed.
+                .filter(|set| !bytes.contains(set))
+                .map(|set| IdenticalSet {
+                    kind: MatchKind::Pixel,
+                    paths: set,
+                }),
+        )
+        .collect();
+
+    if let Some(whole) = group
+        .identical
+        .iter()
+        .find(|set| set.paths.len() == paths.len())
+    {
+        if group.kind != MatchKind::Exact {
+            group.kind = whole.kind;
+        }
+    }
+}
+
+/// CRC32 checksum of the contents of `path`.
+pub(crate) fn checksum(path: &Path) -> io::Result<u32> {
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, archive::open(path)?);
+    let mut hasher = Crc32::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+fn files_equal(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = BufReader::with_capacity(BUFFER_SIZE, archive::open(a)?);
+    let mut b = BufReader::with_capacity(BUFFER_SIZE, archive::open(b)?);
+    let mut buffer_a = vec![0u8; BUFFER_SIZE];
+    let mut buffer_b = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(b.read(&mut buffer_b)? == 0);
+        }
+        b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_identical() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        fs::write(&paths[0], b"same bytes").unwrap();
+        fs::write(&paths[1], b"other data").unwrap();
+        fs::write(&paths[2], b"same bytes").unwrap();
+        fs::write(&paths[3], b"short").unwrap();
+
+        let sets = find_identical(&paths);
+        assert_eq!(sets, vec![vec![paths[0].clone(), paths[2].clone()]]);
+    }
+
+    #[test]
+    fn test_classify_separates_byte_and_pixel_identical_items() {
+        let dir = tempdir().unwrap();
+        let image = image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8, 0]));
+        let paths: Vec<PathBuf> = ["a.png", "b.png", "c.bmp", "d.png"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        image.save(&paths[0]).unwrap();
+        fs::copy(&paths[0], &paths[1]).unwrap();
+        image.save(&paths[2]).unwrap();
+        image::RgbImage::new(16, 16).save(&paths[3]).unwrap();
+        let items = paths.iter().map(ImageInfo::at).collect();
+
+        let mut group = DuplicatesGroup::new(items);
+        classify(&mut group, &DecodeOptions::default());
+        assert_eq!(
+            group.identical,
+            vec![
+                IdenticalSet {
+                    kind: MatchKind::Exact,
+                    paths: paths[..2].to_vec(),
+                },
+                IdenticalSet {
+                    kind: MatchKind::Pixel,
+                    paths: paths[..3].to_vec(),
+                },
+            ]
+        );
+        assert_eq!(group.kind, MatchKind::Perceptual);
+
+        group.items.pop();
+        classify(&mut group, &DecodeOptions::default());
+        assert_eq!(group.kind, MatchKind::Pixel);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::archive;
+use crate::raw;
+use crate::timestamp;
+use image::metadata::Orientation;
+use image::{ImageDecoder, ImageReader};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+const ORIENTATION: u16 = 0x0112;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const DATE_TIME: u16 = 0x0132;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const SUB_SEC_TIME_ORIGINAL: u16 = 0x9291;
+const MAKE: u16 = 0x010F;
+const MODEL: u16 = 0x0110;
+const BODY_SERIAL_NUMBER: u16 = 0xA431;
+
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+
+/// EXIF metadata of an image, kept as the raw TIFF structure it is stored
+/// in and read on demand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exif {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+/// One field of an image file directory.
+struct Field {
+    kind: u16,
+    count: usize,
+    /// Offset of the value in `data`.
+    value: usize,
+}
+
+impl Exif {
+    /// EXIF metadata of the image at `path`, or `None` if it has none or its
+    /// format cannot carry any.
+    pub fn read(path: &Path) -> Option<Exif> {
+        if raw::is_raw(path) {
+            return raw::exif(path);
+        }
+        let source = archive::open(path).ok()?;
+        let mut decoder = ImageReader::new(source)
+            .with_guessed_format()
+            .ok()?
+            .into_decoder()
+            .ok()?;
+        Exif::parse(decoder.exif_metadata().ok()??)
+    }
+
+    /// Wrap the TIFF structure `data`, checking its header.
+    pub fn parse(data: Vec<u8>) -> Option<Exif> {
+        let big_endian = match data.get(..4)? {
+            [b'I', b'I', 42, 0] => false,
+            [b'M', b'M', 0, 42] => true,
+            _ => return None,
+        };
+        Some(Exif { data, big_endian })
+    }
+
+    /// When the picture was taken, or failing that last edited, according
+    /// to the camera clock.
+    pub fn capture_time(&self) -> Option<SystemTime> {
+        let ifd0 = self.u32_at(4)? as usize;
+        let original = self
+            .long(ifd0, EXIF_IFD_POINTER)
+            .and_then(|exif_ifd| self.ascii(exif_ifd as usize, DATE_TIME_ORIGINAL));
+        original
+            .or_else(|| self.ascii(ifd0, DATE_TIME))
+            .and_then(timestamp::parse_exif_datetime)
+    }
+
+    /// When the shutter was released, to a fraction of a second if the
+    /// camera records one.
+    pub fn shot_time(&self) -> Option<SystemTime> {
+        let ifd0 = self.u32_at(4)? as usize;
+        let exif_ifd = self.long(ifd0, EXIF_IFD_POINTER)? as usize;
+        let time = timestamp::parse_exif_datetime(self.ascii(exif_ifd, DATE_TIME_ORIGINAL)?)?;
+        let fraction = self
+            .ascii(exif_ifd, SUB_SEC_TIME_ORIGINAL)
+            .map(|digits| digits.trim_end_matches(['\0', ' ']))
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| format!("0.{}", digits).parse::<f64>().ok())
+            .unwrap_or(0.0);
+        Some(time + Duration::from_secs_f64(fraction))
+    }
+
+    /// Make, model and serial number of the camera body, as far as they are
+    /// recorded.
+    pub fn camera(&self) -> Option<String> {
+        let ifd0 = self.u32_at(4)? as usize;
+        let serial = self
+            .long(ifd0, EXIF_IFD_POINTER)
+            .and_then(|exif_ifd| self.ascii(exif_ifd as usize, BODY_SERIAL_NUMBER));
+        let parts: Vec<&str> = [self.ascii(ifd0, MAKE), self.ascii(ifd0, MODEL), serial]
+            .into_iter()
+            .flatten()
+            .map(|part| part.trim_end_matches(['\0', ' ']))
+            .filter(|part| !part.is_empty())
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    /// How the picture has to be turned to be displayed upright.
+    pub fn orientation(&self) -> Option<Orientation> {
+        let ifd0 = self.u32_at(4)? as usize;
+        Orientation::from_exif(self.long(ifd0, ORIENTATION)? as u8)
+    }
+
+    /// The TIFF structure the metadata is stored in.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The same metadata, saying that the picture is displayed as stored,
+    /// for pixels that are already upright.
+    pub fn upright(mut self) -> Exif {
+        let field = self
+            .u32_at(4)
+            .and_then(|ifd0| self.field(ifd0 as usize, ORIENTATION))
+            .filter(|field| field.kind == SHORT);
+        let normal = match self.big_endian {
+            true => 1u16.to_be_bytes(),
+            false => 1u16.to_le_bytes(),
+        };
+        if let Some(value) = field.and_then(|field| self.data.get_mut(field.value..field.value + 2))
+        {
+            value.copy_from_slice(&normal);
+        }
+        self
+    }
+
+    fn field(&self, ifd: usize, tag: u16) -> Option<Field> {
+        let count = self.u16_at(ifd)? as usize;
+        (0..count).find_map(|i| {
+            let entry = ifd + 2 + i * 12;
+            if self.u16_at(entry)? != tag {
+                return None;
+            }
+            let kind = self.u16_at(entry + 2)?;
+            let count = self.u32_at(entry + 4)? as usize;
+            let size = match kind {
+                SHORT => 2,
+                LONG => 4,
+                _ => 1,
+            };
+            let value = if size * count <= 4 {
+                entry + 8
+            } else {
+                self.u32_at(entry + 8)? as usize
+            };
+            Some(Field { kind, count, value })
+        })
+    }
+
+    fn ascii(&self, ifd: usize, tag: u16) -> Option<&str> {
+        let field = self.field(ifd, tag).filter(|field| field.kind == ASCII)?;
+        let bytes = self.data.get(field.value..field.value + field.count)?;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    fn long(&self, ifd: usize, tag: u16) -> Option<u32> {
+        let field = self.field(ifd, tag)?;
+        match field.kind {
+            SHORT => self.u16_at(field.value).map(u32::from),
+            LONG => self.u32_at(field.value),
+            _ => None,
+        }
+    }
+
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_capture_time() {
+        // Big endian TIFF header, IFD0 at 8 pointing to an Exif IFD at 26.
+        let mut data = b"MM\0\x2a\0\0\0\x08".to_vec();
+        data.extend([0, 1, 0x87, 0x69, 0, LONG as u8, 0, 0, 0, 1, 0, 0, 0, 26]);
+        data.extend([0, 0, 0, 0]);
+        data.extend([0, 1, 0x90, 0x03, 0, ASCII as u8, 0, 0, 0, 20, 0, 0, 0, 44]);
+        data.extend([0, 0, 0, 0]);
+        data.extend(b"2024:03:01 12:30:15\0");
+
+        let exif = Exif::parse(data).unwrap();
+        let expected = UNIX_EPOCH + Duration::from_secs(1_709_296_215);
+        assert_eq!(exif.capture_time(), Some(expected));
+        assert_eq!(Exif::parse(b"not exif".to_vec()), None);
+    }
+
+    #[test]
+    fn test_shot_time_and_camera() {
+        // Little endian, IFD0 at 8 with the make and the Exif IFD at 38,
+        // which holds the capture time and its hundredths of a second.
+        let mut data = b"II\x2a\0\x08\0\0\0".to_vec();
+        data.extend([2, 0, 0x0F, 0x01, ASCII as u8, 0, 6, 0, 0, 0, 68, 0, 0, 0]);
+        data.extend([0x69, 0x87, LONG as u8, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        data.extend([0, 0, 0, 0]);
+        data.extend([2, 0, 0x03, 0x90, ASCII as u8, 0, 20, 0, 0, 0, 74, 0, 0, 0]);
+        data.extend([0x91, 0x92, ASCII as u8, 0, 3, 0, 0, 0, b'2', b'5', 0, 0]);
+        data.extend([0, 0, 0, 0]);
+        data.extend(b"Nikon\0");
+        data.extend(b"2024:03:01 12:30:15\0");
+
+        let exif = Exif::parse(data).unwrap();
+        let expected = UNIX_EPOCH + Duration::from_millis(1_709_296_215_250);
+        assert_eq!(exif.shot_time(), Some(expected));
+        assert_eq!(exif.camera().as_deref(), Some("Nikon"));
+    }
+
+    #[test]
+    fn test_orientation() {
+        // Little endian TIFF header, IFD0 at 8 holding only the orientation.
+        let mut data = b"II\x2a\0\x08\0\0\0".to_vec();
+        data.extend([1, 0, 0x12, 0x01, SHORT as u8, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        data.extend([0, 0, 0, 0]);
+
+        let exif = Exif::parse(data).unwrap();
+        assert_eq!(exif.orientation(), Some(Orientation::Rotate90));
+        assert_eq!(exif.capture_time(), None);
+        assert_eq!(
+            exif.upright().orientation(),
+            Some(Orientation::NoTransforms)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::actions::{self, Keep};
+use crate::archive;
+use crate::deduplicate::{self, DeduplicateOptions};
+use crate::errors::AppError;
+use crate::models::{DeduplicationMetadata, DeduplicationReport};
+use crate::pages;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the report saved in the destination of an export.
+pub const REPORT_FILE: &str = "dedup_report.json";
+
+/// Copy the images of `source` to `destination`, leaving out the
+/// duplicates: one file of every group, picked by `keep`, is copied along
+/// with every image that has no duplicate.
+///
+/// Files keep their path relative to `source`, whose files are never
+/// modified, and archive members are extracted under a directory named
+/// after their archive. The report of the duplicates left out is saved in
+/// `destination`.
+pub fn run(
+    source: &Path,
+    destination: &Path,
+    keep: Keep,
+    options: &DeduplicateOptions,
+) -> Result<(), AppError> {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if absolute(destination).starts_with(absolute(source)) {
+        return Err(AppError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is inside the exported directory", destination),
+        )));
+    }
+    let options = DeduplicateOptions {
+        action: None,
+        ..options.clone()
+    };
+    let directories = [source.to_path_buf()];
+    println!("Starting export of directory: {:?}", source);
+    let scanned = deduplicate::hash_directories(&directories, &options)?;
+    println!("Found {} images.", scanned.images.len());
+    let paths: Vec<PathBuf> = scanned
+        .images
+        .iter()
+        .map(|image| image.path.clone())
+        .collect();
+
+    fs::create_dir_all(destination)?;
+    let report_path = destination.join(REPORT_FILE);
+    let metadata = DeduplicationMetadata {
+        sample: scanned.sample,
+        ..options.metadata(&directories)
+    };
+    deduplicate::report_images(
+        scanned.images,
+        &scanned.identical,
+        scanned.errors,
+        metadata,
+        &report_path,
+        &options,
+    )?;
+    let report: DeduplicationReport = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+
+    let mut left_out: HashSet<PathBuf> = HashSet::new();
+    for group in &report.groups {
+        let kept = actions::keeper(group, keep, &[]);
+        left_out.extend(
+            group
+                .items
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| index != kept)
+                .map(|(_, item)| item.path.clone()),
+        );
+    }
+    // Pages are copied as the whole file holding them.
+    let copied: BTreeSet<PathBuf> = paths
+        .into_iter()
+        .filter(|path| !left_out.contains(path))
+        .map(|path| pages::file_of(&path))
+        .collect();
+    for path in &copied {
+        let target = export_path(destination, source, path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut archive::open(path)?, &mut File::create(&target)?)?;
+    }
+    println!(
+        "Exported {} images to {:?}, leaving out {} duplicates.",
+        copied.len(),
+        destination,
+        left_out.len()
+    );
+    Ok(())
+}
+
+/// Where the file at `path`, found under `source`, is copied in
+/// `destination`.
+fn export_path(destination: &Path, source: &Path, path: &Path) -> PathBuf {
+    match archive::split(path) {
+        Some((archive, name)) => {
+            let extracted = actions::quarantine_path(destination, Some(source), &archive);
+            actions::quarantine_path(&extracted, None, Path::new(name))
+        }
+        None => actions::quarantine_path(destination, Some(source), path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::ScanOptions;
+    use image::RgbImage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_copies_one_file_per_group() {
+        let source = tempdir().unwrap();
+        let destination = tempdir().unwrap();
+        let gradient = RgbImage::from_fn(64, 64, |x, y| image::Rgb([x as u8 * 4, y as u8 * 4, 0]));
+        let stripes = RgbImage::from_fn(64, 64, |x, _| image::Rgb([(x % 8 * 32) as u8, 0, 0]));
+        fs::create_dir(source.path().join("sub")).unwrap();
+        gradient.save(source.path().join("a.png")).unwrap();
+        gradient
+            .save(source.path().join("sub").join("b.png"))
+            .unwrap();
+        stripes
+            .save(source.path().join("sub").join("c.png"))
+            .unwrap();
+        let options = DeduplicateOptions {
+            scan: ScanOptions {
+                recursive: true,
+                ..ScanOptions::default()
+            },
+            ..DeduplicateOptions::default()
+        };
+
+        let exported = destination.path().join("clean");
+        run(source.path(), &exported, Keep::FirstAlphabetical, &options).unwrap();
+        assert!(exported.join("a.png").exists());
+        assert!(!exported.join("sub").join("b.png").exists());
+        assert!(exported.join("sub").join("c.png").exists());
+        assert!(exported.join(REPORT_FILE).exists());
+        assert!(source.path().join("sub").join("b.png").exists());
+        assert!(run(
+            source.path(),
+            &source.path().join("clean"),
+            Keep::Largest,
+            &options
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use crate::archive;
+use crate::decode;
+use crate::exif::Exif;
+use crate::models::Dimensions;
+use crate::raw;
+use clap::ValueEnum;
+use image::ImageReader;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Date of a file compared against `--newer-than` and `--older-than`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateSource {
+    /// Last modification time of the file.
+    #[default]
+    Modified,
+    /// Capture date recorded by the camera in the EXIF metadata, falling
+    /// back to the modification time for images without one.
+    Capture,
+}
+
+impl fmt::Display for DateSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DateSource::Modified => write!(f, "modified"),
+            DateSource::Capture => write!(f, "capture"),
+        }
+    }
+}
+
+/// Limits on file size, image dimensions and file dates, checked before
+/// hashing.
+///
+/// Files not recognised as images by their first bytes are always dropped.
+/// Dimensions are read from the image header only. Files whose header
+/// cannot be read are kept, and dropped later if they fail to decode.
+#[derive(Debug, Clone, Default)]
+pub struct FilterOptions {
+    /// Smallest accepted file size in bytes.
+    pub min_size: Option<u64>,
+    /// Largest accepted file size in bytes.
+    pub max_size: Option<u64>,
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    /// Only keep files dated at or after this time.
+    pub newer_than: Option<SystemTime>,
+    /// Only keep files dated before this time.
+    pub older_than: Option<SystemTime>,
+    /// Which date `newer_than` and `older_than` apply to.
+    pub date_source: DateSource,
+}
+
+impl FilterOptions {
+    fn checks_dimensions(&self) -> bool {
+        self.min_width.is_some() || self.min_height.is_some()
+    }
+
+    fn checks_date(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some()
+    }
+}
+
+/// Date of the file at `path` according to `source`.
+fn date_of(path: &Path, source: DateSource) -> Option<SystemTime> {
+    let captured = match source {
+        DateSource::Capture => Exif::read(path).and_then(|exif| exif.capture_time()),
+        DateSource::Modified => None,
+    };
+    captured.or_else(|| {
+        fs::metadata(archive::backing_file(path))
+            .ok()?
+            .modified()
+            .ok()
+    })
+}
+
+/// Number of files excluded by each filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    pub too_small: usize,
+    pub too_large: usize,
+    pub too_narrow: usize,
+    pub too_short: usize,
+    /// Files dated before `--newer-than`.
+    pub too_old: usize,
+    /// Files dated at or after `--older-than`.
+    pub too_new: usize,
+    /// Files whose contents are not in a known image format.
+    pub not_images: usize,
+}
+
+impl fmt::Display for FilterStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = [
+            (self.too_small, "smaller than --min-size"),
+            (self.too_large, "larger than --max-size"),
+            (self.too_narrow, "narrower than --min-width"),
+            (self.too_short, "shorter than --min-height"),
+            (self.too_old, "dated before --newer-than"),
+            (self.too_new, "dated after --older-than"),
+            (self.not_images, "that are not images"),
+        ];
+        for (count, reason) in counts.iter().filter(|(count, _)| *count > 0) {
+            writeln!(f, "Skipped {} files {}.", count, reason)?;
+        }
+        Ok(())
+    }
+}
+
+enum Outcome {
+    Keep,
+    TooSmall,
+    TooLarge,
+    TooNarrow,
+    TooShort,
+    TooOld,
+    TooNew,
+    NotAnImage,
+}
+
+fn check(path: &Path, options: &FilterOptions) -> Outcome {
+    if options.min_size.is_some() || options.max_size.is_some() {
+        if let Ok(size) = archive::size(path) {
+            if options.min_size.is_some_and(|min| size < min) {
+                return Outcome::TooSmall;
+            }
+            if options.max_size.is_some_and(|max| size > max) {
+                return Outcome::TooLarge;
+            }
+        }
+    }
+    // Raw files are measured by their previews, drawings by the size they
+    // are rasterized at, and HEIF images and videos by what an external
+    // program decodes, which are only known once decoded, so only their
+    // dates are checked here.
+    let format = match decode::format_of(path) {
+        _ if raw::is_raw(path)
+            || decode::is_vector(path)
+            || decode::is_heif(path)
+            || decode::is_video(path) =>
+        {
+            None
+        }
+        Some(format) => Some(format),
+        // Kept so that hashing reports them as errors.
+        None if decode::undecodable_format(path).is_some() => return Outcome::Keep,
+        None => return Outcome::NotAnImage,
+    };
+    if options.checks_date() {
+        if let Some(date) = date_of(path, options.date_source) {
+            if options.newer_than.is_some_and(|newer| date < newer) {
+                return Outcome::TooOld;
+            }
+            if options.older_than.is_some_and(|older| date >= older) {
+                return Outcome::TooNew;
+            }
+        }
+    }
+    if let Some(format) = format.filter(|_| options.checks_dimensions()) {
+        let dimensions = archive::open(path)
+            .map(|source| ImageReader::with_format(source, format).into_dimensions());
+        if let Ok(Ok((width, height))) = dimensions {
+            if options.min_width.is_some_and(|min| width < min) {
+                return Outcome::TooNarrow;
+            }
+            if options.min_height.is_some_and(|min| height < min) {
+                return Outcome::TooShort;
+            }
+        }
+    }
+    Outcome::Keep
+}
+
+/// Drop the files of `paths` rejected by `options`, keeping their order.
+pub fn apply(paths: Vec<PathBuf>, options: &FilterOptions) -> (Vec<PathBuf>, FilterStats) {
+    let outcomes: Vec<Outcome> = paths.par_iter().map(|path| check(path, options)).collect();
+
+    let mut stats = FilterStats::default();
+    let kept = paths
+        .into_iter()
+        .zip(outcomes)
+        .filter_map(|(path, outcome)| {
+            match outcome {
+                Outcome::Keep => return Some(path),
+                Outcome::TooSmall => stats.too_small += 1,
+                Outcome::TooLarge => stats.too_large += 1,
+                Outcome::TooNarrow => stats.too_narrow += 1,
+                Outcome::TooShort => stats.too_short += 1,
+                Outcome::TooOld => stats.too_old += 1,
+                Outcome::TooNew => stats.too_new += 1,
+                Outcome::NotAnImage => stats.not_images += 1,
+            }
+            None
+        })
+        .collect();
+    (kept, stats)
+}
+
+/// Parse a file size such as `500`, `20KB`, `1.5MB` or `2GiB` into bytes.
+///
+/// Decimal units (`KB`, `MB`, `GB`) are powers of 1000 and binary units
+/// (`KiB`, `MiB`, `GiB`) powers of 1024. A single letter (`K`, `M`, `G`)
+/// is taken as decimal.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{}`", value))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(format!("unknown size unit `{}`", unit)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse a resolution given as `WIDTHxHEIGHT`, or a single number for a
+/// square, for `--min-resolution`.
+pub fn parse_resolution(value: &str) -> Result<Dimensions, String> {
+    let value = value.trim().to_ascii_lowercase();
+    let (width, height) = value.split_once('x').unwrap_or((&value, &value));
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) => Ok(Dimensions { width, height }),
+        _ => Err(format!("`{}` is not a resolution such as 512x512", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_apply_counts_each_filter() {
+        let dir = tempdir().unwrap();
+        let icon = dir.path().join("icon.png");
+        GrayImage::new(16, 16).save(&icon).unwrap();
+        let photo = dir.path().join("photo.png");
+        GrayImage::new(200, 32).save(&photo).unwrap();
+        let empty = dir.path().join("empty.png");
+        fs::write(&empty, b"").unwrap();
+
+        let options = FilterOptions {
+            min_size: Some(1),
+            min_width: Some(64),
+            min_height: Some(64),
+            ..FilterOptions::default()
+        };
+        let (kept, stats) = apply(vec![icon, photo, empty], &options);
+        assert!(kept.is_empty());
+        assert_eq!(
+            stats,
+            FilterStats {
+                too_small: 1,
+                too_large: 0,
+                too_narrow: 1,
+                too_short: 1,
+                too_old: 0,
+                too_new: 0,
+                not_images: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_filters_by_date() {
+        let dir = tempdir().unwrap();
+        let photo = dir.path().join("photo.png");
+        GrayImage::new(16, 16).save(&photo).unwrap();
+        let hour = std::time::Duration::from_secs(3600);
+
+        let options = FilterOptions {
+            newer_than: Some(SystemTime::now() - hour),
+            older_than: Some(SystemTime::now() + hour),
+            date_source: DateSource::Capture,
+            ..FilterOptions::default()
+        };
+        assert_eq!(apply(vec![photo.clone()], &options).0, vec![photo.clone()]);
+
+        let options = FilterOptions {
+            older_than: Some(SystemTime::now() - hour),
+            ..FilterOptions::default()
+        };
+        let (kept, stats) = apply(vec![photo], &options);
+        assert!(kept.is_empty());
+        assert_eq!(stats.too_new, 1);
+    }
+
+    #[test]
+    fn test_apply_sniffs_image_contents() {
+        let dir = tempdir().unwrap();
+        let misnamed = dir.path().join("photo.jpg");
+        GrayImage::new(16, 16)
+            .save_with_format(&misnamed, image::ImageFormat::Png)
+            .unwrap();
+        let notes = dir.path().join("notes.png");
+        fs::write(&notes, b"not an image at all").unwrap();
+
+        let (kept, stats) = apply(vec![misnamed.clone(), notes], &FilterOptions::default());
+        assert_eq!(kept, vec![misnamed]);
+        assert_eq!(stats.not_images, 1);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("20KB"), Ok(20_000));
+        assert_eq!(parse_size("1.5M"), Ok(1_500_000));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("5XB").is_err());
+
+        let square = Dimensions {
+            width: 512,
+            height: 512,
+        };
+        assert_eq!(parse_resolution("512x512"), Ok(square));
+        assert_eq!(parse_resolution("512"), Ok(square));
+        assert!(parse_resolution("512 by 512").is_err());
+    }
+}
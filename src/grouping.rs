@@ -0,0 +1,214 @@
+use crate::store::ImageSource;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Strategy used to turn pairwise matches into duplicate groups.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Grouping {
+    /// Single pass that groups every image with the first earlier image it matches.
+    /// Results depend on the order in which images are visited.
+    #[default]
+    Greedy,
+    /// Connected components of the match graph, independent of visiting order.
+    /// Transitive chains are merged, so two members may be far apart.
+    Connected,
+    /// Complete linkage: an image joins a group only if it matches every
+    /// member, so all pairwise distances within a group are under the
+    /// threshold. The most conservative choice for automated deletion.
+    Complete,
+}
+
+/// Disjoint-set forest with path compression and union by size.
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        UnionFind {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+/// Neighbours of every image according to `pairs`, in ascending order.
+fn adjacency(len: usize, pairs: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let mut neighbours: Vec<Vec<usize>> = vec![Vec::new(); len];
+    for &(i, j) in pairs {
+        neighbours[i].push(j);
+        neighbours[j].push(i);
+    }
+    for list in neighbours.iter_mut() {
+        list.sort_unstable();
+    }
+    neighbours
+}
+
+/// Group images using the greedy single-pass strategy.
+///
+/// `pairs` are the matching image pairs, as indices into `images`. Groups
+/// are returned as indices into `images`.
+pub fn greedy<S: ImageSource + ?Sized>(images: &S, pairs: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let neighbours = adjacency(images.len(), pairs);
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut processed = vec![false; images.len()];
+
+    for i in 0..images.len() {
+        if processed[i] {
+            continue;
+        }
+
+        let mut current_group: Vec<usize> = vec![i];
+
+        for &j in neighbours[i].iter().filter(|&&j| j > i) {
+            if !processed[j] {
+                current_group.push(j);
+                processed[j] = true;
+            }
+        }
+
+        if current_group.len() > 1 {
+            groups.push(current_group);
+            processed[i] = true;
+        }
+    }
+
+    groups
+}
+
+/// Connected components of the match graph with at least two members,
+/// ordered by their smallest index.
+fn components(len: usize, pairs: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let mut forest = UnionFind::new(len);
+    for &(i, j) in pairs {
+        forest.union(i, j);
+    }
+
+    // Keyed by the smallest index of each component to keep output order stable.
+    let mut components: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut roots: Vec<Option<usize>> = vec![None; len];
+    for i in 0..len {
+        let root = forest.find(i);
+        let key = *roots[root].get_or_insert(i);
+        components.entry(key).or_default().push(i);
+    }
+
+    components.into_values().filter(|m| m.len() > 1).collect()
+}
+
+/// Group images as connected components of the match graph.
+///
+/// Groups are returned as indices into `images`.
+pub fn connected<S: ImageSource + ?Sized>(images: &S, pairs: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    components(images.len(), pairs)
+}
+
+/// Group images so that every pair of images within a group is a match.
+///
+/// Each connected component is split by visiting its images in path order,
+/// so the result does not depend on the order of `images`. Groups are
+/// returned as indices into `images`.
+pub fn complete<S: ImageSource + ?Sized>(images: &S, pairs: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let edges: HashSet<(usize, usize)> = pairs.iter().copied().collect();
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for members in components(images.len(), pairs) {
+        for mut cluster in complete_clusters(images, members, &edges)
+            .into_iter()
+            .filter(|c| c.len() > 1)
+        {
+            cluster.sort_unstable();
+            groups.push(cluster);
+        }
+    }
+
+    groups
+}
+
+/// Split a component into clusters in which every pair of members is an edge.
+fn complete_clusters<S: ImageSource + ?Sized>(
+    images: &S,
+    mut members: Vec<usize>,
+    edges: &HashSet<(usize, usize)>,
+) -> Vec<Vec<usize>> {
+    members.sort_by(|&a, &b| images.path(a).cmp(images.path(b)));
+    let linked = |a: usize, b: usize| edges.contains(&(a.min(b), a.max(b)));
+
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut assigned = vec![false; members.len()];
+    for start in 0..members.len() {
+        if assigned[start] {
+            continue;
+        }
+        assigned[start] = true;
+        let mut cluster = vec![members[start]];
+        for candidate in (start + 1)..members.len() {
+            if assigned[candidate] {
+                continue;
+            }
+            if cluster.iter().all(|&m| linked(m, members[candidate])) {
+                assigned[candidate] = true;
+                cluster.push(members[candidate]);
+            }
+        }
+        clusters.push(cluster);
+    }
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching::candidate_pairs;
+    use crate::models::ImageInfo;
+    use image_hasher::ImageHash;
+    use std::path::PathBuf;
+
+    fn image(name: &str, bytes: &[u8]) -> ImageInfo {
+        ImageInfo::new(PathBuf::from(name), ImageHash::from_bytes(bytes).unwrap())
+    }
+
+    #[test]
+    fn test_connected_merges_chains() {
+        // a~b and b~c are within distance 2, while a and c are 4 bits apart.
+        let a = image("a.png", &[0b0000_0000]);
+        let b = image("b.png", &[0b0000_0011]);
+        let c = image("c.png", &[0b0000_1111]);
+
+        let forward = [a.clone(), b.clone(), c.clone()];
+        let forward = connected(&forward[..], &candidate_pairs(&forward[..], 3));
+        let backward = [c.clone(), b.clone(), a.clone()];
+        let backward = connected(&backward[..], &candidate_pairs(&backward[..], 3));
+        assert_eq!(forward, vec![vec![0, 1, 2]]);
+        assert_eq!(backward, vec![vec![0, 1, 2]]);
+
+        let images = [c, b, a];
+        let groups = complete(&images[..], &candidate_pairs(&images[..], 3));
+        assert_eq!(groups, vec![vec![1, 2]]);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::distance;
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, Luma};
+use image_hasher::{HashAlg, Hasher, HasherConfig, ImageHash};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Perceptual hash algorithm used to fingerprint images.
+///
+/// See [`image_hasher::HashAlg`] for a description of each algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    Mean,
+    #[default]
+    Gradient,
+    DoubleGradient,
+    Blockhash,
+    VertGradient,
+}
+
+impl From<HashAlgorithm> for HashAlg {
+    fn from(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Mean => HashAlg::Mean,
+            HashAlgorithm::Gradient => HashAlg::Gradient,
+            HashAlgorithm::DoubleGradient => HashAlg::DoubleGradient,
+            HashAlgorithm::Blockhash => HashAlg::Blockhash,
+            HashAlgorithm::VertGradient => HashAlg::VertGradient,
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Color planes hashed on their own, the hash of an image being the hashes
+/// of its planes one after the other.
+///
+/// Distances between such hashes add up the distances of every plane, so
+/// images differing only in color grading are told apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Channels {
+    /// A single hash of the grayscale image.
+    #[default]
+    Luma,
+    /// One hash each of the red, green and blue channels.
+    Rgb,
+    /// One hash of the grayscale image and one of each chroma plane.
+    Ycbcr,
+}
+
+impl Channels {
+    /// Number of hashes making up the hash of an image.
+    pub fn planes(self) -> u32 {
+        match self {
+            Channels::Luma => 1,
+            Channels::Rgb | Channels::Ycbcr => 3,
+        }
+    }
+}
+
+impl fmt::Display for Channels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Filter used to shrink images to the hash size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    #[default]
+    Lanczos,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Processing applied to images before they are hashed.
+///
+/// The default leaves images as decoded and shrinks them with Lanczos
+/// filtering, as `image_hasher` does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preprocessing {
+    #[serde(default)]
+    pub resize_filter: ResizeFilter,
+    /// Standard deviation of a gaussian blur, in pixels of the decoded
+    /// image, smoothing away noise and compression artifacts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blur: Option<f32>,
+    /// Stretch the levels of every hashed plane to the full range, so that
+    /// copies differing in contrast or exposure hash alike.
+    #[serde(default)]
+    pub normalize_contrast: bool,
+}
+
+impl Preprocessing {
+    /// Suffix of the cache settings key for hashes computed with these
+    /// settings, empty for the default ones.
+    pub fn settings_suffix(&self) -> String {
+        let mut suffix = String::new();
+        if self.resize_filter != ResizeFilter::default() {
+            suffix.push_str(&format!("-{}", self.resize_filter));
+        }
+        if let Some(sigma) = self.blur {
+            suffix.push_str(&format!("-blur{}", sigma));
+        }
+        if self.normalize_contrast {
+            suffix.push_str("-normalized");
+        }
+        suffix
+    }
+
+    /// Whether images are changed before the hasher sees them.
+    fn alters_pixels(&self) -> bool {
+        self.blur.is_some() || self.normalize_contrast
+    }
+
+    fn apply(&self, mut plane: GrayImage) -> GrayImage {
+        if let Some(sigma) = self.blur {
+            plane = imageops::blur(&plane, sigma);
+        }
+        if self.normalize_contrast {
+            stretch_levels(&mut plane);
+        }
+        plane
+    }
+}
+
+/// Map the darkest pixel of `plane` to black and the brightest to white.
+fn stretch_levels(plane: &mut GrayImage) {
+    let (low, high) = plane
+        .pixels()
+        .fold((u8::MAX, u8::MIN), |(low, high), pixel| {
+            (low.min(pixel.0[0]), high.max(pixel.0[0]))
+        });
+    if high <= low {
+        return;
+    }
+    let range = (high - low) as u32;
+    for pixel in plane.pixels_mut() {
+        pixel.0[0] = ((pixel.0[0] - low) as u32 * 255 / range) as u8;
+    }
+}
+
+/// A perceptual hash algorithm, fingerprinting images so that similar
+/// images get close hashes.
+///
+/// The hashing pipeline only sees images through this trait, so library
+/// users can match images with their own algorithm by setting
+/// `DeduplicateOptions::hasher`. Hashes of any length can be built with
+/// [`ImageHash::from_bytes`].
+pub trait PerceptualHasher: Send + Sync + fmt::Debug {
+    /// Identifier of the algorithm and its settings, keying cached hashes.
+    /// Two hashers sharing a name must produce the same hashes.
+    fn name(&self) -> String;
+
+    fn hash_image(&self, img: &DynamicImage) -> ImageHash;
+
+    /// Distance between two hashes given as raw bytes, compared against the
+    /// duplicate threshold. Defaults to the Hamming distance.
+    fn distance(&self, a: &[u8], b: &[u8]) -> u32 {
+        distance::hamming(a, b)
+    }
+
+    /// Number of bits in the hashes, against which `--threshold-pct` is
+    /// resolved.
+    fn hash_bits(&self) -> u32 {
+        let probe = DynamicImage::new_luma8(64, 64);
+        self.hash_image(&probe).as_bytes().len() as u32 * 8
+    }
+
+    /// Whether the hashes are vectors of little-endian `f32` values, which
+    /// `--metric cosine` compares, rather than bits.
+    fn is_vector(&self) -> bool {
+        false
+    }
+}
+
+/// Perceptual hasher for the planes chosen by [`Channels`].
+pub struct ImageHasher {
+    hasher: Hasher,
+    algorithm: HashAlgorithm,
+    hash_size: u32,
+    channels: Channels,
+    preprocessing: Preprocessing,
+}
+
+impl fmt::Debug for ImageHasher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImageHasher")
+            .field("algorithm", &self.algorithm)
+            .field("hash_size", &self.hash_size)
+            .field("channels", &self.channels)
+            .field("preprocessing", &self.preprocessing)
+            .finish()
+    }
+}
+
+impl ImageHasher {
+    pub fn new(algorithm: HashAlgorithm, hash_size: u32, channels: Channels) -> ImageHasher {
+        ImageHasher::with_preprocessing(algorithm, hash_size, channels, Preprocessing::default())
+    }
+
+    pub fn with_preprocessing(
+        algorithm: HashAlgorithm,
+        hash_size: u32,
+        channels: Channels,
+        preprocessing: Preprocessing,
+    ) -> ImageHasher {
+        let hasher = HasherConfig::new()
+            .hash_alg(algorithm.into())
+            .hash_size(hash_size, hash_size)
+            .resize_filter(preprocessing.resize_filter.into())
+            .to_hasher();
+        ImageHasher {
+            hasher,
+            algorithm,
+            hash_size,
+            channels,
+            preprocessing,
+        }
+    }
+
+    pub fn hash_image(&self, img: &DynamicImage) -> ImageHash {
+        if self.channels == Channels::Luma && !self.preprocessing.alters_pixels() {
+            return self.hasher.hash_image(img);
+        }
+        let bytes: Vec<u8> = planes(img, self.channels)
+            .into_iter()
+            .flat_map(|plane| {
+                let plane = self.preprocessing.apply(plane);
+                let hash = self.hasher.hash_image(&DynamicImage::ImageLuma8(plane));
+                hash.as_bytes().to_vec()
+            })
+            .collect();
+        ImageHash::from_bytes(&bytes).expect("hashes of any length can be built")
+    }
+}
+
+impl PerceptualHasher for ImageHasher {
+    fn name(&self) -> String {
+        let mut name = settings_key(self.algorithm, self.hash_size);
+        if self.channels != Channels::Luma {
+            name.push_str(&format!("-{}", self.channels));
+        }
+        name.push_str(&self.preprocessing.settings_suffix());
+        name
+    }
+
+    fn hash_image(&self, img: &DynamicImage) -> ImageHash {
+        ImageHasher::hash_image(self, img)
+    }
+
+    fn hash_bits(&self) -> u32 {
+        hash_bits(self.algorithm, self.hash_size, self.channels)
+    }
+}
+
+/// The planes of `img` hashed with `channels`.
+fn planes(img: &DynamicImage, channels: Channels) -> Vec<GrayImage> {
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let plane = |weights: [f32; 3], offset: f32| {
+        GrayImage::from_fn(width, height, |x, y| {
+            let [r, g, b] = rgb.get_pixel(x, y).0.map(f32::from);
+            let value = offset + weights[0] * r + weights[1] * g + weights[2] * b;
+            Luma([value.round().clamp(0.0, 255.0) as u8])
+        })
+    };
+    match channels {
+        Channels::Luma => vec![img.to_luma8()],
+        Channels::Rgb => vec![
+            plane([1.0, 0.0, 0.0], 0.0),
+            plane([0.0, 1.0, 0.0], 0.0),
+            plane([0.0, 0.0, 1.0], 0.0),
+        ],
+        Channels::Ycbcr => vec![
+            plane([0.299, 0.587, 0.114], 0.0),
+            plane([-0.168_736, -0.331_264, 0.5], 128.0),
+            plane([0.5, -0.418_688, -0.081_312], 128.0),
+        ],
+    }
+}
+
+/// Build a hasher producing square hashes of `hash_size` x `hash_size` bits.
+pub fn build_hasher(algorithm: HashAlgorithm, hash_size: u32) -> ImageHasher {
+    ImageHasher::new(algorithm, hash_size, Channels::Luma)
+}
+
+/// Identifier of the hasher settings, used to key cached hashes.
+pub fn settings_key(algorithm: HashAlgorithm, hash_size: u32) -> String {
+    format!("{}-{}", algorithm, hash_size)
+}
+
+/// Number of bits in the hashes built by [`ImageHasher::new`].
+pub fn hash_bits(algorithm: HashAlgorithm, hash_size: u32, channels: Channels) -> u32 {
+    let probe = DynamicImage::new_luma8(64, 64);
+    let hash = build_hasher(algorithm, hash_size).hash_image(&probe);
+    hash.as_bytes().len() as u32 * 8 * channels.planes()
+}
+
+/// Smallest distance threshold rejecting every pair of hashes differing in
+/// more than `fraction` of their `hash_bits` bits.
+pub fn threshold_from_pct(fraction: f64, hash_bits: u32) -> u32 {
+    (fraction * hash_bits as f64).ceil() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_scales_with_hash_size() {
+        assert_eq!(hash_bits(HashAlgorithm::Gradient, 8, Channels::Luma), 64);
+        assert_eq!(hash_bits(HashAlgorithm::Gradient, 16, Channels::Luma), 256);
+        assert_eq!(threshold_from_pct(0.05, 64), 4);
+        assert_eq!(threshold_from_pct(0.05, 256), 13);
+        assert_eq!(threshold_from_pct(0.25, 64), 16);
+    }
+
+    #[test]
+    fn test_rgb_channels_tell_color_grades_apart() {
+        // Pure red and a green of the same brightness, swapped sides.
+        let split = |left: [u8; 3], right: [u8; 3]| {
+            DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, _| {
+                image::Rgb(if x < 32 { left } else { right })
+            }))
+        };
+        let (red, green) = ([255, 0, 0], [0, 76, 0]);
+        let (warm, cool) = (split(red, green), split(green, red));
+        let luma = build_hasher(HashAlgorithm::Mean, 8);
+        let rgb = ImageHasher::new(HashAlgorithm::Mean, 8, Channels::Rgb);
+
+        let warm_hash = rgb.hash_image(&warm);
+        assert_eq!(warm_hash.as_bytes().len() * 8, 3 * 64);
+        assert_eq!(luma.hash_image(&warm).dist(&luma.hash_image(&cool)), 0);
+        assert!(warm_hash.dist(&rgb.hash_image(&cool)) >= 64);
+    }
+
+    #[test]
+    fn test_normalized_contrast_restores_faded_copy() {
+        let gradient = |low: u8, high: u8| {
+            DynamicImage::ImageLuma8(GrayImage::from_fn(64, 64, |x, y| {
+                let step = (x / 8 + y / 8) % 3;
+                Luma([low + (step * (high - low) as u32 / 2) as u8])
+            }))
+        };
+        let (crisp, faded) = (gradient(0, 250), gradient(100, 140));
+        let normalized = Preprocessing {
+            normalize_contrast: true,
+            ..Preprocessing::default()
+        };
+        assert_eq!(
+            normalized.apply(crisp.to_luma8()),
+            normalized.apply(faded.to_luma8())
+        );
+        assert_ne!(crisp.to_luma8(), faded.to_luma8());
+        assert_eq!(normalized.settings_suffix(), "-normalized");
+        assert_eq!(Preprocessing::default().settings_suffix(), "");
+    }
+}
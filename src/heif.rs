@@ -0,0 +1,96 @@
+use crate::archive;
+use crate::source;
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Program from libheif decoding the images, looked up on the `PATH`.
+const HEIF_CONVERT: &str = "heif-convert";
+
+/// Program decoding the images when libheif is not installed.
+const FFMPEG: &str = "ffmpeg";
+
+/// Number of images decoded so far, which names their temporary files.
+static DECODED: AtomicUsize = AtomicUsize::new(0);
+
+/// Decode the HEIF or AVIF image at `path` with `heif-convert`, or with
+/// `ffmpeg` if libheif is not installed.
+///
+/// Both turn the image upright as its container says, which HEIF requires
+/// of every reader.
+pub fn decode(path: &Path) -> ImageResult<DynamicImage> {
+    if archive::is_member(path) || source::is_remote(path) {
+        return Err(ImageError::IoError(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "HEIF images inside archives or remote locations are not decoded",
+        )));
+    }
+    match heif_convert(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => ffmpeg(path),
+        result => result,
+    }
+    .map_err(ImageError::IoError)
+}
+
+/// Decode the image at `path` with `heif-convert`, which only writes to a
+/// file, through a temporary PNG.
+fn heif_convert(path: &Path) -> io::Result<DynamicImage> {
+    let output = temporary_png();
+    let status = Command::new(HEIF_CONVERT)
+        .arg(path)
+        .arg(&output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let decoded = match status {
+        Ok(status) if status.success() => fs::read(&output).and_then(|data| load_png(&data)),
+        Ok(status) => Err(failed(HEIF_CONVERT, status)),
+        Err(err) => Err(err),
+    };
+    let _ = fs::remove_file(&output);
+    decoded
+}
+
+/// Decode the image at `path` with `ffmpeg`, which writes it to its output
+/// as a PNG.
+fn ffmpeg(path: &Path) -> io::Result<DynamicImage> {
+    let output = Command::new(FFMPEG)
+        .args(["-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("cannot run {} or {}: {}", HEIF_CONVERT, FFMPEG, err),
+            )
+        })?;
+    if !output.status.success() {
+        return Err(failed(FFMPEG, output.status));
+    }
+    load_png(&output.stdout)
+}
+
+/// A path in the temporary directory no other decoding writes to.
+fn temporary_png() -> PathBuf {
+    let count = DECODED.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("idar-heif-{}-{}.png", process::id(), count))
+}
+
+fn load_png(data: &[u8]) -> io::Result<DynamicImage> {
+    image::load_from_memory_with_format(data, ImageFormat::Png)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn failed(program: &str, status: process::ExitStatus) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} could not decode the image ({})", program, status),
+    )
+}
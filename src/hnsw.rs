@@ -0,0 +1,276 @@
+use crate::matching::SplitMix64;
+use rayon::prelude::*;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+/// Settings of a [`Hnsw`] index.
+///
+/// `m` is the number of neighbours kept per node on the upper layers (twice
+/// as many on the bottom layer). Larger `ef_construction` and `ef_search`
+/// values build a better graph and search it more thoroughly, at the cost
+/// of speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswOptions {
+    pub m: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+    pub seed: u64,
+}
+
+impl Default for HnswOptions {
+    fn default() -> Self {
+        HnswOptions {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+            seed: 0,
+        }
+    }
+}
+
+/// Distance used to order heaps; `f32` is not `Ord` by itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Distance(f32);
+
+impl Eq for Distance {}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Hierarchical navigable small world graph over float vectors, for
+/// approximate nearest-neighbour search by cosine similarity.
+///
+/// Vectors are normalized when inserted, so the distance between two nodes
+/// is `1 - cosine similarity`.
+pub struct Hnsw {
+    options: HnswOptions,
+    vectors: Vec<Vec<f32>>,
+    /// Neighbours of each node, on each layer the node belongs to.
+    neighbours: Vec<Vec<Vec<usize>>>,
+    entry: Option<usize>,
+    level_factor: f64,
+    rng: SplitMix64,
+}
+
+impl Hnsw {
+    pub fn new(options: HnswOptions) -> Hnsw {
+        let m = options.m.max(2);
+        Hnsw {
+            options: HnswOptions { m, ..options },
+            vectors: Vec::new(),
+            neighbours: Vec::new(),
+            entry: None,
+            level_factor: 1.0 / (m as f64).ln(),
+            rng: SplitMix64(options.seed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// Add `vector` to the index, returning its id. Ids are assigned in
+    /// insertion order, starting from 0.
+    pub fn insert(&mut self, vector: &[f32]) -> usize {
+        let id = self.vectors.len();
+        self.vectors.push(normalized(vector));
+        let level = self.random_level();
+        self.neighbours.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(id);
+            return id;
+        };
+        let top = self.neighbours[entry].len() - 1;
+        let query = self.vectors[id].clone();
+
+        let mut nearest = entry;
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(&query, nearest, 1, layer)[0].1;
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, nearest, self.options.ef_construction, layer);
+            let limit = self.max_neighbours(layer);
+            let selected: Vec<usize> = found.iter().take(limit).map(|&(_, n)| n).collect();
+            for &neighbour in &selected {
+                self.neighbours[neighbour][layer].push(id);
+                if self.neighbours[neighbour][layer].len() > limit {
+                    self.prune(neighbour, layer, limit);
+                }
+            }
+            self.neighbours[id][layer] = selected;
+            nearest = found[0].1;
+        }
+
+        if level > top {
+            self.entry = Some(id);
+        }
+        id
+    }
+
+    /// Ids and cosine similarities of about `k` indexed vectors nearest to
+    /// `query`, most similar first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(usize, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let query = normalized(query);
+        let top = self.neighbours[entry].len() - 1;
+
+        let mut nearest = entry;
+        for layer in (1..=top).rev() {
+            nearest = self.search_layer(&query, nearest, 1, layer)[0].1;
+        }
+        self.search_layer(&query, nearest, self.options.ef_search.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|(distance, id)| (id, 1.0 - distance.0))
+            .collect()
+    }
+
+    fn random_level(&mut self) -> usize {
+        // Uniform in (0, 1], so that the logarithm is finite.
+        let uniform = ((self.rng.next() >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        (-uniform.ln() * self.level_factor) as usize
+    }
+
+    fn max_neighbours(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.options.m * 2
+        } else {
+            self.options.m
+        }
+    }
+
+    fn distance(&self, query: &[f32], id: usize) -> Distance {
+        Distance(1.0 - dot(query, &self.vectors[id]))
+    }
+
+    /// Keep only the `limit` closest neighbours of `id` on `layer`.
+    fn prune(&mut self, id: usize, layer: usize, limit: usize) {
+        let vector = &self.vectors[id];
+        let mut scored: Vec<(Distance, usize)> = self.neighbours[id][layer]
+            .iter()
+            .map(|&n| (Distance(1.0 - dot(vector, &self.vectors[n])), n))
+            .collect();
+        scored.sort_unstable();
+        self.neighbours[id][layer] = scored.into_iter().take(limit).map(|(_, n)| n).collect();
+    }
+
+    /// Best-first search of one layer, returning up to `ef` nodes sorted by
+    /// distance to `query`.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry: usize,
+        ef: usize,
+        layer: usize,
+    ) -> Vec<(Distance, usize)> {
+        let ef = ef.max(1);
+        let mut visited: HashSet<usize> = HashSet::from([entry]);
+        let start = (self.distance(query, entry), entry);
+        let mut candidates: BinaryHeap<Reverse<(Distance, usize)>> =
+            BinaryHeap::from([Reverse(start)]);
+        let mut results: BinaryHeap<(Distance, usize)> = BinaryHeap::from([start]);
+
+        while let Some(Reverse((distance, id))) = candidates.pop() {
+            if results.len() >= ef && distance > results.peek().unwrap().0 {
+                break;
+            }
+            for &neighbour in &self.neighbours[id][layer] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let distance = self.distance(query, neighbour);
+                if results.len() < ef || distance < results.peek().unwrap().0 {
+                    candidates.push(Reverse((distance, neighbour)));
+                    results.push((distance, neighbour));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+/// Pairs of indices `(i, j)`, with `i < j`, of `vectors` whose cosine
+/// similarity is at least `min_similarity`. Pairs are sorted.
+///
+/// Each vector is looked up in an HNSW index of all of them, so some
+/// similar pairs may be missed, but every returned pair is within the
+/// threshold. At most `options.ef_search` neighbours are considered per
+/// vector.
+pub fn similar_pairs(
+    vectors: &[Vec<f32>],
+    min_similarity: f32,
+    options: &HnswOptions,
+) -> Vec<(usize, usize)> {
+    let mut index = Hnsw::new(*options);
+    for vector in vectors {
+        index.insert(vector);
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..vectors.len())
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            index
+                .search(&vectors[i], options.ef_search)
+                .into_iter()
+                .filter(move |&(j, similarity)| j != i && similarity >= min_similarity)
+                .map(move |(j, _)| (i.min(j), i.max(j)))
+        })
+        .collect();
+    pairs.par_sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similar_pairs_finds_near_copies() {
+        let mut rng = SplitMix64(7);
+        let mut random = || (rng.next() % 2001) as f32 / 1000.0 - 1.0;
+        let mut vectors: Vec<Vec<f32>> = (0..300)
+            .map(|_| (0..32).map(|_| random()).collect())
+            .collect();
+        // Near copies of the first 20 vectors.
+        for i in 0..20 {
+            let copy: Vec<f32> = vectors[i].iter().map(|x| x + random() * 0.01).collect();
+            vectors.push(copy);
+        }
+
+        let pairs = similar_pairs(&vectors, 0.99, &HnswOptions::default());
+        let expected: Vec<(usize, usize)> = (0..20).map(|i| (i, 300 + i)).collect();
+        assert_eq!(pairs, expected);
+    }
+}
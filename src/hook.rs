@@ -0,0 +1,104 @@
+use crate::errors::AppError;
+use crate::models::ReportSummary;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+/// Run the shell `command` given with `--on-complete` once the report at
+/// `report` is saved, returning how it exited.
+///
+/// The command learns about the run from environment variables:
+/// `IDAR_REPORT` holds the path of the report, and `IDAR_GROUPS`,
+/// `IDAR_EXACT_GROUPS`, `IDAR_DUPLICATES`, `IDAR_RECLAIMABLE_BYTES`,
+/// `IDAR_ERRORS`, `IDAR_ACTIONS` and `IDAR_FAILED_ACTIONS` its totals. It
+/// runs with `sh -c`, or `cmd /C` on Windows, sharing the terminal of idar.
+pub fn on_complete(
+    command: &str,
+    report: &Path,
+    summary: &ReportSummary,
+) -> Result<ExitStatus, AppError> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let totals = [
+        ("IDAR_GROUPS", summary.groups as u64),
+        ("IDAR_EXACT_GROUPS", summary.exact_groups as u64),
+        ("IDAR_DUPLICATES", summary.total_duplicates as u64),
+        ("IDAR_RECLAIMABLE_BYTES", summary.reclaimable_bytes),
+        ("IDAR_ERRORS", summary.errors as u64),
+        ("IDAR_ACTIONS", summary.actions as u64),
+        ("IDAR_FAILED_ACTIONS", summary.failed_actions as u64),
+    ];
+    shell
+        .arg(command)
+        .env("IDAR_REPORT", std::path::absolute(report)?)
+        .envs(totals.map(|(name, total)| (name, total.to_string())))
+        .status()
+        .map_err(|err| {
+            AppError::IoError(io::Error::new(
+                err.kind(),
+                format!("cannot run the --on-complete command: {}", err),
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::{Channels, HashAlgorithm, Preprocessing};
+    use crate::models::DeduplicationMetadata;
+    use crate::verify::Verification;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_on_complete_sees_the_report_and_its_totals() {
+        let dir = tempdir().unwrap();
+        let report = dir.path().join("dedup_report.json");
+        let summary = ReportSummary {
+            metadata: DeduplicationMetadata {
+                directory_path: PathBuf::from("/images"),
+                roots: vec![PathBuf::from("/images")],
+                cross_root: false,
+                threshold: 10,
+                threshold_pct: 10.0 / 64.0,
+                hash_bits: 64,
+                algorithm: HashAlgorithm::Gradient,
+                channels: Channels::Luma,
+                preprocessing: Preprocessing::default(),
+                verification: Verification::None,
+                sample: None,
+                min_resolution: None,
+                scanned: None,
+            },
+            groups: 2,
+            exact_groups: 1,
+            total_duplicates: 3,
+            reclaimable_bytes: 4096,
+            below_resolution: 0,
+            errors: 0,
+            crops: 0,
+            bursts: 0,
+            actions: 0,
+            failed_actions: 0,
+        };
+        let output = dir.path().join("out.txt");
+        let command = format!(
+            "echo \"$IDAR_REPORT $IDAR_GROUPS $IDAR_DUPLICATES $IDAR_RECLAIMABLE_BYTES\" > {:?}; exit 2",
+            output
+        );
+
+        let status = on_complete(&command, &report, &summary).unwrap();
+        assert_eq!(status.code(), Some(2));
+        let expected = format!("{} 2 3 4096\n", report.display());
+        assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+    }
+}
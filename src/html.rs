@@ -0,0 +1,241 @@
+use crate::archive;
+use crate::decode::{self, DecodeOptions};
+use crate::errors::AppError;
+use crate::models::{
+    DeduplicationMetadata, DeduplicationReport, DuplicatesGroup, ReportSummary, SuggestedAction,
+};
+use crate::pages;
+use crate::report::item_rows;
+use crate::trash::encode_path;
+use image::codecs::jpeg::JpegEncoder;
+use indicatif::HumanBytes;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Longest side of the thumbnails embedded in an HTML report.
+const THUMBNAIL_SIZE: u32 = 240;
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; background: #fafafa; }
+h1 { margin-bottom: 0.2em; }
+.group { background: #fff; border: 1px solid #ddd; border-radius: 6px; margin: 1.5em 0; padding: 1em; }
+.group h2 { font-size: 1.1em; margin: 0 0 0.3em; }
+.kind { font-weight: normal; color: #666; }
+.items { display: flex; flex-wrap: wrap; gap: 1em; }
+figure { margin: 0; width: 260px; border: 2px solid #ccc; border-radius: 4px; padding: 8px; }
+figure.keep { border-color: #3a3; }
+figure.remove { border-color: #c33; }
+figure img, .missing { display: block; margin: 0 auto; max-width: 240px; max-height: 240px; }
+.missing { width: 240px; height: 120px; line-height: 120px; text-align: center; background: #eee; color: #888; }
+figcaption { font-size: 0.85em; margin-top: 0.5em; word-break: break-all; }
+dl { display: grid; grid-template-columns: auto 1fr; gap: 0 0.8em; margin: 0.4em 0 0; }
+dt { color: #666; }
+dd { margin: 0; }
+.action { font-weight: bold; text-transform: uppercase; font-size: 0.8em; }
+.keep .action { color: #3a3; }
+.remove .action { color: #c33; }
+footer pre { background: #fff; border: 1px solid #ddd; padding: 1em; }
+";
+
+/// Write the start of an HTML report describing the run of `metadata`.
+pub(crate) fn write_header(
+    out: &mut impl Write,
+    metadata: &DeduplicationMetadata,
+) -> io::Result<()> {
+    let roots: Vec<String> = metadata
+        .roots
+        .iter()
+        .map(|root| escape(&root.display().to_string()))
+        .collect();
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>Duplicates in {}</title>", roots.join(", "))?;
+    writeln!(out, "<style>\n{}</style>\n</head>\n<body>", STYLE)?;
+    writeln!(out, "<h1>Duplicates in {}</h1>", roots.join(", "))?;
+    writeln!(
+        out,
+        "<p>Matched by {} hashes within a distance of {}. Images outlined in green are kept \
+         by the keep strategy, and those in red would be removed.</p>",
+        metadata.algorithm, metadata.threshold
+    )?;
+    Ok(())
+}
+
+/// Write `group` to an HTML report, its items side by side with their
+/// thumbnails, sizes, dimensions and distances.
+pub(crate) fn write_group(out: &mut impl Write, group: &DuplicatesGroup) -> io::Result<()> {
+    let rows = item_rows(group);
+    let thumbnails: Vec<Option<String>> = rows
+        .par_iter()
+        .map(|row| thumbnail(&row.item.path))
+        .collect();
+    let kind = format!("{:?}", group.kind).to_lowercase();
+    writeln!(out, "<section class=\"group\" id=\"group-{}\">", group.id)?;
+    writeln!(
+        out,
+        "<h2>Group {} <span class=\"kind\">{}</span></h2>",
+        group.id, kind
+    )?;
+    writeln!(
+        out,
+        "<p>{} images, {} reclaimable</p>\n<div class=\"items\">",
+        group.items.len(),
+        HumanBytes(group.reclaimable_bytes)
+    )?;
+    for (row, thumbnail) in rows.iter().zip(thumbnails) {
+        let path = escape(&row.item.path.to_string_lossy());
+        let action = match row.item.suggested_action {
+            Some(SuggestedAction::Keep) => "keep",
+            Some(SuggestedAction::Remove) => "remove",
+            None => "",
+        };
+        writeln!(out, "<figure class=\"{}\">", action)?;
+        match thumbnail {
+            Some(data) => writeln!(
+                out,
+                "<img src=\"data:image/jpeg;base64,{}\" alt=\"{}\" loading=\"lazy\">",
+                data, path
+            )?,
+            None => writeln!(out, "<div class=\"missing\">No preview</div>")?,
+        }
+        writeln!(out, "<figcaption>")?;
+        let item = &row.item.path;
+        match std::path::absolute(item) {
+            Ok(absolute) if !archive::is_member(item) && !pages::is_page(item) => writeln!(
+                out,
+                "<a href=\"file://{}\">{}</a>",
+                escape(&encode_path(&absolute)),
+                path
+            )?,
+            _ => writeln!(out, "{}", path)?,
+        }
+        writeln!(out, "<dl>")?;
+        if let Some(size) = row.size {
+            writeln!(out, "<dt>Size</dt><dd>{}</dd>", HumanBytes(size))?;
+        }
+        if let Some(dimensions) = row.item.dimensions {
+            writeln!(out, "<dt>Dimensions</dt><dd>{}</dd>", dimensions)?;
+        }
+        writeln!(out, "<dt>Distance</dt><dd>{}</dd>", row.distance)?;
+        writeln!(out, "</dl>")?;
+        if !action.is_empty() {
+            writeln!(out, "<span class=\"action\">{}</span>", action)?;
+        }
+        writeln!(out, "</figcaption>\n</figure>")?;
+    }
+    writeln!(out, "</div>\n</section>")
+}
+
+/// Close an HTML report with the totals of `summary`.
+pub(crate) fn write_footer(out: &mut impl Write, summary: &ReportSummary) -> io::Result<()> {
+    writeln!(
+        out,
+        "<footer><pre>{}</pre></footer>\n</body>\n</html>",
+        escape(&summary.to_string())
+    )
+}
+
+/// Save `report` as a single HTML file at `path`.
+pub(crate) fn save(report: &DeduplicationReport, path: &Path) -> Result<(), AppError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_header(&mut out, &report.metadata)?;
+    for group in &report.groups {
+        write_group(&mut out, group)?;
+    }
+    write_footer(&mut out, &report.summary())?;
+    out.flush()?;
+    Ok(())
+}
+
+/// A JPEG thumbnail of the image at `path`, encoded in base64, or `None`
+/// if it cannot be decoded.
+fn thumbnail(path: &Path) -> Option<String> {
+    let options = DecodeOptions {
+        max_pixels: Some(u64::from(THUMBNAIL_SIZE * 2).pow(2)),
+        ..DecodeOptions::default()
+    };
+    let img = decode::open(path, &options).ok()?;
+    let rgb = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, 80)
+        .encode_image(&rgb)
+        .ok()?;
+    Some(base64(&data))
+}
+
+/// `data` in standard base64, padded with `=`.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// `text` with the characters that are special in HTML escaped.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+    use image::RgbImage;
+    use image_hasher::ImageHash;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_groups_show_thumbnails_and_details() {
+        assert_eq!(base64(b"idar"), "aWRhcg==");
+        assert_eq!(base64(b"ida"), "aWRh");
+
+        let dir = tempdir().unwrap();
+        let image = dir.path().join("a <b>.png");
+        RgbImage::new(480, 320).save(&image).unwrap();
+        let item = |path: &Path| ImageInfo {
+            suggested_action: Some(SuggestedAction::Remove),
+            ..ImageInfo::new(
+                path.to_path_buf(),
+                ImageHash::from_base64("DAIDBwMHAf8").unwrap(),
+            )
+        };
+        let group = DuplicatesGroup::new(vec![item(&image), item(&dir.path().join("gone.png"))]);
+
+        let mut out = Vec::new();
+        write_group(&mut out, &group).unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert_eq!(html.matches("<figure class=\"remove\">").count(), 2);
+        assert_eq!(html.matches("data:image/jpeg;base64,").count(), 1);
+        assert_eq!(html.matches("No preview").count(), 1);
+        assert!(html.contains("a &lt;b&gt;.png"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains(&format!("id=\"group-{}\"", group.id)));
+    }
+}
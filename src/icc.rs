@@ -0,0 +1,361 @@
+use image::{DynamicImage, Rgb, Rgba};
+
+/// Length of the header before the tag table.
+const HEADER_LEN: usize = 128;
+
+/// Entries of the table encoding linear light back to sRGB.
+const ENCODE_STEPS: usize = 4096;
+
+/// Conversion from D50 XYZ, the connection space of ICC profiles, to
+/// linear sRGB, adapted from D65 with the Bradford transform.
+const SRGB_FROM_XYZ: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_141, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+/// Tone curve of one channel, mapping an encoded value to linear light.
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+    Gamma(f32),
+    /// Values sampled at even steps from 0 to 1.
+    Table(Vec<f32>),
+    /// Parametric curve: `(a·x + b)^g + e` from `d` on, `c·x + f` below.
+    Parametric {
+        g: f32,
+        a: f32,
+        b: f32,
+        c: f32,
+        d: f32,
+        e: f32,
+        f: f32,
+    },
+}
+
+impl Curve {
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            Curve::Gamma(gamma) => x.powf(*gamma),
+            Curve::Table(table) => {
+                let position = x * (table.len() - 1) as f32;
+                let below = (position.floor() as usize).min(table.len() - 1);
+                let above = (below + 1).min(table.len() - 1);
+                let t = position - below as f32;
+                table[below] + (table[above] - table[below]) * t
+            }
+            Curve::Parametric {
+                g,
+                a,
+                b,
+                c,
+                d,
+                e,
+                f,
+            } => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        }
+    }
+}
+
+/// An RGB display profile made of a colorant matrix and a tone curve per
+/// channel, the kind cameras and editors embed for sRGB, Display P3 and
+/// Adobe RGB.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Columns are the D50 XYZ colours of the red, green and blue primaries.
+    to_xyz: [[f32; 3]; 3],
+    curves: [Curve; 3],
+}
+
+impl Profile {
+    /// Parse the ICC profile `data`, or return `None` if it is not an RGB
+    /// matrix profile, such as CMYK and lookup table profiles.
+    pub fn parse(data: &[u8]) -> Option<Profile> {
+        if data.get(16..20)? != b"RGB " || data.get(36..40)? != b"acsp" {
+            return None;
+        }
+        let count = read_u32(data, HEADER_LEN)? as usize;
+        let tag = |signature: &[u8; 4]| {
+            (0..count.min(1024)).find_map(|i| {
+                let entry = HEADER_LEN + 4 + i * 12;
+                if data.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = read_u32(data, entry + 4)? as usize;
+                let size = read_u32(data, entry + 8)? as usize;
+                data.get(offset..offset.checked_add(size)?)
+            })
+        };
+        let mut to_xyz = [[0.0; 3]; 3];
+        for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let xyz = read_xyz(tag(signature)?)?;
+            for row in 0..3 {
+                to_xyz[row][column] = xyz[row];
+            }
+        }
+        let curves = [
+            read_curve(tag(b"rTRC")?)?,
+            read_curve(tag(b"gTRC")?)?,
+            read_curve(tag(b"bTRC")?)?,
+        ];
+        Some(Profile { to_xyz, curves })
+    }
+
+    /// Whether the profile is sRGB, give or take rounding, so converting
+    /// with it would leave the pixels as they are.
+    fn is_srgb(&self) -> bool {
+        let matrix = multiply(&SRGB_FROM_XYZ, &self.to_xyz);
+        let identity = (0..3).all(|row| {
+            (0..3).all(|column| {
+                let expected = if row == column { 1.0 } else { 0.0 };
+                (matrix[row][column] - expected).abs() < 0.01
+            })
+        });
+        identity
+            && self.curves.iter().all(|curve| {
+                (0..=16).all(|step| {
+                    let x = step as f32 / 16.0;
+                    (curve.apply(x) - srgb_decode(x)).abs() < 0.5 / 255.0
+                })
+            })
+    }
+}
+
+/// Convert `img`, whose colours are described by the ICC profile `data`,
+/// to sRGB, so a wide-gamut export and its web copy have the same pixels.
+///
+/// Images without an RGB matrix profile, greyscale images and images
+/// already in sRGB are returned as they are. Colours outside the sRGB
+/// gamut are clipped.
+pub fn to_srgb(img: DynamicImage, data: &[u8]) -> DynamicImage {
+    let Some(profile) = Profile::parse(data).filter(|profile| !profile.is_srgb()) else {
+        return img;
+    };
+    let matrix = multiply(&SRGB_FROM_XYZ, &profile.to_xyz);
+    let decode: Vec<[f32; 256]> = profile
+        .curves
+        .iter()
+        .map(|curve| std::array::from_fn(|v| curve.apply(v as f32 / 255.0)))
+        .collect();
+    let encode: Vec<u8> = (0..ENCODE_STEPS)
+        .map(|step| {
+            let linear = step as f32 / (ENCODE_STEPS - 1) as f32;
+            (srgb_encode(linear) * 255.0).round() as u8
+        })
+        .collect();
+    let convert = |rgb: [u8; 3]| -> [u8; 3] {
+        let linear = [
+            decode[0][rgb[0] as usize],
+            decode[1][rgb[1] as usize],
+            decode[2][rgb[2] as usize],
+        ];
+        std::array::from_fn(|row| {
+            let value = (0..3)
+                .map(|column| matrix[row][column] * linear[column])
+                .sum::<f32>();
+            encode[(value.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32).round() as usize]
+        })
+    };
+    match img {
+        DynamicImage::ImageRgb8(mut rgb) => {
+            for Rgb(pixel) in rgb.pixels_mut() {
+                *pixel = convert(*pixel);
+            }
+            DynamicImage::ImageRgb8(rgb)
+        }
+        DynamicImage::ImageRgba8(mut rgba) => {
+            for Rgba(pixel) in rgba.pixels_mut() {
+                let [r, g, b] = convert([pixel[0], pixel[1], pixel[2]]);
+                *pixel = [r, g, b, pixel[3]];
+            }
+            DynamicImage::ImageRgba8(rgba)
+        }
+        img => img,
+    }
+}
+
+fn multiply(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    std::array::from_fn(|row| {
+        std::array::from_fn(|column| (0..3).map(|k| a[row][k] * b[k][column]).sum())
+    })
+}
+
+fn srgb_decode(x: f32) -> f32 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_encode(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Read a signed 15.16 fixed point number.
+fn read_fixed(data: &[u8], offset: usize) -> Option<f32> {
+    Some(read_u32(data, offset)? as i32 as f32 / 65536.0)
+}
+
+fn read_xyz(data: &[u8]) -> Option<[f32; 3]> {
+    if data.get(..4)? != b"XYZ " {
+        return None;
+    }
+    Some([
+        read_fixed(data, 8)?,
+        read_fixed(data, 12)?,
+        read_fixed(data, 16)?,
+    ])
+}
+
+fn read_curve(data: &[u8]) -> Option<Curve> {
+    match data.get(..4)? {
+        b"curv" => {
+            let count = read_u32(data, 8)? as usize;
+            let entry = |i: usize| {
+                let bytes = data.get(12 + i * 2..14 + i * 2)?;
+                Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+            };
+            match count {
+                0 => Some(Curve::Gamma(1.0)),
+                1 => Some(Curve::Gamma(entry(0)? as f32 / 256.0)),
+                _ => (0..count)
+                    .map(|i| entry(i).map(|value| value as f32 / 65535.0))
+                    .collect::<Option<Vec<f32>>>()
+                    .map(Curve::Table),
+            }
+        }
+        b"para" => {
+            let kind = u16::from_be_bytes(data.get(8..10)?.try_into().ok()?);
+            let len = match kind {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut p = [0.0; 7];
+            for (i, value) in p.iter_mut().enumerate().take(len) {
+                *value = read_fixed(data, 12 + i * 4)?;
+            }
+            let [g, a, b, c, d, e, f] = p;
+            Some(match kind {
+                0 => Curve::Parametric {
+                    g,
+                    a: 1.0,
+                    b: 0.0,
+                    c: 0.0,
+                    d: 0.0,
+                    e: 0.0,
+                    f: 0.0,
+                },
+                // Below -b/a the curve is flat at 0, or at c for type 2.
+                1 | 2 => Curve::Parametric {
+                    g,
+                    a,
+                    b,
+                    c: 0.0,
+                    d: -b / a,
+                    e: c,
+                    f: c,
+                },
+                3 => Curve::Parametric {
+                    g,
+                    a,
+                    b,
+                    c,
+                    d,
+                    e: 0.0,
+                    f: 0.0,
+                },
+                _ => Curve::Parametric {
+                    g,
+                    a,
+                    b,
+                    c,
+                    d,
+                    e,
+                    f,
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    /// An RGB profile with the sRGB primaries and the curve tag `curve`.
+    fn profile(curve: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_LEN];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[36..40].copy_from_slice(b"acsp");
+        let fixed = |value: f32| ((value * 65536.0).round() as i32).to_be_bytes();
+        let primaries = [
+            [0.436_075, 0.222_504, 0.013_932],
+            [0.385_065, 0.716_879, 0.097_104],
+            [0.143_080, 0.060_617, 0.714_173],
+        ];
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (signature, xyz) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().zip(primaries) {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            xyz.iter().for_each(|&value| tag.extend(fixed(value)));
+            tags.push((signature, tag));
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((signature, curve.to_vec()));
+        }
+        data.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = HEADER_LEN + 4 + tags.len() * 12;
+        let mut contents: Vec<u8> = Vec::new();
+        for (signature, tag) in &tags {
+            data.extend(*signature);
+            data.extend((offset as u32).to_be_bytes());
+            data.extend((tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+            contents.extend(tag);
+        }
+        data.extend(contents);
+        data
+    }
+
+    #[test]
+    fn test_profiles_are_converted_to_srgb() {
+        let img = || DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([128, 0, 255])));
+
+        // A linear profile with the sRGB primaries only needs encoding.
+        let linear = profile(b"curv\0\0\0\0\0\0\0\0");
+        let converted = to_srgb(img(), &linear).to_rgb8();
+        assert_eq!(converted.get_pixel(0, 0).0, [188, 0, 255]);
+
+        // The sRGB curve as a parametric tag leaves the pixels alone.
+        let mut srgb = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for value in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.040_45] {
+            srgb.extend(((value * 65536.0f32).round() as i32).to_be_bytes());
+        }
+        assert!(Profile::parse(&profile(&srgb)).unwrap().is_srgb());
+        assert_eq!(to_srgb(img(), &profile(&srgb)), img());
+
+        assert_eq!(Profile::parse(b"not a profile"), None);
+        assert_eq!(to_srgb(img(), b"not a profile"), img());
+    }
+}
@@ -0,0 +1,126 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Identity of a file on disk, shared by every path leading to it.
+///
+/// On Unix this is the device and inode number, so symbolic and hard links
+/// to the same file compare equal. Elsewhere the canonical path is used,
+/// which resolves symbolic links only.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileId {
+    Inode { device: u64, inode: u64 },
+    Canonical(PathBuf),
+}
+
+impl FileId {
+    /// Identity of the file at `path`, following symbolic links.
+    pub fn of(path: &Path) -> io::Result<FileId> {
+        FileId::from_metadata(path, &path.metadata()?)
+    }
+
+    /// Identity of the file at `path` described by `metadata`, which must
+    /// be the metadata of the link target when `path` is a link.
+    #[cfg(unix)]
+    pub fn from_metadata(_path: &Path, metadata: &Metadata) -> io::Result<FileId> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(FileId::Inode {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(path: &Path, _metadata: &Metadata) -> io::Result<FileId> {
+        Ok(FileId::Canonical(path.canonicalize()?))
+    }
+}
+
+/// Number of hard links to the file described by `metadata`.
+#[cfg(unix)]
+fn link_count(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &Metadata) -> u64 {
+    1
+}
+
+/// Collapse the paths of `paths` that are hard links to the same file.
+///
+/// Returns the first path of every distinct file, in input order, and the
+/// remaining links of each first path that has any. Only files with more
+/// than one link are compared, so the common case costs one `stat` per file.
+pub fn collapse_hardlinks(paths: Vec<PathBuf>) -> (Vec<PathBuf>, HashMap<PathBuf, Vec<PathBuf>>) {
+    let ids: Vec<Option<FileId>> = paths
+        .par_iter()
+        .map(|path| {
+            let metadata = path.symlink_metadata().ok()?;
+            if link_count(&metadata) < 2 {
+                return None;
+            }
+            FileId::from_metadata(path, &metadata).ok()
+        })
+        .collect();
+
+    let mut first_of: HashMap<FileId, usize> = HashMap::new();
+    let mut links: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut kept: Vec<PathBuf> = Vec::with_capacity(paths.len());
+    for (path, id) in paths.into_iter().zip(ids) {
+        match id {
+            Some(id) => match first_of.get(&id) {
+                Some(&first) => links.entry(kept[first].clone()).or_default().push(path),
+                None => {
+                    first_of.insert(id, kept.len());
+                    kept.push(path);
+                }
+            },
+            None => kept.push(path),
+        }
+    }
+    (kept, links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_links_share_identity() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("target.png");
+        let other = dir.path().join("other.png");
+        let link = dir.path().join("link.png");
+        fs::write(&target, b"same").unwrap();
+        fs::write(&other, b"same").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert_eq!(FileId::of(&link).unwrap(), FileId::of(&target).unwrap());
+        assert_ne!(FileId::of(&other).unwrap(), FileId::of(&target).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collapse_hardlinks() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        fs::write(&paths[0], b"same").unwrap();
+        fs::write(&paths[1], b"same").unwrap();
+        fs::hard_link(&paths[0], &paths[2]).unwrap();
+
+        let (kept, links) = collapse_hardlinks(paths.clone());
+        assert_eq!(kept, paths[..2]);
+        assert_eq!(links[&paths[0]], vec![paths[2].clone()]);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::pattern::Pattern;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Name of the file listing paths to leave out of a scanned directory.
+pub const IGNORE_FILE: &str = ".idarignore";
+
+/// A single line of an ignore file.
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    /// Re-include paths matched by earlier rules (`!pattern`).
+    negated: bool,
+    /// Only match directories (`pattern/`).
+    dir_only: bool,
+}
+
+/// Rules of an ignore file, in gitignore syntax.
+///
+/// Blank lines and lines starting with `#` are skipped. A leading `!`
+/// re-includes paths ignored by an earlier rule, a trailing `/` only matches
+/// directories, and a pattern containing a `/` is matched against the whole
+/// path relative to the directory holding the file. The last matching rule
+/// decides.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreFile {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    /// Read the ignore file of `directory`, or `None` if it has none.
+    pub fn load(directory: &Path) -> io::Result<Option<IgnoreFile>> {
+        match fs::read_to_string(directory.join(IGNORE_FILE)) {
+            Ok(contents) => Ok(Some(IgnoreFile::parse(&contents))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Parse the contents of an ignore file, skipping invalid patterns.
+    pub fn parse(contents: &str) -> IgnoreFile {
+        let rules = contents
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let dir_only = line.ends_with('/');
+                // A leading slash anchors the pattern like any other slash.
+                let pattern = Pattern::new(line.trim_end_matches('/')).ok()?;
+                Some(Rule {
+                    pattern,
+                    negated,
+                    dir_only,
+                })
+            })
+            .collect();
+        IgnoreFile { rules }
+    }
+
+    /// Whether `path`, relative to the directory holding the ignore file, is
+    /// ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.pattern.matches(path))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let ignore = IgnoreFile::parse(
+            "# exports and caches\n\
+             *.tmp\n\
+             exports/\n\
+             /vendor\n\
+             raw/**/*.cr2\n\
+             !keep.tmp\n",
+        );
+
+        assert!(ignore.is_ignored(Path::new("a/b.tmp"), false));
+        assert!(!ignore.is_ignored(Path::new("a/keep.tmp"), false));
+        assert!(ignore.is_ignored(Path::new("2024/exports"), true));
+        assert!(!ignore.is_ignored(Path::new("exports"), false));
+        assert!(ignore.is_ignored(Path::new("vendor"), true));
+        assert!(!ignore.is_ignored(Path::new("lib/vendor"), true));
+        assert!(ignore.is_ignored(Path::new("raw/2024/a.cr2"), false));
+        assert!(!ignore.is_ignored(Path::new("photo.jpg"), false));
+    }
+}
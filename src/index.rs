@@ -0,0 +1,150 @@
+use crate::decode::{self, DecodeOptions};
+use crate::deduplicate::{self, DeduplicateOptions};
+use crate::distance;
+use crate::errors::AppError;
+use crate::hashing::{Channels, HashAlgorithm, ImageHasher, Preprocessing};
+use crate::invariance::Invariance;
+use crate::models::ImageInfo;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Hashes of a scanned directory, persisted so that single images can be
+/// looked up without scanning the directory again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HashIndex {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub directory_path: PathBuf,
+    pub algorithm: HashAlgorithm,
+    pub hash_size: u32,
+    /// Color planes hashed on their own, with `--channels`.
+    #[serde(default)]
+    pub channels: Channels,
+    /// Processing applied to images before hashing them.
+    #[serde(default)]
+    pub preprocessing: Preprocessing,
+    /// Variants hashed along with every image, with `--invariant`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invariance: Vec<Invariance>,
+    pub images: Vec<ImageInfo>,
+}
+
+impl HashIndex {
+    pub fn load(path: &Path) -> Result<HashIndex, AppError> {
+        if !path.is_file() {
+            return Err(AppError::FileNotFound(path.display().to_string()));
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), AppError> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Indexed images closer than `threshold` to `image`, nearest first.
+    ///
+    /// Images indexed with their variants are at the distance of the
+    /// closest variant.
+    pub fn query(&self, image: &Path, threshold: u32) -> Result<Vec<(&ImageInfo, u32)>, AppError> {
+        let img = decode::open(image, &DecodeOptions::default())
+            .map_err(|_| AppError::InvalidImage(image.to_path_buf()))?;
+        let hasher = ImageHasher::with_preprocessing(
+            self.algorithm,
+            self.hash_size,
+            self.channels,
+            self.preprocessing,
+        );
+        let hash = hasher.hash_image(&img);
+
+        let mut matches: Vec<(&ImageInfo, u32)> = self
+            .images
+            .iter()
+            .map(|indexed| {
+                let dist = std::iter::once(&indexed.hash)
+                    .chain(&indexed.variants)
+                    .map(|other| distance::hamming(hash.as_bytes(), other.as_bytes()))
+                    .min()
+                    .unwrap_or(u32::MAX);
+                (indexed, dist)
+            })
+            .filter(|&(_, dist)| dist < threshold)
+            .collect();
+        matches.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.path.cmp(&b.0.path)));
+        Ok(matches)
+    }
+}
+
+/// Hash every image in `directory` and save the hashes to `index_path`.
+pub fn build(
+    directory: &Path,
+    index_path: &Path,
+    options: &DeduplicateOptions,
+) -> Result<(), AppError> {
+    println!("Building index of directory: {:?}", directory);
+    let scanned = deduplicate::hash_directories(&[directory.to_path_buf()], options)?;
+
+    let index = HashIndex {
+        directory_path: directory.to_path_buf(),
+        algorithm: options.algorithm,
+        hash_size: options.hash_size,
+        channels: options.channels,
+        preprocessing: options.preprocessing,
+        invariance: options.invariance.clone(),
+        images: scanned.images,
+    };
+    index.save(index_path)?;
+    println!(
+        "Indexed {} images into {:?}",
+        index.images.len(),
+        index_path
+    );
+    Ok(())
+}
+
+/// Print the indexed near-duplicates of `image`.
+pub fn query(image: &Path, index_path: &Path, threshold: u32) -> Result<(), AppError> {
+    let index = HashIndex::load(index_path)?;
+    let matches = index.query(image, threshold)?;
+
+    if matches.is_empty() {
+        println!("No near-duplicates of {:?} found.", image);
+    }
+    for (indexed, dist) in matches {
+        println!("{:>4}  {}", dist, indexed.path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_query_finds_indexed_copy() {
+        let dir = tempdir().unwrap();
+        let mut image = RgbImage::new(64, 64);
+        for x in 0..32 {
+            for y in 0..64 {
+                *image.get_pixel_mut(x, y) = image::Rgb([255, 255, 255]);
+            }
+        }
+        image.save(dir.path().join("original.png")).unwrap();
+        let query_path = dir.path().join("query.png");
+        image.save(&query_path).unwrap();
+
+        let index_path = dir.path().join("index.json");
+        build(dir.path(), &index_path, &DeduplicateOptions::default()).unwrap();
+
+        let index = HashIndex::load(&index_path).unwrap();
+        let matches = index.query(&query_path, 1).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert!(matches.iter().all(|&(_, dist)| dist == 0));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::archive;
+use crate::models::DuplicatesGroup;
+use indicatif::HumanBytes;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+
+/// What to do with a group, as answered at the prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    /// Keep these items, the first one being linked to, and act on the
+    /// others.
+    Keep(Vec<usize>),
+    /// Leave every item of the group untouched.
+    Skip,
+}
+
+/// Asks which items of every group to keep.
+pub struct Prompter<R, W> {
+    input: R,
+    output: W,
+    asked: usize,
+    /// Whether every group left is skipped, after `q` or the end of input.
+    quit: bool,
+    /// Whether the prompts are shown at a terminal, rather than to a
+    /// script.
+    terminal: bool,
+}
+
+impl Prompter<BufReader<io::Stdin>, io::Stdout> {
+    /// Prompt at the terminal, if the output goes to one.
+    pub fn terminal() -> Self {
+        Prompter {
+            terminal: io::stdout().is_terminal(),
+            ..Prompter::new(BufReader::new(io::stdin()), io::stdout())
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Prompter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Prompter {
+            input,
+            output,
+            asked: 0,
+            quit: false,
+            terminal: true,
+        }
+    }
+
+    /// The same prompter, reading the answers of a script rather than of
+    /// someone at a terminal.
+    pub fn scripted(self) -> Self {
+        Prompter {
+            terminal: false,
+            ..self
+        }
+    }
+
+    /// Whether someone at a terminal answers the prompts.
+    pub fn at_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// List the items of `group` with their size, dimensions and distance
+    /// to the `suggested` one, and ask which to keep until the answer makes
+    /// sense. An empty answer keeps the suggested item.
+    pub fn ask(&mut self, group: &DuplicatesGroup, suggested: usize) -> io::Result<Answer> {
+        if self.quit {
+            return Ok(Answer::Skip);
+        }
+        self.asked += 1;
+        let kind = format!("{:?}", group.kind).to_lowercase();
+        writeln!(self.output, "\nGroup {} ({})", self.asked, kind)?;
+        let hash = &group.items[suggested].hash;
+        for (index, item) in group.items.iter().enumerate() {
+            let size = archive::size(&item.path)
+                .map_or("?".to_string(), |bytes| HumanBytes(bytes).to_string());
+            let dimensions = item
+                .dimensions
+                .map_or("?".to_string(), |d| format!("{}x{}", d.width, d.height));
+            write!(
+                self.output,
+                "  [{}] {:?}  {}  {}  distance {}",
+                index + 1,
+                item.path,
+                size,
+                dimensions,
+                item.hash.dist(hash)
+            )?;
+            if index == suggested {
+                write!(self.output, "  (suggested)")?;
+            }
+            writeln!(self.output)?;
+        }
+        loop {
+            write!(
+                self.output,
+                "Keep which files? Numbers separated by spaces, Enter for [{}], \
+                 s to skip, q to skip the rest: ",
+                suggested + 1
+            )?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                self.quit = true;
+                return Ok(Answer::Skip);
+            }
+            match line.trim() {
+                "" => return Ok(Answer::Keep(vec![suggested])),
+                "s" => return Ok(Answer::Skip),
+                "q" => {
+                    self.quit = true;
+                    return Ok(Answer::Skip);
+                }
+                answer => match parse_keep(answer, group.items.len()) {
+                    Some(kept) => return Ok(Answer::Keep(kept)),
+                    None => writeln!(
+                        self.output,
+                        "Answer with numbers from 1 to {}, s or q.",
+                        group.items.len()
+                    )?,
+                },
+            }
+        }
+    }
+
+    /// Ask `question`, which only a `y` or `yes` answers positively.
+    pub fn confirm(&mut self, question: &str) -> io::Result<bool> {
+        write!(self.output, "{} [y/N] ", question)?;
+        self.output.flush()?;
+        let mut line = String::new();
+        self.input.read_line(&mut line)?;
+        Ok(matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+}
+
+/// Indices of the items numbered in `answer`, from 1 to `count`, without
+/// repeats.
+fn parse_keep(answer: &str, count: usize) -> Option<Vec<usize>> {
+    let mut kept: Vec<usize> = Vec::new();
+    for number in answer.split([' ', ',']).filter(|part| !part.is_empty()) {
+        let index = number.parse::<usize>().ok()?.checked_sub(1)?;
+        if index >= count {
+            return None;
+        }
+        if !kept.contains(&index) {
+            kept.push(index);
+        }
+    }
+    Some(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+
+    #[test]
+    fn test_ask_until_the_answer_makes_sense() {
+        let items = ["a.jpg", "b.jpg", "c.jpg"]
+            .iter()
+            .map(ImageInfo::at)
+            .collect();
+        let group = DuplicatesGroup::new(items);
+        let input: &[u8] = b"4\n3 1 3\n\ns\nq\n";
+        let mut output = Vec::new();
+        let mut prompter = Prompter::new(input, &mut output);
+
+        let answers: Vec<Answer> = (0..5).map(|_| prompter.ask(&group, 1).unwrap()).collect();
+        assert_eq!(
+            answers,
+            [
+                Answer::Keep(vec![2, 0]),
+                Answer::Keep(vec![1]),
+                Answer::Skip,
+                Answer::Skip,
+                Answer::Skip,
+            ]
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("[2] \"b.jpg\"  ?  ?  distance 0  (suggested)"));
+        assert!(output.contains("Answer with numbers from 1 to 3"));
+        assert_eq!(output.matches("Group ").count(), 4);
+    }
+}
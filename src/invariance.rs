@@ -0,0 +1,224 @@
+use crate::content::ContentKind;
+use crate::decode::Colorspace;
+use crate::hashing::PerceptualHasher;
+use crate::models::{Dimensions, ImageInfo};
+use crate::store::{Hashes, ImageSource};
+use clap::ValueEnum;
+use image::DynamicImage;
+use image_hasher::ImageHash;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Transformation that duplicates may differ by and still match.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Invariance {
+    /// Rotation by 90, 180 or 270 degrees.
+    Rotate,
+    /// Mirroring, horizontally or vertically.
+    Flip,
+}
+
+impl fmt::Display for Invariance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The rotated and mirrored versions of `img` allowed by `invariance`,
+/// leaving out `img` itself.
+///
+/// With both rotation and flips these are the seven other ways of laying
+/// the image on a rectangle; flips alone give the two mirror images.
+pub fn variants(img: &DynamicImage, invariance: &[Invariance]) -> Vec<DynamicImage> {
+    let rotate = invariance.contains(&Invariance::Rotate);
+    let flip = invariance.contains(&Invariance::Flip);
+    let mut variants = Vec::new();
+    if rotate {
+        variants.extend([img.rotate90(), img.rotate180(), img.rotate270()]);
+    }
+    if flip {
+        let mirrored = img.fliph();
+        if rotate {
+            variants.extend([
+                mirrored.rotate90(),
+                mirrored.rotate180(),
+                mirrored.rotate270(),
+            ]);
+        } else {
+            variants.push(img.flipv());
+        }
+        variants.push(mirrored);
+    }
+    variants
+}
+
+/// Hashes of the [`variants`] of `img`.
+pub fn hash_variants(
+    hasher: &dyn PerceptualHasher,
+    img: &DynamicImage,
+    invariance: &[Invariance],
+) -> Vec<ImageHash> {
+    variants(img, invariance)
+        .iter()
+        .map(|variant| hasher.hash_image(variant))
+        .collect()
+}
+
+/// Suffix of the cache settings key for hashes stored with the variants
+/// allowed by `invariance`, empty when there are none.
+pub fn settings_suffix(invariance: &[Invariance]) -> String {
+    [Invariance::Rotate, Invariance::Flip]
+        .iter()
+        .filter(|kind| invariance.contains(kind))
+        .map(|kind| format!("-{}", kind))
+        .collect()
+}
+
+/// Every image of a source followed by the variant hashes of each, so that
+/// matching finds pairs at the smallest distance between one image and any
+/// variant of the other.
+pub struct Expanded<'a, S: ?Sized> {
+    images: &'a S,
+    /// Image and variant of every entry, where variant 0 is the image as
+    /// stored and `n` is its `n`th variant hash.
+    entries: Vec<(usize, usize)>,
+}
+
+impl<'a, S: ImageSource + ?Sized> Expanded<'a, S> {
+    pub fn new(images: &'a S) -> Expanded<'a, S> {
+        let mut entries: Vec<(usize, usize)> = (0..images.len()).map(|i| (i, 0)).collect();
+        for index in 0..images.len() {
+            let count = images.variants(index).len();
+            entries.extend((1..=count).map(|variant| (index, variant)));
+        }
+        Expanded { images, entries }
+    }
+
+    /// Turn pairs of entries into sorted, distinct pairs of the images they
+    /// belong to, dropping those pairing an image with its own variants.
+    pub fn owner_pairs(&self, pairs: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        let mut owners: Vec<(usize, usize)> = pairs
+            .into_iter()
+            .map(|(i, j)| (self.entries[i].0, self.entries[j].0))
+            .filter(|(i, j)| i != j)
+            .map(|(i, j)| (i.min(j), i.max(j)))
+            .collect();
+        owners.sort_unstable();
+        owners.dedup();
+        owners
+    }
+}
+
+impl<S: ImageSource + ?Sized> ImageSource for Expanded<'_, S> {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn path(&self, index: usize) -> &Path {
+        self.images.path(self.entries[index].0)
+    }
+
+    fn hash_bytes(&self, index: usize) -> &[u8] {
+        match self.entries[index] {
+            (owner, 0) => self.images.hash_bytes(owner),
+            (owner, variant) => self.images.variants(owner).bytes(variant - 1),
+        }
+    }
+
+    fn root(&self, index: usize) -> Option<&Path> {
+        self.images.root(self.entries[index].0)
+    }
+
+    fn links(&self, index: usize) -> Vec<PathBuf> {
+        self.images.links(self.entries[index].0)
+    }
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf> {
+        self.images.sidecars(self.entries[index].0)
+    }
+
+    fn variants(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn segments(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn windows(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn frames(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn masked(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions> {
+        self.images.dimensions(self.entries[index].0)
+    }
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace> {
+        self.images.colorspace(self.entries[index].0)
+    }
+
+    fn content(&self, index: usize) -> Option<ContentKind> {
+        self.images.content(self.entries[index].0)
+    }
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String> {
+        self.images.columns(self.entries[index].0)
+    }
+
+    fn image(&self, index: usize) -> ImageInfo {
+        self.images.image(self.entries[index].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::{self, HashAlgorithm};
+    use crate::matching;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn test_rotated_copy_matches_through_variants() {
+        // An L-shaped mark in the top left corner.
+        let original = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 48, |x, y| {
+            if (x < 16 && y < 40) || (y < 12 && x < 48) {
+                Luma([255])
+            } else {
+                Luma([0])
+            }
+        }));
+        let hasher = hashing::build_hasher(HashAlgorithm::Gradient, 8);
+        let invariance = [Invariance::Rotate, Invariance::Flip];
+        let images: Vec<ImageInfo> = [original.clone(), original.rotate90().fliph()]
+            .iter()
+            .enumerate()
+            .map(|(i, img)| ImageInfo {
+                variants: hash_variants(&hasher, img, &invariance),
+                ..ImageInfo::new(PathBuf::from(format!("{}.png", i)), hasher.hash_image(img))
+            })
+            .collect();
+        assert_eq!(images[0].variants.len(), 7);
+        assert!(matching::candidate_pairs(&images[..], 5).is_empty());
+
+        let expanded = Expanded::new(&images[..]);
+        let pairs = expanded.owner_pairs(matching::candidate_pairs(&expanded, 5));
+        assert_eq!(pairs, vec![(0, 1)]);
+        assert_eq!(settings_suffix(&invariance), "-rotate-flip");
+    }
+}
@@ -1,5 +1,61 @@
+pub mod accepted;
+pub mod actions;
+pub mod animation;
+pub mod archive;
+pub mod bursts;
+pub mod cache;
+pub mod checkpoint;
+pub mod content;
+pub mod crops;
+pub mod decode;
 pub mod deduplicate;
+pub mod distance;
 pub mod errors;
+pub mod exact;
+pub mod exif;
+pub mod export;
+pub mod filter;
+pub mod grouping;
+pub mod hashing;
+#[cfg(feature = "heif")]
+pub mod heif;
+pub mod hnsw;
+pub mod hook;
+pub mod html;
+pub mod icc;
+pub mod identity;
+pub mod ignore;
+pub mod index;
+pub mod interactive;
+pub mod invariance;
+pub mod manifest;
+pub mod matching;
+pub mod metadata;
 pub mod models;
+pub mod pages;
+pub mod pattern;
+pub mod pipeline;
+pub mod plan;
+pub mod quality;
+pub mod raw;
+pub mod reflink;
 pub mod removal;
+pub mod report;
+pub mod scan;
+pub mod segments;
+pub mod semantic;
 pub mod serialization;
+pub mod shard;
+pub mod sidecar;
+pub mod source;
+pub mod store;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod timestamp;
+pub mod trash;
+pub mod undo;
+pub mod verify;
+#[cfg(feature = "video")]
+pub mod video;
+pub mod watch;
+pub mod watermark;
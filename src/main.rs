@@ -1,33 +1,947 @@
-use clap::{Parser, Subcommand};
-use idar::deduplicate;
+use clap::{Args, Parser, Subcommand};
+use idar::accepted;
+use idar::actions::{self, ActionKind, ActionOptions, Keep, Limits};
+use idar::cache;
+use idar::content::ContentKind;
+use idar::decode::{self, DecodeLimiter, DecodeOptions};
+use idar::deduplicate::{self, DeduplicateOptions};
+use idar::distance::{Cosine, MetricKind, WeightedHamming};
 use idar::errors::AppError;
+use idar::export;
+use idar::filter::{self, DateSource, FilterOptions};
+use idar::grouping::Grouping;
+use idar::hashing::{Channels, HashAlgorithm, Preprocessing, ResizeFilter};
+use idar::hook;
+use idar::index;
+use idar::invariance::Invariance;
+use idar::manifest::Manifest;
+use idar::matching::{LshOptions, Matcher};
+use idar::models::{Dimensions, ReportSummary};
+use idar::pattern::{self, Pattern};
 use idar::removal;
+use idar::report::ReportFormat;
+use idar::scan::{self, ScanOptions};
+use idar::segments;
+use idar::semantic::{self, Embedder, Embeddings};
+use idar::shard::{self, Part};
+use idar::source::{self, RemoteOptions};
+use idar::timestamp::{self, Since};
+use idar::undo;
+use idar::verify::{Verification, VerifyOptions};
+use idar::watch::{self, WatchOptions};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Exit status of `--fail-on-duplicates` when duplicates are found, and
+/// when some files could not be read.
+const EXIT_DUPLICATES: i32 = 3;
+const EXIT_UNREADABLE: i32 = 4;
+
+/// Options selecting the files to scan and how they are hashed.
+#[derive(Args)]
+struct ScanArgs {
+    /// Size of the hash to use for image comparison
+    #[arg(short = 'H', long, default_value_t = 16)]
+    hash_size: u32,
+
+    /// Perceptual hash algorithm used to fingerprint images
+    #[arg(short, long, value_enum, default_value_t = HashAlgorithm::Gradient)]
+    algorithm: HashAlgorithm,
+
+    /// Color planes hashed on their own. Their distances add up, so the
+    /// threshold applies to the sum; `--threshold-pct` scales with it
+    #[arg(long, value_enum, default_value_t = Channels::Luma)]
+    channels: Channels,
+
+    /// Filter used to shrink images to the hash size
+    #[arg(long, value_enum, default_value_t = ResizeFilter::Lanczos)]
+    resize_filter: ResizeFilter,
+
+    /// Blur images with a gaussian of this standard deviation, in pixels,
+    /// before hashing them
+    #[arg(long, value_name = "SIGMA", value_parser = parse_sigma)]
+    blur: Option<f32>,
+
+    /// Stretch the levels of images to the full range before hashing them,
+    /// so that copies differing in contrast hash alike
+    #[arg(long)]
+    normalize_contrast: bool,
+
+    /// Scan subdirectories recursively
+    #[arg(short = 'R', long)]
+    recursive: bool,
+
+    /// Maximum depth of subdirectories to descend into (0 scans only the top level)
+    #[arg(long, requires = "recursive")]
+    max_depth: Option<usize>,
+
+    /// Follow symbolic links to files and directories. Every file is still
+    /// scanned once, however many links lead to it
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Scan hidden files and directories
+    #[arg(long)]
+    hidden: bool,
+
+    /// Scan files and folders left by photo tools and NAS shares, such as
+    /// Thumbs.db, .DS_Store, @eaDir and .thumbnails
+    #[arg(long)]
+    junk: bool,
+
+    /// Scan reports, indexes, checkpoints and quarantine folders written by
+    /// idar, which are skipped by default
+    #[arg(long)]
+    scan_artifacts: bool,
+
+    /// Scan the images inside zip, cbz and tar archives, which are reported
+    /// as `archive.zip!/photo.jpg` and never modified
+    #[arg(long)]
+    archives: bool,
+
+    /// Scan every page of multi-page TIFFs as a separate image, reported as
+    /// `scan.tif#2` and so on; by default only the first page is hashed
+    #[arg(long)]
+    tiff_pages: bool,
+
+    /// Scan the files listed one per line in FILE (`-` for standard input)
+    /// instead of walking the directories, e.g. `fd -e jpg | idar deduplicate --files-from -`
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Never descend into directories with this name (repeatable), e.g.
+    /// `node_modules`
+    #[arg(long, value_name = "NAME")]
+    exclude_dir: Vec<String>,
+
+    /// Only scan files matching this glob pattern (repeatable), e.g. `*.jpg`
+    #[arg(long, value_parser = pattern::parse_pattern)]
+    include: Vec<Pattern>,
+
+    /// Skip files and directories matching this glob pattern (repeatable),
+    /// e.g. `**/thumbnails/**`
+    #[arg(long, value_parser = pattern::parse_pattern)]
+    exclude: Vec<Pattern>,
+
+    /// Scan the paths listed in the `.idarignore` file of a directory too
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Only try files with these extensions, e.g. `jpg,jpeg,png,webp`
+    #[arg(long, value_delimiter = ',', value_parser = scan::parse_extension)]
+    ext: Vec<String>,
+
+    /// Skip files smaller than this size (e.g. 20KB, 1.5MB)
+    #[arg(long, value_parser = filter::parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than this size (e.g. 200MB, 1GiB)
+    #[arg(long, value_parser = filter::parse_size)]
+    max_size: Option<u64>,
+
+    /// Skip images narrower than this many pixels
+    #[arg(long)]
+    min_width: Option<u32>,
+
+    /// Skip images shorter than this many pixels
+    #[arg(long)]
+    min_height: Option<u32>,
+
+    /// Skip files dated before this time, given like --since (e.g. `30d` or
+    /// `2024-05-01`)
+    #[arg(long, value_parser = timestamp::parse_time)]
+    newer_than: Option<SystemTime>,
+
+    /// Skip files dated at or after this time, given like --newer-than
+    #[arg(long, value_parser = timestamp::parse_time)]
+    older_than: Option<SystemTime>,
+
+    /// Date compared with --newer-than and --older-than
+    #[arg(long, value_enum, default_value_t = DateSource::Modified)]
+    date_source: DateSource,
+
+    /// Only hash files modified after this time: `last` (the previous run),
+    /// a duration such as `12h` or `7d`, a date such as `2024-05-01`, or a
+    /// Unix timestamp. Older files are matched only if their hash is cached
+    #[arg(long, value_parser = timestamp::parse_since)]
+    since: Option<Since>,
+
+    /// Continue an interrupted run from its checkpoint instead of hashing
+    /// every file again
+    #[arg(long)]
+    resume: bool,
+
+    /// Skip the byte-level pass that groups identical files before hashing
+    #[arg(long)]
+    no_exact_pass: bool,
+
+    /// Hash every file again instead of reusing hashes from the cache
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Location of the hash cache [default: user cache directory]
+    #[arg(long, conflicts_with = "no_cache")]
+    cache_path: Option<PathBuf>,
+
+    /// Only hash a pseudo-random sample of this many files and extrapolate
+    /// how many duplicates the whole collection holds
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    limit: Option<u64>,
+
+    /// Hash files in batches of this size and stream results to disk,
+    /// bounding memory use on very large directories
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    chunk_size: Option<u64>,
+
+    /// Downscale larger images while decoding them to at most this many
+    /// pixels (0 decodes every image at full resolution)
+    #[arg(long, default_value_t = decode::DEFAULT_MAX_PIXELS)]
+    max_decode_pixels: u64,
+
+    /// Largest number of images decoded at the same time [default: one per thread]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    decode_concurrency: Option<u64>,
+
+    /// Hash images as stored, without first turning them upright according
+    /// to their EXIF orientation
+    #[arg(long)]
+    ignore_orientation: bool,
+
+    /// Convert images with an embedded ICC profile, such as Display P3 or
+    /// Adobe RGB exports, to sRGB before hashing them
+    #[arg(long)]
+    color_profiles: bool,
+
+    /// Also match images that are rotated (`rotate`) or mirrored (`flip`)
+    /// copies of each other, e.g. `--invariant rotate,flip`. Hashes every
+    /// variant of each image, so hashing and matching take longer
+    #[arg(long, value_enum, value_delimiter = ',')]
+    invariant: Vec<Invariance>,
+
+    /// Also hash the regions of every image, so that cropped and
+    /// letterboxed copies are matched. Hashing takes longer, and --verify
+    /// compares whole images, rejecting most cropped copies
+    #[arg(long)]
+    crop_resistant: bool,
+
+    /// Also hash windows of every image at several scales, and list images
+    /// that show part of another one in a `crops` section of the report
+    #[arg(long)]
+    find_crops: bool,
+
+    /// Hash animated GIF, WebP and PNG files by their first frame only.
+    /// By default their keyframes are hashed, so re-encoded and trimmed
+    /// animations are matched
+    #[arg(long)]
+    first_frame: bool,
+
+    /// Tag every image in the report as a photo, screenshot or document,
+    /// guessed from its name, metadata and colours
+    #[arg(long)]
+    tag_content: bool,
+
+    /// Also hash every image with its corners masked, and group copies that
+    /// only differ by a logo, watermark or date in a corner as watermarked
+    /// variants
+    #[arg(long)]
+    watermarks: bool,
+
+    /// Only match images of these kinds, e.g. `--only-content screenshot`
+    /// to deduplicate screenshots with a threshold of their own. Implies
+    /// --tag-content
+    #[arg(long, value_enum, value_delimiter = ',')]
+    only_content: Vec<ContentKind>,
+}
+
+impl ScanArgs {
+    fn options(&self) -> DeduplicateOptions {
+        DeduplicateOptions {
+            hash_size: self.hash_size,
+            algorithm: self.algorithm,
+            channels: self.channels,
+            preprocessing: Preprocessing {
+                resize_filter: self.resize_filter,
+                blur: self.blur,
+                normalize_contrast: self.normalize_contrast,
+            },
+            scan: ScanOptions {
+                recursive: self.recursive,
+                max_depth: self.max_depth,
+                include: self.include.clone(),
+                exclude: self.exclude.clone(),
+                extensions: self.ext.clone(),
+                ignore_files: !self.no_ignore,
+                exclude_dirs: self.exclude_dir.clone(),
+                follow_symlinks: self.follow_symlinks,
+                hidden: self.hidden,
+                junk: self.junk,
+                artifacts: self.scan_artifacts,
+                archives: self.archives,
+                pages: self.tiff_pages,
+                files_from: self.files_from.clone(),
+            },
+            filter: FilterOptions {
+                min_size: self.min_size,
+                max_size: self.max_size,
+                min_width: self.min_width,
+                min_height: self.min_height,
+                newer_than: self.newer_than,
+                older_than: self.older_than,
+                date_source: self.date_source,
+            },
+            exact_pass: !self.no_exact_pass,
+            cache_path: if self.no_cache {
+                None
+            } else {
+                self.cache_path.clone().or_else(cache::default_cache_path)
+            },
+            chunk_size: self.chunk_size.map(|size| size as usize),
+            limit: self.limit.map(|limit| limit as usize),
+            since: self.since,
+            checkpoint: true,
+            resume: self.resume,
+            decode: DecodeOptions {
+                max_pixels: (self.max_decode_pixels > 0).then_some(self.max_decode_pixels),
+                limiter: self
+                    .decode_concurrency
+                    .map(|limit| Arc::new(DecodeLimiter::new(limit as usize))),
+                orientation: !self.ignore_orientation,
+                color_profiles: self.color_profiles,
+            },
+            invariance: self.invariant.clone(),
+            crop_resistant: self.crop_resistant,
+            find_crops: self.find_crops,
+            first_frame: self.first_frame,
+            tag_content: self.tag_content || !self.only_content.is_empty(),
+            only_content: self.only_content.clone(),
+            watermarks: self.watermarks,
+            ..DeduplicateOptions::default()
+        }
+    }
+}
+
+#[derive(Args)]
+struct DeduplicateArgs {
+    /// Directories to scan for duplicates, matched together as one collection.
+    /// With --files-from or --manifest they only locate the report and label
+    /// the roots [default: the current directory]
+    #[arg(required_unless_present_any = ["files_from", "manifest"])]
+    directories: Vec<PathBuf>,
+
+    /// CSV file listing the images to scan in a `path` column; its other
+    /// columns, such as labels or splits, are copied to the report entries
+    #[arg(long, value_name = "FILE", conflicts_with = "files_from")]
+    manifest: Option<PathBuf>,
+
+    #[command(flatten)]
+    scan: ScanArgs,
+
+    #[command(flatten)]
+    matching: MatchArgs,
+
+    /// Match images by the cosine similarity of the embeddings in this JSON
+    /// lines file of `{"path": ..., "embedding": [...]}` objects, instead of
+    /// by their perceptual hashes, e.g. as computed ahead of time by
+    /// --embed-command
+    #[arg(long, value_name = "FILE", group = "semantic")]
+    embeddings: Option<PathBuf>,
+
+    /// Match images by the cosine similarity of their embeddings under this
+    /// model, such as a CLIP image encoder exported to ONNX, instead of by
+    /// their perceptual hashes. Catches duplicates that survive heavy edits
+    #[arg(long, value_name = "FILE", group = "semantic")]
+    model: Option<PathBuf>,
+
+    /// Shell command running --model over the images, which finds the path
+    /// of the model in IDAR_MODEL, reads the paths of the images on its
+    /// input, one per line, and writes their embeddings in the format of
+    /// --embeddings
+    #[arg(long, value_name = "COMMAND", default_value = semantic::DEFAULT_EMBED_COMMAND,
+          requires = "model")]
+    embed_command: String,
+
+    /// Smallest cosine similarity between the embeddings of duplicates
+    #[arg(long, default_value_t = semantic::DEFAULT_MIN_SIMILARITY, requires = "semantic")]
+    min_similarity: f32,
+
+    /// Only report duplicates spanning different directories, e.g. to find
+    /// images shared between a training and a test set
+    #[arg(long)]
+    cross_root: bool,
+
+    /// Name of the file to save the deduplication report, in the first
+    /// directory [default: dedup_report.json, or dedup_report.csv and
+    /// dedup_report.html with --format csv and html]
+    #[arg(short, long)]
+    report_file_name: Option<String>,
+
+    /// Format of the report. CSV reports list one row per image with its
+    /// group, distance to the image kept, size and dimensions, and HTML
+    /// reports show the images of every group side by side for review, but
+    /// neither can be read by apply or ignore-group
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    format: ReportFormat,
+
+    /// Exit with status 3 if any duplicate group is found, or else with 4 if
+    /// some files or directories could not be read, to fail a CI check of a
+    /// dataset. Other errors exit with 1
+    #[arg(long)]
+    fail_on_duplicates: bool,
+
+    /// Shell command to run once the report is saved, e.g. to send a
+    /// notification or upload the report. It finds the path of the report
+    /// in IDAR_REPORT, and the totals of the run in IDAR_GROUPS,
+    /// IDAR_EXACT_GROUPS, IDAR_DUPLICATES, IDAR_RECLAIMABLE_BYTES,
+    /// IDAR_ERRORS, IDAR_ACTIONS and IDAR_FAILED_ACTIONS
+    #[arg(long, value_name = "COMMAND")]
+    on_complete: Option<String>,
+
+    #[command(flatten)]
+    actions: ActionArgs,
+}
+
+impl DeduplicateArgs {
+    fn options(&self) -> Result<DeduplicateOptions, AppError> {
+        let options = self.matching.apply(self.scan.options())?;
+        Ok(DeduplicateOptions {
+            quality: options.quality || self.actions.keep == Some(Keep::BestQuality),
+            cross_root: self.cross_root,
+            min_similarity: self.min_similarity,
+            format: self.format,
+            action: self.actions.options(),
+            keep: self.actions.keep.unwrap_or(Keep::FirstAlphabetical),
+            ..options
+        })
+    }
+
+    fn report_file_name(&self) -> String {
+        self.report_file_name
+            .clone()
+            .unwrap_or_else(|| format!("dedup_report.{}", self.format.extension()))
+    }
+}
+
+/// Options choosing what is done to the duplicates of every group.
+#[derive(Args)]
+struct ActionArgs {
+    /// Move every file of a group but the one picked by --keep to the
+    /// trash, listing what was deleted in the report
+    #[arg(long, group = "action", requires = "keep")]
+    delete: bool,
+
+    /// Delete files for good with --delete, instead of moving them to the
+    /// trash
+    #[arg(long, requires = "delete")]
+    permanent: bool,
+
+    /// Move every file of a group but the one picked by --keep to this
+    /// quarantine directory, under the same path relative to its scanned
+    /// directory, e.g. `photos/idar_quarantine`
+    #[arg(long, value_name = "DIR", group = "action", requires = "keep")]
+    move_to: Option<PathBuf>,
+
+    /// Replace every file of a group but the one picked by --keep with a
+    /// hard link to it, so that every path stays valid while the space of
+    /// the copies is freed. Files on other filesystems are left alone
+    #[arg(long, group = "action", requires = "keep")]
+    hardlink: bool,
+
+    /// Replace every file of a group but the one picked by --keep with a
+    /// copy-on-write clone of it, on filesystems that support them such as
+    /// btrfs, XFS and APFS. Unlike hard links, the files stay independent
+    #[arg(long, group = "action", requires = "keep")]
+    reflink: bool,
+
+    /// Replace every file of a group but the one picked by --keep with a
+    /// relative symbolic link to it, which also works across filesystems
+    #[arg(long, group = "action", requires = "keep")]
+    symlink: bool,
+
+    /// Move all the files of every group to a folder of its own in this
+    /// directory, named after the file picked by --keep, to review the
+    /// groups in a file manager
+    #[arg(long, value_name = "DIR", group = "action")]
+    consolidate: Option<PathBuf>,
+
+    /// Rename every file of a group but the one picked by --keep after it,
+    /// as `name.dup1.jpg`, `name.dup2.jpg` and so on, in its own directory
+    #[arg(long, group = "action")]
+    rename_duplicates: bool,
+
+    /// Which file of every group --delete, --move-to, --hardlink, --reflink
+    /// or --symlink keeps, and --consolidate or --rename-duplicates name
+    /// the others after [default for those: first-alphabetical]. Without
+    /// an action, it picks the file kept when estimating the space the
+    /// duplicates take
+    #[arg(long, alias = "strategy", value_enum)]
+    keep: Option<Keep>,
+
+    /// Keep the file of a group in this directory over the others, whatever
+    /// --keep says, which then picks among those in it. Repeat to rank
+    /// several directories, the first one winning
+    #[arg(long, value_name = "DIR", requires = "action")]
+    prefer_dir: Vec<PathBuf>,
+
+    /// Show the files of every group with their sizes, dimensions and
+    /// distances, and ask which ones to keep, suggesting the one picked by
+    /// --keep. The others are acted on, and groups can be skipped
+    #[arg(long, requires = "action")]
+    interactive: bool,
+
+    /// Copy the EXIF, XMP and IPTC metadata the kept file lacks, such as
+    /// the capture time, GPS position and keywords, from the files done
+    /// away with. Only kept JPEG files are written to
+    #[arg(
+        long,
+        requires = "action",
+        conflicts_with_all = ["consolidate", "rename_duplicates"]
+    )]
+    merge_metadata: bool,
+
+    /// Leave the sidecar files of the files deleted or moved, such as
+    /// `.xmp` and Google Takeout `.json` files, where they are instead of
+    /// taking them along, listing them in the report as orphaned
+    #[arg(long, requires = "action")]
+    no_sidecars: bool,
+
+    /// Do away with at most this many files in a run, leaving every file
+    /// untouched if more would be, with --delete, --move-to, --hardlink,
+    /// --reflink or --symlink
+    #[arg(long, value_name = "N", requires = "action")]
+    max_delete: Option<usize>,
+
+    /// Do away with at most this many bytes of files in a run (e.g. 500MB,
+    /// 2GiB), leaving every file untouched if more would be
+    #[arg(long, value_name = "SIZE", value_parser = filter::parse_size, requires = "action")]
+    max_delete_bytes: Option<u64>,
+
+    /// Largest share of the scanned files, between 0 and 1, done away with
+    /// in a run before every file is left untouched, unless --force is
+    /// given
+    #[arg(long, default_value_t = 0.5, value_parser = parse_fraction)]
+    max_delete_pct: f64,
+
+    /// Do away with more than --max-delete-pct of the scanned files
+    #[arg(long, requires = "action")]
+    force: bool,
+
+    /// Never delete, move or link over the files matching this glob pattern
+    /// (repeatable), relative to their scanned directory, e.g.
+    /// `originals/**`. They are kept even when --keep would not keep them,
+    /// which the report notes
+    #[arg(long, value_name = "GLOB", value_parser = pattern::parse_pattern, requires = "action")]
+    protect: Vec<Pattern>,
+
+    /// File of patterns to --protect, one per line, skipping blank lines
+    /// and those starting with `#`
+    #[arg(long, value_name = "FILE", requires = "action")]
+    protect_file: Option<PathBuf>,
+
+    /// Take the actions without showing the plan and asking to confirm it
+    /// first. Scripts, whose output is not a terminal, must give it: without
+    /// it they fail, leaving every file untouched
+    #[arg(short, long, requires = "action")]
+    yes: bool,
+
+    /// Only print and record in the report what the action on the
+    /// duplicates would do, with the space it would free, leaving every
+    /// file untouched
+    #[arg(long, requires = "action")]
+    dry_run: bool,
+}
+
+impl ActionArgs {
+    /// The options of the action, with the patterns of --protect-file
+    /// loaded.
+    fn load(&self) -> Result<Option<ActionOptions>, AppError> {
+        let mut options = self.options();
+        if let (Some(options), Some(file)) = (&mut options, &self.protect_file) {
+            options.protect.extend(pattern::load_patterns(file)?);
+        }
+        Ok(options)
+    }
+
+    fn options(&self) -> Option<ActionOptions> {
+        let kind = if self.delete && self.permanent {
+            ActionKind::Delete
+        } else if self.delete {
+            ActionKind::Trash
+        } else if self.move_to.is_some() {
+            ActionKind::Move
+        } else if self.hardlink {
+            ActionKind::Hardlink
+        } else if self.reflink {
+            ActionKind::Reflink
+        } else if self.symlink {
+            ActionKind::Symlink
+        } else if self.consolidate.is_some() {
+            ActionKind::Consolidate
+        } else if self.rename_duplicates {
+            ActionKind::Rename
+        } else {
+            return None;
+        };
+        let keep = match (self.keep, kind) {
+            (Some(keep), _) => keep,
+            (None, ActionKind::Consolidate | ActionKind::Rename) => Keep::FirstAlphabetical,
+            (None, _) => return None,
+        };
+        Some(ActionOptions {
+            kind,
+            keep,
+            dry_run: self.dry_run,
+            move_to: self.move_to.clone().or_else(|| self.consolidate.clone()),
+            prefer_dirs: self.prefer_dir.clone(),
+            interactive: self.interactive,
+            yes: self.yes,
+            merge_metadata: self.merge_metadata,
+            sidecars: !self.no_sidecars,
+            limits: Limits {
+                max_files: self.max_delete,
+                max_bytes: self.max_delete_bytes,
+                max_share: (!self.force).then_some(self.max_delete_pct),
+            },
+            protect: self.protect.clone(),
+        })
+    }
+}
+
+/// Options controlling how hashed images are matched and grouped.
+#[derive(Args)]
+struct MatchArgs {
+    /// Similarity threshold for detecting duplicates
+    #[arg(short, long, default_value_t = 10)]
+    duplicate_threshold: u32,
+
+    /// Similarity threshold as a fraction of the hash bits (e.g. 0.05 for
+    /// 5%), so that it does not depend on the hash size
+    #[arg(long, conflicts_with = "duplicate_threshold", value_parser = parse_fraction)]
+    threshold_pct: Option<f64>,
+
+    /// Strategy used to form duplicate groups from matching pairs
+    #[arg(long, value_enum, default_value_t = Grouping::Greedy)]
+    grouping: Grouping,
+
+    /// Leave images smaller than this (e.g. `512x512`) out of matching and
+    /// list them in a separate section of the report
+    #[arg(long, value_name = "WxH", value_parser = filter::parse_resolution)]
+    min_resolution: Option<Dimensions>,
+
+    /// Stricter check applied to candidate pairs before grouping them
+    #[arg(long, value_enum, default_value_t = Verification::None)]
+    verify: Verification,
+
+    /// Largest difference (0 to 1) accepted by the verification stage
+    #[arg(long, default_value_t = 0.05)]
+    verify_tolerance: f64,
+
+    /// Smallest SSIM score (0 to 1) accepted by `--verify ssim`
+    #[arg(long, default_value_t = 0.9)]
+    min_ssim: f64,
+
+    /// Exact strategy used to find matching pairs
+    #[arg(long, value_enum, default_value_t = Matcher::Exhaustive)]
+    matcher: Matcher,
+
+    /// Keep the hashes and paths of the images in a memory-mapped file at
+    /// this path while matching, instead of in memory
+    #[arg(long)]
+    hash_store: Option<PathBuf>,
+
+    /// Only compare images sharing a locality-sensitive hash bucket. Much
+    /// faster on large collections, at the cost of missing some duplicates
+    #[arg(long, conflicts_with = "matcher")]
+    approx: bool,
+
+    /// Number of LSH bands; more bands find more duplicates
+    #[arg(long, default_value_t = 8, requires = "approx")]
+    approx_bands: usize,
+
+    /// Number of hash bits per LSH band; wider bands compare fewer pairs
+    #[arg(long, default_value_t = 16, requires = "approx",
+          value_parser = clap::value_parser!(u64).range(1..=64))]
+    approx_band_bits: u64,
+
+    /// Seed used to pick the bits of each LSH band
+    #[arg(long, default_value_t = 0, requires = "approx")]
+    seed: u64,
+
+    /// Number of regions two images hashed with --crop-resistant must share
+    /// to match
+    #[arg(long, default_value_t = segments::DEFAULT_MIN_MATCHES as u64,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    min_segment_matches: u64,
+
+    /// Distance used to compare hashes. Other metrics than hamming compare
+    /// every pair of images, and the threshold is in their units
+    #[arg(long, value_enum, default_value_t = MetricKind::Hamming, conflicts_with = "approx")]
+    metric: MetricKind,
+
+    /// Weights of the equal parts of the hash, such as the planes hashed
+    /// with --channels, for `--metric weighted-hamming`
+    #[arg(
+        long,
+        value_delimiter = ',',
+        required_if_eq("metric", "weighted-hamming")
+    )]
+    metric_weights: Vec<u32>,
+
+    /// Compare the files of every group byte for byte and pixel for pixel,
+    /// listing in the report which ones are identical
+    #[arg(long)]
+    classify: bool,
+
+    /// Score the sharpness, exposure clipping and JPEG quality of the files
+    /// of every group, listing in the report which copy is best
+    #[arg(long)]
+    quality: bool,
+
+    /// Also list photos taken by the same camera within this many
+    /// milliseconds of one another as bursts, apart from the duplicates
+    #[arg(long, value_name = "MS")]
+    bursts: Option<u64>,
+}
+
+impl MatchArgs {
+    /// `options` with the matching settings, which fails if the metric
+    /// cannot compare the hashes they produce.
+    fn apply(&self, options: DeduplicateOptions) -> Result<DeduplicateOptions, AppError> {
+        let options = DeduplicateOptions {
+            duplicate_threshold: self.duplicate_threshold,
+            threshold_pct: self.threshold_pct,
+            grouping: self.grouping,
+            verify: VerifyOptions {
+                method: self.verify,
+                tolerance: self.verify_tolerance,
+                min_ssim: self.min_ssim,
+            },
+            matcher: self.matcher,
+            hash_store: self.hash_store.clone(),
+            min_resolution: self.min_resolution,
+            min_segment_matches: self.min_segment_matches as usize,
+            classify: self.classify,
+            quality: self.quality,
+            bursts: self.bursts.map(Duration::from_millis),
+            metric: match self.metric {
+                MetricKind::Hamming => None,
+                MetricKind::WeightedHamming => {
+                    Some(Arc::new(WeightedHamming::new(self.metric_weights.clone())))
+                }
+                MetricKind::Cosine => Some(Arc::new(Cosine)),
+            },
+            approx: self.approx.then_some(LshOptions {
+                bands: self.approx_bands,
+                band_bits: self.approx_band_bits as usize,
+                seed: self.seed,
+            }),
+            ..options
+        };
+        let hasher = options.build_hasher();
+        if self.metric == MetricKind::Cosine && !hasher.is_vector() {
+            return Err(AppError::IncompatibleMetric {
+                metric: "cosine".to_string(),
+                hasher: hasher.name(),
+            });
+        }
+        Ok(options)
+    }
+}
+
+fn parse_sigma(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(sigma) if sigma > 0.0 && sigma.is_finite() => Ok(sigma),
+        _ => Err(format!("`{}` is not a positive number", value)),
+    }
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("`{}` is not a number between 0 and 1", value)),
+    }
+}
 
 #[derive(Subcommand)]
 enum Commands {
     /// Deduplicate images within a directory.
-    Deduplicate {
-        /// Directory to scan for duplicates
-        directory: String,
+    Deduplicate(Box<DeduplicateArgs>),
+
+    /// Remove duplicates from a directory based on a report file
+    /// generated by the deduplicate command.
+    Remove {
+        report_file: String,
+        output_dir: String,
+    },
+
+    /// Copy the images of a directory to another one, leaving out the
+    /// duplicates, to produce a deduplicated dataset without modifying the
+    /// source.
+    ExportClean {
+        /// Directory to export
+        source: PathBuf,
+
+        /// Directory the images are copied to, where the report is saved
+        destination: PathBuf,
+
+        /// Which file of every group is copied
+        #[arg(long, value_enum, default_value_t = Keep::HighestResolution)]
+        keep: Keep,
+
+        #[command(flatten)]
+        scan: Box<ScanArgs>,
+
+        #[command(flatten)]
+        matching: Box<MatchArgs>,
+    },
+
+    /// Act on the duplicates of a report generated by the deduplicate
+    /// command, leaving alone the groups whose files changed since.
+    #[command(group(clap::ArgGroup::new("action").required(true)))]
+    Apply {
+        /// Report file to act on, where the actions are recorded
+        report_file: PathBuf,
+
+        #[command(flatten)]
+        actions: ActionArgs,
+    },
+
+    /// Restore the files changed by a run, from the action log it saved.
+    Undo {
+        /// Action log saved by deduplicate or apply
+        log_file: PathBuf,
+    },
+
+    /// Accept groups of a report as intentional duplicates, so that later
+    /// scans of its directory and `apply` leave them alone.
+    IgnoreGroup {
+        /// Ids of the groups, as listed in the report
+        #[arg(required = true)]
+        group_ids: Vec<String>,
+
+        /// Report file listing the groups
+        #[arg(short, long, default_value = "dedup_report.json")]
+        report_file: PathBuf,
+    },
+
+    /// Build or query a persistent index of image hashes.
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+
+    /// Manage the cache of previously computed hashes.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Hash a directory in parts on several machines, then merge the parts.
+    Shard {
+        #[command(subcommand)]
+        command: ShardCommand,
+    },
+
+    /// Watch a directory and report new images that duplicate known ones.
+    Watch {
+        /// Directory to watch
+        directory: PathBuf,
+
+        /// Index file to check new images against [default: the images
+        /// already in the directory]
+        #[arg(short, long)]
+        index: Option<PathBuf>,
+
+        /// Seconds between two scans of the directory when it is polled
+        /// rather than watched with inotify, and between two checks of a
+        /// file still being written
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Print alerts as one JSON object per line
+        #[arg(long)]
+        ndjson: bool,
+
+        /// Similarity threshold for detecting duplicates
+        #[arg(short, long, default_value_t = 10)]
+        duplicate_threshold: u32,
+
+        #[command(flatten)]
+        scan: Box<ScanArgs>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Hash the images of a directory into an index file.
+    Build {
+        /// Directory to index
+        directory: PathBuf,
+
+        /// Index file to write
+        #[arg(short, long, default_value = "idar_index.json")]
+        index: PathBuf,
+
+        #[command(flatten)]
+        scan: Box<ScanArgs>,
+    },
+
+    /// Find the indexed near-duplicates of a single image.
+    Query {
+        /// Image to look up
+        image: PathBuf,
+
+        /// Index file built by `index build`
+        #[arg(short, long, default_value = "idar_index.json")]
+        index: PathBuf,
 
         /// Similarity threshold for detecting duplicates
         #[arg(short, long, default_value_t = 10)]
         duplicate_threshold: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum ShardCommand {
+    /// Hash one part of a directory into a partial hash file.
+    Hash {
+        /// Directory to hash
+        directory: PathBuf,
+
+        /// Part of the files to hash, as INDEX/COUNT (e.g. 3/8)
+        #[arg(long, value_parser = shard::parse_part)]
+        part: Part,
+
+        /// Partial hash file to write [default: idar_part_INDEX_of_COUNT.json]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        scan: Box<ScanArgs>,
+    },
 
-        /// Size of the hash to use for image comparison
-        #[arg(short, long, default_value_t = 16)]
-        hash_size: u32,
+    /// Combine partial hash files and find the duplicates across them.
+    Merge {
+        /// Partial hash files written by `shard hash`
+        #[arg(required = true)]
+        partials: Vec<PathBuf>,
 
         /// Name of the file to save the deduplication report
         #[arg(short, long, default_value = "dedup_report.json")]
-        report_file_name: String,
+        report_file_name: PathBuf,
+
+        #[command(flatten)]
+        matching: MatchArgs,
     },
+}
 
-    /// Remove duplicates from a directory based on a report file
-    /// generated by the deduplicate command.
-    Remove {
-        report_file: String,
-        output_dir: String,
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Delete all cached hashes.
+    Clear {
+        /// Location of the hash cache [default: user cache directory]
+        #[arg(long)]
+        cache_path: Option<PathBuf>,
     },
 }
 
@@ -36,25 +950,179 @@ enum Commands {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Number of worker threads [default: one per CPU]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    threads: Option<u64>,
+
+    /// Most files downloaded at the same time from remote locations such as
+    /// s3:// buckets
+    #[arg(long, global = true, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..))]
+    remote_concurrency: u64,
+
+    /// Times a failed listing or download from a remote location is tried
+    /// again
+    #[arg(long, global = true, default_value_t = 3)]
+    remote_retries: u32,
 }
 
 impl Cli {
     fn run(self) -> Result<(), AppError> {
+        if let Some(threads) = self.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads as usize)
+                .build_global()
+                .expect("the thread pool is only configured once");
+        }
+        source::configure_remote(RemoteOptions {
+            concurrency: self.remote_concurrency as usize,
+            retries: self.remote_retries,
+        });
         match self.command {
-            Commands::Deduplicate {
-                directory,
-                duplicate_threshold,
-                hash_size, 
-                report_file_name,
-            } => deduplicate::run(directory, duplicate_threshold, hash_size, &report_file_name),
+            Commands::Deduplicate(args) => {
+                let mut options = args.options()?;
+                let report_file_name = args.report_file_name();
+                options.action = args.actions.load()?;
+                if let Some(manifest) = &args.manifest {
+                    options.manifest = Some(Arc::new(Manifest::load(manifest)?));
+                }
+                if let Some(path) = &args.embeddings {
+                    let embeddings = Embeddings::load(path)?;
+                    println!("Loaded {} embeddings from {:?}", embeddings.len(), path);
+                    options.embeddings = Some(Arc::new(embeddings));
+                }
+                options.embedder = args.model.clone().map(|model| Embedder {
+                    command: args.embed_command.clone(),
+                    model,
+                });
+                let mut directories = args.directories;
+                if directories.is_empty() {
+                    directories.push(PathBuf::from("."));
+                }
+                let summary = deduplicate::run(&directories, &report_file_name, &options)?;
+                if let Some(command) = &args.on_complete {
+                    let report = source::local_dir(&directories[0]).join(&report_file_name);
+                    let status = hook::on_complete(command, &report, &summary)?;
+                    if !status.success() {
+                        eprintln!("warning: the --on-complete command exited with {}", status);
+                    }
+                }
+                match failure(&summary) {
+                    Some(code) if args.fail_on_duplicates => std::process::exit(code),
+                    _ => Ok(()),
+                }
+            }
             Commands::Remove {
                 report_file,
                 output_dir,
             } => removal::run(&report_file, &output_dir),
+            Commands::ExportClean {
+                source,
+                destination,
+                keep,
+                scan,
+                matching,
+            } => export::run(
+                &source,
+                &destination,
+                keep,
+                &matching.apply(scan.options())?,
+            ),
+            Commands::Apply {
+                report_file,
+                actions,
+            } => {
+                let options = actions.load()?.expect("an action and --keep are required");
+                actions::run(&report_file, &options)
+            }
+            Commands::Undo { log_file } => undo::run(&log_file),
+            Commands::IgnoreGroup {
+                group_ids,
+                report_file,
+            } => accepted::run(&report_file, &group_ids),
+            Commands::Index {
+                command:
+                    IndexCommand::Build {
+                        directory,
+                        index: index_path,
+                        scan,
+                    },
+            } => index::build(&directory, &index_path, &scan.options()),
+            Commands::Index {
+                command:
+                    IndexCommand::Query {
+                        image,
+                        index: index_path,
+                        duplicate_threshold,
+                    },
+            } => index::query(&image, &index_path, duplicate_threshold),
+            Commands::Cache {
+                command: CacheCommand::Clear { cache_path },
+            } => match cache_path.or_else(cache::default_cache_path) {
+                Some(path) => cache::clear(&path),
+                None => Ok(()),
+            },
+            Commands::Shard {
+                command:
+                    ShardCommand::Hash {
+                        directory,
+                        part,
+                        output,
+                        scan,
+                    },
+            } => {
+                let output = output.unwrap_or_else(|| {
+                    PathBuf::from(format!("idar_part_{}_of_{}.json", part.index, part.count))
+                });
+                shard::hash(&directory, part, &output, &scan.options())
+            }
+            Commands::Shard {
+                command:
+                    ShardCommand::Merge {
+                        partials,
+                        report_file_name,
+                        matching,
+                    },
+            } => shard::merge(
+                &partials,
+                &report_file_name,
+                &matching.apply(DeduplicateOptions::default())?,
+            ),
+            Commands::Watch {
+                directory,
+                index,
+                interval,
+                ndjson,
+                duplicate_threshold,
+                scan,
+            } => {
+                let watch = WatchOptions {
+                    interval: Duration::from_secs(interval),
+                    ndjson,
+                    index,
+                };
+                let options = DeduplicateOptions {
+                    duplicate_threshold,
+                    ..scan.options()
+                };
+                watch::run(&directory, &watch, &options)
+            }
         }
     }
 }
 
+/// Exit status of `--fail-on-duplicates` for a run with the totals
+/// `summary`, if it should fail.
+fn failure(summary: &ReportSummary) -> Option<i32> {
+    if summary.groups > 0 {
+        Some(EXIT_DUPLICATES)
+    } else if summary.errors > 0 {
+        Some(EXIT_UNREADABLE)
+    } else {
+        None
+    }
+}
+
 fn run() -> Result<(), AppError> {
     let cli = Cli::parse();
     cli.run()
@@ -66,3 +1134,14 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,160 @@
+use crate::errors::AppError;
+use crate::models::ImageInfo;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Header of the manifest column holding image paths. Without one, the
+/// first column is used.
+pub const PATH_COLUMN: &str = "path";
+
+/// A CSV file listing the images of a dataset with arbitrary extra
+/// columns, such as a label, a split or an id, which are copied to the
+/// entries of the report.
+///
+/// The first row holds the column headers. Relative paths are resolved
+/// against the directory holding the manifest.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    /// Headers of the extra columns.
+    headers: Vec<String>,
+    /// Paths in manifest order.
+    paths: Vec<PathBuf>,
+    /// Extra column values of each path, in `headers` order.
+    rows: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, AppError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(AppError::FileNotFound(path.display().to_string()));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let base = path.parent().unwrap_or(Path::new(""));
+        Ok(Manifest::parse(&contents, base))
+    }
+
+    /// Parse the CSV `contents` of a manifest, resolving relative paths
+    /// against `base`. Rows without a path are skipped.
+    pub fn parse(contents: &str, base: &Path) -> Manifest {
+        let mut records = parse_csv(contents).into_iter();
+        let Some(mut headers) = records.next() else {
+            return Manifest::default();
+        };
+        let path_column = headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(PATH_COLUMN))
+            .unwrap_or(0);
+        headers.remove(path_column);
+
+        let mut manifest = Manifest {
+            headers,
+            ..Manifest::default()
+        };
+        for mut record in records {
+            if record.len() <= path_column || record[path_column].trim().is_empty() {
+                continue;
+            }
+            let path = base.join(record.remove(path_column).trim());
+            record.resize(manifest.headers.len(), String::new());
+            if manifest.rows.insert(path.clone(), record).is_none() {
+                manifest.paths.push(path);
+            }
+        }
+        manifest
+    }
+
+    /// Paths listed by the manifest, in manifest order and without repeats.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Copy the manifest columns of `image` into it.
+    pub fn annotate(&self, image: &mut ImageInfo) {
+        if let Some(row) = self.rows.get(&image.path) {
+            image.columns = self
+                .headers
+                .iter()
+                .cloned()
+                .zip(row.iter().cloned())
+                .collect::<BTreeMap<String, String>>();
+        }
+    }
+}
+
+/// Split CSV text into records of fields.
+///
+/// Fields may be quoted with `"`, in which case they can hold commas, line
+/// breaks and doubled quotes. Blank lines are skipped.
+fn parse_csv(contents: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image_hasher::ImageHash;
+
+    #[test]
+    fn test_manifest_annotates_images() {
+        let manifest = Manifest::parse(
+            "id,path,label,split\n\
+             1,cats/a.jpg,cat,train\r\n\
+             \n\
+             2,/data/b.jpg,\"dog, small\",\"te\"\"st\"\n\
+             3,,bird,train\n\
+             4,cats/c.jpg\n",
+            Path::new("/datasets"),
+        );
+        assert_eq!(
+            manifest.paths(),
+            [
+                PathBuf::from("/datasets/cats/a.jpg"),
+                PathBuf::from("/data/b.jpg"),
+                PathBuf::from("/datasets/cats/c.jpg"),
+            ]
+        );
+
+        let mut image = ImageInfo::new(
+            PathBuf::from("/data/b.jpg"),
+            ImageHash::from_base64("DAIDBwMHAf8").unwrap(),
+        );
+        manifest.annotate(&mut image);
+        let column = |name: &str| image.columns[name].as_str();
+        assert_eq!(column("id"), "2");
+        assert_eq!(column("label"), "dog, small");
+        assert_eq!(column("split"), "te\"st");
+    }
+}
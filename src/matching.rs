@@ -0,0 +1,247 @@
+use crate::distance;
+use crate::store::ImageSource;
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Exact strategy used to find the pairs of images closer than the threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Matcher {
+    /// Compare every pair of images.
+    #[default]
+    Exhaustive,
+    /// Multi-index hashing: only compare images sharing a hash substring.
+    /// Fastest for low thresholds.
+    Mih,
+}
+
+fn distance<S: ImageSource + ?Sized>(images: &S, i: usize, j: usize) -> u32 {
+    distance::hamming(images.hash_bytes(i), images.hash_bytes(j))
+}
+
+/// Pairs of image indices `(i, j)`, with `i < j`, whose hashes are closer
+/// than `threshold`. Pairs are sorted.
+///
+/// Rows of the comparison matrix are spread across the rayon thread pool.
+pub fn candidate_pairs<S: ImageSource + ?Sized>(images: &S, threshold: u32) -> Vec<(usize, usize)> {
+    candidate_pairs_by(images, threshold, distance::hamming)
+}
+
+/// Like [`candidate_pairs`], comparing hashes by `distance` instead of the
+/// Hamming distance.
+pub fn candidate_pairs_by<S, F>(images: &S, threshold: u32, distance: F) -> Vec<(usize, usize)>
+where
+    S: ImageSource + ?Sized,
+    F: Fn(&[u8], &[u8]) -> u32 + Sync,
+{
+    let distance = &distance;
+    (0..images.len())
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            ((i + 1)..images.len())
+                .filter(move |&j| distance(images.hash_bytes(i), images.hash_bytes(j)) < threshold)
+                .map(move |j| (i, j))
+        })
+        .collect()
+}
+
+/// Settings for locality-sensitive hashing of image hashes.
+///
+/// The hash bits are shuffled with `seed` and split into `bands` bands of
+/// `band_bits` bits each; only images that agree on every bit of at least
+/// one band are compared. Two hashes that differ in `d` of their `n` bits
+/// are compared with probability `1 - (1 - (1 - d/n)^band_bits)^bands`, so
+/// more bands raise recall and wider bands reduce the number of comparisons.
+/// With 256-bit hashes, the defaults find pairs at distance 10 about 99.8%
+/// of the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LshOptions {
+    pub bands: usize,
+    pub band_bits: usize,
+    pub seed: u64,
+}
+
+impl Default for LshOptions {
+    fn default() -> Self {
+        LshOptions {
+            bands: 8,
+            band_bits: 16,
+            seed: 0,
+        }
+    }
+}
+
+/// Like [`candidate_pairs`], but only compares images sharing an LSH bucket.
+///
+/// Every returned pair is closer than `threshold`, but some close pairs may
+/// be missed. The result is deterministic for a given `options.seed`.
+pub fn approximate_pairs<S: ImageSource + ?Sized>(
+    images: &S,
+    threshold: u32,
+    options: &LshOptions,
+) -> Vec<(usize, usize)> {
+    let hash_bits = min_hash_bits(images);
+    if hash_bits == 0 {
+        return Vec::new();
+    }
+
+    let mut positions: Vec<usize> = (0..hash_bits).collect();
+    let mut rng = SplitMix64(options.seed);
+    for i in (1..positions.len()).rev() {
+        let j = (rng.next() % (i as u64 + 1)) as usize;
+        positions.swap(i, j);
+    }
+
+    let band_bits = options.band_bits.clamp(1, 64).min(hash_bits);
+    let bands: Vec<Vec<usize>> = (0..options.bands)
+        .map(|band| {
+            (0..band_bits)
+                .map(|k| positions[(band * band_bits + k) % hash_bits])
+                .collect()
+        })
+        .collect();
+    bucket_pairs(images, threshold, &bands)
+}
+
+/// Like [`candidate_pairs`], using multi-index hashing.
+///
+/// The hash bits are split into more than `threshold - 1` disjoint
+/// substrings. Two hashes closer than `threshold` agree on at least one of
+/// them, so comparing only the images sharing a substring finds exactly the
+/// same pairs as [`candidate_pairs`]. This is much faster for low
+/// thresholds, and degrades to many short substrings for high ones.
+pub fn multi_index_pairs<S: ImageSource + ?Sized>(
+    images: &S,
+    threshold: u32,
+) -> Vec<(usize, usize)> {
+    let hash_bits = min_hash_bits(images);
+    if threshold == 0 || hash_bits == 0 {
+        return Vec::new();
+    }
+
+    let substrings = (threshold as usize).max(hash_bits.div_ceil(64));
+    if substrings > hash_bits {
+        return candidate_pairs(images, threshold);
+    }
+    let bands: Vec<Vec<usize>> = (0..substrings)
+        .map(|k| (k * hash_bits / substrings..(k + 1) * hash_bits / substrings).collect())
+        .collect();
+    bucket_pairs(images, threshold, &bands)
+}
+
+fn min_hash_bits<S: ImageSource + ?Sized>(images: &S) -> usize {
+    (0..images.len())
+        .map(|index| images.hash_bytes(index).len() * 8)
+        .min()
+        .unwrap_or(0)
+}
+
+/// Pairs closer than `threshold` among the images that agree on every bit
+/// of at least one of `bands`. Each band has at most 64 bit positions.
+fn bucket_pairs<S: ImageSource + ?Sized>(
+    images: &S,
+    threshold: u32,
+    bands: &[Vec<usize>],
+) -> Vec<(usize, usize)> {
+    let mut candidates: Vec<(usize, usize)> = bands
+        .par_iter()
+        .flat_map_iter(|bits| {
+            let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+            for index in 0..images.len() {
+                let bytes = images.hash_bytes(index);
+                let key = bits.iter().fold(0u64, |key, &bit| {
+                    (key << 1) | ((bytes[bit / 8] >> (bit % 8)) & 1) as u64
+                });
+                buckets.entry(key).or_default().push(index);
+            }
+
+            let mut pairs: Vec<(usize, usize)> = Vec::new();
+            for bucket in buckets.values().filter(|b| b.len() > 1) {
+                for (n, &i) in bucket.iter().enumerate() {
+                    pairs.extend(bucket[n + 1..].iter().map(|&j| (i, j)));
+                }
+            }
+            pairs
+        })
+        .collect();
+    candidates.par_sort_unstable();
+    candidates.dedup();
+
+    candidates
+        .into_par_iter()
+        .filter(|&(i, j)| distance(images, i, j) < threshold)
+        .collect()
+}
+
+/// Small deterministic generator used to shuffle hash bits.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+    use image_hasher::ImageHash;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_approximate_pairs_are_subset_of_exact() {
+        let images: Vec<ImageInfo> = (0..64u32)
+            .map(|i| {
+                ImageInfo::new(
+                    PathBuf::from(format!("{}.png", i)),
+                    ImageHash::from_bytes(&(i / 2 * 0x0101_0101).to_le_bytes()).unwrap(),
+                )
+            })
+            .collect();
+
+        let exact = candidate_pairs(&images[..], 3);
+        let approx = approximate_pairs(&images[..], 3, &LshOptions::default());
+        assert!(approx.iter().all(|pair| exact.contains(pair)));
+        // Images with identical hashes always share every bucket.
+        assert!(approx.contains(&(0, 1)));
+        assert_eq!(
+            approx,
+            approximate_pairs(&images[..], 3, &LshOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_multi_index_pairs_match_exhaustive() {
+        let mut rng = SplitMix64(3);
+        let base: Vec<u8> = (0..8).map(|_| rng.next() as u8).collect();
+        let images: Vec<ImageInfo> = (0..80)
+            .map(|i| {
+                let mut bytes = base.clone();
+                for _ in 0..(rng.next() % 12) {
+                    let bit = rng.next() as usize % 64;
+                    bytes[bit / 8] ^= 1 << (bit % 8);
+                }
+                ImageInfo::new(
+                    PathBuf::from(format!("{}.png", i)),
+                    ImageHash::from_bytes(&bytes).unwrap(),
+                )
+            })
+            .collect();
+
+        for threshold in [0, 1, 4, 10, 70] {
+            assert_eq!(
+                multi_index_pairs(&images[..], threshold),
+                candidate_pairs(&images[..], threshold),
+                "threshold {}",
+                threshold
+            );
+        }
+    }
+}
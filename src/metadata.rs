@@ -0,0 +1,261 @@
+use crate::actions::replace;
+use crate::archive;
+use crate::exif::Exif;
+use crate::pages;
+use crate::raw;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+const SOI: [u8; 2] = [0xFF, 0xD8];
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP13: u8 = 0xED;
+const SOS: u8 = 0xDA;
+
+/// Largest payload of a JPEG segment, whose length counts its own two bytes.
+const MAX_SEGMENT: usize = u16::MAX as usize - 2;
+
+/// Kinds of metadata carried over to a kept JPEG, each stored in a segment
+/// of its own starting with a header saying what it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Camera settings, capture time and GPS position.
+    Exif,
+    /// Keywords, ratings and descriptions written by photo tools.
+    Xmp,
+    /// Keywords and captions in the older format of press agencies.
+    Iptc,
+}
+
+const KINDS: [Kind; 3] = [Kind::Exif, Kind::Xmp, Kind::Iptc];
+
+impl Kind {
+    fn marker(self) -> u8 {
+        match self {
+            Kind::Exif | Kind::Xmp => APP1,
+            Kind::Iptc => APP13,
+        }
+    }
+
+    fn header(self) -> &'static [u8] {
+        match self {
+            Kind::Exif => b"Exif\0\0",
+            Kind::Xmp => b"http://ns.adobe.com/xap/1.0/\0",
+            Kind::Iptc => b"Photoshop 3.0\0",
+        }
+    }
+}
+
+/// A segment of the header of a JPEG file.
+struct Segment<'a> {
+    marker: u8,
+    /// Offset in the file of the marker starting the segment.
+    start: usize,
+    /// Contents of the segment, after its length.
+    data: &'a [u8],
+}
+
+impl Segment<'_> {
+    fn kind(&self) -> Option<Kind> {
+        KINDS
+            .into_iter()
+            .find(|kind| self.marker == kind.marker() && self.data.starts_with(kind.header()))
+    }
+
+    /// What the segment holds, after its header.
+    fn payload(&self, kind: Kind) -> &[u8] {
+        &self.data[kind.header().len()..]
+    }
+}
+
+/// Copy the metadata the kept JPEG at `kept` lacks from the first of the
+/// `removed` duplicates that has it, returning whether any was copied.
+///
+/// EXIF, XMP and IPTC metadata are each copied whole, so that a stripped
+/// web copy kept for its quality gets the capture time, GPS position and
+/// keywords of the original, but a kept file with EXIF of its own keeps
+/// it. Copied EXIF says the picture is upright, as the pixels of a
+/// duplicate that matched once turned are. Only JPEG files are written to,
+/// keeping their modification time and permissions; other files, archive
+/// members and pages are left alone.
+pub fn merge(kept: &Path, removed: &[&Path]) -> io::Result<bool> {
+    if archive::is_member(kept) || pages::is_page(kept) {
+        return Ok(false);
+    }
+    let data = fs::read(kept)?;
+    let Some(segments) = segments(&data) else {
+        return Ok(false);
+    };
+    let mut missing: Vec<Kind> = KINDS
+        .into_iter()
+        .filter(|&kind| !segments.iter().any(|segment| segment.kind() == Some(kind)))
+        .collect();
+    let mut added = Vec::new();
+    for path in removed {
+        if missing.is_empty() {
+            break;
+        }
+        let found = metadata_of(path, &missing);
+        missing.retain(|kind| !found.iter().any(|(found, _)| found == kind));
+        for (kind, payload) in found {
+            added.extend([0xFF, kind.marker()]);
+            added.extend(((kind.header().len() + payload.len() + 2) as u16).to_be_bytes());
+            added.extend(kind.header());
+            added.extend(payload);
+        }
+    }
+    if added.is_empty() {
+        return Ok(false);
+    }
+
+    // The new segments go after the JFIF header, which must come first.
+    let at = segments
+        .iter()
+        .find(|segment| segment.marker != APP0)
+        .map_or(data.len(), |segment| segment.start);
+    let metadata = fs::metadata(kept)?;
+    replace(kept, |merged| {
+        fs::write(merged, [&data[..at], &added, &data[at..]].concat())?;
+        File::options()
+            .write(true)
+            .open(merged)?
+            .set_modified(metadata.modified()?)?;
+        fs::set_permissions(merged, metadata.permissions())
+    })?;
+    Ok(true)
+}
+
+/// The metadata of the kinds `wanted` that the file at `path` holds, with
+/// what each holds. Files that cannot be read hold none.
+fn metadata_of(path: &Path, wanted: &[Kind]) -> Vec<(Kind, Vec<u8>)> {
+    let mut data = Vec::new();
+    if archive::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .is_err()
+    {
+        return Vec::new();
+    }
+    let found: Vec<(Kind, Vec<u8>)> = match segments(&data) {
+        Some(segments) => wanted
+            .iter()
+            .filter_map(|&kind| {
+                let segment = segments
+                    .iter()
+                    .find(|segment| segment.kind() == Some(kind))?;
+                Some((kind, segment.payload(kind).to_vec()))
+            })
+            .collect(),
+        // The EXIF of raw files points into the rest of the file, and is
+        // not copied.
+        None if wanted.contains(&Kind::Exif) && !raw::is_raw(path) => Exif::read(path)
+            .map(|exif| (Kind::Exif, exif.as_bytes().to_vec()))
+            .into_iter()
+            .collect(),
+        None => Vec::new(),
+    };
+    found
+        .into_iter()
+        .filter_map(|(kind, payload)| {
+            let payload = match kind {
+                Kind::Exif => Exif::parse(payload)?.upright().as_bytes().to_vec(),
+                _ => payload,
+            };
+            (kind.header().len() + payload.len() <= MAX_SEGMENT).then_some((kind, payload))
+        })
+        .collect()
+}
+
+/// The segments of the JPEG file `data` up to its image data, or `None` if
+/// it is not a JPEG file.
+fn segments(data: &[u8]) -> Option<Vec<Segment<'_>>> {
+    if !data.starts_with(&SOI) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut at = SOI.len();
+    while let [0xFF, marker, ..] = &data[at.min(data.len())..] {
+        let marker = *marker;
+        if marker == SOS {
+            segments.push(Segment {
+                marker,
+                start: at,
+                data: &[],
+            });
+            break;
+        }
+        let length = u16::from_be_bytes(data.get(at + 2..at + 4)?.try_into().ok()?) as usize;
+        let contents = data.get(at + 4..at + 2 + length.max(2))?;
+        segments.push(Segment {
+            marker,
+            start: at,
+            data: contents,
+        });
+        at += 2 + length.max(2);
+    }
+    Some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::metadata::Orientation;
+    use tempfile::tempdir;
+
+    /// A JPEG file with the segments `extra` after its JFIF header.
+    fn jpeg(extra: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        image::RgbImage::new(8, 8)
+            .write_to(&mut io::Cursor::new(&mut data), image::ImageFormat::Jpeg)
+            .unwrap();
+        let segments = segments(&data).unwrap();
+        let at = segments.iter().find(|s| s.marker != APP0).unwrap().start;
+        [&data[..at], extra, &data[at..]].concat()
+    }
+
+    fn segment(kind: Kind, payload: &[u8]) -> Vec<u8> {
+        let length = (kind.header().len() + payload.len() + 2) as u16;
+        [
+            &[0xFF, kind.marker()],
+            &length.to_be_bytes()[..],
+            kind.header(),
+            payload,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_merge_copies_missing_metadata_into_the_kept_file() {
+        let dir = tempdir().unwrap();
+        // Little endian, IFD0 at 8 holding only the orientation.
+        let mut exif = b"II\x2a\0\x08\0\0\0".to_vec();
+        exif.extend([1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        exif.extend([0, 0, 0, 0]);
+        let xmp = b"<x:xmpmeta><dc:subject>beach</dc:subject></x:xmpmeta>";
+        let kept = dir.path().join("web.jpg");
+        let original = dir.path().join("original.jpg");
+        let other = dir.path().join("other.jpg");
+        fs::write(&kept, jpeg(&segment(Kind::Xmp, b"<x:xmpmeta/>"))).unwrap();
+        fs::write(&original, jpeg(&segment(Kind::Exif, &exif))).unwrap();
+        fs::write(&other, jpeg(&segment(Kind::Xmp, xmp))).unwrap();
+        image::open(&kept).unwrap();
+
+        assert!(merge(&kept, &[&original, &other]).unwrap());
+        let data = fs::read(&kept).unwrap();
+        let segments = segments(&data).unwrap();
+        let kinds: Vec<_> = segments.iter().filter_map(Segment::kind).collect();
+        assert_eq!(kinds, [Kind::Exif, Kind::Xmp]);
+        let payload = |kind| {
+            let segment = segments.iter().find(|s| s.kind() == Some(kind)).unwrap();
+            segment.payload(kind).to_vec()
+        };
+        let copied = Exif::parse(payload(Kind::Exif)).unwrap();
+        assert_eq!(copied.orientation(), Some(Orientation::NoTransforms));
+        assert_eq!(payload(Kind::Xmp), b"<x:xmpmeta/>");
+        image::open(&kept).unwrap();
+
+        assert!(!merge(&kept, &[&original, &other]).unwrap());
+        fs::write(dir.path().join("kept.png"), b"not a jpeg").unwrap();
+        assert!(!merge(&dir.path().join("kept.png"), &[&original]).unwrap());
+    }
+}
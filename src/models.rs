@@ -1,69 +1,643 @@
+use crate::actions::Action;
+use crate::bursts::Burst;
+use crate::cache::FileStamp;
+use crate::content::ContentKind;
+use crate::crops::Crop;
+use crate::decode::Colorspace;
+use crate::hashing::{Channels, HashAlgorithm, Preprocessing};
+use crate::verify::Verification;
 use image_hasher::ImageHash;
+use indicatif::HumanBytes;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{self};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ImageInfo {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
     pub path: PathBuf,
     #[serde(
         serialize_with = "crate::serialization::hash_to_base64",
         deserialize_with = "crate::serialization::hash_from_base64"
     )]
     pub hash: ImageHash,
+    /// Input directory the image was found under.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::serialization::optional_path_to_json",
+        deserialize_with = "crate::serialization::optional_path_from_json"
+    )]
+    pub root: Option<PathBuf>,
+    /// Other paths hard linked to the same file, collapsed into this entry.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    pub links: Vec<PathBuf>,
+    /// Size of the image before it was downscaled for hashing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<Dimensions>,
+    /// Columns of the `--manifest` row describing the image, by header.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub columns: BTreeMap<String, String>,
+    /// Metadata files kept next to the image, such as `.xmp` or Google
+    /// Takeout `.json` files, which belong with it wherever it goes.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    pub sidecars: Vec<PathBuf>,
+    /// Hashes of the rotated and mirrored versions of the image, computed
+    /// with `--invariant` and matched alongside `hash`.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::hashes_to_base64",
+        deserialize_with = "crate::serialization::hashes_from_base64"
+    )]
+    pub variants: Vec<ImageHash>,
+    /// Hashes of the regions of the image, computed with `--crop-resistant`
+    /// so that cropped copies can be matched.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::hashes_to_base64",
+        deserialize_with = "crate::serialization::hashes_from_base64"
+    )]
+    pub segments: Vec<ImageHash>,
+    /// Hashes of windows of the image at several scales, computed with
+    /// `--find-crops` so that crops of it can be found.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::hashes_to_base64",
+        deserialize_with = "crate::serialization::hashes_from_base64"
+    )]
+    pub windows: Vec<ImageHash>,
+    /// Hashes of the keyframes of an animated image, matched as a sequence
+    /// unless `--first-frame` is given.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::hashes_to_base64",
+        deserialize_with = "crate::serialization::hashes_from_base64"
+    )]
+    pub frames: Vec<ImageHash>,
+    /// Hash of the image with its corners masked, computed with
+    /// `--watermarks` to match copies with a logo or date in a corner.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "crate::serialization::hashes_to_base64",
+        deserialize_with = "crate::serialization::hashes_from_base64"
+    )]
+    pub masked: Vec<ImageHash>,
+    /// Colour model the image is stored in when it had to be converted to
+    /// RGB to be hashed, such as CMYK for press-ready JPEGs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colorspace: Option<Colorspace>,
+    /// What the image most likely shows, tagged with `--tag-content`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<ContentKind>,
+    /// What the keep strategy suggests doing with the image when it is in
+    /// a duplicate group, so that scripts reading the report need not pick
+    /// the copy to keep themselves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggested_action: Option<SuggestedAction>,
+}
+
+impl ImageInfo {
+    /// An image at `path` with the perceptual `hash`, and nothing else known
+    /// about it.
+    pub fn new(path: PathBuf, hash: ImageHash) -> ImageInfo {
+        ImageInfo {
+            path,
+            hash,
+            root: None,
+            links: Vec::new(),
+            dimensions: None,
+            columns: BTreeMap::new(),
+            sidecars: Vec::new(),
+            variants: Vec::new(),
+            segments: Vec::new(),
+            windows: Vec::new(),
+            frames: Vec::new(),
+            masked: Vec::new(),
+            colorspace: None,
+            content: None,
+            suggested_action: None,
+        }
+    }
+
+    /// An image at `path` with a one-byte hash of zeros, for tests that
+    /// need an image but not its hash.
+    #[cfg(test)]
+    pub(crate) fn at(path: impl Into<PathBuf>) -> ImageInfo {
+        ImageInfo::new(path.into(), ImageHash::from_bytes(&[0]).unwrap())
+    }
+
+    /// Drop the variant, region, window, keyframe and masked hashes, which
+    /// only serve matching and are left out of reports.
+    pub fn clear_match_hashes(&mut self) {
+        self.variants.clear();
+        self.segments.clear();
+        self.windows.clear();
+        self.frames.clear();
+        self.masked.clear();
+    }
+}
+
+/// Width and height of an image in pixels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Dimensions {
+    /// Whether these dimensions are narrower or shorter than `minimum`.
+    pub fn is_below(&self, minimum: Dimensions) -> bool {
+        self.width < minimum.width || self.height < minimum.height
+    }
+}
+
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
 }
 
+/// A file or directory that could not be read, and why.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EntryError {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub path: PathBuf,
+    pub cause: String,
+}
+
+impl EntryError {
+    pub fn new(path: &Path, cause: impl fmt::Display) -> Self {
+        EntryError {
+            path: path.to_path_buf(),
+            cause: cause.to_string(),
+        }
+    }
+}
+
+/// How the members of a duplicate group were matched.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchKind {
+    /// Members have similar perceptual hashes.
+    #[default]
+    Perceptual,
+    /// Members are byte-identical files.
+    Exact,
+    /// Members decode to the same pixels, but are not all byte-identical.
+    Pixel,
+    /// Some members only match once the corners, where logos, watermarks
+    /// and dates are put, are masked, with `--watermarks`.
+    Watermarked,
+}
+
+/// What the keep strategy suggests doing with an item of a duplicate group.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestedAction {
+    /// The item is the copy to keep.
+    Keep,
+    /// The item is a duplicate of the kept copy.
+    Remove,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DuplicatesGroup {
+    /// Short name of the group, derived from the paths of its items, that
+    /// `idar ignore-group` takes.
+    #[serde(default)]
+    pub id: String,
     pub items: Vec<ImageInfo>,
+    #[serde(default)]
+    pub kind: MatchKind,
+    /// Similarity of the verified pairs of items, when the verification
+    /// method scores them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<PairScore>,
+    /// Sets of items found identical by `--classify`. Items in no set only
+    /// match perceptually.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identical: Vec<IdenticalSet>,
+    /// Quality of the items, measured with `--quality`, to help pick the
+    /// copy to keep.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quality: Vec<ImageQuality>,
+    /// Items from best to worst for `--keep best-quality`, with why each
+    /// ranks below the one before it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranking: Vec<QualityRank>,
+    /// Bytes freed by removing every item but the one the keep strategy
+    /// picks.
+    #[serde(default)]
+    pub reclaimable_bytes: u64,
+    /// Pairs of items that only match once their corners are masked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watermarked: Vec<VariantPair>,
+    /// Size and modification time of the items when the report was
+    /// written, checked by `idar apply` before acting on them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileState>,
 }
 
+impl DuplicatesGroup {
+    pub fn new(items: Vec<ImageInfo>) -> Self {
+        DuplicatesGroup {
+            id: group_id(&items),
+            items,
+            kind: MatchKind::Perceptual,
+            scores: Vec::new(),
+            identical: Vec::new(),
+            quality: Vec::new(),
+            ranking: Vec::new(),
+            reclaimable_bytes: 0,
+            watermarked: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+}
+
+/// Checksum of the sorted paths of `items`, the same whatever order they
+/// are found in.
+fn group_id(items: &[ImageInfo]) -> String {
+    let mut paths: Vec<&Path> = items.iter().map(|item| item.path.as_path()).collect();
+    paths.sort();
+    let mut hasher = crc32fast::Hasher::new();
+    for path in paths {
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(&[0]);
+    }
+    format!("{:08x}", hasher.finalize())
+}
+
+/// Size and modification time of a file at some point.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileState {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub stamp: FileStamp,
+}
+
+/// Items of a group that are identical beyond their perceptual hashes.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdenticalSet {
+    /// [`MatchKind::Exact`] for byte-identical files, or
+    /// [`MatchKind::Pixel`] for files with the same pixels.
+    pub kind: MatchKind,
+    #[serde(
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    pub paths: Vec<PathBuf>,
+}
+
+/// Two images that differ by a logo, watermark or date in a corner.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VariantPair {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub first: PathBuf,
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub second: PathBuf,
+}
+
+/// SSIM score of a pair of images accepted by `--verify ssim`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PairScore {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub first: PathBuf,
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub second: PathBuf,
+    pub ssim: f64,
+}
+
+/// Quality measures of an item of a duplicate group.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageQuality {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub path: PathBuf,
+    /// Variance of the Laplacian of the image at a common size; blurred
+    /// copies score lower.
+    pub sharpness: f64,
+    /// Fraction of the pixels clipped to black or white.
+    pub clipped: f64,
+    /// Quality a JPEG was saved with, estimated from its quantization
+    /// tables on the usual 1 to 100 scale.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jpeg_quality: Option<u8>,
+    /// Overall score from 0 to 100, relative to the other items of the
+    /// group: the best copy of every measure scores 100.
+    pub score: f64,
+}
+
+/// Place of an item of a duplicate group in its ranking by quality, with
+/// the measures that put it there.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QualityRank {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pixels: Option<u64>,
+    /// Whether the file is in a format that keeps every pixel, such as PNG.
+    pub lossless: bool,
+    /// Overall score of [`ImageQuality`], when measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Why the item ranks below the previous one; none for the best.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DeduplicationMetadata {
+    /// First scanned directory, where the report is saved.
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
     pub directory_path: PathBuf,
+    /// Every scanned directory, including `directory_path`.
+    #[serde(
+        default,
+        serialize_with = "crate::serialization::paths_to_json",
+        deserialize_with = "crate::serialization::paths_from_json"
+    )]
+    pub roots: Vec<PathBuf>,
+    /// Whether only duplicates spanning different roots were reported.
+    #[serde(default)]
+    pub cross_root: bool,
+    /// Largest hamming distance accepted between duplicates, exclusive.
     pub threshold: u32,
+    /// `threshold` as a fraction of `hash_bits`.
+    #[serde(default)]
+    pub threshold_pct: f64,
+    /// Number of bits in each image hash.
+    #[serde(default)]
+    pub hash_bits: u32,
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    /// Color planes hashed on their own, with `--channels`.
+    #[serde(default)]
+    pub channels: Channels,
+    /// Processing applied to images before hashing them.
+    #[serde(default)]
+    pub preprocessing: Preprocessing,
+    #[serde(default)]
+    pub verification: Verification,
+    /// Set when only a sample of the scanned files was hashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<Sample>,
+    /// Images smaller than this were left out of matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_resolution: Option<Dimensions>,
+    /// Number of images hashed, against which `idar apply` checks
+    /// `--max-delete-pct`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanned: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// Size of the sample hashed by a `--limit` run.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Sample {
+    /// Files hashed.
+    pub sampled: usize,
+    /// Files found before sampling.
+    pub total: usize,
+}
+
+impl Sample {
+    /// Number of duplicates expected among all `total` files, given the
+    /// `duplicates` found in the sample.
+    ///
+    /// A duplicate is only found when its original was sampled too, so with
+    /// a fraction `p` of the files sampled about `p²` of the duplicate pairs
+    /// show up. The estimate assumes duplicates mostly come in pairs.
+    pub fn estimate(&self, duplicates: usize) -> usize {
+        if self.sampled == 0 {
+            return 0;
+        }
+        let fraction = self.sampled as f64 / self.total as f64;
+        let estimate = (duplicates as f64 / (fraction * fraction)).round() as usize;
+        estimate.min(self.total.saturating_sub(1))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DeduplicationReport {
     pub metadata: DeduplicationMetadata,
     pub groups: Vec<DuplicatesGroup>,
     pub total_duplicates: usize,
+    /// Bytes freed by removing the duplicates of every group.
+    #[serde(default)]
+    pub reclaimable_bytes: u64,
+    /// Images below `metadata.min_resolution`, listed but never matched.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub below_resolution: Vec<ImageInfo>,
+    /// Files and directories skipped because they could not be read.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<EntryError>,
+    /// Images that appear to be crops of other images, found with
+    /// `--find-crops`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub crops: Vec<Crop>,
+    /// Photos taken in quick succession by the same camera, found with
+    /// `--bursts`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bursts: Vec<Burst>,
+    /// What was done to the duplicates of every group, with `--delete`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<Action>,
 }
 
 impl DeduplicationReport {
-    pub fn new(
-        directory_path: PathBuf,
-        groups: Vec<DuplicatesGroup>,
-        duplicate_threshold: u32,
-    ) -> Self {
-        let metadata = DeduplicationMetadata {
-            directory_path,
-            threshold: duplicate_threshold,
-        };
-
+    pub fn new(metadata: DeduplicationMetadata, groups: Vec<DuplicatesGroup>) -> Self {
         let total_duplicates: usize =
             groups.iter().map(|g| g.items.len()).sum::<usize>() - groups.len();
+        let reclaimable_bytes = groups.iter().map(|g| g.reclaimable_bytes).sum();
 
         DeduplicationReport {
             metadata,
             groups,
             total_duplicates,
+            reclaimable_bytes,
+            below_resolution: Vec::new(),
+            errors: Vec::new(),
+            crops: Vec::new(),
+            bursts: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn summary(&self) -> ReportSummary {
+        ReportSummary {
+            metadata: self.metadata.clone(),
+            groups: self.groups.len(),
+            exact_groups: self
+                .groups
+                .iter()
+                .filter(|g| g.kind == MatchKind::Exact)
+                .count(),
+            total_duplicates: self.total_duplicates,
+            reclaimable_bytes: self.reclaimable_bytes,
+            below_resolution: self.below_resolution.len(),
+            errors: self.errors.len(),
+            crops: self.crops.len(),
+            bursts: self.bursts.len(),
+            actions: self.actions.iter().filter(|a| a.taken()).count(),
+            failed_actions: self.actions.iter().filter(|a| !a.succeeded()).count(),
         }
     }
 }
 
 impl fmt::Display for DeduplicationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
+/// Totals of a deduplication report, printed at the end of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportSummary {
+    pub metadata: DeduplicationMetadata,
+    pub groups: usize,
+    pub exact_groups: usize,
+    pub total_duplicates: usize,
+    pub reclaimable_bytes: u64,
+    pub below_resolution: usize,
+    pub errors: usize,
+    pub crops: usize,
+    pub bursts: usize,
+    /// Actions taken on duplicates outside of dry runs, and those that
+    /// failed or were skipped.
+    pub actions: usize,
+    pub failed_actions: usize,
+}
+
+impl fmt::Display for ReportSummary {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Deduplication Report:")?;
-        writeln!(
-            f,
-            "Directory path: {}",
-            self.metadata.directory_path.display()
-        )?;
-        writeln!(f, "Similarity threshold: {}", self.metadata.threshold)?;
-        writeln!(f, "Number of duplicate groups: {}", self.groups.len())?;
+        if self.metadata.roots.len() > 1 {
+            let roots: Vec<String> = self
+                .metadata
+                .roots
+                .iter()
+                .map(|root| root.display().to_string())
+                .collect();
+            writeln!(f, "Directories: {}", roots.join(", "))?;
+            if self.metadata.cross_root {
+                writeln!(f, "Only duplicates across directories are reported")?;
+            }
+        } else {
+            writeln!(
+                f,
+                "Directory path: {}",
+                self.metadata.directory_path.display()
+            )?;
+        }
+        if self.metadata.hash_bits > 0 {
+            writeln!(
+                f,
+                "Similarity threshold: {} ({:.1}% of {} bits)",
+                self.metadata.threshold,
+                self.metadata.threshold_pct * 100.0,
+                self.metadata.hash_bits
+            )?;
+        } else {
+            writeln!(f, "Similarity threshold: {}", self.metadata.threshold)?;
+        }
+        writeln!(f, "Hash algorithm: {}", self.metadata.algorithm)?;
+        if self.metadata.channels != Channels::Luma {
+            writeln!(f, "Hashed channels: {}", self.metadata.channels)?;
+        }
+        if self.metadata.verification != Verification::None {
+            writeln!(f, "Verification: {}", self.metadata.verification)?;
+        }
+        writeln!(f, "Number of duplicate groups: {}", self.groups)?;
+        writeln!(f, "Exact duplicate groups: {}", self.exact_groups)?;
         writeln!(f, "Total number of duplicates: {}", self.total_duplicates)?;
+        if self.reclaimable_bytes > 0 {
+            writeln!(
+                f,
+                "Reclaimable space: {}",
+                HumanBytes(self.reclaimable_bytes)
+            )?;
+        }
+        if let Some(minimum) = self.metadata.min_resolution {
+            writeln!(
+                f,
+                "Images below {} (not matched): {}",
+                minimum, self.below_resolution
+            )?;
+        }
+        if self.crops > 0 {
+            writeln!(f, "Crops of other images: {}", self.crops)?;
+        }
+        if self.bursts > 0 {
+            writeln!(f, "Bursts of photos: {}", self.bursts)?;
+        }
+        if self.actions > 0 || self.failed_actions > 0 {
+            writeln!(f, "Duplicates acted on: {}", self.actions)?;
+        }
+        if self.failed_actions > 0 {
+            writeln!(f, "Duplicates left untouched: {}", self.failed_actions)?;
+        }
+        if self.errors > 0 {
+            writeln!(f, "Unreadable files and directories: {}", self.errors)?;
+        }
+        if let Some(sample) = self.metadata.sample {
+            let estimate = sample.estimate(self.total_duplicates);
+            writeln!(
+                f,
+                "Sampled {} of {} files; about {} duplicates ({:.1}%) expected among all of them",
+                sample.sampled,
+                sample.total,
+                estimate,
+                estimate as f64 * 100.0 / sample.total.max(1) as f64
+            )?;
+        }
         Ok(())
     }
 }
@@ -75,20 +649,35 @@ mod test {
     #[test]
     fn test_serialize_deserialize_dedupe_report() {
         let hash: ImageHash = ImageHash::from_base64("DAIDBwMHAf8").unwrap();
-        let image = ImageInfo {
-            path: PathBuf::from("/path/to/image.jpg"),
-            hash,
-        };
+        let image = ImageInfo::new(PathBuf::from("/path/to/image.jpg"), hash);
 
         let report = DeduplicationReport {
             metadata: DeduplicationMetadata {
                 directory_path: PathBuf::from("/path/to/directory"),
+                roots: vec![PathBuf::from("/path/to/directory")],
+                cross_root: false,
                 threshold: 10,
+                threshold_pct: 10.0 / 64.0,
+                hash_bits: 64,
+                algorithm: HashAlgorithm::Gradient,
+                channels: Channels::Luma,
+                preprocessing: Preprocessing::default(),
+                verification: Verification::None,
+                sample: None,
+                min_resolution: None,
+                scanned: Some(2),
             },
-            groups: vec![DuplicatesGroup {
-                items: vec![image.clone()],
-            }],
+            groups: vec![DuplicatesGroup::new(vec![image.clone()])],
             total_duplicates: 1,
+            reclaimable_bytes: 0,
+            below_resolution: Vec::new(),
+            errors: vec![EntryError::new(
+                Path::new("/path/to/private"),
+                "Permission denied (os error 13)",
+            )],
+            crops: Vec::new(),
+            bursts: Vec::new(),
+            actions: Vec::new(),
         };
 
         // Serialize and then deserialize the report
@@ -96,4 +685,15 @@ mod test {
         let deserialized: DeduplicationReport = serde_json::from_str(&serialized).unwrap();
         assert_eq!(report, deserialized);
     }
+
+    #[test]
+    fn test_sample_estimate() {
+        let sample = Sample {
+            sampled: 100,
+            total: 1000,
+        };
+        assert_eq!(sample.estimate(0), 0);
+        assert_eq!(sample.estimate(2), 200);
+        assert_eq!(sample.estimate(50), 999);
+    }
 }
@@ -0,0 +1,174 @@
+use crate::archive;
+use image::error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use image::{DynamicImage, ImageBuffer, ImageError, ImageFormat, ImageResult};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::{ColorType, TiffError};
+
+/// Separates the path of a multi-page file from a page number, as in
+/// `scans/contract.tif#2`.
+pub const SEPARATOR: char = '#';
+
+/// Extensions of the files whose pages can be scanned separately.
+const EXTENSIONS: &[&str] = &["tif", "tiff"];
+
+/// Whether `path` names a file that may hold several pages, by its
+/// extension.
+pub fn is_multipage(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy();
+        EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    })
+}
+
+/// Path of page `page` of `file`, counting from 1.
+pub fn page_path(file: &Path, page: usize) -> PathBuf {
+    let mut path = OsString::from(file.as_os_str());
+    path.push(format!("{}{}", SEPARATOR, page));
+    PathBuf::from(path)
+}
+
+/// Split the path of a page into the file and page number, or return
+/// `None` for any other path.
+///
+/// The first page of a file is the file itself, so only pages from the
+/// second on have paths of their own.
+pub fn split(path: &Path) -> Option<(PathBuf, usize)> {
+    let (file, page) = path.to_str()?.rsplit_once(SEPARATOR)?;
+    let page: usize = page.parse().ok().filter(|&page| page >= 2)?;
+    let file = Path::new(file);
+    (is_multipage(file) && (file.is_file() || archive::is_member(file)))
+        .then(|| (file.to_path_buf(), page))
+}
+
+/// Whether `path` names a page of a multi-page file.
+pub fn is_page(path: &Path) -> bool {
+    split(path).is_some()
+}
+
+/// The file holding `path`: the file of a page, or the path itself.
+pub fn file_of(path: &Path) -> PathBuf {
+    match split(path) {
+        Some((file, _)) => file,
+        None => path.to_path_buf(),
+    }
+}
+
+/// Number of pages of the TIFF file or archive member at `path`.
+pub fn count(path: &Path) -> io::Result<usize> {
+    let mut decoder = Decoder::new(archive::open(path)?).map_err(io_error)?;
+    let mut pages = 1;
+    while decoder.more_images() {
+        decoder.next_image().map_err(io_error)?;
+        pages += 1;
+    }
+    Ok(pages)
+}
+
+/// Decode page `page` of the TIFF file at `file`, counting from 1.
+///
+/// Grayscale and RGB pages, with or without alpha, at 8 or 16 bits per
+/// sample are supported.
+pub fn decode(file: &Path, page: usize) -> ImageResult<DynamicImage> {
+    let mut decoder = Decoder::new(archive::open(file)?).map_err(image_error)?;
+    decoder
+        .seek_to_image(page.saturating_sub(1))
+        .map_err(image_error)?;
+    let (width, height) = decoder.dimensions().map_err(image_error)?;
+    let color = decoder.colortype().map_err(image_error)?;
+    let img = match (decoder.read_image().map_err(image_error)?, color) {
+        (DecodingResult::U8(buf), ColorType::Gray(8)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLuma8)
+        }
+        (DecodingResult::U8(buf), ColorType::GrayA(8)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLumaA8)
+        }
+        (DecodingResult::U8(buf), ColorType::RGB(8)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)
+        }
+        (DecodingResult::U8(buf), ColorType::RGBA(8)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgba8)
+        }
+        (DecodingResult::U16(buf), ColorType::Gray(16)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLuma16)
+        }
+        (DecodingResult::U16(buf), ColorType::GrayA(16)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLumaA16)
+        }
+        (DecodingResult::U16(buf), ColorType::RGB(16)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb16)
+        }
+        (DecodingResult::U16(buf), ColorType::RGBA(16)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgba16)
+        }
+        _ => {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormat::Tiff.into(),
+                    UnsupportedErrorKind::GenericFeature(format!("{:?} pages", color)),
+                ),
+            ))
+        }
+    };
+    img.ok_or_else(|| image_error(TiffError::LimitsExceeded))
+}
+
+fn io_error(err: TiffError) -> io::Error {
+    match err {
+        TiffError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+fn image_error(err: TiffError) -> ImageError {
+    match err {
+        TiffError::IoError(err) => ImageError::IoError(err),
+        err => ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Tiff),
+            err,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{self, DecodeOptions};
+    use image::codecs::tiff::TiffEncoder;
+    use image::{ExtendedColorType, ImageEncoder, RgbImage};
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_decode_pages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scan.tif");
+        let pages: Vec<RgbImage> = (0..3u8)
+            .map(|page| RgbImage::from_pixel(8, 4, image::Rgb([page * 100, 0, 0])))
+            .collect();
+        let mut encoder = tiff::encoder::TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+        for page in &pages {
+            encoder
+                .write_image::<tiff::encoder::colortype::RGB8>(8, 4, page.as_raw())
+                .unwrap();
+        }
+        drop(encoder);
+
+        assert_eq!(count(&path).unwrap(), 3);
+        let third = page_path(&path, 3);
+        assert_eq!(split(&third), Some((path.clone(), 3)));
+        assert_eq!(split(&page_path(&path, 1)), None);
+        let (decoded, _) = decode::open_sized(&third, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.to_rgb8(), pages[2]);
+
+        let single = dir.path().join("single.tif");
+        TiffEncoder::new(File::create(&single).unwrap())
+            .write_image(pages[0].as_raw(), 8, 4, ExtendedColorType::Rgb8)
+            .unwrap();
+        assert_eq!(count(&single).unwrap(), 1);
+    }
+}
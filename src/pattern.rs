@@ -0,0 +1,216 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+/// A glob pattern matched against paths relative to a scanned directory.
+///
+/// `*` matches any run of characters within a path component, `?` a single
+/// character and `[abc]`, `[a-z]` or `[!abc]` one character of a set. A `**`
+/// component matches any number of directories, including none. Patterns
+/// without a `/` match the name of a file or directory at any depth, so
+/// `*.jpg` selects every JPEG file and `thumbnails` every folder of that
+/// name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    source: String,
+    segments: Vec<Segment>,
+    anchored: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    AnyDirs,
+    Glob(Vec<Token>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    AnyRun,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern, String> {
+        let trimmed = source.trim_start_matches("./").trim_end_matches('/');
+        if trimmed.is_empty() {
+            return Err(format!("`{}` is not a valid pattern", source));
+        }
+        let anchored = trimmed.contains('/');
+        let segments = trimmed
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match segment {
+                "**" => Ok(Segment::AnyDirs),
+                _ => parse_segment(segment)
+                    .map(Segment::Glob)
+                    .ok_or_else(|| format!("unclosed `[` in pattern `{}`", source)),
+            })
+            .collect::<Result<Vec<Segment>, String>>()?;
+        Ok(Pattern {
+            source: source.to_string(),
+            segments,
+            anchored,
+        })
+    }
+
+    /// The pattern as written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether `path`, relative to the scanned directory, matches.
+    pub fn matches(&self, path: &Path) -> bool {
+        let names: Vec<String> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        if self.anchored {
+            match_segments(&self.segments, &names)
+        } else {
+            names
+                .last()
+                .is_some_and(|name| match_segments(&self.segments, std::slice::from_ref(name)))
+        }
+    }
+}
+
+/// Parse a pattern for use as a command-line argument.
+pub fn parse_pattern(value: &str) -> Result<Pattern, String> {
+    Pattern::new(value)
+}
+
+/// Read the patterns of the file at `path`, one per line, skipping blank
+/// lines and those starting with `#`.
+pub fn load_patterns(path: &Path) -> io::Result<Vec<Pattern>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            Pattern::new(line).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, err))
+            })
+        })
+        .collect()
+}
+
+fn parse_segment(segment: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '*' => {
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                Token::AnyRun
+            }
+            '?' => Token::AnyChar,
+            '[' => {
+                let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                if negated {
+                    chars.next();
+                }
+                let mut ranges = Vec::new();
+                let mut closed = false;
+                while let Some(start) = chars.next() {
+                    if start == ']' && !ranges.is_empty() {
+                        closed = true;
+                        break;
+                    }
+                    let mut lookahead = chars.clone();
+                    match (lookahead.next(), lookahead.next()) {
+                        (Some('-'), Some(end)) if end != ']' => {
+                            chars.next();
+                            chars.next();
+                            ranges.push((start, end));
+                        }
+                        _ => ranges.push((start, start)),
+                    }
+                }
+                if !closed {
+                    return None;
+                }
+                Token::Class { negated, ranges }
+            }
+            '\\' => Token::Literal(chars.next().unwrap_or('\\')),
+            c => Token::Literal(c),
+        };
+        tokens.push(token);
+    }
+    Some(tokens)
+}
+
+fn match_segments(segments: &[Segment], names: &[String]) -> bool {
+    match segments.split_first() {
+        None => names.is_empty(),
+        Some((Segment::AnyDirs, rest)) => {
+            (0..=names.len()).any(|skip| match_segments(rest, &names[skip..]))
+        }
+        Some((Segment::Glob(tokens), rest)) => match names.split_first() {
+            Some((name, names)) => {
+                let chars: Vec<char> = name.chars().collect();
+                match_tokens(tokens, &chars) && match_segments(rest, names)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_tokens(tokens: &[Token], chars: &[char]) -> bool {
+    match tokens.split_first() {
+        None => chars.is_empty(),
+        Some((Token::AnyRun, rest)) => {
+            (0..=chars.len()).any(|skip| match_tokens(rest, &chars[skip..]))
+        }
+        Some((token, rest)) => match chars.split_first() {
+            Some((&c, chars)) => {
+                let matched = match token {
+                    Token::Literal(literal) => c == *literal,
+                    Token::AnyChar => true,
+                    Token::Class { negated, ranges } => {
+                        ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+                    }
+                    Token::AnyRun => unreachable!(),
+                };
+                matched && match_tokens(rest, chars)
+            }
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_matches() {
+        let matches =
+            |pattern: &str, path: &str| Pattern::new(pattern).unwrap().matches(Path::new(path));
+
+        assert!(matches("*.jpg", "a.jpg"));
+        assert!(matches("*.jpg", "photos/2024/a.jpg"));
+        assert!(!matches("*.jpg", "a.jpeg"));
+        assert!(matches("IMG_????.[jp][pn]g", "IMG_0001.png"));
+        assert!(!matches("[!a]*", "abc"));
+
+        assert!(matches("**/thumbnails/**", "thumbnails/a.jpg"));
+        assert!(matches("**/thumbnails/**", "x/y/thumbnails/z/a.jpg"));
+        assert!(matches("**/thumbnails/**", "x/thumbnails"));
+        assert!(!matches("**/thumbnails/**", "x/thumbnail/a.jpg"));
+        assert!(matches("raw/*.cr2", "raw/a.cr2"));
+        assert!(!matches("raw/*.cr2", "x/raw/a.cr2"));
+
+        assert!(Pattern::new("[abc").is_err());
+    }
+}
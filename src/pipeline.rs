@@ -0,0 +1,78 @@
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+/// Number of queued items allowed per worker before the producer blocks.
+const QUEUE_DEPTH: usize = 2;
+
+/// Apply `work` to every item of `inputs` on a pool of `workers` threads.
+///
+/// A producer thread pulls items from `inputs` into a bounded queue that
+/// the workers drain, so producing items overlaps with processing them and
+/// at most a few items per worker are in flight at any time. When the queue
+/// is full the producer waits for the workers to catch up. Results are
+/// returned in input order; items for which `work` returns `None` are
+/// dropped.
+pub fn process<I, T, R, F>(inputs: I, workers: usize, work: F) -> Vec<R>
+where
+    I: IntoIterator<Item = T>,
+    I::IntoIter: Send,
+    T: Send,
+    R: Send,
+    F: Fn(T) -> Option<R> + Sync,
+{
+    let workers = workers.max(1);
+    let (input_tx, input_rx) = mpsc::sync_channel::<(usize, T)>(workers * QUEUE_DEPTH);
+    let (output_tx, output_rx) = mpsc::sync_channel::<(usize, R)>(workers * QUEUE_DEPTH);
+    let input_rx = Mutex::new(input_rx);
+    let inputs = inputs.into_iter();
+
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        scope.spawn(move || {
+            for item in inputs.enumerate() {
+                if input_tx.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..workers {
+            let output_tx = output_tx.clone();
+            let (input_rx, work) = (&input_rx, &work);
+            scope.spawn(move || {
+                while let Some((index, item)) = next(input_rx) {
+                    if let Some(result) = work(item) {
+                        if output_tx.send((index, result)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+        drop(output_tx);
+
+        output_rx.iter().collect()
+    });
+
+    results.sort_unstable_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Take the next queued item, or `None` once the producer is done.
+fn next<T>(queue: &Mutex<Receiver<T>>) -> Option<T> {
+    queue.lock().ok()?.recv().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_keeps_input_order() {
+        let odd_squares = process(0..1000u64, 4, |n| (n % 2 == 1).then_some(n * n));
+        let expected: Vec<u64> = (0..1000u64).filter(|n| n % 2 == 1).map(|n| n * n).collect();
+        assert_eq!(odd_squares, expected);
+
+        assert_eq!(process(Vec::<u64>::new(), 4, Some), Vec::<u64>::new());
+    }
+}
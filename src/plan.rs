@@ -0,0 +1,286 @@
+use crate::actions::{self, Action, ActionOptions};
+use crate::archive;
+use crate::errors::AppError;
+use crate::interactive::Prompter;
+use crate::models::{DuplicatesGroup, MatchKind};
+use ansi_term::Color;
+use indicatif::{HumanBytes, HumanCount};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the file the plan is saved to, next to the report.
+pub const PLAN_FILE: &str = "idar_plan.json";
+
+/// The actions a run would take on the duplicates of every group, shown and
+/// saved for review before any is taken.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    pub groups: Vec<PlannedGroup>,
+}
+
+/// The file kept in a group and what would be done to the others.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedGroup {
+    pub kind: MatchKind,
+    #[serde(serialize_with = "crate::serialization::path_to_json")]
+    pub kept: PathBuf,
+    pub kept_bytes: u64,
+    pub actions: Vec<PlannedAction>,
+}
+
+/// An action of a dry run, with how far the file is from the kept one.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedAction {
+    #[serde(flatten)]
+    pub action: Action,
+    /// Hamming distance between the hashes of the file and the kept one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<u32>,
+}
+
+impl Plan {
+    /// Add the `planned` actions of a dry run on `group`.
+    pub fn add(&mut self, group: &DuplicatesGroup, planned: Vec<Action>) {
+        let Some(first) = planned.first() else {
+            return;
+        };
+        let item_of = |path: &Path| {
+            group
+                .items
+                .iter()
+                .find(|item| item.path == path || item.links.iter().any(|link| link == path))
+        };
+        let kept = first.kept.clone();
+        let kept_hash = item_of(&kept).map(|item| &item.hash);
+        let actions = planned
+            .into_iter()
+            .map(|action| PlannedAction {
+                distance: item_of(&action.path)
+                    .zip(kept_hash)
+                    .map(|(item, hash)| item.hash.dist(hash)),
+                action,
+            })
+            .collect();
+        self.groups.push(PlannedGroup {
+            kind: group.kind,
+            kept_bytes: archive::size(&kept).unwrap_or(0),
+            kept,
+            actions,
+        });
+    }
+
+    /// The planned actions, group after group.
+    pub fn actions(&self) -> Vec<Action> {
+        self.groups
+            .iter()
+            .flat_map(|group| group.actions.iter().map(|planned| planned.action.clone()))
+            .collect()
+    }
+
+    /// Write the table of the kept files and of what would be done to the
+    /// others to `out`, in colour if `color`.
+    pub fn write_table(&self, out: &mut impl Write, color: bool) -> io::Result<()> {
+        let paint = |color_of: Color, text: String| match color {
+            true => color_of.paint(text).to_string(),
+            false => text,
+        };
+        writeln!(
+            out,
+            "  {:<11}  {:>10}  {:>8}  PATH",
+            "ACTION", "SIZE", "DISTANCE"
+        )?;
+        for (number, group) in self.groups.iter().enumerate() {
+            let kind = format!("{:?}", group.kind).to_lowercase();
+            writeln!(out, "Group {} ({})", number + 1, kind)?;
+            let row = format!(
+                "  {:<11}  {:>10}  {:>8}  {:?}",
+                "keep",
+                HumanBytes(group.kept_bytes).to_string(),
+                "",
+                group.kept
+            );
+            writeln!(out, "{}", paint(Color::Green, row))?;
+            for planned in &group.actions {
+                let action = &planned.action;
+                let distance = planned.distance.map_or(String::new(), |d| d.to_string());
+                let (verb, color_of) = match &action.error {
+                    None => (action.kind.to_string(), Color::Red),
+                    Some(_) => ("untouched".to_string(), Color::Yellow),
+                };
+                let mut row = format!(
+                    "  {:<11}  {:>10}  {:>8}  {:?}",
+                    verb,
+                    HumanBytes(action.bytes).to_string(),
+                    distance,
+                    action.path
+                );
+                match (&action.error, &action.destination) {
+                    (Some(error), _) => row.push_str(&format!(" ({})", error)),
+                    (None, Some(destination)) => row.push_str(&format!(" -> {:?}", destination)),
+                    (None, None) => {}
+                }
+                writeln!(out, "{}", paint(color_of, row))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the plan as JSON to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), AppError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Save the plan to `path` and, unless `options` is a dry run, show it
+    /// and take its actions once confirmed at `prompter`, or right away
+    /// with `options.yes`, which scripts must give as there is no one to
+    /// confirm. Returns the actions the run records.
+    ///
+    /// None is taken when they would go over `options.limits`, the share of
+    /// the files acted on counting against the `scanned` ones when known.
+    pub fn carry_out<R: BufRead, W: Write>(
+        &self,
+        options: &ActionOptions,
+        prompter: &mut Prompter<R, W>,
+        path: &Path,
+        scanned: Option<usize>,
+    ) -> Result<Vec<Action>, AppError> {
+        let planned = self.actions();
+        if planned.is_empty() {
+            return Ok(planned);
+        }
+        self.save(path)?;
+        println!("Action plan saved to {:?}", path);
+        let possible: Vec<&Action> = planned.iter().filter(|a| a.succeeded()).collect();
+        let bytes = possible.iter().map(|action| action.bytes).sum();
+        let over = options
+            .kind
+            .removes()
+            .then(|| options.limits.exceeded(possible.len(), bytes, scanned));
+        if let Some(reason) = over.flatten() {
+            eprintln!("warning: {}", reason);
+            if options.dry_run {
+                return Ok(planned);
+            }
+            println!("Left every file untouched.");
+            let refused = planned
+                .into_iter()
+                .map(|action| Action {
+                    error: action.error.or_else(|| Some(reason.clone())),
+                    dry_run: false,
+                    ..action
+                })
+                .collect();
+            return Ok(refused);
+        }
+        if options.dry_run {
+            return Ok(planned);
+        }
+        if !possible.is_empty() && !options.yes {
+            if !prompter.at_terminal() {
+                println!("Left every file untouched.");
+                return Err(AppError::ConfirmationRequired {
+                    action: options.kind.to_string(),
+                });
+            }
+            let color = io::stdout().is_terminal();
+            self.write_table(&mut io::stdout(), color)?;
+            let question = format!(
+                "{} {} files, {} — proceed?",
+                options.kind,
+                HumanCount(possible.len() as u64),
+                HumanBytes(bytes)
+            );
+            if !prompter.confirm(&question)? {
+                println!("Left every file untouched.");
+                return Ok(Vec::new());
+            }
+        }
+        Ok(actions::execute(&planned, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::{ActionKind, Limits};
+    use crate::models::ImageInfo;
+    use image_hasher::ImageHash;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_is_carried_out_once_confirmed() {
+        let dir = tempdir().unwrap();
+        let paths = [dir.path().join("a.jpg"), dir.path().join("b.jpg")];
+        let items = paths
+            .iter()
+            .map(|path| {
+                fs::write(path, b"image").unwrap();
+                ImageInfo::new(path.clone(), ImageHash::from_bytes(&[0b0110]).unwrap())
+            })
+            .collect();
+        let mut group = DuplicatesGroup::new(items);
+        group.items[1].hash = ImageHash::from_bytes(&[0b0011]).unwrap();
+        let options = ActionOptions {
+            kind: ActionKind::Delete,
+            ..Default::default()
+        };
+        let mut plan = Plan::default();
+        plan.add(&group, actions::apply(&group, &options.planning()));
+
+        let mut table = Vec::new();
+        plan.write_table(&mut table, false).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        let row = |action: &str, distance: &str, path: &Path| {
+            format!(
+                "  {:<11}  {:>10}  {:>8}  {:?}",
+                action, "5 B", distance, path
+            )
+        };
+        assert!(table.contains(&row("keep", "", &paths[0])));
+        assert!(table.contains(&row("delete", "2", &paths[1])));
+
+        let plan_path = dir.path().join(PLAN_FILE);
+        let limited = ActionOptions {
+            yes: true,
+            limits: Limits {
+                max_share: Some(0.4),
+                ..Limits::default()
+            },
+            ..options.clone()
+        };
+        let mut prompter = Prompter::new(&b""[..], Vec::new());
+        let actions = plan
+            .carry_out(&limited, &mut prompter, &plan_path, Some(2))
+            .unwrap();
+        assert!(!actions[0].succeeded() && paths[1].exists());
+        let actions = plan
+            .carry_out(&limited, &mut prompter, &plan_path, None)
+            .unwrap();
+        assert!(actions[0].taken());
+        fs::write(&paths[1], b"image").unwrap();
+
+        let mut scripted = Prompter::new(&b"y\n"[..], Vec::new()).scripted();
+        let refused = plan.carry_out(&options, &mut scripted, &plan_path, None);
+        assert!(matches!(
+            refused,
+            Err(AppError::ConfirmationRequired { .. })
+        ));
+        assert!(paths[1].exists());
+
+        let mut declined = Prompter::new(&b"n\n"[..], Vec::new());
+        let actions = plan
+            .carry_out(&options, &mut declined, &plan_path, None)
+            .unwrap();
+        assert!(actions.is_empty() && paths[1].exists() && plan_path.exists());
+
+        let mut confirmed = Prompter::new(&b"y\n"[..], Vec::new());
+        let actions = plan
+            .carry_out(&options, &mut confirmed, &plan_path, Some(2))
+            .unwrap();
+        assert!(actions[0].taken() && actions[0].checksum.is_some());
+        assert!(!paths[1].exists() && paths[0].exists());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::archive;
+use crate::decode::{self, DecodeOptions};
+use crate::models::{DuplicatesGroup, ImageQuality, QualityRank};
+use image::imageops::FilterType;
+use image::{GrayImage, ImageFormat};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Side of the square images are fitted in before their sharpness and
+/// exposure are measured, so copies at different sizes compare fairly.
+const SAMPLE_SIZE: u32 = 512;
+
+/// Grey levels this close to black or white count as clipped.
+const CLIP_MARGIN: u8 = 2;
+
+/// Weights of the resolution, sharpness, exposure and compression of an
+/// item in its overall score.
+const WEIGHTS: [f64; 4] = [0.4, 0.3, 0.1, 0.2];
+
+/// Luminance quantization table of the JPEG standard, which encoders
+/// scale by the quality setting.
+const STANDARD_LUMINANCE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Measure the quality of the items of `group` and score them against
+/// each other. Items that cannot be decoded are left out.
+pub fn score(group: &mut DuplicatesGroup, decode: &DecodeOptions) {
+    let measured: Vec<(ImageQuality, u64)> = group
+        .items
+        .par_iter()
+        .filter_map(|item| {
+            let img = decode::open(&item.path, decode).ok()?;
+            let pixels = item
+                .dimensions
+                .map_or(img.width() as u64 * img.height() as u64, |d| {
+                    d.width as u64 * d.height as u64
+                });
+            let sample = img.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle);
+            let grey = sample.to_luma8();
+            let quality = ImageQuality {
+                path: item.path.clone(),
+                sharpness: sharpness(&grey),
+                clipped: clipped(&grey),
+                jpeg_quality: jpeg_quality(&item.path),
+                score: 0.0,
+            };
+            Some((quality, pixels))
+        })
+        .collect();
+
+    let most_pixels = measured.iter().map(|(_, pixels)| *pixels).max();
+    let sharpest = measured
+        .iter()
+        .map(|(quality, _)| quality.sharpness)
+        .fold(0.0, f64::max);
+    group.quality = measured
+        .into_iter()
+        .map(|(mut quality, pixels)| {
+            let measures = [
+                pixels as f64 / most_pixels.unwrap_or(1).max(1) as f64,
+                if sharpest > 0.0 {
+                    quality.sharpness / sharpest
+                } else {
+                    1.0
+                },
+                1.0 - quality.clipped,
+                quality.jpeg_quality.map_or(1.0, |q| q as f64 / 100.0),
+            ];
+            let total: f64 = measures.iter().zip(WEIGHTS).map(|(m, w)| m * w).sum();
+            quality.score = (total * 1000.0).round() / 10.0;
+            quality
+        })
+        .collect();
+}
+
+/// Rank the items of `group` from best to worst: by resolution, then
+/// lossless formats over lossy ones, then by the score of [`score`] if it
+/// was measured, then by file size, ties going to the first path
+/// alphabetically.
+pub fn rank(group: &DuplicatesGroup) -> Vec<QualityRank> {
+    let mut ranks: Vec<QualityRank> = group
+        .items
+        .iter()
+        .map(|item| QualityRank {
+            path: item.path.clone(),
+            pixels: item.dimensions.map(|d| d.width as u64 * d.height as u64),
+            lossless: is_lossless(&item.path),
+            score: group
+                .quality
+                .iter()
+                .find(|quality| quality.path == item.path)
+                .map(|quality| quality.score),
+            bytes: archive::size(&item.path).ok(),
+            reason: None,
+        })
+        .collect();
+    ranks.sort_by(|a, b| {
+        compare(b, a)
+            .map(|(ordering, _)| ordering)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    for i in 1..ranks.len() {
+        ranks[i].reason = Some(match compare(&ranks[i - 1], &ranks[i]) {
+            Some((_, reason)) => reason.to_string(),
+            None => "as good; comes later alphabetically".to_string(),
+        });
+    }
+    ranks
+}
+
+/// How `a` compares to `b`, by the first measure they differ in, and that
+/// measure as the reason `b` ranks lower when `a` is better.
+fn compare(a: &QualityRank, b: &QualityRank) -> Option<(Ordering, &'static str)> {
+    let score = |rank: &QualityRank| rank.score.unwrap_or(0.0);
+    [
+        (a.pixels.cmp(&b.pixels), "lower resolution"),
+        (a.lossless.cmp(&b.lossless), "lossy format"),
+        (score(a).total_cmp(&score(b)), "lower quality score"),
+        (a.bytes.cmp(&b.bytes), "smaller file"),
+    ]
+    .into_iter()
+    .find(|(ordering, _)| ordering.is_ne())
+}
+
+/// Whether the file at `path` is in a format that keeps every pixel; WebP
+/// files are when they use its lossless codec.
+pub fn is_lossless(path: &Path) -> bool {
+    match decode::format_of(path) {
+        Some(ImageFormat::WebP) => {
+            let mut header = [0u8; 16];
+            archive::open(path)
+                .and_then(|mut file| file.read_exact(&mut header))
+                .is_ok_and(|_| &header[12..16] == b"VP8L")
+        }
+        Some(
+            ImageFormat::Png
+            | ImageFormat::Bmp
+            | ImageFormat::Tiff
+            | ImageFormat::Gif
+            | ImageFormat::Tga
+            | ImageFormat::Pnm
+            | ImageFormat::Qoi
+            | ImageFormat::Farbfeld
+            | ImageFormat::OpenExr,
+        ) => true,
+        _ => false,
+    }
+}
+
+/// Variance of the 4-neighbour Laplacian of `img`.
+fn sharpness(img: &GrayImage) -> f64 {
+    let (width, height) = img.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| img.get_pixel(x, y).0[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_squares = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian =
+                at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    sum_squares / count - mean * mean
+}
+
+/// Fraction of the pixels of `img` that are nearly black or white.
+fn clipped(img: &GrayImage) -> f64 {
+    let count = img
+        .pixels()
+        .filter(|pixel| pixel.0[0] <= CLIP_MARGIN || pixel.0[0] >= 255 - CLIP_MARGIN)
+        .count();
+    count as f64 / (img.width() as u64 * img.height() as u64).max(1) as f64
+}
+
+/// Quality setting the JPEG at `path` was most likely saved with, from how
+/// far its luminance table is scaled from the standard one.
+pub fn jpeg_quality(path: &Path) -> Option<u8> {
+    if decode::format_of(path)? != ImageFormat::Jpeg {
+        return None;
+    }
+    let mut table = None;
+    let reader = BufReader::new(archive::open(path).ok()?);
+    decode::read_jpeg_segments(reader, |marker, payload| {
+        if marker == 0xDB {
+            table = luminance_table(payload);
+        }
+        table.is_none()
+    })
+    .ok()?;
+    let total: u64 = table?.iter().map(|&q| q as u64).sum();
+    let standard: u64 = STANDARD_LUMINANCE.iter().map(|&q| q as u64).sum();
+    let scale = total as f64 * 100.0 / standard as f64;
+    let quality = if scale <= 100.0 {
+        (200.0 - scale) / 2.0
+    } else {
+        5000.0 / scale
+    };
+    Some(quality.round().clamp(1.0, 100.0) as u8)
+}
+
+/// The table with id 0, which encoders use for luminance, among the tables
+/// of a DQT segment.
+fn luminance_table(mut payload: &[u8]) -> Option<[u16; 64]> {
+    while let Some(&header) = payload.first() {
+        let wide = header >> 4 == 1;
+        let len = if wide { 128 } else { 64 };
+        let values = payload.get(1..1 + len)?;
+        if header & 0x0F == 0 {
+            return Some(std::array::from_fn(|i| match wide {
+                true => u16::from_be_bytes([values[2 * i], values[2 * i + 1]]),
+                false => values[i] as u16,
+            }));
+        }
+        payload = &payload[1 + len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::DynamicImage;
+    use image::{Luma, RgbImage};
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sharp_high_quality_copy_scores_best() {
+        let dir = tempdir().unwrap();
+        let original = RgbImage::from_fn(256, 256, |x, y| {
+            let v = if (x / 8 + y / 8).is_multiple_of(2) {
+                230
+            } else {
+                30
+            };
+            image::Rgb([v, v, v])
+        });
+        let blurred = DynamicImage::ImageRgb8(original.clone()).blur(3.0);
+        let paths = ["original.jpg", "blurred.jpg", "low.jpg"].map(|name| dir.path().join(name));
+        let save = |path: &Path, img: &DynamicImage, quality: u8| {
+            let file = File::create(path).unwrap();
+            img.write_with_encoder(JpegEncoder::new_with_quality(file, quality))
+                .unwrap();
+        };
+        let original = DynamicImage::ImageRgb8(original);
+        save(&paths[0], &original, 95);
+        save(&paths[1], &blurred, 95);
+        save(&paths[2], &original, 30);
+        assert_eq!(jpeg_quality(&paths[0]), Some(95));
+        assert_eq!(jpeg_quality(&paths[2]), Some(30));
+
+        let items = paths.iter().map(ImageInfo::at).collect();
+        let mut group = DuplicatesGroup::new(items);
+        score(&mut group, &DecodeOptions::default());
+        let scores: Vec<f64> = group.quality.iter().map(|q| q.score).collect();
+        assert!(scores[0] > scores[1], "{scores:?}");
+        assert!(scores[0] > scores[2], "{scores:?}");
+        assert!(group.quality[1].sharpness < group.quality[0].sharpness);
+
+        let dark = GrayImage::from_fn(10, 10, |x, _| Luma([if x < 5 { 0 } else { 128 }]));
+        assert_eq!(clipped(&dark), 0.5);
+    }
+
+    #[test]
+    fn test_rank_prefers_resolution_then_lossless_formats() {
+        let dir = tempdir().unwrap();
+        let img = RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 0]));
+        let paths = ["a.jpg", "b.png", "small.png"].map(|name| dir.path().join(name));
+        img.save(&paths[0]).unwrap();
+        img.save(&paths[1]).unwrap();
+        image::imageops::thumbnail(&img, 16, 16)
+            .save(&paths[2])
+            .unwrap();
+        let items = paths
+            .iter()
+            .zip([32, 32, 16])
+            .map(|(path, side)| ImageInfo {
+                dimensions: Some(crate::models::Dimensions {
+                    width: side,
+                    height: side,
+                }),
+                ..ImageInfo::at(path)
+            })
+            .collect();
+
+        let ranks = rank(&DuplicatesGroup::new(items));
+        let order: Vec<&Path> = ranks.iter().map(|rank| rank.path.as_path()).collect();
+        assert_eq!(order, [&paths[1], &paths[0], &paths[2]]);
+        assert_eq!(ranks[0].reason, None);
+        assert_eq!(ranks[1].reason.as_deref(), Some("lossy format"));
+        assert_eq!(ranks[2].reason.as_deref(), Some("lower resolution"));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::archive;
+use crate::exif::Exif;
+use image::error::{DecodingError, ImageFormatHint};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult};
+use jpeg_decoder::CodingProcess;
+use std::io::Read;
+use std::path::Path;
+
+/// Extensions of the camera raw formats whose previews are decoded.
+const EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "cr3", "dng", "erf", "nef", "nrw", "orf", "pef", "raf", "rw2", "srw",
+];
+
+/// Number of leading bytes searched for the TIFF structure holding the
+/// metadata of a raw file.
+const METADATA_LEN: u64 = 1 << 20;
+
+/// Start of every JPEG stream.
+const JPEG_START: [u8; 3] = [0xFF, 0xD8, 0xFF];
+
+/// Whether `path` names a camera raw file, by its extension.
+pub fn is_raw(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy();
+        EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    })
+}
+
+/// EXIF metadata of the raw file at `path`, for the formats that are laid
+/// out as TIFF files.
+pub fn exif(path: &Path) -> Option<Exif> {
+    let mut data = Vec::new();
+    archive::open(path)
+        .ok()?
+        .take(METADATA_LEN)
+        .read_to_end(&mut data)
+        .ok()?;
+    Exif::parse(data)
+}
+
+/// Decode the largest JPEG preview embedded in the raw file at `path`,
+/// along with the orientation recorded by the camera.
+///
+/// Cameras store a full size or nearly full size JPEG rendering next to
+/// the sensor data, which is as good as the sensor data itself for telling
+/// duplicates apart and matches the JPEG written alongside it. Lossless
+/// JPEG streams hold sensor data rather than a rendering, and are skipped.
+pub fn decode_preview(path: &Path) -> ImageResult<(DynamicImage, Option<Orientation>)> {
+    let mut data = Vec::new();
+    archive::open(path)?.read_to_end(&mut data)?;
+
+    let mut best: Option<(usize, u64)> = None;
+    for start in data
+        .windows(JPEG_START.len())
+        .enumerate()
+        .filter(|(_, window)| *window == JPEG_START)
+        .map(|(start, _)| start)
+    {
+        let mut decoder = jpeg_decoder::Decoder::new(&data[start..]);
+        if decoder.read_info().is_err() {
+            continue;
+        }
+        let Some(info) = decoder.info() else {
+            continue;
+        };
+        if info.coding_process == CodingProcess::Lossless {
+            continue;
+        }
+        let pixels = info.width as u64 * info.height as u64;
+        if best.is_none_or(|(_, most)| pixels > most) {
+            best = Some((start, pixels));
+        }
+    }
+    let Some((start, _)) = best else {
+        return Err(ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::PathExtension(path.extension().unwrap_or_default().into()),
+            "no embedded JPEG preview",
+        )));
+    };
+
+    let img = image::load_from_memory_with_format(&data[start..], ImageFormat::Jpeg)?;
+    data.truncate(METADATA_LEN as usize);
+    let orientation = Exif::parse(data).and_then(|exif| exif.orientation());
+    Ok((img, orientation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+    use std::fs;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 7) as u8, (y * 5) as u8, 128])
+        });
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut bytes, ImageFormat::Jpeg)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_largest_embedded_preview_is_decoded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("IMG_0001.CR2");
+
+        // A TIFF header, a thumbnail and a full preview, with sensor data
+        // standing in between.
+        let mut data = vec![b'I', b'I', 42, 0, 8, 0, 0, 0, 0, 0];
+        data.extend(jpeg(16, 12));
+        data.extend([0x5A; 300]);
+        data.extend(jpeg(64, 48));
+        data.extend([0xA5; 300]);
+        fs::write(&path, &data).unwrap();
+
+        assert!(is_raw(&path));
+        assert!(!is_raw(Path::new("IMG_0001.JPG")));
+        let (img, orientation) = decode_preview(&path).unwrap();
+        assert_eq!((img.width(), img.height()), (64, 48));
+        assert_eq!(orientation, None);
+
+        fs::write(&path, [0x5A; 100]).unwrap();
+        assert!(decode_preview(&path).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use std::io;
+use std::path::Path;
+
+/// Create `destination` as a copy-on-write clone of `source`, sharing its
+/// blocks until either is modified.
+///
+/// Clones are made with `FICLONE` on Linux, where btrfs, XFS and a few
+/// other filesystems support them, and with `clonefile` on macOS for APFS.
+/// Filesystems without clones, and files on different filesystems, fail
+/// with an error saying so rather than falling back to a full copy.
+pub fn clone_file(source: &Path, destination: &Path) -> io::Result<()> {
+    platform_clone(source, destination).map_err(explain)
+}
+
+#[cfg(target_os = "linux")]
+fn platform_clone(source: &Path, destination_path: &Path) -> io::Result<()> {
+    use std::fs::{self, File, OpenOptions};
+    use std::os::unix::io::AsRawFd;
+
+    let source = File::open(source)?;
+    let destination = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(destination_path)?;
+    // SAFETY: both descriptors stay open for the duration of the call.
+    let result = unsafe {
+        libc::ioctl(
+            destination.as_raw_fd(),
+            libc::FICLONE as _,
+            source.as_raw_fd(),
+        )
+    };
+    if result == -1 {
+        let err = io::Error::last_os_error();
+        drop(destination);
+        let _ = fs::remove_file(destination_path);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn platform_clone(source: &Path, destination: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    };
+    let (source, destination) = (path(source)?, path(destination)?);
+    // SAFETY: both paths are valid NUL-terminated strings.
+    if unsafe { libc::clonefile(source.as_ptr(), destination.as_ptr(), 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn platform_clone(_source: &Path, _destination: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are not supported on this platform",
+    ))
+}
+
+/// Turn the errors clones fail with on filesystems without them into ones
+/// that say so.
+fn explain(err: io::Error) -> io::Error {
+    #[cfg(unix)]
+    match err.raw_os_error() {
+        Some(libc::EXDEV) => {
+            return io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                "the kept file is on another filesystem",
+            )
+        }
+        Some(code)
+            if [libc::EOPNOTSUPP, libc::ENOTSUP, libc::ENOTTY, libc::EINVAL].contains(&code) =>
+        {
+            return io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the filesystem does not support reflinks",
+            )
+        }
+        _ => {}
+    }
+    err
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_clone_copies_or_says_reflinks_are_unsupported() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("a.jpg");
+        let destination = dir.path().join("b.jpg");
+        fs::write(&source, b"image").unwrap();
+
+        match clone_file(&source, &destination) {
+            Ok(()) => assert_eq!(fs::read(&destination).unwrap(), b"image"),
+            Err(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+                assert!(!destination.exists());
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 use std::path::PathBuf;
 
+use crate::archive;
 use crate::errors::AppError;
 use crate::models::DeduplicationReport;
 use serde_json;
@@ -23,21 +24,29 @@ pub fn run(results_file: &str, output_dir: &str) -> Result<(), AppError> {
             if index == 0 {
                 let file_name = image.path.file_name().unwrap();
                 let output_path = output_dir.join(file_name);
-                std::fs::copy(&image.path, &output_path)?;
+                // Archive members are extracted, leaving the archive untouched.
+                let mut source = archive::open(&image.path)?;
+                std::io::copy(&mut source, &mut std::fs::File::create(&output_path)?)?;
             } else {
                 all_duplicates.insert(image.path.clone());
             }
         }
     }
     // Move all non-duplicates to the output directory
-    for entry in std::fs::read_dir(&results.metadata.directory_path)? {
-        let entry = entry?;
-        let path = entry.path();
+    let mut roots = results.metadata.roots;
+    if roots.is_empty() {
+        roots.push(results.metadata.directory_path);
+    }
+    for root in &roots {
+        for entry in std::fs::read_dir(root)? {
+            let entry = entry?;
+            let path = entry.path();
 
-        if !all_duplicates.contains(&path) {
-            let file_name = path.file_name().unwrap();
-            let output_path = output_dir.join(file_name);
-            std::fs::copy(&path, &output_path)?;
+            if !all_duplicates.contains(&path) {
+                let file_name = path.file_name().unwrap();
+                let output_path = output_dir.join(file_name);
+                std::fs::copy(&path, &output_path)?;
+            }
         }
     }
 
@@ -0,0 +1,342 @@
+use crate::actions::Action;
+use crate::archive;
+use crate::bursts::Burst;
+use crate::crops::Crop;
+use crate::errors::AppError;
+use crate::html;
+use crate::models::{
+    DeduplicationMetadata, DuplicatesGroup, EntryError, ImageInfo, MatchKind, ReportSummary,
+    SuggestedAction,
+};
+use clap::ValueEnum;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Format a deduplication report is saved in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Every section of the report, which `apply` and `ignore-group` read.
+    #[default]
+    Json,
+    /// One row per item of every group, for spreadsheets and data frames.
+    Csv,
+    /// A single page showing the items of every group side by side, with
+    /// embedded thumbnails, to review the groups by eye.
+    Html,
+}
+
+impl ReportFormat {
+    /// Extension of the reports saved in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+            ReportFormat::Html => "html",
+        }
+    }
+}
+
+/// Columns of a CSV report.
+const CSV_HEADER: &str = "group_id,path,distance_to_representative,size,dimensions";
+
+/// An item of a group as the CSV and HTML reports list it.
+pub(crate) struct ItemRow<'a> {
+    pub item: &'a ImageInfo,
+    /// Hamming distance between the hashes of the item and the
+    /// representative of its group.
+    pub distance: u32,
+    /// Size of the file in bytes, if it could be read.
+    pub size: Option<u64>,
+}
+
+/// The items of `group`, with how far each is from its representative: the
+/// first item suggested to be kept, or its first item.
+pub(crate) fn item_rows(group: &DuplicatesGroup) -> Vec<ItemRow<'_>> {
+    let representative = group
+        .items
+        .iter()
+        .find(|item| item.suggested_action == Some(SuggestedAction::Keep))
+        .or(group.items.first());
+    group
+        .items
+        .iter()
+        .map(|item| ItemRow {
+            item,
+            distance: representative.map_or(0, |kept| item.hash.dist(&kept.hash)),
+            size: group
+                .files
+                .iter()
+                .find(|file| file.path == item.path)
+                .map(|file| file.stamp.size)
+                .or_else(|| archive::size(&item.path).ok()),
+        })
+        .collect()
+}
+
+/// Write a row for every item of `group` to the CSV report `out`, with its
+/// size in bytes and its dimensions, left empty if unknown.
+pub(crate) fn write_csv_rows(out: &mut impl Write, group: &DuplicatesGroup) -> io::Result<()> {
+    for row in item_rows(group) {
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            csv_field(&group.id),
+            csv_field(&row.item.path.to_string_lossy()),
+            row.distance,
+            row.size.map_or(String::new(), |size| size.to_string()),
+            row.item
+                .dimensions
+                .map_or(String::new(), |dimensions| dimensions.to_string())
+        )?;
+    }
+    Ok(())
+}
+
+/// Save the `groups` of a report as CSV to `path`.
+pub(crate) fn save_csv(groups: &[DuplicatesGroup], path: &Path) -> Result<(), AppError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{}", CSV_HEADER)?;
+    for group in groups {
+        write_csv_rows(&mut out, group)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// `text` as a CSV field, quoted if it holds a comma, a quote or a line
+/// break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes a deduplication report one group at a time.
+///
+/// A JSON report has the same layout as a serialized
+/// [`DeduplicationReport`](crate::models::DeduplicationReport), but groups
+/// never need to be held in memory all at once. CSV and HTML reports only
+/// list the groups, an HTML report ending with the totals.
+pub struct ReportWriter {
+    writer: BufWriter<File>,
+    format: ReportFormat,
+    summary: ReportSummary,
+}
+
+impl ReportWriter {
+    pub fn create(
+        path: &Path,
+        metadata: DeduplicationMetadata,
+        format: ReportFormat,
+    ) -> Result<ReportWriter, AppError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            ReportFormat::Json => {
+                write!(writer, "{{\"metadata\":")?;
+                write!(writer, "{}", serde_json::to_string(&metadata)?)?;
+                write!(writer, ",\"groups\":[")?;
+            }
+            ReportFormat::Csv => writeln!(writer, "{}", CSV_HEADER)?,
+            ReportFormat::Html => html::write_header(&mut writer, &metadata)?,
+        }
+
+        Ok(ReportWriter {
+            writer,
+            format,
+            summary: ReportSummary {
+                metadata,
+                groups: 0,
+                exact_groups: 0,
+                total_duplicates: 0,
+                reclaimable_bytes: 0,
+                below_resolution: 0,
+                errors: 0,
+                crops: 0,
+                bursts: 0,
+                actions: 0,
+                failed_actions: 0,
+            },
+        })
+    }
+
+    pub fn write_group(&mut self, group: &DuplicatesGroup) -> Result<(), AppError> {
+        match self.format {
+            ReportFormat::Json => {
+                if self.summary.groups > 0 {
+                    write!(self.writer, ",")?;
+                }
+                write!(self.writer, "{}", serde_json::to_string(group)?)?;
+            }
+            ReportFormat::Csv => write_csv_rows(&mut self.writer, group)?,
+            ReportFormat::Html => html::write_group(&mut self.writer, group)?,
+        }
+
+        self.summary.groups += 1;
+        if group.kind == MatchKind::Exact {
+            self.summary.exact_groups += 1;
+        }
+        self.summary.total_duplicates += group.items.len().saturating_sub(1);
+        self.summary.reclaimable_bytes += group.reclaimable_bytes;
+        Ok(())
+    }
+
+    /// Close the report, listing the images of `below_resolution`, the read
+    /// `errors` and the other sections after the groups of a JSON report,
+    /// and return its totals.
+    pub fn finish(
+        mut self,
+        below_resolution: &[ImageInfo],
+        errors: &[EntryError],
+        crops: &[Crop],
+        bursts: &[Burst],
+        actions: &[Action],
+    ) -> Result<ReportSummary, AppError> {
+        self.summary.below_resolution = below_resolution.len();
+        self.summary.errors = errors.len();
+        self.summary.crops = crops.len();
+        self.summary.bursts = bursts.len();
+        self.summary.actions = actions.iter().filter(|a| a.taken()).count();
+        self.summary.failed_actions = actions.iter().filter(|a| !a.succeeded()).count();
+        match self.format {
+            ReportFormat::Json => {}
+            ReportFormat::Csv => {
+                self.writer.flush()?;
+                return Ok(self.summary);
+            }
+            ReportFormat::Html => {
+                html::write_footer(&mut self.writer, &self.summary)?;
+                self.writer.flush()?;
+                return Ok(self.summary);
+            }
+        }
+
+        write!(
+            self.writer,
+            "],\"total_duplicates\":{},\"reclaimable_bytes\":{}",
+            self.summary.total_duplicates, self.summary.reclaimable_bytes
+        )?;
+        if !below_resolution.is_empty() {
+            write!(
+                self.writer,
+                ",\"below_resolution\":{}",
+                serde_json::to_string(below_resolution)?
+            )?;
+        }
+        if !errors.is_empty() {
+            write!(
+                self.writer,
+                ",\"errors\":{}",
+                serde_json::to_string(errors)?
+            )?;
+        }
+        if !crops.is_empty() {
+            write!(self.writer, ",\"crops\":{}", serde_json::to_string(crops)?)?;
+        }
+        if !bursts.is_empty() {
+            write!(
+                self.writer,
+                ",\"bursts\":{}",
+                serde_json::to_string(bursts)?
+            )?;
+        }
+        if !actions.is_empty() {
+            write!(
+                self.writer,
+                ",\"actions\":{}",
+                serde_json::to_string(actions)?
+            )?;
+        }
+        write!(self.writer, "}}")?;
+        self.writer.flush()?;
+        Ok(self.summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::{Channels, HashAlgorithm, Preprocessing};
+    use crate::models::DeduplicationReport;
+    use crate::verify::Verification;
+    use image_hasher::ImageHash;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_streamed_report_matches_serialized_report() {
+        let hash = ImageHash::from_base64("DAIDBwMHAf8").unwrap();
+        let items: Vec<ImageInfo> = ["a.png", "b.png"]
+            .iter()
+            .map(|name| ImageInfo::new(PathBuf::from(name), hash.clone()))
+            .collect();
+        let metadata = DeduplicationMetadata {
+            directory_path: PathBuf::from("/images"),
+            roots: vec![PathBuf::from("/images")],
+            cross_root: false,
+            threshold: 10,
+            threshold_pct: 10.0 / 64.0,
+            hash_bits: 64,
+            algorithm: HashAlgorithm::Gradient,
+            channels: Channels::Luma,
+            preprocessing: Preprocessing::default(),
+            verification: Verification::None,
+            sample: None,
+            min_resolution: None,
+            scanned: None,
+        };
+        let mut groups = vec![
+            DuplicatesGroup::new(items.clone()),
+            DuplicatesGroup::new(items),
+        ];
+        groups[1].reclaimable_bytes = 2048;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let mut writer = ReportWriter::create(&path, metadata.clone(), ReportFormat::Json).unwrap();
+        for group in &groups {
+            writer.write_group(group).unwrap();
+        }
+        let summary = writer.finish(&[], &[], &[], &[], &[]).unwrap();
+
+        let expected = DeduplicationReport::new(metadata, groups);
+        let written: DeduplicationReport =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written, expected);
+        assert_eq!(summary, expected.summary());
+    }
+
+    #[test]
+    fn test_csv_report_lists_every_item() {
+        let dir = tempdir().unwrap();
+        let item = |name: &str, hash: &str| ImageInfo {
+            dimensions: Some(crate::models::Dimensions {
+                width: 640,
+                height: 480,
+            }),
+            ..ImageInfo::new(dir.path().join(name), ImageHash::from_base64(hash).unwrap())
+        };
+        let mut items = vec![
+            item("a.png", "DAIDBwMHAf8"),
+            item("b, c.png", "DAIDBwMHAf4"),
+        ];
+        items[1].dimensions = None;
+        items[1].suggested_action = Some(SuggestedAction::Keep);
+        std::fs::write(&items[0].path, b"12345").unwrap();
+        let group = DuplicatesGroup::new(items);
+
+        let path = dir.path().join("report.csv");
+        save_csv(std::slice::from_ref(&group), &path).unwrap();
+        let expected = format!(
+            "{}\n{},{},1,5,640x480\n{},\"{}\",0,,\n",
+            CSV_HEADER,
+            group.id,
+            dir.path().join("a.png").display(),
+            group.id,
+            dir.path().join("b, c.png").display()
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    }
+}
@@ -0,0 +1,1008 @@
+use crate::archive;
+use crate::errors::AppError;
+use crate::identity::FileId;
+use crate::ignore::{IgnoreFile, IGNORE_FILE};
+use crate::models::EntryError;
+use crate::pages;
+use crate::pattern::Pattern;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Names of files and folders left by photo tools, file managers and NAS
+/// shares, skipped unless [`ScanOptions::junk`] is set.
+pub const JUNK_NAMES: &[&str] = &["Thumbs.db", ".DS_Store", "@eaDir", ".thumbnails"];
+
+/// Names of the reports, indexes, partial hashes, checkpoints and caches
+/// written by idar, and of its quarantine and `.idar` folders, skipped unless
+/// [`ScanOptions::artifacts`] is set so that one run never scans the output
+/// of another.
+pub const ARTIFACT_PATTERNS: &[&str] = &[
+    "dedup_report*.json",
+    "dedup_report*.csv",
+    "dedup_report*.html",
+    "idar_*.json",
+    ".idar_checkpoint*.json",
+    "hashes.json",
+    "idar_quarantine",
+    ".idar",
+];
+
+/// Options controlling how the input directory is walked.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Descend into subdirectories.
+    pub recursive: bool,
+    /// Maximum number of subdirectory levels to descend into when
+    /// `recursive` is set. `None` means unlimited.
+    pub max_depth: Option<usize>,
+    /// Only collect files matching at least one of these patterns, if any.
+    pub include: Vec<Pattern>,
+    /// Skip files and directories matching any of these patterns.
+    pub exclude: Vec<Pattern>,
+    /// Only collect files with one of these lowercase extensions, if any.
+    pub extensions: Vec<String>,
+    /// Skip the paths listed in the `.idarignore` file of the directory.
+    pub ignore_files: bool,
+    /// Names of directories never descended into, e.g. `node_modules`.
+    pub exclude_dirs: Vec<String>,
+    /// Follow symbolic links to files and directories instead of skipping
+    /// them.
+    pub follow_symlinks: bool,
+    /// Scan hidden files and directories.
+    pub hidden: bool,
+    /// Scan the files and folders listed in [`JUNK_NAMES`].
+    pub junk: bool,
+    /// Scan the files and folders matching [`ARTIFACT_PATTERNS`].
+    pub artifacts: bool,
+    /// Collect the members of zip, cbz and tar archives instead of the
+    /// archives themselves.
+    pub archives: bool,
+    /// Scan every page of multi-page TIFFs as a separate image, named like
+    /// `contract.tif#2`, instead of only the first one.
+    pub pages: bool,
+    /// Scan the files listed one per line in this file, or on standard
+    /// input for `-`, instead of walking the directories.
+    pub files_from: Option<PathBuf>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            recursive: false,
+            max_depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            extensions: Vec::new(),
+            ignore_files: true,
+            exclude_dirs: Vec::new(),
+            follow_symlinks: false,
+            hidden: false,
+            junk: false,
+            artifacts: false,
+            archives: false,
+            pages: false,
+            files_from: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn has_listed_extension(&self, path: &Path) -> bool {
+        self.extensions.is_empty()
+            || path.extension().is_some_and(|ext| {
+                let ext = ext.to_string_lossy().to_lowercase();
+                self.extensions.contains(&ext)
+            })
+    }
+}
+
+/// Number of files left out of a walk by the scan patterns, and the entries
+/// that could not be read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Files matching the patterns and collected.
+    pub matched: usize,
+    /// Files matching an `--exclude` pattern, or inside an excluded directory.
+    pub excluded: usize,
+    /// Files matching no `--include` pattern.
+    pub not_included: usize,
+    /// Files without one of the `--ext` extensions.
+    pub wrong_extension: usize,
+    /// Files listed in an ignore file, or inside an ignored directory.
+    pub ignored: usize,
+    /// Directories skipped by `--exclude-dir`, whose files are not counted.
+    pub pruned_dirs: usize,
+    /// Symbolic links skipped because they are not followed, or broken.
+    pub symlinks: usize,
+    /// Links to files or directories already reached by another path.
+    pub duplicate_links: usize,
+    /// Hidden files and directories, whose files are not counted.
+    pub hidden: usize,
+    /// Junk files and folders, whose files are not counted.
+    pub junk: usize,
+    /// Files and folders written by idar, whose files are not counted.
+    pub artifacts: usize,
+    /// Archives whose members were scanned.
+    pub archives: usize,
+    /// Pages collected after the first of multi-page files.
+    pub pages: usize,
+    /// Paths given with `--files-from` or `--manifest` that are not files.
+    pub not_files: usize,
+    /// Directories, entries and archives that could not be read.
+    pub errors: Vec<EntryError>,
+}
+
+impl AddAssign for ScanStats {
+    fn add_assign(&mut self, other: ScanStats) {
+        self.matched += other.matched;
+        self.excluded += other.excluded;
+        self.not_included += other.not_included;
+        self.wrong_extension += other.wrong_extension;
+        self.ignored += other.ignored;
+        self.pruned_dirs += other.pruned_dirs;
+        self.symlinks += other.symlinks;
+        self.duplicate_links += other.duplicate_links;
+        self.hidden += other.hidden;
+        self.junk += other.junk;
+        self.artifacts += other.artifacts;
+        self.archives += other.archives;
+        self.pages += other.pages;
+        self.not_files += other.not_files;
+        self.errors.extend(other.errors);
+    }
+}
+
+impl fmt::Display for ScanStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let matched_only = ScanStats {
+            matched: self.matched,
+            ..ScanStats::default()
+        };
+        if *self == matched_only {
+            return Ok(());
+        }
+        writeln!(f, "Matched {} files.", self.matched)?;
+        if self.not_files > 0 {
+            writeln!(
+                f,
+                "Skipped {} listed paths that are not files.",
+                self.not_files
+            )?;
+        }
+        if !self.errors.is_empty() {
+            writeln!(
+                f,
+                "Skipped {} files and directories that could not be read.",
+                self.errors.len()
+            )?;
+        }
+        if self.archives > 0 {
+            writeln!(f, "Scanned the members of {} archives.", self.archives)?;
+        }
+        if self.pages > 0 {
+            writeln!(f, "Scanned {} more pages of multi-page files.", self.pages)?;
+        }
+        if self.hidden > 0 {
+            writeln!(
+                f,
+                "Skipped {} hidden files and directories; pass --hidden to scan them.",
+                self.hidden
+            )?;
+        }
+        if self.junk > 0 {
+            writeln!(
+                f,
+                "Skipped {} junk files and folders ({}); pass --junk to scan them.",
+                self.junk,
+                JUNK_NAMES.join(", ")
+            )?;
+        }
+        if self.artifacts > 0 {
+            writeln!(
+                f,
+                "Skipped {} idar reports and working files; pass --scan-artifacts to scan them.",
+                self.artifacts
+            )?;
+        }
+        if self.ignored > 0 {
+            writeln!(f, "Skipped {} files listed in .idarignore.", self.ignored)?;
+        }
+        if self.pruned_dirs > 0 {
+            writeln!(
+                f,
+                "Skipped {} directories matching --exclude-dir.",
+                self.pruned_dirs
+            )?;
+        }
+        if self.symlinks > 0 {
+            writeln!(
+                f,
+                "Skipped {} symbolic links; pass --follow-symlinks to scan them.",
+                self.symlinks
+            )?;
+        }
+        if self.duplicate_links > 0 {
+            writeln!(
+                f,
+                "Skipped {} links to files already scanned.",
+                self.duplicate_links
+            )?;
+        }
+        if self.excluded > 0 {
+            writeln!(f, "Skipped {} files matching --exclude.", self.excluded)?;
+        }
+        if self.not_included > 0 {
+            writeln!(
+                f,
+                "Skipped {} files not matching --include.",
+                self.not_included
+            )?;
+        }
+        if self.wrong_extension > 0 {
+            writeln!(
+                f,
+                "Skipped {} files without an --ext extension.",
+                self.wrong_extension
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a directory entry is hidden: its name starts with a dot, or on
+/// Windows it has the hidden attribute.
+fn is_hidden(entry: &fs::DirEntry) -> bool {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    false
+}
+
+fn is_artifact(name: &OsStr) -> bool {
+    static PATTERNS: OnceLock<Vec<Pattern>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        ARTIFACT_PATTERNS
+            .iter()
+            .map(|pattern| Pattern::new(pattern).unwrap())
+            .collect()
+    });
+    let name = Path::new(name);
+    patterns.iter().any(|pattern| pattern.matches(name))
+}
+
+fn is_junk(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    JUNK_NAMES
+        .iter()
+        .any(|junk| name.eq_ignore_ascii_case(junk))
+}
+
+/// Number of files below `dir`, counted for excluded directories.
+fn count_files(dir: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => count_files(&entry.path()),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// `path` made absolute in the `\\?\` extended-length form on Windows, so
+/// that files nested deeper than the 260 character limit of the plain
+/// Windows APIs can still be read. Paths below it keep that form.
+///
+/// Elsewhere, or if `path` is already in that form, it is returned as is.
+#[cfg(windows)]
+pub fn extended_length(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let mut components = absolute.components();
+    let mut extended = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut extended = OsString::from(r"\\?\");
+                extended.push(prefix.as_os_str());
+                PathBuf::from(extended)
+            }
+            Prefix::UNC(server, share) => {
+                let mut extended = OsString::from(r"\\?\UNC\");
+                extended.push(server);
+                extended.push(r"\");
+                extended.push(share);
+                PathBuf::from(extended)
+            }
+            _ => return absolute,
+        },
+        _ => return absolute,
+    };
+    for component in components {
+        match component {
+            Component::RootDir => extended.push(r"\"),
+            component => extended.push(component),
+        }
+    }
+    extended
+}
+
+#[cfg(not(windows))]
+pub fn extended_length(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Collect the paths of all files found under the given directory.
+///
+/// Only the top-level directory is read unless `options.recursive` is set.
+/// Directories are read in parallel on the rayon pool, and subdirectories
+/// that cannot be read are skipped. Paths are returned sorted,
+/// so that results do not depend on the order the filesystem lists them in.
+///
+/// Patterns are matched against paths relative to `directory`, as are the
+/// rules of an `.idarignore` file at its top level. Excluded and ignored
+/// directories are pruned without being walked, except to count their files
+/// when building the returned stats.
+///
+/// Symbolic links are skipped unless `options.follow_symlinks` is set. When
+/// they are followed, every file and directory is collected through a single
+/// path, preferring the link-free one for files, so a link and its target
+/// are never matched as duplicates of each other.
+///
+/// With `options.archives`, zip, cbz and tar archives are replaced by their
+/// members, named like `photos.zip!/2024/beach.jpg`. The include and
+/// extension filters apply to members; exclude and ignore rules to the
+/// archive itself.
+pub fn collect_files(
+    directory: &Path,
+    options: &ScanOptions,
+) -> Result<(Vec<PathBuf>, ScanStats), AppError> {
+    if !directory.is_dir() {
+        return Err(AppError::InvalidDirectory(directory.to_path_buf()));
+    }
+    let directory = &extended_length(directory);
+
+    let ignore = if options.ignore_files {
+        IgnoreFile::load(directory)?.unwrap_or_default()
+    } else {
+        IgnoreFile::default()
+    };
+    let walker = Walker {
+        root: directory,
+        options,
+        ignore,
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut links: Vec<PathBuf> = Vec::new();
+    let mut visited: HashSet<FileId> = HashSet::new();
+    if options.follow_symlinks {
+        visited.extend(FileId::of(directory).ok());
+    }
+    let mut stats = ScanStats::default();
+
+    // Directories are listed one level at a time, every directory of a
+    // level in parallel, which hides the latency of network filesystems.
+    // Listings are merged in path order, so that the path kept for a
+    // directory reached through several links does not depend on timing.
+    let mut level: Vec<PathBuf> = vec![directory.to_path_buf()];
+    let mut depth = 0;
+    while !level.is_empty() {
+        let listings: Vec<Result<Listing, (&PathBuf, io::Error)>> = level
+            .par_iter()
+            .map(|dir| walker.list(dir, depth).map_err(|err| (dir, err)))
+            .collect();
+        let mut next: Vec<PathBuf> = Vec::new();
+        for listing in listings {
+            let listing = match listing {
+                Ok(listing) => listing,
+                Err((_, err)) if depth == 0 => return Err(err.into()),
+                Err((dir, err)) => {
+                    stats.errors.push(EntryError::new(dir, err));
+                    continue;
+                }
+            };
+            files.extend(listing.files);
+            links.extend(listing.links);
+            stats += listing.stats;
+            next.extend(listing.subdirs);
+        }
+        next.sort();
+        if options.follow_symlinks {
+            next.retain(|dir| {
+                let first = FileId::of(dir).map_or(true, |id| visited.insert(id));
+                if !first {
+                    stats.duplicate_links += 1;
+                }
+                first
+            });
+        }
+        level = next;
+        depth += 1;
+    }
+
+    if !links.is_empty() {
+        let mut seen: HashSet<FileId> = files.iter().filter_map(|f| FileId::of(f).ok()).collect();
+        links.sort();
+        for link in links {
+            if FileId::of(&link).is_ok_and(|id| seen.insert(id)) {
+                files.push(link);
+            } else {
+                stats.duplicate_links += 1;
+            }
+        }
+    }
+
+    files.sort();
+    stats.matched = files.len();
+    Ok((files, stats))
+}
+
+/// Entries of a single directory, sorted out by [`Walker::list`].
+#[derive(Default)]
+struct Listing {
+    files: Vec<PathBuf>,
+    /// Links to files, kept only if their target is not collected otherwise.
+    links: Vec<PathBuf>,
+    /// Subdirectories to descend into.
+    subdirs: Vec<PathBuf>,
+    stats: ScanStats,
+}
+
+/// Rules deciding which entries below `root` are collected.
+struct Walker<'a> {
+    root: &'a Path,
+    options: &'a ScanOptions,
+    ignore: IgnoreFile,
+}
+
+impl Walker<'_> {
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(self.root).unwrap_or(path).to_path_buf()
+    }
+
+    fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignore.is_ignored(&self.relative(path), is_dir)
+    }
+
+    fn excluded(&self, path: &Path) -> bool {
+        let path = self.relative(path);
+        self.options.exclude.iter().any(|p| p.matches(&path))
+    }
+
+    fn included(&self, path: &Path) -> bool {
+        let path = self.relative(path);
+        self.options.include.is_empty() || self.options.include.iter().any(|p| p.matches(&path))
+    }
+
+    /// Add the file at `path` to `files`, followed by its pages after the
+    /// first when `options.pages` is set.
+    fn collect(&self, files: &mut Vec<PathBuf>, stats: &mut ScanStats, path: PathBuf) {
+        if self.options.pages && pages::is_multipage(&path) {
+            let count = pages::count(&path).unwrap_or(1);
+            files.extend((2..=count).map(|page| pages::page_path(&path, page)));
+            stats.pages += count - 1;
+        }
+        files.push(path);
+    }
+
+    /// Sort out the entries of `dir`, found `depth` levels below the root.
+    fn list(&self, dir: &Path, depth: usize) -> io::Result<Listing> {
+        let options = self.options;
+        let mut listing = Listing::default();
+        let stats = &mut listing.stats;
+        for entry in fs::read_dir(dir)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    stats.errors.push(EntryError::new(dir, err));
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let mut kind = match entry.file_type() {
+                Ok(kind) => kind,
+                Err(err) => {
+                    stats.errors.push(EntryError::new(&path, err));
+                    continue;
+                }
+            };
+            if depth == 0 && entry.file_name() == IGNORE_FILE {
+                continue;
+            }
+            if !options.junk && is_junk(&entry.file_name()) {
+                stats.junk += 1;
+                continue;
+            }
+            if !options.artifacts && is_artifact(&entry.file_name()) {
+                stats.artifacts += 1;
+                continue;
+            }
+            if !options.hidden && is_hidden(&entry) {
+                stats.hidden += 1;
+                continue;
+            }
+            let is_link = kind.is_symlink();
+            if is_link {
+                match fs::metadata(&path) {
+                    Ok(target) if options.follow_symlinks => kind = target.file_type(),
+                    _ => {
+                        stats.symlinks += 1;
+                        continue;
+                    }
+                }
+            }
+            let is_dir = kind.is_dir();
+            if !is_dir {
+                if self.ignored(&path, false) {
+                    stats.ignored += 1;
+                } else if self.excluded(&path) {
+                    stats.excluded += 1;
+                } else if options.archives && archive::is_archive(&path) {
+                    let names = match archive::list(&path) {
+                        Ok(names) => names,
+                        Err(err) => {
+                            stats.errors.push(EntryError::new(&path, err));
+                            continue;
+                        }
+                    };
+                    stats.archives += 1;
+                    for name in names {
+                        let member = archive::member_path(&path, &name);
+                        if !self.included(&member) {
+                            stats.not_included += 1;
+                        } else if !options.has_listed_extension(&member) {
+                            stats.wrong_extension += 1;
+                        } else {
+                            self.collect(&mut listing.files, stats, member);
+                        }
+                    }
+                } else if !self.included(&path) {
+                    stats.not_included += 1;
+                } else if !options.has_listed_extension(&path) {
+                    stats.wrong_extension += 1;
+                } else if is_link {
+                    listing.links.push(path);
+                } else {
+                    self.collect(&mut listing.files, stats, path);
+                }
+            } else if options.recursive && options.max_depth.is_none_or(|max| depth < max) {
+                let name = entry.file_name();
+                if options.exclude_dirs.iter().any(|dir| name == dir.as_str()) {
+                    stats.pruned_dirs += 1;
+                } else if self.ignored(&path, true) {
+                    stats.ignored += count_files(&path);
+                } else if self.excluded(&path) {
+                    stats.excluded += count_files(&path);
+                } else {
+                    listing.subdirs.push(path);
+                }
+            }
+        }
+        Ok(listing)
+    }
+}
+
+/// Apply the scan patterns to the files of a remote location, listed by their
+/// paths relative to `root`, as [`collect_files`] applies them to a walk.
+///
+/// Remote locations hold no ignore files, archives are not opened and pages
+/// are not split out. Skipped folders are counted once, as in a walk.
+pub fn collect_listed(
+    root: &Path,
+    listed: Vec<PathBuf>,
+    options: &ScanOptions,
+) -> (Vec<PathBuf>, ScanStats) {
+    let walker = Walker {
+        root,
+        options,
+        ignore: IgnoreFile::default(),
+    };
+    let mut files = Vec::new();
+    let mut stats = ScanStats::default();
+    let mut skipped: HashSet<PathBuf> = HashSet::new();
+    'files: for relative in listed {
+        let parents: Vec<&Path> = relative
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+        let deep = !parents.is_empty() && !options.recursive
+            || options.max_depth.is_some_and(|max| parents.len() > max);
+        if deep {
+            continue;
+        }
+        for dir in parents.iter().rev() {
+            if skipped.contains(*dir) {
+                continue 'files;
+            }
+            let name = dir.file_name().unwrap_or_default();
+            let skip = if !options.junk && is_junk(name) {
+                &mut stats.junk
+            } else if !options.artifacts && is_artifact(name) {
+                &mut stats.artifacts
+            } else if !options.hidden && name.to_string_lossy().starts_with('.') {
+                &mut stats.hidden
+            } else if options
+                .exclude_dirs
+                .iter()
+                .any(|excluded| name == excluded.as_str())
+            {
+                &mut stats.pruned_dirs
+            } else {
+                continue;
+            };
+            *skip += 1;
+            skipped.insert(dir.to_path_buf());
+            continue 'files;
+        }
+        let Some(name) = relative.file_name() else {
+            continue;
+        };
+        let path = root.join(&relative);
+        if parents.is_empty() && name == IGNORE_FILE {
+            continue;
+        } else if !options.junk && is_junk(name) {
+            stats.junk += 1;
+        } else if !options.artifacts && is_artifact(name) {
+            stats.artifacts += 1;
+        } else if !options.hidden && name.to_string_lossy().starts_with('.') {
+            stats.hidden += 1;
+        } else if walker.excluded(&path)
+            || parents.iter().any(|dir| walker.excluded(&root.join(dir)))
+        {
+            stats.excluded += 1;
+        } else if !walker.included(&path) {
+            stats.not_included += 1;
+        } else if !options.has_listed_extension(&path) {
+            stats.wrong_extension += 1;
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    stats.matched = files.len();
+    (files, stats)
+}
+
+/// Read the list of files to scan from `source`, one path per line, or from
+/// standard input if `source` is `-`.
+///
+/// No directory is walked and no scan pattern is applied, so the list can
+/// come from any tool, e.g. `fd -e jpg | idar deduplicate --files-from -`.
+/// Blank lines are skipped, as are listed paths that are neither files nor
+/// archive members. Paths are returned sorted and without repeats.
+pub fn read_file_list(source: &Path) -> Result<(Vec<PathBuf>, ScanStats), AppError> {
+    if source == Path::new("-") {
+        parse_file_list(io::stdin().lock())
+    } else {
+        match File::open(source) {
+            Ok(file) => parse_file_list(BufReader::new(file)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(AppError::FileNotFound(source.display().to_string()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+fn parse_file_list(reader: impl BufRead) -> Result<(Vec<PathBuf>, ScanStats), AppError> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if !line.trim().is_empty() {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    Ok(listed_files(paths))
+}
+
+/// Keep the paths of `paths` naming files, archive members or pages, sorted and
+/// without repeats, counting the others in the returned stats.
+pub fn listed_files(paths: Vec<PathBuf>) -> (Vec<PathBuf>, ScanStats) {
+    let mut stats = ScanStats::default();
+    let mut files: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|path| {
+            let found = path.is_file() || archive::is_member(path) || pages::is_page(path);
+            if !found {
+                stats.not_files += 1;
+            }
+            found
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+    stats.matched = files.len();
+    (files, stats)
+}
+
+/// Parse a file extension for `--ext`, ignoring case and a leading dot.
+pub fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.trim().trim_start_matches('.').to_lowercase();
+    if extension.is_empty() || extension.contains(['/', '\\']) {
+        return Err(format!("`{}` is not a file extension", value));
+    }
+    Ok(extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_files_follows_symlinks_once() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir().unwrap();
+        let photos = dir.path().join("photos");
+        fs::create_dir(&photos).unwrap();
+        fs::write(photos.join("a.png"), b"").unwrap();
+        symlink(photos.join("a.png"), dir.path().join("link.png")).unwrap();
+        symlink(&photos, dir.path().join("alias")).unwrap();
+        symlink(dir.path(), photos.join("loop")).unwrap();
+
+        let options = ScanOptions {
+            recursive: true,
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(files, vec![photos.join("a.png")]);
+        assert_eq!(stats.symlinks, 3);
+
+        let options = ScanOptions {
+            follow_symlinks: true,
+            ..options
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(stats.duplicate_links, 3);
+    }
+
+    #[test]
+    fn test_collect_files_respects_depth() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join("top.png"), b"").unwrap();
+        fs::write(dir.path().join("a").join("mid.png"), b"").unwrap();
+        fs::write(nested.join("deep.png"), b"").unwrap();
+
+        let (flat, _) = collect_files(dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(flat, vec![dir.path().join("top.png")]);
+
+        let options = ScanOptions {
+            recursive: true,
+            max_depth: Some(1),
+            ..ScanOptions::default()
+        };
+        let (limited, _) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(
+            limited,
+            vec![
+                dir.path().join("a").join("mid.png"),
+                dir.path().join("top.png")
+            ]
+        );
+
+        let options = ScanOptions {
+            recursive: true,
+            max_depth: None,
+            ..ScanOptions::default()
+        };
+        assert_eq!(collect_files(dir.path(), &options).unwrap().0.len(), 3);
+    }
+
+    /// A directory holding `top.jpg`, `notes.txt`, `a/mid.jpg` and
+    /// `a/thumbnails/small.jpg`.
+    fn photo_tree() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        let thumbnails = dir.path().join("a").join("thumbnails");
+        fs::create_dir_all(&thumbnails).unwrap();
+        fs::write(dir.path().join("top.jpg"), b"").unwrap();
+        fs::write(dir.path().join("notes.txt"), b"").unwrap();
+        fs::write(dir.path().join("a").join("mid.jpg"), b"").unwrap();
+        fs::write(thumbnails.join("small.jpg"), b"").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_collect_files_applies_patterns() {
+        let dir = photo_tree();
+        let options = ScanOptions {
+            recursive: true,
+            include: vec![Pattern::new("*.jpg").unwrap()],
+            exclude: vec![Pattern::new("**/thumbnails/**").unwrap()],
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a").join("mid.jpg"),
+                dir.path().join("top.jpg")
+            ]
+        );
+        assert_eq!(
+            stats,
+            ScanStats {
+                matched: 2,
+                excluded: 1,
+                not_included: 1,
+                ..ScanStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_collect_files_filters_extensions() {
+        let dir = photo_tree();
+        let options = ScanOptions {
+            extensions: vec![parse_extension(".JPG").unwrap()],
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(files, vec![dir.path().join("top.jpg")]);
+        assert_eq!(stats.wrong_extension, 1);
+    }
+
+    #[test]
+    fn test_collect_files_honours_ignore_files() {
+        let dir = photo_tree();
+        fs::write(dir.path().join(".idarignore"), "a/\n").unwrap();
+        let options = ScanOptions {
+            recursive: true,
+            include: vec![Pattern::new("*.jpg").unwrap()],
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(files, vec![dir.path().join("top.jpg")]);
+        assert_eq!(stats.ignored, 2);
+
+        let options = ScanOptions {
+            ignore_files: false,
+            ..options
+        };
+        assert_eq!(collect_files(dir.path(), &options).unwrap().0.len(), 3);
+    }
+
+    #[test]
+    fn test_collect_files_prunes_excluded_dirs() {
+        let dir = photo_tree();
+        let options = ScanOptions {
+            recursive: true,
+            exclude_dirs: vec!["thumbnails".to_string()],
+            extensions: vec!["jpg".to_string()],
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(stats.pruned_dirs, 1);
+    }
+
+    #[test]
+    fn test_collect_files_skips_hidden_and_junk() {
+        let dir = photo_tree();
+        fs::create_dir(dir.path().join("@eaDir")).unwrap();
+        fs::write(dir.path().join("@eaDir").join("top.jpg"), b"").unwrap();
+        fs::write(dir.path().join(".hidden.jpg"), b"").unwrap();
+        let options = ScanOptions {
+            extensions: vec!["jpg".to_string()],
+            recursive: true,
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!((stats.hidden, stats.junk), (1, 1));
+
+        let options = ScanOptions {
+            hidden: true,
+            junk: true,
+            ..options
+        };
+        assert_eq!(collect_files(dir.path(), &options).unwrap().0.len(), 5);
+    }
+
+    #[test]
+    fn test_collect_files_skips_idar_artifacts() {
+        let dir = photo_tree();
+        fs::write(dir.path().join("dedup_report.json"), b"{}").unwrap();
+        fs::write(dir.path().join("idar_index.json"), b"{}").unwrap();
+        fs::create_dir(dir.path().join("idar_quarantine")).unwrap();
+        fs::write(dir.path().join("idar_quarantine").join("old.jpg"), b"").unwrap();
+        let options = ScanOptions {
+            recursive: true,
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_files(dir.path(), &options).unwrap();
+        assert_eq!((files.len(), stats.artifacts), (4, 3));
+
+        let options = ScanOptions {
+            artifacts: true,
+            ..options
+        };
+        assert_eq!(collect_files(dir.path(), &options).unwrap().0.len(), 7);
+    }
+
+    #[test]
+    fn test_collect_listed_applies_the_scan_patterns() {
+        let root = Path::new("s3://bucket/photos");
+        let listed = [
+            "top.jpg",
+            "notes.txt",
+            "a/mid.jpg",
+            "a/thumbnails/small.jpg",
+            "@eaDir/top.jpg",
+            "@eaDir/other.jpg",
+            ".hidden/x.jpg",
+            "a/b/c/deep.jpg",
+        ];
+        let listed: Vec<PathBuf> = listed.iter().map(PathBuf::from).collect();
+        let options = ScanOptions {
+            recursive: true,
+            max_depth: Some(2),
+            exclude: vec![Pattern::new("**/thumbnails/**").unwrap()],
+            extensions: vec!["jpg".to_string()],
+            ..ScanOptions::default()
+        };
+        let (files, stats) = collect_listed(root, listed.clone(), &options);
+        assert_eq!(files, vec![root.join("a/mid.jpg"), root.join("top.jpg")]);
+        assert_eq!(
+            stats,
+            ScanStats {
+                matched: 2,
+                excluded: 1,
+                wrong_extension: 1,
+                hidden: 1,
+                junk: 1,
+                ..ScanStats::default()
+            }
+        );
+
+        let (files, _) = collect_listed(root, listed, &ScanOptions::default());
+        assert_eq!(files, vec![root.join("notes.txt"), root.join("top.jpg")]);
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.png");
+        let b = dir.path().join("b.png");
+        fs::write(&a, b"").unwrap();
+        fs::write(&b, b"").unwrap();
+        let list = format!(
+            "{}\n\n{}\r\n{}\n{}\n",
+            b.display(),
+            a.display(),
+            b.display(),
+            dir.path().join("missing.png").display()
+        );
+
+        let (files, stats) = parse_file_list(list.as_bytes()).unwrap();
+        assert_eq!(files, vec![a, b]);
+        assert_eq!((stats.matched, stats.not_files), (2, 1));
+    }
+}
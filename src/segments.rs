@@ -0,0 +1,277 @@
+use crate::content::ContentKind;
+use crate::decode::Colorspace;
+use crate::hashing::PerceptualHasher;
+use crate::models::{Dimensions, ImageInfo};
+use crate::store::{Hashes, ImageSource};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, GrayImage};
+use image_hasher::ImageHash;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Side length of the square grayscale image segmented into regions.
+const SEGMENTATION_SIZE: u32 = 300;
+
+/// Smallest region kept, in pixels of the segmentation image.
+const MIN_SEGMENT_PIXELS: usize = 500;
+
+/// Largest number of regions hashed per image, keeping the largest ones.
+const MAX_SEGMENTS: usize = 16;
+
+/// Default number of regions two images must share to match.
+pub const DEFAULT_MIN_MATCHES: usize = 2;
+
+/// Hash the regions of `img`, largest first, for crop-resistant matching.
+///
+/// The image is split into connected regions of bright and dark pixels,
+/// and the bounding box of every region is cut from the full image and
+/// hashed on its own. Regions follow the content rather than the frame,
+/// so a cropped or letterboxed copy keeps most of them.
+pub fn hash_segments(hasher: &dyn PerceptualHasher, img: &DynamicImage) -> Vec<ImageHash> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let small = img
+        .resize_exact(SEGMENTATION_SIZE, SEGMENTATION_SIZE, FilterType::Triangle)
+        .to_luma8();
+    let small = imageops::blur(&small, 2.0);
+
+    let scale =
+        |value: u32, full: u32| (value as u64 * full as u64 / SEGMENTATION_SIZE as u64) as u32;
+    segment(&small)
+        .into_iter()
+        .take(MAX_SEGMENTS)
+        .filter_map(|[left, top, right, bottom]| {
+            let x = scale(left, width);
+            let y = scale(top, height);
+            let region_width = scale(right + 1, width).saturating_sub(x);
+            let region_height = scale(bottom + 1, height).saturating_sub(y);
+            (region_width > 0 && region_height > 0)
+                .then(|| hasher.hash_image(&img.crop_imm(x, y, region_width, region_height)))
+        })
+        .collect()
+}
+
+/// Bounding boxes, as `[left, top, right, bottom]` inclusive, of the
+/// connected regions of `image` on either side of mid-gray, largest first.
+fn segment(image: &GrayImage) -> Vec<[u32; 4]> {
+    let (width, height) = image.dimensions();
+    let bright: Vec<bool> = image.pixels().map(|pixel| pixel.0[0] >= 128).collect();
+    let mut seen = vec![false; bright.len()];
+    let mut regions: Vec<(usize, [u32; 4])> = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..bright.len() {
+        if seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        stack.push(start);
+        let mut size = 0;
+        let mut bounds = [u32::MAX, u32::MAX, 0, 0];
+        while let Some(index) = stack.pop() {
+            size += 1;
+            let (x, y) = ((index as u32) % width, (index as u32) / width);
+            bounds = [
+                bounds[0].min(x),
+                bounds[1].min(y),
+                bounds[2].max(x),
+                bounds[3].max(y),
+            ];
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width as usize),
+                (y + 1 < height).then(|| index + width as usize),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if !seen[neighbour] && bright[neighbour] == bright[start] {
+                    seen[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        if size >= MIN_SEGMENT_PIXELS {
+            regions.push((size, bounds));
+        }
+    }
+    regions.sort_by_key(|&(size, _)| std::cmp::Reverse(size));
+    regions.into_iter().map(|(_, bounds)| bounds).collect()
+}
+
+/// The region hashes of every image of a source, so that matching finds
+/// the pairs of regions closer than the threshold.
+pub struct Segments<'a, S: ?Sized> {
+    images: &'a S,
+    /// Image and region of every entry.
+    entries: Vec<(usize, usize)>,
+}
+
+impl<'a, S: ImageSource + ?Sized> Segments<'a, S> {
+    pub fn new(images: &'a S) -> Segments<'a, S> {
+        let entries = (0..images.len())
+            .flat_map(|index| (0..images.segments(index).len()).map(move |region| (index, region)))
+            .collect();
+        Segments { images, entries }
+    }
+
+    /// The sorted pairs of images that share at least `min_matches`
+    /// regions, given the pairs of matching entries.
+    ///
+    /// A region counts once however many regions of the other image it
+    /// matches, and both images must have that many regions matched.
+    pub fn owner_pairs(
+        &self,
+        pairs: Vec<(usize, usize)>,
+        min_matches: usize,
+    ) -> Vec<(usize, usize)> {
+        let mut shared: HashMap<(usize, usize), (HashSet<usize>, HashSet<usize>)> = HashMap::new();
+        for (i, j) in pairs {
+            let (mut first, mut second) = (self.entries[i], self.entries[j]);
+            if first.0 == second.0 {
+                continue;
+            }
+            if first.0 > second.0 {
+                std::mem::swap(&mut first, &mut second);
+            }
+            let regions = shared.entry((first.0, second.0)).or_default();
+            regions.0.insert(first.1);
+            regions.1.insert(second.1);
+        }
+        let mut owners: Vec<(usize, usize)> = shared
+            .into_iter()
+            .filter(|(_, (first, second))| first.len().min(second.len()) >= min_matches.max(1))
+            .map(|(pair, _)| pair)
+            .collect();
+        owners.sort_unstable();
+        owners
+    }
+}
+
+impl<S: ImageSource + ?Sized> ImageSource for Segments<'_, S> {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn path(&self, index: usize) -> &Path {
+        self.images.path(self.entries[index].0)
+    }
+
+    fn hash_bytes(&self, index: usize) -> &[u8] {
+        let (owner, region) = self.entries[index];
+        self.images.segments(owner).bytes(region)
+    }
+
+    fn root(&self, index: usize) -> Option<&Path> {
+        self.images.root(self.entries[index].0)
+    }
+
+    fn links(&self, index: usize) -> Vec<PathBuf> {
+        self.images.links(self.entries[index].0)
+    }
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf> {
+        self.images.sidecars(self.entries[index].0)
+    }
+
+    fn variants(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn segments(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn windows(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn frames(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn masked(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions> {
+        self.images.dimensions(self.entries[index].0)
+    }
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace> {
+        self.images.colorspace(self.entries[index].0)
+    }
+
+    fn content(&self, index: usize) -> Option<ContentKind> {
+        self.images.content(self.entries[index].0)
+    }
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String> {
+        self.images.columns(self.entries[index].0)
+    }
+
+    fn image(&self, index: usize) -> ImageInfo {
+        self.images.image(self.entries[index].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::{self, HashAlgorithm};
+    use crate::matching;
+    use image::{Luma, RgbImage};
+
+    /// Bright textured blocks at `blocks` on a dark background, with a
+    /// pattern chosen by `seed`.
+    fn blocks(blocks: &[(u32, u32)], seed: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(400, 300, |x, y| {
+            let inside = blocks
+                .iter()
+                .any(|&(bx, by)| (bx..bx + 90).contains(&x) && (by..by + 90).contains(&y));
+            if inside {
+                let cell = (x / 10 * 31 + y / 10 * 17 + seed).wrapping_mul(2_654_435_761);
+                Luma([150 + (cell >> 24) as u8 % 100])
+            } else {
+                Luma([20])
+            }
+        }))
+    }
+
+    fn info(name: &str, hasher: &dyn PerceptualHasher, img: &DynamicImage) -> ImageInfo {
+        ImageInfo {
+            segments: hash_segments(hasher, img),
+            ..ImageInfo::new(PathBuf::from(name), hasher.hash_image(img))
+        }
+    }
+
+    #[test]
+    fn test_cropped_copy_shares_segments() {
+        let hasher = hashing::build_hasher(HashAlgorithm::Gradient, 8);
+        let original = blocks(&[(20, 20), (140, 30), (260, 40), (60, 150), (200, 160)], 1);
+        // Cut off the left block and pad the bottom, as a repost might.
+        let mut cropped = RgbImage::new(270, 320);
+        imageops::replace(
+            &mut cropped,
+            &original.crop_imm(130, 0, 270, 300).to_rgb8(),
+            0,
+            0,
+        );
+        let images = [
+            info("original.png", &hasher, &original),
+            info("cropped.png", &hasher, &DynamicImage::ImageRgb8(cropped)),
+            info(
+                "other.png",
+                &hasher,
+                &blocks(&[(10, 150), (120, 10), (300, 180), (180, 120)], 4),
+            ),
+        ];
+        assert!(images[0].segments.len() >= 5);
+        assert!(matching::candidate_pairs(&images[..], 5).is_empty());
+
+        let segments = Segments::new(&images[..]);
+        let pairs = segments.owner_pairs(matching::candidate_pairs(&segments, 5), 2);
+        assert_eq!(pairs, vec![(0, 1)]);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::errors::AppError;
+use crate::hnsw::{self, HnswOptions};
+use crate::store::ImageSource;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Default smallest cosine similarity between the embeddings of duplicates.
+pub const DEFAULT_MIN_SIMILARITY: f32 = 0.95;
+
+/// Command run by default to embed images with a model, the script shipped
+/// in `scripts/` for CLIP image encoders exported to ONNX.
+pub const DEFAULT_EMBED_COMMAND: &str = "idar-clip-embed";
+
+#[derive(Deserialize)]
+struct Line {
+    path: PathBuf,
+    embedding: Vec<f32>,
+}
+
+/// Image embeddings computed by a model, such as the image encoder of CLIP,
+/// either ahead of time or by an [`Embedder`] during the run.
+///
+/// Embeddings are read as JSON lines holding one
+/// `{"path": ..., "embedding": [...]}` object per image. Relative paths in
+/// a file are resolved against the directory holding it.
+#[derive(Debug, Clone, Default)]
+pub struct Embeddings {
+    vectors: HashMap<PathBuf, Vec<f32>>,
+}
+
+impl Embeddings {
+    pub fn load(path: &Path) -> Result<Embeddings, AppError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(AppError::FileNotFound(path.display().to_string()));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let base = path.parent().unwrap_or(Path::new(""));
+        Ok(Embeddings::parse(&contents, base)?)
+    }
+
+    /// Parse the JSON lines `contents` of an embeddings file, resolving
+    /// relative paths against `base`. Blank lines are skipped.
+    pub fn parse(contents: &str, base: &Path) -> Result<Embeddings, serde_json::Error> {
+        let mut embeddings = Embeddings::default();
+        for line in contents.lines() {
+            embeddings.add_line(line, base)?;
+        }
+        Ok(embeddings)
+    }
+
+    /// Add the embedding held by the JSON line `line`, unless it is blank.
+    fn add_line(&mut self, line: &str, base: &Path) -> Result<(), serde_json::Error> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let Line { path, embedding } = serde_json::from_str(line)?;
+        self.vectors.insert(key(&base.join(path)), embedding);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// Embedding of the image at `path`, if the file lists it.
+    pub fn get(&self, path: &Path) -> Option<&[f32]> {
+        self.vectors.get(&key(path)).map(Vec::as_slice)
+    }
+}
+
+/// A command running an embedding model over the scanned images, such as
+/// the `idar-clip-embed` script running a CLIP image encoder with ONNX
+/// Runtime.
+///
+/// The command runs with `sh -c`, or `cmd /C` on Windows, and finds the
+/// path of the model in `IDAR_MODEL`. It reads the paths of the images on
+/// its input, one per line, and writes the embedding of every image it
+/// could read to its output as a JSON line, in the format of an embeddings
+/// file. Images it leaves out match nothing.
+#[derive(Debug, Clone)]
+pub struct Embedder {
+    pub command: String,
+    pub model: PathBuf,
+}
+
+impl Embedder {
+    /// Embed the images at `paths` by running the command.
+    pub fn embed(&self, paths: Vec<PathBuf>) -> Result<Embeddings, AppError> {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let mut child = shell
+            .arg(&self.command)
+            .env("IDAR_MODEL", std::path::absolute(&self.model)?)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("cannot run the embedding command: {}", err),
+                )
+            })?;
+        let mut input = child.stdin.take().expect("the input is piped");
+        // Written from another thread, as the command may answer before it
+        // has read every path.
+        let writer = thread::spawn(move || -> io::Result<()> {
+            for path in paths {
+                writeln!(input, "{}", path.display())?;
+            }
+            Ok(())
+        });
+        let mut embeddings = Embeddings::default();
+        let output = BufReader::new(child.stdout.take().expect("the output is piped"));
+        for line in output.lines() {
+            embeddings.add_line(&line?, Path::new(""))?;
+        }
+        let status = child.wait()?;
+        // The command may stop reading early, which its status tells.
+        let _ = writer.join();
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "the embedding command `{}` exited with {}",
+                self.command, status
+            ))
+            .into());
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Path under which an embedding is stored, the same however the image was
+/// reached. Paths that cannot be resolved, such as archive members, are
+/// kept as they are.
+fn key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Sorted pairs of images whose embeddings have a cosine similarity of at
+/// least `min_similarity`, found through an HNSW index of the embeddings.
+/// Images without an embedding match nothing.
+pub fn similar_pairs<S: ImageSource + ?Sized>(
+    images: &S,
+    embeddings: &Embeddings,
+    min_similarity: f32,
+) -> Vec<(usize, usize)> {
+    let (owners, vectors): (Vec<usize>, Vec<Vec<f32>>) = (0..images.len())
+        .filter_map(|index| Some((index, embeddings.get(images.path(index))?.to_vec())))
+        .unzip();
+    // Owners are increasing, so sorted pairs of vectors stay sorted.
+    hnsw::similar_pairs(&vectors, min_similarity, &HnswOptions::default())
+        .into_iter()
+        .map(|(i, j)| (owners[i], owners[j]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+
+    #[test]
+    fn test_similar_embeddings_pair_up() {
+        let base = Path::new("/photos");
+        let embeddings = Embeddings::parse(
+            "{\"path\": \"beach.jpg\", \"embedding\": [1.0, 0.0, 0.1]}\n\
+             {\"path\": \"beach_edited.jpg\", \"embedding\": [2.0, 0.1, 0.2]}\n\n\
+             {\"path\": \"/photos/forest.jpg\", \"embedding\": [0.0, 1.0, 0.0]}\n",
+            base,
+        )
+        .unwrap();
+        assert_eq!(embeddings.len(), 3);
+
+        let images: Vec<ImageInfo> = ["beach.jpg", "forest.jpg", "beach_edited.jpg", "new.jpg"]
+            .iter()
+            .map(|name| ImageInfo::at(base.join(name)))
+            .collect();
+        assert_eq!(similar_pairs(&images[..], &embeddings, 0.95), vec![(0, 2)]);
+        assert!(similar_pairs(&images[..], &embeddings, 0.9999).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_embedder_runs_the_command_over_the_images() {
+        let embedder = |command: &str| Embedder {
+            command: command.to_string(),
+            model: PathBuf::from("/models/clip.onnx"),
+        };
+        let paths = vec![
+            PathBuf::from("/photos/a.jpg"),
+            PathBuf::from("/photos/b.jpg"),
+        ];
+        let command = "[ \"$IDAR_MODEL\" = /models/clip.onnx ] || exit 3
+            while read -r path; do
+                [ \"$path\" = /photos/b.jpg ] && continue
+                printf '{\"path\": \"%s\", \"embedding\": [1.0, 0.5]}\\n' \"$path\"
+            done";
+        let embeddings = embedder(command).embed(paths.clone()).unwrap();
+        assert_eq!(embeddings.len(), 1);
+        assert_eq!(embeddings.get(&paths[0]), Some([1.0, 0.5].as_slice()));
+
+        assert!(embedder("exit 1").embed(paths).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use image_hasher::{ImageHash, InvalidBytesError};
 use serde::de::Error as SerdeError;
-use serde::{Deserialize, Deserializer, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
 pub fn hash_to_base64<S>(hash: &ImageHash, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -18,3 +20,150 @@ where
         D::Error::custom(format!("Failed to parse hash from base64: {:?}", err))
     })
 }
+
+pub fn hashes_to_base64<S>(hashes: &[ImageHash], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(hashes.iter().map(ImageHash::to_base64))
+}
+
+pub fn hashes_from_base64<'de, D>(deserializer: D) -> Result<Vec<ImageHash>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|base64_str| {
+            ImageHash::from_base64(base64_str).map_err(|err: InvalidBytesError| {
+                D::Error::custom(format!("Failed to parse hash from base64: {:?}", err))
+            })
+        })
+        .collect()
+}
+
+/// A path serialized as a string when it is valid Unicode, and otherwise in
+/// serde's encoding of an `OsStr`, `{"Unix": [bytes]}` or
+/// `{"Windows": [code units]}`, so that no file name is lost or altered by
+/// a round trip through a report.
+struct PathRepr<'a>(&'a Path);
+
+impl Serialize for PathRepr<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.to_str() {
+            Some(text) => serializer.serialize_str(text),
+            None => self.0.as_os_str().serialize(serializer),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PathValue {
+    Text(String),
+    Native(OsString),
+}
+
+impl From<PathValue> for PathBuf {
+    fn from(value: PathValue) -> PathBuf {
+        match value {
+            PathValue::Text(text) => PathBuf::from(text),
+            PathValue::Native(native) => PathBuf::from(native),
+        }
+    }
+}
+
+pub fn path_to_json<S>(path: &Path, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    PathRepr(path).serialize(serializer)
+}
+
+pub fn path_from_json<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    PathValue::deserialize(deserializer).map(PathBuf::from)
+}
+
+pub fn optional_path_to_json<S>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    path.as_deref().map(PathRepr).serialize(serializer)
+}
+
+pub fn optional_path_from_json<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<PathValue>::deserialize(deserializer).map(|value| value.map(PathBuf::from))
+}
+
+pub fn paths_to_json<S>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(paths.iter().map(|path| PathRepr(path)))
+}
+
+pub fn paths_from_json<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Vec::<PathValue>::deserialize(deserializer)?;
+    Ok(values.into_iter().map(PathBuf::from).collect())
+}
+
+pub fn path_sets_to_json<S>(sets: &[Vec<PathBuf>], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let sets = sets
+        .iter()
+        .map(|set| set.iter().map(|path| PathRepr(path)).collect::<Vec<_>>());
+    serializer.collect_seq(sets)
+}
+
+pub fn path_sets_from_json<'de, D>(deserializer: D) -> Result<Vec<Vec<PathBuf>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let sets = Vec::<Vec<PathValue>>::deserialize(deserializer)?;
+    Ok(sets
+        .into_iter()
+        .map(|set| set.into_iter().map(PathBuf::from).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        #[serde(serialize_with = "path_to_json", deserialize_with = "path_from_json")]
+        path: PathBuf,
+    }
+
+    #[test]
+    fn test_paths_round_trip() {
+        let unicode = Entry {
+            path: PathBuf::from("photos/été/🐈.jpg"),
+        };
+        let json = serde_json::to_string(&unicode).unwrap();
+        assert_eq!(json, r#"{"path":"photos/été/🐈.jpg"}"#);
+        assert_eq!(serde_json::from_str::<Entry>(&json).unwrap(), unicode);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let latin1 = Entry {
+                path: PathBuf::from(OsString::from_vec(b"photos/\xe9t\xe9.jpg".to_vec())),
+            };
+            let json = serde_json::to_string(&latin1).unwrap();
+            assert_eq!(serde_json::from_str::<Entry>(&json).unwrap(), latin1);
+        }
+    }
+}
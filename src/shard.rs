@@ -0,0 +1,262 @@
+use crate::deduplicate::{self, DeduplicateOptions};
+use crate::errors::AppError;
+use crate::hashing::{Channels, HashAlgorithm, Preprocessing};
+use crate::invariance::Invariance;
+use crate::models::ImageInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One of `count` equal slices of the scanned files, numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Part {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Part {
+    /// The slice of `items` belonging to this part. Every item belongs to
+    /// exactly one of the `count` parts.
+    pub fn select<T>(&self, items: Vec<T>) -> Vec<T> {
+        let start = (self.index - 1) * items.len() / self.count;
+        let end = self.index * items.len() / self.count;
+        items.into_iter().skip(start).take(end - start).collect()
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// Parse a part given as `INDEX/COUNT`, e.g. `3/8`.
+pub fn parse_part(value: &str) -> Result<Part, String> {
+    let invalid = || format!("`{}` is not a part such as 3/8", value);
+    let (index, count) = value.split_once('/').ok_or_else(invalid)?;
+    let index: usize = index.trim().parse().map_err(|_| invalid())?;
+    let count: usize = count.trim().parse().map_err(|_| invalid())?;
+    if index == 0 || index > count {
+        return Err(format!("part {} must be between 1 and {}", index, count));
+    }
+    Ok(Part { index, count })
+}
+
+/// Hashes of one part of a directory, written by `shard hash`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PartialHashes {
+    #[serde(
+        serialize_with = "crate::serialization::path_to_json",
+        deserialize_with = "crate::serialization::path_from_json"
+    )]
+    pub directory_path: PathBuf,
+    pub algorithm: HashAlgorithm,
+    pub hash_size: u32,
+    /// Color planes hashed on their own, with `--channels`.
+    #[serde(default)]
+    pub channels: Channels,
+    /// Processing applied to images before hashing them.
+    #[serde(default)]
+    pub preprocessing: Preprocessing,
+    /// Variants hashed along with every image, with `--invariant`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invariance: Vec<Invariance>,
+    /// Whether region hashes were computed, with `--crop-resistant`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub crop_resistant: bool,
+    /// Whether window hashes were computed, with `--find-crops`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub find_crops: bool,
+    /// Whether animations were hashed by their first frame only, with
+    /// `--first-frame`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub first_frame: bool,
+    /// Whether images were tagged by content, with `--tag-content`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tag_content: bool,
+    /// Whether images were also hashed with their corners masked, with
+    /// `--watermarks`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub watermarks: bool,
+    pub part: Part,
+    pub images: Vec<ImageInfo>,
+    /// Sets of byte-identical files found within this part.
+    #[serde(
+        serialize_with = "crate::serialization::path_sets_to_json",
+        deserialize_with = "crate::serialization::path_sets_from_json"
+    )]
+    pub identical: Vec<Vec<PathBuf>>,
+}
+
+impl PartialHashes {
+    pub fn load(path: &Path) -> Result<PartialHashes, AppError> {
+        if !path.is_file() {
+            return Err(AppError::FileNotFound(path.display().to_string()));
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), AppError> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Hash `part` of the files in `directory` and save them to `output`.
+pub fn hash(
+    directory: &Path,
+    part: Part,
+    output: &Path,
+    options: &DeduplicateOptions,
+) -> Result<(), AppError> {
+    println!("Hashing part {} of directory: {:?}", part, directory);
+    let options = DeduplicateOptions {
+        part: Some(part),
+        ..options.clone()
+    };
+    let scanned = deduplicate::hash_directories(&[directory.to_path_buf()], &options)?;
+
+    let partial = PartialHashes {
+        directory_path: directory.to_path_buf(),
+        algorithm: options.algorithm,
+        hash_size: options.hash_size,
+        channels: options.channels,
+        preprocessing: options.preprocessing,
+        invariance: options.invariance.clone(),
+        crop_resistant: options.crop_resistant,
+        find_crops: options.find_crops,
+        first_frame: options.first_frame,
+        tag_content: options.tag_content,
+        watermarks: options.watermarks,
+        part,
+        images: scanned.images,
+        identical: scanned.identical,
+    };
+    partial.save(output)?;
+    println!(
+        "Saved {} hashes of part {} to {:?}",
+        partial.images.len(),
+        part,
+        output
+    );
+    Ok(())
+}
+
+/// Combine the partial hash files written by [`hash`], match them as a
+/// single collection and save the report to `report_path`.
+///
+/// All partials must use the same hash settings. The algorithm, hash size
+/// and the extra hashes asked for by `options` are ignored in favour of
+/// theirs.
+pub fn merge(
+    partials: &[PathBuf],
+    report_path: &Path,
+    options: &DeduplicateOptions,
+) -> Result<(), AppError> {
+    let mut loaded: Vec<PartialHashes> = Vec::with_capacity(partials.len());
+    for path in partials {
+        let partial = PartialHashes::load(path)?;
+        if let Some(first) = loaded.first() {
+            let settings = |p: &PartialHashes| {
+                let extras = (
+                    p.invariance.clone(),
+                    p.crop_resistant,
+                    p.find_crops,
+                    p.first_frame,
+                    p.tag_content,
+                    p.watermarks,
+                );
+                let hashing = (p.algorithm, p.hash_size, p.channels, p.preprocessing);
+                (hashing, extras)
+            };
+            if settings(&partial) != settings(first) {
+                return Err(AppError::IncompatibleShard(path.clone()));
+            }
+        }
+        loaded.push(partial);
+    }
+    let Some(first) = loaded.first() else {
+        return Ok(());
+    };
+
+    let directory = first.directory_path.clone();
+    let options = DeduplicateOptions {
+        algorithm: first.algorithm,
+        hash_size: first.hash_size,
+        channels: first.channels,
+        preprocessing: first.preprocessing,
+        invariance: first.invariance.clone(),
+        crop_resistant: first.crop_resistant,
+        find_crops: first.find_crops,
+        first_frame: first.first_frame,
+        tag_content: first.tag_content,
+        watermarks: first.watermarks,
+        ..options.clone()
+    };
+    let counts: BTreeSet<usize> = loaded.iter().map(|p| p.part.count).collect();
+    let seen: BTreeSet<Part> = loaded.iter().map(|p| p.part).collect();
+    for &count in &counts {
+        let missing: Vec<String> = (1..=count)
+            .map(|index| Part { index, count })
+            .filter(|part| !seen.contains(part))
+            .map(|part| part.to_string())
+            .collect();
+        if !missing.is_empty() {
+            eprintln!(
+                "warning: missing parts {}; their images are not matched",
+                missing.join(", ")
+            );
+        }
+    }
+
+    let mut images: Vec<ImageInfo> = Vec::new();
+    let mut identical: Vec<Vec<PathBuf>> = Vec::new();
+    for partial in loaded {
+        images.extend(partial.images);
+        identical.extend(partial.identical);
+    }
+    images.sort_by(|a, b| a.path.cmp(&b.path));
+    images.dedup_by(|a, b| a.path == b.path);
+    println!(
+        "Merged {} images from {} partial hash files.",
+        images.len(),
+        partials.len()
+    );
+
+    let metadata = options.metadata(&[directory]);
+    let summary = deduplicate::report_images(
+        images,
+        &identical,
+        Vec::new(),
+        metadata,
+        report_path,
+        &options,
+    )?;
+
+    println!("Process completed successfully.\n");
+    println!("{}", summary);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parts_cover_every_item_once() {
+        let items: Vec<usize> = (0..10).collect();
+        let mut covered: Vec<usize> = Vec::new();
+        for index in 1..=3 {
+            covered.extend(Part { index, count: 3 }.select(items.clone()));
+        }
+        assert_eq!(covered, items);
+
+        assert_eq!(parse_part("3/8"), Ok(Part { index: 3, count: 8 }));
+        assert!(parse_part("0/8").is_err());
+        assert!(parse_part("9/8").is_err());
+        assert!(parse_part("three").is_err());
+    }
+}
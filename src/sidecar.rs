@@ -0,0 +1,137 @@
+use crate::archive;
+use crate::models::ImageInfo;
+use crate::pages;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Names of the sidecar files of the image named `name`, lowercase, as
+/// written by photo tools:
+///
+/// - `IMG_1.jpg.xmp` and `IMG_1.xmp`, XMP metadata from Lightroom,
+///   darktable and others;
+/// - `IMG_1.jpg.json` and `IMG_1.jpg.supplemental-metadata.json`, metadata
+///   exported by Google Takeout;
+/// - `IMG_1.aae`, edits made in Apple Photos.
+fn candidates(name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name.as_str(),
+    };
+    vec![
+        format!("{}.xmp", name),
+        format!("{}.xmp", stem),
+        format!("{}.json", name),
+        format!("{}.supplemental-metadata.json", name),
+        format!("{}.aae", stem),
+    ]
+}
+
+/// Set the sidecars of every image of `images` to the sidecar files found
+/// next to it, matching names without regard to case.
+///
+/// Each directory holding images is listed once. Archive members and pages
+/// of multi-page files have no sidecars.
+pub fn attach(images: &mut [ImageInfo]) {
+    let mut by_dir: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (index, image) in images.iter().enumerate() {
+        if archive::is_member(&image.path) || pages::is_page(&image.path) {
+            continue;
+        }
+        if let Some(dir) = image.path.parent() {
+            by_dir.entry(dir.to_path_buf()).or_default().push(index);
+        }
+    }
+
+    let found: Vec<(usize, Vec<PathBuf>)> = by_dir
+        .par_iter()
+        .flat_map_iter(|(dir, indices)| {
+            let names = lowercase_names(dir);
+            indices
+                .iter()
+                .filter_map(|&index| {
+                    let image = &images[index].path;
+                    let name = image.file_name()?.to_str()?;
+                    let mut sidecars: Vec<PathBuf> = candidates(name)
+                        .iter()
+                        .filter_map(|candidate| names.get(candidate))
+                        .map(|sidecar| dir.join(sidecar))
+                        .filter(|sidecar| sidecar != image)
+                        .collect();
+                    sidecars.dedup();
+                    (!sidecars.is_empty()).then_some((index, sidecars))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for (index, sidecars) in found {
+        images[index].sidecars = sidecars;
+    }
+}
+
+/// Names of the files in `dir`, by their lowercase form.
+fn lowercase_names(dir: &Path) -> HashMap<String, OsString> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            Some((name.to_str()?.to_lowercase(), name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image_hasher::ImageHash;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_attach_finds_sidecars() {
+        let dir = tempdir().unwrap();
+        for name in [
+            "IMG_1.JPG",
+            "IMG_1.JPG.xmp",
+            "IMG_1.AAE",
+            "IMG_1.jpg.supplemental-metadata.json",
+            "IMG_2.png",
+            "IMG_20.png.xmp",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let mut images: Vec<ImageInfo> = ["IMG_1.JPG", "IMG_2.png"]
+            .iter()
+            .map(|name| {
+                ImageInfo::new(
+                    dir.path().join(name),
+                    ImageHash::from_base64("DAIDBwMHAf8").unwrap(),
+                )
+            })
+            .collect();
+
+        attach(&mut images);
+        let mut sidecars = images[0].sidecars.clone();
+        sidecars.sort();
+        assert_eq!(
+            sidecars,
+            [
+                dir.path().join("IMG_1.AAE"),
+                dir.path().join("IMG_1.JPG.xmp"),
+                dir.path().join("IMG_1.jpg.supplemental-metadata.json"),
+            ]
+        );
+        assert!(images[1].sidecars.is_empty());
+    }
+}
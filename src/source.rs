@@ -0,0 +1,393 @@
+use crate::archive;
+use crate::decode::DecodeLimiter;
+use crate::errors::AppError;
+use crate::scan::{self, ScanOptions, ScanStats};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Program listing and reading the files of remote locations, looked up on
+/// the `PATH`.
+const RCLONE: &str = "rclone";
+
+/// A location holding the files to scan.
+///
+/// A remote location lists its files under URLs below its own, which
+/// [`archive::open`] reads into memory, so that they are decoded like
+/// archive members.
+pub trait Source: Sync {
+    /// The files worth hashing, by the rules of `options`, with what was
+    /// left out while listing them.
+    fn list(&self, options: &ScanOptions) -> Result<(Vec<PathBuf>, ScanStats), AppError>;
+
+    /// Open the file at `path`, one of those listed, for reading.
+    fn open(&self, path: &Path) -> io::Result<archive::Source>;
+}
+
+/// A directory on a local or mounted filesystem.
+pub struct LocalSource {
+    directory: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(directory: &Path) -> Self {
+        LocalSource {
+            directory: directory.to_path_buf(),
+        }
+    }
+}
+
+impl Source for LocalSource {
+    fn list(&self, options: &ScanOptions) -> Result<(Vec<PathBuf>, ScanStats), AppError> {
+        scan::collect_files(&self.directory, options)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<archive::Source> {
+        archive::open(path)
+    }
+}
+
+/// Settings of the downloads from remote locations, shared by all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteOptions {
+    /// Most listings and downloads running at the same time.
+    pub concurrency: usize,
+    /// Times a failed listing or download is tried again.
+    pub retries: u32,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        RemoteOptions {
+            concurrency: 8,
+            retries: 3,
+        }
+    }
+}
+
+struct Remote {
+    limiter: DecodeLimiter,
+    retries: u32,
+    /// Sizes of the remote files listed so far.
+    sizes: Mutex<HashMap<PathBuf, u64>>,
+}
+
+static REMOTE: OnceLock<Remote> = OnceLock::new();
+
+fn remote() -> &'static Remote {
+    REMOTE.get_or_init(|| Remote::new(RemoteOptions::default()))
+}
+
+impl Remote {
+    fn new(options: RemoteOptions) -> Remote {
+        Remote {
+            limiter: DecodeLimiter::new(options.concurrency),
+            retries: options.retries,
+            sizes: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// Use `options` for every download from remote locations. Only the first
+/// call, made before any download, counts.
+pub fn configure_remote(options: RemoteOptions) {
+    let _ = REMOTE.set(Remote::new(options));
+}
+
+/// An object store bucket or a folder on a file server, listed and read with
+/// rclone.
+///
+/// Credentials for object stores are taken from the environment, as the
+/// cloud SDKs take them, and SFTP logins from the SSH agent. Anything else,
+/// such as the region of a bucket or a WebDAV password, is set with rclone's
+/// `RCLONE_S3_REGION`, `RCLONE_WEBDAV_PASS` and similar variables.
+pub struct RemoteSource {
+    location: PathBuf,
+}
+
+/// A file listed by `rclone lsjson`.
+#[derive(Deserialize)]
+struct Listed {
+    #[serde(rename = "Path")]
+    path: String,
+    /// Size in bytes, or -1 if unknown.
+    #[serde(rename = "Size")]
+    size: i64,
+}
+
+fn parse_listing(output: &[u8]) -> io::Result<Vec<Listed>> {
+    serde_json::from_slice(output)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+impl Source for RemoteSource {
+    fn list(&self, options: &ScanOptions) -> Result<(Vec<PathBuf>, ScanStats), AppError> {
+        let mut args = vec!["lsjson", "--files-only", "--no-mimetype", "--no-modtime"];
+        let depth;
+        if options.recursive {
+            args.push("--recursive");
+            if let Some(max) = options.max_depth {
+                depth = (max + 1).to_string();
+                args.extend(["--max-depth", &depth]);
+            }
+        }
+        let location = rclone_location(&self.location).expect("remote sources are supported");
+        args.push(&location);
+        let listed = parse_listing(&rclone(&args)?)?;
+        let mut sizes = remote().sizes.lock().unwrap();
+        let mut paths = Vec::with_capacity(listed.len());
+        for file in listed {
+            let path = PathBuf::from(&file.path);
+            if let Ok(size) = u64::try_from(file.size) {
+                sizes.insert(self.location.join(&path), size);
+            }
+            paths.push(path);
+        }
+        Ok(scan::collect_listed(&self.location, paths, options))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<archive::Source> {
+        archive::open(path)
+    }
+}
+
+/// Run rclone with `args`, under the limit on concurrent downloads, trying
+/// again after a failure as many times as configured. Returns its output.
+fn rclone(args: &[&str]) -> io::Result<Vec<u8>> {
+    let remote = remote();
+    let _permit = remote.limiter.acquire();
+    let mut attempt = 0;
+    loop {
+        // rclone retries requests itself, which would multiply the retries.
+        let output = Command::new(RCLONE)
+            .args(["--low-level-retries", "1"])
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| io::Error::new(err.kind(), format!("cannot run {}: {}", RCLONE, err)))?;
+        if output.status.success() {
+            return Ok(output.stdout);
+        }
+        if attempt >= remote.retries {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!(
+                "{} {} failed ({}): {}",
+                RCLONE,
+                args[0],
+                output.status,
+                stderr.lines().last().unwrap_or_default().trim()
+            )));
+        }
+        attempt += 1;
+        thread::sleep(Duration::from_millis(250 << attempt.min(6)));
+    }
+}
+
+/// The rclone location of the remote URL `path`, configured on the fly, or
+/// `None` if `path` names no remote location.
+///
+/// Object stores are named `s3://`, `gs://`, `az://` or `abfs://`, file
+/// servers `sftp://[user@]host[:port]/path` or `webdav://`, `webdavs://` and
+/// `dav://` followed by the same.
+fn rclone_location(path: &Path) -> Option<String> {
+    let (scheme, rest) = path.to_str()?.split_once("://")?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let (user, host) = match authority.rsplit_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, authority),
+    };
+    let login = user.map_or(String::new(), |user| format!(",user={}", quoted(user)));
+    let location = match scheme {
+        "s3" => format!(":s3,env_auth=true:{}", rest),
+        "gs" => format!(":gcs,env_auth=true:{}", rest),
+        "az" => format!(":azureblob,env_auth=true:{}", rest),
+        // abfs://container@account.dfs.core.windows.net/path
+        "abfs" => {
+            let account = host.split('.').next()?;
+            format!(
+                ":azureblob,env_auth=true,account={}:{}/{}",
+                account, user?, path
+            )
+        }
+        "sftp" => {
+            let port = match host.rsplit_once(':') {
+                Some((host, port)) if !host.ends_with(':') && port.parse::<u16>().is_ok() => {
+                    Some((host, port))
+                }
+                _ => None,
+            };
+            let (host, port) = match port {
+                Some((host, port)) => (host, format!(",port={}", port)),
+                None => (host, String::new()),
+            };
+            format!(":sftp,host={}{}{}:/{}", quoted(host), login, port, path)
+        }
+        "webdav" | "webdavs" | "dav" => {
+            let protocol = if scheme == "webdavs" { "https" } else { "http" };
+            let url = format!("{}://{}", protocol, host);
+            format!(":webdav,url={}{}:{}", quoted(&url), login, path)
+        }
+        _ => return None,
+    };
+    Some(location)
+}
+
+/// `value` quoted for an rclone connection string, which it could not
+/// appear in as is if it held a colon or a comma.
+fn quoted(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Whether `path` names a file or folder of a remote location that can be
+/// scanned.
+pub fn is_remote(path: &Path) -> bool {
+    rclone_location(path).is_some()
+}
+
+thread_local! {
+    /// The remote file this thread downloaded last, which is read several
+    /// times in a row while an image is hashed.
+    static LAST_DOWNLOAD: RefCell<Option<(PathBuf, Vec<u8>)>> = const { RefCell::new(None) };
+}
+
+/// The first `count` bytes of the remote file at `path`, or all of them.
+pub fn read_remote(path: &Path, count: Option<u64>) -> io::Result<Vec<u8>> {
+    let location = rclone_location(path).ok_or(io::ErrorKind::NotFound)?;
+    let last = LAST_DOWNLOAD.with_borrow(|last| match last {
+        Some((last, data)) if last == path => {
+            let len = count.map_or(data.len(), |count| data.len().min(count as usize));
+            Some(data[..len].to_vec())
+        }
+        _ => None,
+    });
+    if let Some(data) = last {
+        return Ok(data);
+    }
+    let Some(count) = count else {
+        let data = rclone(&["cat", &location])?;
+        LAST_DOWNLOAD.set(Some((path.to_path_buf(), data.clone())));
+        return Ok(data);
+    };
+    rclone(&["cat", "--count", &count.to_string(), &location])
+}
+
+/// Size in bytes of the remote file at `path`, as listed, or looked up.
+pub fn remote_size(path: &Path) -> io::Result<u64> {
+    if let Some(&size) = remote().sizes.lock().unwrap().get(path) {
+        return Ok(size);
+    }
+    let location = rclone_location(path).ok_or(io::ErrorKind::NotFound)?;
+    let args = [
+        "lsjson",
+        "--files-only",
+        "--no-mimetype",
+        "--no-modtime",
+        &location,
+    ];
+    parse_listing(&rclone(&args)?)?
+        .first()
+        .and_then(|file| u64::try_from(file.size).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such remote file"))
+}
+
+/// The local directory to write the reports and working files of a scan of
+/// `location` to: the current directory for a remote location.
+pub fn local_dir(location: &Path) -> &Path {
+    if is_remote(location) {
+        Path::new(".")
+    } else {
+        location
+    }
+}
+
+/// The source of the files at `location`, a remote URL or a local path.
+pub fn for_location(location: &Path) -> Result<Box<dyn Source>, AppError> {
+    if is_remote(location) {
+        return Ok(Box::new(RemoteSource {
+            location: location.to_path_buf(),
+        }));
+    }
+    Ok(Box::new(LocalSource::new(location)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_local_directories_are_listed() {
+        let dir = tempdir().unwrap();
+        let image = dir.path().join("a.png");
+        fs::write(&image, b"png").unwrap();
+
+        let source = for_location(dir.path()).unwrap();
+        let (files, _) = source.list(&ScanOptions::default()).unwrap();
+        assert_eq!(files, std::slice::from_ref(&image));
+        let mut contents = Vec::new();
+        source
+            .open(&image)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"png");
+
+        assert!(!is_remote(Path::new("photos/s3")));
+    }
+
+    #[test]
+    fn test_remote_urls_map_to_rclone_locations() {
+        let location = |url: &str| rclone_location(Path::new(url));
+        assert_eq!(
+            location("s3://bucket/photos/a.jpg").as_deref(),
+            Some(":s3,env_auth=true:bucket/photos/a.jpg")
+        );
+        assert_eq!(
+            location("gs://bucket").as_deref(),
+            Some(":gcs,env_auth=true:bucket")
+        );
+        assert_eq!(
+            location("abfs://photos@shop.dfs.core.windows.net/2024").as_deref(),
+            Some(":azureblob,env_auth=true,account=shop:photos/2024")
+        );
+        assert_eq!(
+            location("sftp://nas/photos").as_deref(),
+            Some(":sftp,host='nas':/photos")
+        );
+        assert_eq!(
+            location("sftp://me@nas.local:2222/volume1/photos").as_deref(),
+            Some(":sftp,host='nas.local',user='me',port=2222:/volume1/photos")
+        );
+        assert_eq!(
+            location("webdavs://cloud.example.com/remote.php/dav/files/me").as_deref(),
+            Some(":webdav,url='https://cloud.example.com':remote.php/dav/files/me")
+        );
+        assert_eq!(
+            location("dav://me@nas:5005/photos").as_deref(),
+            Some(":webdav,url='http://nas:5005',user='me':photos")
+        );
+        assert_eq!(location("ftp://nas/photos"), None);
+        assert_eq!(location("photos/a.jpg"), None);
+        assert_eq!(local_dir(Path::new("s3://bucket/photos")), Path::new("."));
+    }
+
+    #[test]
+    fn test_listings_keep_paths_and_sizes() {
+        let output = br#"[{"Path":"a/b.jpg","Name":"b.jpg","Size":12,"IsDir":false},
+            {"Path":"c.png","Name":"c.png","Size":-1,"IsDir":false}]"#;
+        let listed = parse_listing(output).unwrap();
+        let listed: Vec<(&str, i64)> = listed
+            .iter()
+            .map(|file| (file.path.as_str(), file.size))
+            .collect();
+        assert_eq!(listed, [("a/b.jpg", 12), ("c.png", -1)]);
+    }
+}
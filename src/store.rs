@@ -0,0 +1,624 @@
+use crate::content::ContentKind;
+use crate::decode::Colorspace;
+use crate::errors::AppError;
+use crate::models::{Dimensions, ImageInfo};
+use image_hasher::ImageHash;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"IDARHASH";
+const VERSION: u32 = 4;
+const HEADER_LEN: usize = 24;
+/// Width and height, root, hash length, colour model, content and the
+/// offset of the entry of an image, stored after its hash.
+const RECORD_TAIL_LEN: usize = 28;
+
+/// Sections of the entry of every image, in the order they are written.
+const PATH: usize = 0;
+const LINKS: usize = 1;
+const SIDECARS: usize = 2;
+const VARIANTS: usize = 3;
+const SEGMENTS: usize = 4;
+const WINDOWS: usize = 5;
+const FRAMES: usize = 6;
+const MASKED: usize = 7;
+const COLUMNS: usize = 8;
+
+/// Values stored as their position plus one, zero standing for none.
+const COLORSPACES: [Colorspace; 2] = [Colorspace::Cmyk, Colorspace::Ycck];
+const CONTENTS: [ContentKind; 3] = [
+    ContentKind::Photo,
+    ContentKind::Screenshot,
+    ContentKind::Document,
+];
+
+/// Hashes of one kind computed for an image, such as its variants or
+/// keyframes, borrowed from the image or from a [`HashStore`].
+#[derive(Debug, Clone, Copy)]
+pub enum Hashes<'a> {
+    Owned(&'a [ImageHash]),
+    /// Hashes of at most `hash_len` bytes, back to back, each after its
+    /// length as 4 bytes and padded to `hash_len`.
+    Packed {
+        bytes: &'a [u8],
+        hash_len: usize,
+    },
+}
+
+impl<'a> Hashes<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            Hashes::Owned(hashes) => hashes.len(),
+            Hashes::Packed { bytes, hash_len } => bytes.len() / (4 + hash_len),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of the hash at `index`.
+    pub fn bytes(&self, index: usize) -> &'a [u8] {
+        match *self {
+            Hashes::Owned(hashes) => hashes[index].as_bytes(),
+            Hashes::Packed { bytes, hash_len } => {
+                let start = index * (4 + hash_len);
+                &bytes[start + 4..start + 4 + read_u32(bytes, start)]
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        (0..self.len()).map(|index| self.bytes(index))
+    }
+
+    pub fn to_vec(&self) -> Vec<ImageHash> {
+        self.iter()
+            .map(|bytes| {
+                ImageHash::from_bytes(bytes).expect("hash bytes come from an existing hash")
+            })
+            .collect()
+    }
+}
+
+impl Default for Hashes<'_> {
+    fn default() -> Self {
+        Hashes::Owned(&[])
+    }
+}
+
+/// Random access to the paths and hashes of a set of images, whether they
+/// are held in memory or in a [`HashStore`].
+pub trait ImageSource: Sync {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn path(&self, index: usize) -> &Path;
+
+    fn hash_bytes(&self, index: usize) -> &[u8];
+
+    fn root(&self, index: usize) -> Option<&Path>;
+
+    fn links(&self, index: usize) -> Vec<PathBuf>;
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf>;
+
+    fn variants(&self, index: usize) -> Hashes<'_>;
+
+    fn segments(&self, index: usize) -> Hashes<'_>;
+
+    fn windows(&self, index: usize) -> Hashes<'_>;
+
+    fn frames(&self, index: usize) -> Hashes<'_>;
+
+    fn masked(&self, index: usize) -> Hashes<'_>;
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions>;
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace>;
+
+    fn content(&self, index: usize) -> Option<ContentKind>;
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String>;
+
+    fn image(&self, index: usize) -> ImageInfo {
+        ImageInfo {
+            root: self.root(index).map(Path::to_path_buf),
+            links: self.links(index),
+            dimensions: self.dimensions(index),
+            columns: self.columns(index),
+            sidecars: self.sidecars(index),
+            variants: self.variants(index).to_vec(),
+            segments: self.segments(index).to_vec(),
+            windows: self.windows(index).to_vec(),
+            frames: self.frames(index).to_vec(),
+            masked: self.masked(index).to_vec(),
+            colorspace: self.colorspace(index),
+            content: self.content(index),
+            ..ImageInfo::new(
+                self.path(index).to_path_buf(),
+                ImageHash::from_bytes(self.hash_bytes(index))
+                    .expect("hash bytes come from an existing hash"),
+            )
+        }
+    }
+}
+
+impl ImageSource for [ImageInfo] {
+    fn len(&self) -> usize {
+        <[ImageInfo]>::len(self)
+    }
+
+    fn path(&self, index: usize) -> &Path {
+        &self[index].path
+    }
+
+    fn hash_bytes(&self, index: usize) -> &[u8] {
+        self[index].hash.as_bytes()
+    }
+
+    fn root(&self, index: usize) -> Option<&Path> {
+        self[index].root.as_deref()
+    }
+
+    fn links(&self, index: usize) -> Vec<PathBuf> {
+        self[index].links.clone()
+    }
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf> {
+        self[index].sidecars.clone()
+    }
+
+    fn variants(&self, index: usize) -> Hashes<'_> {
+        Hashes::Owned(&self[index].variants)
+    }
+
+    fn segments(&self, index: usize) -> Hashes<'_> {
+        Hashes::Owned(&self[index].segments)
+    }
+
+    fn windows(&self, index: usize) -> Hashes<'_> {
+        Hashes::Owned(&self[index].windows)
+    }
+
+    fn frames(&self, index: usize) -> Hashes<'_> {
+        Hashes::Owned(&self[index].frames)
+    }
+
+    fn masked(&self, index: usize) -> Hashes<'_> {
+        Hashes::Owned(&self[index].masked)
+    }
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions> {
+        self[index].dimensions
+    }
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace> {
+        self[index].colorspace
+    }
+
+    fn content(&self, index: usize) -> Option<ContentKind> {
+        self[index].content
+    }
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String> {
+        self[index].columns.clone()
+    }
+}
+
+/// Images kept in a memory-mapped file, so that neither their hashes nor
+/// their paths take heap memory and the operating system pages them in and
+/// out as needed.
+///
+/// The file holds a 24-byte header (magic, version, hash length and count),
+/// then a fixed-width record for every image: its hash padded to the
+/// longest length, dimensions (zero when unknown), the index of its root,
+/// the length of its hash, its colour model and content (zero when none)
+/// and the offset of its entry. Entries follow the records, each holding the path, links,
+/// sidecars, the five kinds of extra hashes and the manifest columns of an
+/// image as sections prefixed by their length in bytes. Only the few
+/// distinct roots stay in memory.
+pub struct HashStore {
+    path: PathBuf,
+    /// Distinct roots of the stored images.
+    roots: Vec<Option<PathBuf>>,
+    len: usize,
+    hash_len: usize,
+    data: Mapping,
+}
+
+impl HashStore {
+    /// Write `images` to a new store at `path`, consuming them so their heap
+    /// allocations are released.
+    ///
+    /// Shorter hashes are padded with zero bytes to the longest length, as
+    /// are those of a kind of extra hashes of an image, but keep their own
+    /// length, so that only their real bytes are compared and returned.
+    pub fn create(path: &Path, images: Vec<ImageInfo>) -> Result<HashStore, AppError> {
+        let hash_len = images
+            .iter()
+            .map(|image| image.hash.as_bytes().len())
+            .max()
+            .unwrap_or(0);
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(hash_len as u32).to_le_bytes())?;
+        writer.write_all(&(images.len() as u64).to_le_bytes())?;
+
+        // Records go first, so the length of every entry is measured before
+        // the entries are written.
+        let mut roots: Vec<Option<PathBuf>> = Vec::new();
+        let mut entry = Vec::new();
+        let mut offset = HEADER_LEN + images.len() * (hash_len + RECORD_TAIL_LEN);
+        let padding = vec![0u8; hash_len];
+        for image in &images {
+            let bytes = image.hash.as_bytes();
+            writer.write_all(bytes)?;
+            writer.write_all(&padding[bytes.len()..])?;
+            let dimensions = image.dimensions.map_or((0, 0), |d| (d.width, d.height));
+            writer.write_all(&dimensions.0.to_le_bytes())?;
+            writer.write_all(&dimensions.1.to_le_bytes())?;
+            let root = match roots.iter().position(|root| *root == image.root) {
+                Some(position) => position,
+                None => {
+                    roots.push(image.root.clone());
+                    roots.len() - 1
+                }
+            };
+            writer.write_all(&(root as u32).to_le_bytes())?;
+            writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            writer.write_all(&[
+                code(&COLORSPACES, image.colorspace),
+                code(&CONTENTS, image.content),
+                0,
+                0,
+            ])?;
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+            entry.clear();
+            encode_entry(image, &mut entry)?;
+            offset += entry.len();
+        }
+        for image in images {
+            entry.clear();
+            encode_entry(&image, &mut entry)?;
+            writer.write_all(&entry)?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+
+        let data = Mapping::open(path)?;
+        let len = u64::from_le_bytes(data.bytes()[16..HEADER_LEN].try_into().unwrap()) as usize;
+        Ok(HashStore {
+            path: path.to_path_buf(),
+            roots,
+            len,
+            hash_len,
+            data,
+        })
+    }
+
+    /// Delete the file backing the store.
+    pub fn remove(self) -> Result<(), AppError> {
+        let HashStore { path, data, .. } = self;
+        drop(data);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// The fixed-width record of the image at `index`.
+    fn record(&self, index: usize) -> &[u8] {
+        let record_len = self.hash_len + RECORD_TAIL_LEN;
+        let start = HEADER_LEN + index * record_len;
+        &self.data.bytes()[start..start + record_len]
+    }
+
+    /// The bytes of section `section` of the entry of the image at `index`.
+    fn section(&self, index: usize, section: usize) -> &[u8] {
+        let tail = &self.record(index)[self.hash_len..];
+        let mut start = u64::from_le_bytes(tail[20..28].try_into().unwrap()) as usize;
+        let bytes = self.data.bytes();
+        let mut len = read_u32(bytes, start);
+        for _ in 0..section {
+            start += 4 + len;
+            len = read_u32(bytes, start);
+        }
+        &bytes[start + 4..start + 4 + len]
+    }
+
+    fn paths(&self, index: usize, section: usize) -> Vec<PathBuf> {
+        let bytes = self.section(index, section);
+        let mut paths = Vec::new();
+        let mut start = 0;
+        while start < bytes.len() {
+            let len = read_u32(bytes, start);
+            paths.push(to_path(&bytes[start + 4..start + 4 + len]).to_path_buf());
+            start += 4 + len;
+        }
+        paths
+    }
+
+    fn hashes(&self, index: usize, section: usize) -> Hashes<'_> {
+        let bytes = self.section(index, section);
+        match bytes.len() {
+            0 => Hashes::default(),
+            _ => Hashes::Packed {
+                bytes: &bytes[4..],
+                hash_len: read_u32(bytes, 0),
+            },
+        }
+    }
+}
+
+impl ImageSource for HashStore {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn path(&self, index: usize) -> &Path {
+        to_path(self.section(index, PATH))
+    }
+
+    fn hash_bytes(&self, index: usize) -> &[u8] {
+        let record = self.record(index);
+        &record[..read_u32(record, self.hash_len + 12)]
+    }
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions> {
+        let tail = &self.record(index)[self.hash_len..];
+        let width = u32::from_le_bytes(tail[..4].try_into().unwrap());
+        let height = u32::from_le_bytes(tail[4..8].try_into().unwrap());
+        (width > 0 && height > 0).then_some(Dimensions { width, height })
+    }
+
+    fn root(&self, index: usize) -> Option<&Path> {
+        let tail = &self.record(index)[self.hash_len..];
+        let root = u32::from_le_bytes(tail[8..12].try_into().unwrap());
+        self.roots[root as usize].as_deref()
+    }
+
+    fn links(&self, index: usize) -> Vec<PathBuf> {
+        self.paths(index, LINKS)
+    }
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf> {
+        self.paths(index, SIDECARS)
+    }
+
+    fn variants(&self, index: usize) -> Hashes<'_> {
+        self.hashes(index, VARIANTS)
+    }
+
+    fn segments(&self, index: usize) -> Hashes<'_> {
+        self.hashes(index, SEGMENTS)
+    }
+
+    fn windows(&self, index: usize) -> Hashes<'_> {
+        self.hashes(index, WINDOWS)
+    }
+
+    fn frames(&self, index: usize) -> Hashes<'_> {
+        self.hashes(index, FRAMES)
+    }
+
+    fn masked(&self, index: usize) -> Hashes<'_> {
+        self.hashes(index, MASKED)
+    }
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace> {
+        decode_code(&COLORSPACES, self.record(index)[self.hash_len + 16])
+    }
+
+    fn content(&self, index: usize) -> Option<ContentKind> {
+        decode_code(&CONTENTS, self.record(index)[self.hash_len + 17])
+    }
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String> {
+        let bytes = self.section(index, COLUMNS);
+        match bytes.is_empty() {
+            true => BTreeMap::new(),
+            false => serde_json::from_slice(bytes).expect("columns were written as JSON"),
+        }
+    }
+}
+
+/// Write the entry of `image` to `out`: its path, links, sidecars, extra
+/// hashes and columns, each prefixed by its length.
+fn encode_entry(image: &ImageInfo, out: &mut Vec<u8>) -> Result<(), AppError> {
+    let section = |out: &mut Vec<u8>, bytes: &[u8]| {
+        out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        out.extend_from_slice(bytes);
+    };
+    let paths = |paths: &[PathBuf]| {
+        let mut bytes = Vec::new();
+        for path in paths {
+            section(&mut bytes, path.as_os_str().as_encoded_bytes());
+        }
+        bytes
+    };
+    let hashes = |hashes: &[ImageHash]| {
+        let hash_len = hashes.iter().map(|hash| hash.as_bytes().len()).max();
+        let Some(hash_len) = hash_len else {
+            return Vec::new();
+        };
+        let mut bytes = (hash_len as u32).to_le_bytes().to_vec();
+        for hash in hashes {
+            bytes.extend_from_slice(&(hash.as_bytes().len() as u32).to_le_bytes());
+            bytes.extend_from_slice(hash.as_bytes());
+            bytes.resize(bytes.len() + hash_len - hash.as_bytes().len(), 0);
+        }
+        bytes
+    };
+    section(out, image.path.as_os_str().as_encoded_bytes());
+    section(out, &paths(&image.links));
+    section(out, &paths(&image.sidecars));
+    section(out, &hashes(&image.variants));
+    section(out, &hashes(&image.segments));
+    section(out, &hashes(&image.windows));
+    section(out, &hashes(&image.frames));
+    section(out, &hashes(&image.masked));
+    match image.columns.is_empty() {
+        true => section(out, &[]),
+        false => section(out, &serde_json::to_vec(&image.columns)?),
+    }
+    Ok(())
+}
+
+/// The path written as `bytes` by [`encode_entry`].
+fn to_path(bytes: &[u8]) -> &Path {
+    // SAFETY: the bytes were produced by `OsStr::as_encoded_bytes` in this
+    // process, and are split only at the boundaries they were written with.
+    Path::new(unsafe { OsStr::from_encoded_bytes_unchecked(bytes) })
+}
+
+fn read_u32(bytes: &[u8], start: usize) -> usize {
+    u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()) as usize
+}
+
+fn code<T: PartialEq>(values: &[T], value: Option<T>) -> u8 {
+    value.map_or(0, |value| {
+        values.iter().position(|known| *known == value).unwrap() as u8 + 1
+    })
+}
+
+fn decode_code<T: Copy>(values: &[T], code: u8) -> Option<T> {
+    code.checked_sub(1)
+        .map(|position| values[position as usize])
+}
+
+/// Read-only contents of a file, memory-mapped where supported.
+enum Mapping {
+    #[cfg(unix)]
+    Mapped { ptr: *mut libc::c_void, len: usize },
+    #[cfg_attr(unix, allow(dead_code))]
+    Owned(Vec<u8>),
+}
+
+// SAFETY: the mapping is read-only and never aliased mutably.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    #[cfg(unix)]
+    fn open(path: &Path) -> io::Result<Mapping> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        // SAFETY: the file is open for reading, and `len` is its size. The
+        // store owns the file, so it is not truncated while mapped.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping::Mapped { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> io::Result<Mapping> {
+        use std::io::Read;
+
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Ok(Mapping::Owned(bytes))
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            // SAFETY: `ptr` maps `len` readable bytes until dropped.
+            #[cfg(unix)]
+            Mapping::Mapped { ptr, len } => unsafe {
+                std::slice::from_raw_parts(*ptr as *const u8, *len)
+            },
+            Mapping::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Mapping::Mapped { ptr, len } = *self {
+            // SAFETY: `ptr` and `len` come from a successful `mmap`.
+            unsafe {
+                libc::munmap(ptr, len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_store_returns_written_hashes() {
+        let dir = tempdir().unwrap();
+        let mut images: Vec<ImageInfo> = ["DAIDBwMHAf8", "8/JwVtbOVy4", "DwcHBwcHBwc"]
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| ImageInfo {
+                root: (i > 0).then(|| PathBuf::from("photos")),
+                links: (i == 2)
+                    .then(|| PathBuf::from("link.png"))
+                    .into_iter()
+                    .collect(),
+                sidecars: vec![PathBuf::from(format!("{}.png.xmp", i)); i],
+                variants: vec![ImageHash::from_base64(hash).unwrap(); i],
+                frames: (i == 1)
+                    .then(|| ImageHash::from_base64("DwcHBwcHBwc").unwrap())
+                    .into_iter()
+                    .collect(),
+                columns: (i == 2)
+                    .then(|| ("label".to_string(), "cat".to_string()))
+                    .into_iter()
+                    .collect(),
+                colorspace: (i == 1).then_some(Colorspace::Ycck),
+                content: (i == 2).then_some(ContentKind::Document),
+                dimensions: (i != 1).then_some(Dimensions {
+                    width: 64,
+                    height: 48,
+                }),
+                ..ImageInfo::new(
+                    PathBuf::from(format!("{}.png", i)),
+                    ImageHash::from_base64(hash).unwrap(),
+                )
+            })
+            .collect();
+        // Hashes shorter than the others keep their length.
+        let short = ImageHash::from_bytes(&[0xF0, 0x0F]).unwrap();
+        images[1].hash = short.clone();
+        images[2].variants.push(short);
+
+        let store_path = dir.path().join("hashes.bin");
+        let store = HashStore::create(&store_path, images.clone()).unwrap();
+        assert_eq!(ImageSource::len(&store), 3);
+        assert_eq!(store.hash_bytes(1), [0xF0, 0x0F]);
+        assert_eq!(store.variants(2).bytes(2), [0xF0, 0x0F]);
+        for (index, image) in images.iter().enumerate() {
+            assert_eq!(store.image(index), *image);
+        }
+
+        store.remove().unwrap();
+        assert!(!store_path.exists());
+    }
+}
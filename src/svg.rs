@@ -0,0 +1,57 @@
+use crate::archive;
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Program from librsvg rasterizing the drawings, looked up on the `PATH`.
+const RSVG_CONVERT: &str = "rsvg-convert";
+
+/// Length in pixels of the longer side of rasterized drawings.
+pub const SIZE: u32 = 512;
+
+/// Whether `path` names an SVG drawing, by its extension.
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg") || ext.eq_ignore_ascii_case("svgz"))
+}
+
+/// Rasterize the SVG drawing at `path`, gzipped or not, with `rsvg-convert`
+/// so that its longer side is [`SIZE`] pixels.
+///
+/// Drawings are put on white, as browsers show them, since the hashes
+/// ignore transparency and would see dark shapes on black. The drawing is
+/// piped to the program, so that those inside archives are rasterized too.
+pub fn rasterize(path: &Path) -> ImageResult<DynamicImage> {
+    let mut data = Vec::new();
+    archive::open(path)?.read_to_end(&mut data)?;
+    let size = SIZE.to_string();
+    let mut child = Command::new(RSVG_CONVERT)
+        .args(["--width", &size, "--height", &size, "--keep-aspect-ratio"])
+        .args(["--background-color", "white", "--format", "png"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| {
+            io::Error::new(err.kind(), format!("cannot run {}: {}", RSVG_CONVERT, err))
+        })?;
+    let mut input = child.stdin.take().expect("the input is piped");
+    // Written from another thread, so that a large drawing cannot block on
+    // an output nobody reads yet.
+    let writer = thread::spawn(move || input.write_all(&data));
+    let output = child.wait_with_output()?;
+    // The program stops reading a drawing it rejects, which its status says.
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(ImageError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} could not rasterize the drawing ({})",
+                RSVG_CONVERT, output.status
+            ),
+        )));
+    }
+    image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)
+}
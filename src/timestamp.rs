@@ -0,0 +1,215 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Point in time selected by `--since`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    Time(SystemTime),
+    /// The start of the previous run over the same directory, as recorded
+    /// in the hash cache.
+    LastRun,
+}
+
+/// Parse a `--since` value.
+///
+/// Accepts `last`, a duration before now such as `90m`, `12h`, `7d` or
+/// `2w`, a UTC date or date-time such as `2024-05-01` or
+/// `2024-05-01T18:30:00Z`, or a Unix timestamp in seconds.
+pub fn parse_since(value: &str) -> Result<Since, String> {
+    if value.trim().eq_ignore_ascii_case("last") {
+        return Ok(Since::LastRun);
+    }
+    parse_time(value).map(Since::Time)
+}
+
+/// Parse a point in time given like a `--since` value, other than `last`.
+pub fn parse_time(value: &str) -> Result<SystemTime, String> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    if let Some(duration) = parse_duration(value) {
+        return SystemTime::now()
+            .checked_sub(duration)
+            .ok_or_else(|| format!("duration `{}` is too long", value));
+    }
+    parse_datetime(value)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .ok_or_else(|| format!("invalid time or duration `{}`", value))
+}
+
+/// Time of an EXIF date such as `2024:05:01 18:30:00`. EXIF dates carry no
+/// time zone, so they are read as UTC.
+pub fn parse_exif_datetime(value: &str) -> Option<SystemTime> {
+    let value = value.trim_end_matches(['\0', ' ']);
+    let (date, time) = value.split_once(' ')?;
+    let date = date.replace(':', "-");
+    parse_datetime(&format!("{}T{}", date, time)).map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let count: u64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(count.checked_mul(seconds)?))
+}
+
+/// Seconds since the Unix epoch of `YYYY-MM-DD[THH:MM[:SS]][Z]`, in UTC.
+fn parse_datetime(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let parts: Vec<u32> = time
+            .split(':')
+            .map(|part| part.parse().ok())
+            .collect::<Option<_>>()?;
+        let (hour, minute, second) = match parts[..] {
+            [hour, minute] => (hour, minute, 0),
+            [hour, minute, second] => (hour, minute, second),
+            _ => return None,
+        };
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        seconds = (hour * 3600 + minute * 60 + second) as u64;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + seconds)
+}
+
+/// `time` as `YYYY-MM-DDTHH:MM:SS`, in UTC.
+pub fn format_datetime(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let secs = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// `time` as `YYYY-MM-DDTHH:MM:SS`, in the local time zone. Only Unix
+/// systems are asked for the time zone; elsewhere this is UTC.
+pub fn format_local_datetime(time: SystemTime) -> String {
+    let offset = Duration::from_secs(utc_offset(time).unsigned_abs());
+    let local = match utc_offset(time) >= 0 {
+        true => time.checked_add(offset),
+        false => time.checked_sub(offset),
+    };
+    format_datetime(local.unwrap_or(time))
+}
+
+/// Seconds the local time zone is ahead of UTC at `time`.
+#[cfg(unix)]
+fn utc_offset(time: SystemTime) -> i64 {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: `tm` is plain data, and both pointers are valid for the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return 0;
+    }
+    let days = days_from_civil(
+        (tm.tm_year + 1900) as u32,
+        (tm.tm_mon + 1) as u32,
+        tm.tm_mday as u32,
+    );
+    let local = days as i64 * 86_400
+        + i64::from(tm.tm_hour) * 3600
+        + i64::from(tm.tm_min) * 60
+        + i64::from(tm.tm_sec);
+    local - secs as i64
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_time: SystemTime) -> i64 {
+    0
+}
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: u32, month: u32, day: u32) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year } as u64;
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month = month as u64;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Date of the proleptic Gregorian calendar this many days after
+/// 1970-01-01; the inverse of [`days_from_civil`].
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since() {
+        let at = |secs| Ok(Since::Time(UNIX_EPOCH + Duration::from_secs(secs)));
+        assert_eq!(parse_since("last"), Ok(Since::LastRun));
+        assert_eq!(parse_since("1700000000"), at(1_700_000_000));
+        assert_eq!(parse_since("1970-01-01"), at(0));
+        assert_eq!(parse_since("2024-03-01"), at(1_709_251_200));
+        assert_eq!(parse_since("2024-03-01T12:30:15Z"), at(1_709_296_215));
+
+        let Ok(Since::Time(time)) = parse_since("2h") else {
+            panic!("expected a time");
+        };
+        let age = SystemTime::now().duration_since(time).unwrap();
+        assert!(age >= Duration::from_secs(7200) && age < Duration::from_secs(7260));
+
+        assert!(parse_since("yesterday").is_err());
+        assert!(parse_since("2024-13-01").is_err());
+
+        let exif = parse_exif_datetime("2024:03:01 12:30:15\0");
+        assert_eq!(exif, Some(UNIX_EPOCH + Duration::from_secs(1_709_296_215)));
+        assert_eq!(parse_exif_datetime("0000:00:00 00:00:00"), None);
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_296_215);
+        assert_eq!(format_datetime(time), "2024-03-01T12:30:15");
+        // Time zones are whole quarters of an hour away from UTC.
+        let offset = utc_offset(time);
+        assert!(offset % 900 == 0 && offset.abs() <= 14 * 3600);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::actions::move_file;
+use crate::timestamp;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Trash directory of the current user, when the platform has one that
+/// files can be moved to.
+///
+/// On Linux and other Unix systems this is the home trash of the
+/// FreeDesktop specification, which file managers list and restore from;
+/// on macOS it is `~/.Trash`.
+pub fn default_trash_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        None
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".Trash"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+            .map(|data| data.join("Trash"))
+    }
+}
+
+/// Move `path` to the trash of the current user, returning where it went.
+///
+/// Files on another filesystem than the home trash go to the trash at the
+/// top of their own filesystem if one can be used, so that they are not
+/// copied across.
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    let trash_dir = default_trash_dir().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no trash on this platform, use --permanent",
+        )
+    })?;
+    if cfg!(target_os = "macos") {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        let destination = free_name(&trash_dir, Path::new(name), |candidate| {
+            fs::symlink_metadata(candidate).is_err()
+        });
+        move_file(path, &destination)?;
+        Ok(destination)
+    } else {
+        let deleted = SystemTime::now();
+        if let Some(volume_trash) = volume_trash_dir(path, &trash_dir) {
+            if let Ok(destination) = trash_into(&volume_trash, path, deleted) {
+                return Ok(destination);
+            }
+        }
+        trash_into(&trash_dir, path, deleted)
+    }
+}
+
+/// Trash directory at the top of the filesystem holding `path`, if that is
+/// not the one of `home_trash`.
+#[cfg(unix)]
+fn volume_trash_dir(path: &Path, home_trash: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = std::path::absolute(path).ok()?;
+    let device = fs::symlink_metadata(&path).ok()?.dev();
+    let home_device = home_trash
+        .ancestors()
+        .find_map(|dir| fs::metadata(dir).ok())?
+        .dev();
+    if device == home_device {
+        return None;
+    }
+    let mut top = path.parent()?;
+    while let Some(parent) = top.parent() {
+        if fs::metadata(parent).ok()?.dev() != device {
+            break;
+        }
+        top = parent;
+    }
+    top_trash_dir(top)
+}
+
+#[cfg(not(unix))]
+fn volume_trash_dir(_path: &Path, _home_trash: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Trash directory of the current user under `top`, the top directory of
+/// a filesystem, creating it if needed.
+///
+/// As the FreeDesktop specification has it, this is `$top/.Trash/$uid` if
+/// the administrator set up `$top/.Trash` as a sticky directory that is not
+/// a link, and `$top/.Trash-$uid` otherwise.
+#[cfg(unix)]
+fn top_trash_dir(top: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    // SAFETY: getuid has no preconditions and cannot fail.
+    let uid = unsafe { libc::getuid() };
+    let create = |dir: PathBuf| match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => Some(dir),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => Some(dir),
+        Err(_) => None,
+    };
+    let shared = top.join(".Trash");
+    let sticky = fs::symlink_metadata(&shared)
+        .is_ok_and(|meta| meta.is_dir() && meta.permissions().mode() & 0o1000 != 0);
+    sticky
+        .then(|| create(shared.join(uid.to_string())))
+        .flatten()
+        .or_else(|| create(top.join(format!(".Trash-{}", uid))))
+}
+
+/// Move the file trashed to `trashed` back to `original`, and remove the
+/// record of it the trash keeps.
+pub fn restore(trashed: &Path, original: &Path) -> io::Result<()> {
+    move_file(trashed, original)?;
+    if let (Some(files), Some(name)) = (trashed.parent(), trashed.file_name()) {
+        if !cfg!(target_os = "macos") && files.file_name() == Some("files".as_ref()) {
+            let mut name = name.to_owned();
+            name.push(".trashinfo");
+            let _ = fs::remove_file(files.with_file_name("info").join(name));
+        }
+    }
+    Ok(())
+}
+
+/// Move `path` to the FreeDesktop trash at `trash_dir`, recording where it
+/// came from so that it can be restored.
+///
+/// The `.trashinfo` file is created first and exclusively, which reserves
+/// the name of the trashed file. Deletion dates are written in local time,
+/// as the specification asks.
+fn trash_into(trash_dir: &Path, path: &Path, deleted: SystemTime) -> io::Result<PathBuf> {
+    let files = trash_dir.join("files");
+    let info = trash_dir.join("info");
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+    let original = std::path::absolute(path)?;
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    let info_path = |file: &Path| {
+        let mut name = file.file_name().unwrap_or_default().to_owned();
+        name.push(".trashinfo");
+        info.join(name)
+    };
+    loop {
+        let destination = free_name(&files, Path::new(name), |candidate| {
+            fs::symlink_metadata(candidate).is_err()
+                && fs::symlink_metadata(info_path(candidate)).is_err()
+        });
+        let info_path = info_path(&destination);
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        write!(
+            file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(&original),
+            timestamp::format_local_datetime(deleted)
+        )?;
+        return match move_file(path, &destination) {
+            Ok(()) => Ok(destination),
+            Err(err) => {
+                let _ = fs::remove_file(&info_path);
+                Err(err)
+            }
+        };
+    }
+}
+
+/// The first of `dir/name`, `dir/name 2`, `dir/name 3` and so on, the
+/// number going before the extension, that is `free`.
+pub(crate) fn free_name(dir: &Path, name: &Path, free: impl Fn(&Path) -> bool) -> PathBuf {
+    let first = dir.join(name);
+    if free(&first) {
+        return first;
+    }
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name.extension().map(|ext| ext.to_string_lossy());
+    (2..)
+        .map(|n| match &extension {
+            Some(ext) => dir.join(format!("{} {}.{}", stem, n, ext)),
+            None => dir.join(format!("{} {}", stem, n)),
+        })
+        .find(|candidate| free(candidate))
+        .expect("some name is free")
+}
+
+/// `path` with the bytes that are not allowed in a URL percent-encoded, as
+/// the `Path` key of a `.trashinfo` file and `file://` links want it.
+pub(crate) fn encode_path(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trashed_files_can_be_restored() {
+        let dir = tempdir().unwrap();
+        let trash_dir = dir.path().join("Trash");
+        let deleted = UNIX_EPOCH + Duration::from_secs(1_709_296_215);
+        let first = dir.path().join("my photo.jpg");
+        let second = dir.path().join("sub").join("my photo.jpg");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(&first, b"first").unwrap();
+        fs::write(&second, b"second").unwrap();
+
+        let trashed = trash_into(&trash_dir, &first, deleted).unwrap();
+        assert_eq!(trashed, trash_dir.join("files").join("my photo.jpg"));
+        let trashed = trash_into(&trash_dir, &second, deleted).unwrap();
+        assert_eq!(trashed, trash_dir.join("files").join("my photo 2.jpg"));
+        assert!(!first.exists() && !second.exists());
+        assert_eq!(fs::read(&trashed).unwrap(), b"second");
+
+        let info = fs::read_to_string(trash_dir.join("info").join("my photo 2.jpg.trashinfo"));
+        let expected = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(&second),
+            timestamp::format_local_datetime(deleted)
+        );
+        assert_eq!(info.unwrap(), expected);
+        assert!(expected.contains("/sub/my%20photo.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_top_trash_is_shared_only_when_sticky() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let uid = unsafe { libc::getuid() };
+        let own = top_trash_dir(dir.path()).unwrap();
+        assert_eq!(own, dir.path().join(format!(".Trash-{}", uid)));
+        assert_eq!(
+            fs::metadata(&own).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        assert_eq!(volume_trash_dir(&own, dir.path()), None);
+
+        let shared = dir.path().join(".Trash");
+        fs::create_dir(&shared).unwrap();
+        assert_eq!(top_trash_dir(dir.path()), Some(own));
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o1777)).unwrap();
+        assert_eq!(
+            top_trash_dir(dir.path()),
+            Some(shared.join(uid.to_string()))
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use crate::actions::{self, Action, ActionKind, Sidecar};
+use crate::errors::AppError;
+use crate::exact;
+use crate::timestamp;
+use crate::trash;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The actions of a run that changed files, from which `idar undo`
+/// restores them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ActionLog {
+    /// When the actions were taken, in UTC.
+    pub written: String,
+    pub actions: Vec<Action>,
+}
+
+/// Write the `actions` that were taken to a new log in `directory`, named
+/// after the time they were, and return its path.
+///
+/// Paths are made absolute so that the log can be undone from anywhere.
+/// Nothing is written for a dry run or when no file was changed.
+pub fn write_log(actions: &[Action], directory: &Path) -> Result<Option<PathBuf>, AppError> {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let taken: Vec<Action> = actions
+        .iter()
+        .filter(|action| action.taken())
+        .map(|action| Action {
+            path: absolute(&action.path),
+            kept: absolute(&action.kept),
+            destination: action.destination.as_deref().map(absolute),
+            sidecars: action
+                .sidecars
+                .iter()
+                .map(|sidecar| Sidecar {
+                    path: absolute(&sidecar.path),
+                    destination: sidecar.destination.as_deref().map(absolute),
+                })
+                .collect(),
+            orphaned: action.orphaned.iter().map(|path| absolute(path)).collect(),
+            ..action.clone()
+        })
+        .collect();
+    if taken.is_empty() {
+        return Ok(None);
+    }
+    let written = timestamp::format_datetime(SystemTime::now());
+    let stem = format!("idar_actions_{}", written.replace(['-', ':'], ""));
+    let log = ActionLog {
+        written,
+        actions: taken,
+    };
+    let mut attempt = 1;
+    loop {
+        let path = match attempt {
+            1 => directory.join(format!("{}.json", stem)),
+            n => directory.join(format!("{}_{}.json", stem, n)),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(serde_json::to_string_pretty(&log)?.as_bytes())?;
+                return Ok(Some(path));
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Like [`write_log`], saying where the log went.
+pub fn record(actions: &[Action], directory: &Path) -> Result<(), AppError> {
+    if let Some(path) = write_log(actions, directory)? {
+        println!(
+            "Action log saved to {:?}; `idar undo` it to restore the files.",
+            path
+        );
+    }
+    Ok(())
+}
+
+/// Undo the actions of the log at `log_path`, latest first.
+///
+/// Trashed and moved files are moved back, and files replaced by links or
+/// clones, or deleted for good, are copied back from the kept file when it
+/// had the same contents, along with the sidecar files that followed them
+/// but were not deleted for good. Every restored file is first checked
+/// against the checksum in the log. The actions that could not be undone are left in
+/// the log, which is removed once there are none.
+pub fn run(log_path: &Path) -> Result<(), AppError> {
+    let mut log: ActionLog = serde_json::from_str(&fs::read_to_string(log_path)?)?;
+    let mut left = Vec::new();
+    let mut restored = 0;
+    for action in log.actions.into_iter().rev() {
+        match restore(&action) {
+            Ok(()) => restored += 1,
+            Err(err) => {
+                eprintln!("Could not restore {:?}: {}", action.path, err);
+                left.push(action);
+            }
+        }
+    }
+    println!("Restored {} files.", restored);
+    if left.is_empty() {
+        fs::remove_file(log_path)?;
+    } else {
+        left.reverse();
+        println!(
+            "{} files could not be restored and are left in {:?}.",
+            left.len(),
+            log_path
+        );
+        log.actions = left;
+        fs::write(log_path, serde_json::to_string_pretty(&log)?)?;
+    }
+    Ok(())
+}
+
+/// Undo `action`, checking that the files it restores are those it acted
+/// on.
+fn restore(action: &Action) -> Result<(), String> {
+    let path = &action.path;
+    let replaced = matches!(
+        action.kind,
+        ActionKind::Hardlink | ActionKind::Reflink | ActionKind::Symlink
+    );
+    if !replaced && fs::symlink_metadata(path).is_ok() {
+        return Err(format!("{:?} exists again", path));
+    }
+    for sidecar in &action.sidecars {
+        restore_sidecar(action.kind, sidecar)?;
+    }
+    let result = match action.kind {
+        ActionKind::Trash | ActionKind::Move | ActionKind::Consolidate | ActionKind::Rename => {
+            let from = action
+                .destination
+                .as_deref()
+                .ok_or("the log does not say where the file went")?;
+            verify(from, action.checksum)?;
+            if action.kind == ActionKind::Trash {
+                trash::restore(from, path)
+            } else {
+                actions::move_file(from, path)
+            }
+        }
+        ActionKind::Delete => {
+            copy_of_kept(action)?;
+            fs::copy(&action.kept, path).map(|_| ())
+        }
+        ActionKind::Hardlink | ActionKind::Reflink | ActionKind::Symlink => {
+            verify(path, action.kept_checksum)?;
+            copy_of_kept(action)?;
+            actions::replace(path, |copy| fs::copy(&action.kept, copy).map(|_| ()))
+        }
+    };
+    result.map_err(|err| err.to_string())
+}
+
+/// Move the `sidecar` that followed a file back, unless it was deleted for
+/// good or is back already.
+fn restore_sidecar(kind: ActionKind, sidecar: &Sidecar) -> Result<(), String> {
+    let Some(from) = &sidecar.destination else {
+        return Ok(());
+    };
+    if fs::symlink_metadata(&sidecar.path).is_ok() {
+        return match fs::symlink_metadata(from) {
+            Ok(_) => Err(format!("{:?} exists again", sidecar.path)),
+            Err(_) => Ok(()),
+        };
+    }
+    let result = if kind == ActionKind::Trash {
+        trash::restore(from, &sidecar.path)
+    } else {
+        actions::move_file(from, &sidecar.path)
+    };
+    result.map_err(|err| format!("{:?} cannot be moved back: {}", sidecar.path, err))
+}
+
+/// Check that the file `action` did away with can be copied back from the
+/// kept file, which had the same contents and still has.
+fn copy_of_kept(action: &Action) -> Result<(), String> {
+    if action.checksum.is_none() || action.checksum != action.kept_checksum {
+        return Err(format!(
+            "its contents differed from {:?} and were not kept",
+            action.kept
+        ));
+    }
+    verify(&action.kept, action.kept_checksum)
+}
+
+/// Check that `path` still has the checksum `expected`, if known.
+fn verify(path: &Path, expected: Option<u32>) -> Result<(), String> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    match exact::checksum(path) {
+        Ok(checksum) if checksum == expected => Ok(()),
+        Ok(_) => Err(format!("{:?} changed since it was acted on", path)),
+        Err(err) => Err(format!("{:?} cannot be read: {}", path, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::ActionOptions;
+    use crate::models::{DuplicatesGroup, ImageInfo};
+    use tempfile::tempdir;
+
+    fn group(paths: &[PathBuf]) -> DuplicatesGroup {
+        let items = paths.iter().map(ImageInfo::at).collect();
+        DuplicatesGroup::new(items)
+    }
+
+    #[test]
+    fn test_undo_moves_files_back() {
+        let dir = tempdir().unwrap();
+        let paths = vec![dir.path().join("a.jpg"), dir.path().join("b.jpg")];
+        fs::write(&paths[0], b"original").unwrap();
+        fs::write(&paths[1], b"edited copy").unwrap();
+        let options = ActionOptions {
+            kind: ActionKind::Move,
+            move_to: Some(dir.path().join("quarantine")),
+            ..Default::default()
+        };
+        let sidecar = dir.path().join("b.xmp");
+        fs::write(&sidecar, b"keywords").unwrap();
+        let mut group = group(&paths);
+        group.items[1].sidecars = vec![sidecar.clone()];
+        let actions = actions::apply(&group, &options);
+        let log = write_log(&actions, dir.path()).unwrap().unwrap();
+        assert!(!paths[1].exists() && !sidecar.exists());
+
+        run(&log).unwrap();
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"edited copy");
+        assert_eq!(fs::read(&sidecar).unwrap(), b"keywords");
+        assert!(!log.exists());
+    }
+
+    #[test]
+    fn test_undo_copies_back_only_what_the_kept_file_holds() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.jpg", "b.jpg", "c.jpg"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        fs::write(&paths[0], b"original").unwrap();
+        fs::write(&paths[1], b"original").unwrap();
+        fs::write(&paths[2], b"edited copy").unwrap();
+        let options = ActionOptions {
+            kind: ActionKind::Delete,
+            ..Default::default()
+        };
+        let actions = actions::apply(&group(&paths), &options);
+        assert!(write_log(&actions, &dir.path().join("missing")).is_err());
+        let log = write_log(&actions, dir.path()).unwrap().unwrap();
+
+        run(&log).unwrap();
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"original");
+        assert!(!paths[2].exists());
+        let left: ActionLog = serde_json::from_str(&fs::read_to_string(&log).unwrap()).unwrap();
+        assert_eq!(left.actions.len(), 1);
+        assert_eq!(left.actions[0].path, paths[2]);
+    }
+}
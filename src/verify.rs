@@ -0,0 +1,375 @@
+use crate::decode::{self, DecodeOptions};
+use crate::distance;
+use crate::hashing::{self, HashAlgorithm};
+use crate::invariance::{self, Invariance};
+use crate::store::ImageSource;
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image_hasher::ImageHash;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Side length of the grayscale thumbnails compared by [`Verification::Pixel`].
+const THUMBNAIL_SIZE: u32 = 32;
+
+/// Side length of the thumbnails whose colors are counted by
+/// [`Verification::Color`].
+const HISTOGRAM_SIZE: u32 = 64;
+
+/// Number of levels each color channel is reduced to in a histogram.
+const HISTOGRAM_LEVELS: usize = 4;
+
+/// Side length of the grayscale thumbnails compared by [`Verification::Ssim`].
+const SSIM_SIZE: u32 = 64;
+
+/// Side length of the windows SSIM is computed over, and the step between
+/// them.
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+
+/// Stricter check applied to candidate pairs after perceptual matching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verification {
+    /// Accept every candidate pair.
+    #[default]
+    None,
+    /// Re-hash both images with a hash twice as large and compare the
+    /// fraction of differing bits.
+    Hash,
+    /// Compare small grayscale thumbnails by mean absolute pixel difference.
+    Pixel,
+    /// Compare color histograms, rejecting images that share their shapes
+    /// but not their colors, such as recolored versions of a design.
+    Color,
+    /// Compare small grayscale thumbnails by structural similarity (SSIM),
+    /// recording the score of every accepted pair in the report.
+    Ssim,
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifyOptions {
+    pub method: Verification,
+    /// Largest accepted difference, between 0 (identical) and 1.
+    pub tolerance: f64,
+    /// Smallest accepted SSIM score, used by [`Verification::Ssim`] instead
+    /// of `tolerance`.
+    pub min_ssim: f64,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            method: Verification::None,
+            tolerance: 0.05,
+            min_ssim: 0.9,
+        }
+    }
+}
+
+/// Scores of the pairs accepted by SSIM verification, by pair of indices.
+pub type Scores = HashMap<(usize, usize), f64>;
+
+enum Fingerprint {
+    Hash(ImageHash),
+    Pixels(Vec<u8>),
+    /// Fraction of the pixels in each bin of an RGB histogram.
+    Histogram(Vec<f64>),
+    /// Grayscale thumbnail compared by SSIM.
+    Structure(Vec<u8>),
+}
+
+impl Fingerprint {
+    /// Normalized difference between two fingerprints of the same kind.
+    fn difference(&self, other: &Fingerprint) -> f64 {
+        match (self, other) {
+            (Fingerprint::Hash(a), Fingerprint::Hash(b)) => {
+                let bits = (a.as_bytes().len() * 8).max(1);
+                distance::hamming(a.as_bytes(), b.as_bytes()) as f64 / bits as f64
+            }
+            (Fingerprint::Pixels(a), Fingerprint::Pixels(b)) => {
+                let total: u64 = a.iter().zip(b).map(|(x, y)| x.abs_diff(*y) as u64).sum();
+                total as f64 / (a.len().max(1) as f64 * 255.0)
+            }
+            (Fingerprint::Histogram(a), Fingerprint::Histogram(b)) => {
+                // One minus the share of pixels the histograms have in common.
+                let common: f64 = a.iter().zip(b).map(|(x, y)| x.min(*y)).sum();
+                (1.0 - common).max(0.0)
+            }
+            (Fingerprint::Structure(a), Fingerprint::Structure(b)) => {
+                1.0 - ssim(a, b, SSIM_SIZE as usize)
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+/// Keep only the candidate pairs that pass the configured verification,
+/// returning them with their scores if the method gives any.
+///
+/// With `invariance`, the second image of a pair is also compared in each
+/// of its allowed variants, and the closest one decides. Pairs involving an
+/// image that can no longer be decoded are discarded.
+pub fn verify_pairs<S: ImageSource + ?Sized>(
+    images: &S,
+    pairs: Vec<(usize, usize)>,
+    options: &VerifyOptions,
+    algorithm: HashAlgorithm,
+    hash_size: u32,
+    decode: &DecodeOptions,
+    invariance: &[Invariance],
+) -> (Vec<(usize, usize)>, Scores) {
+    if options.method == Verification::None || pairs.is_empty() {
+        return (pairs, Scores::new());
+    }
+
+    let hasher = hashing::build_hasher(algorithm, hash_size * 2);
+    let involved: BTreeSet<usize> = pairs.iter().flat_map(|&(i, j)| [i, j]).collect();
+    let fingerprint = |img: &image::DynamicImage| match options.method {
+        Verification::Hash => Fingerprint::Hash(hasher.hash_image(img)),
+        Verification::Color => Fingerprint::Histogram(histogram(img)),
+        Verification::Ssim => Fingerprint::Structure(
+            img.resize_exact(SSIM_SIZE, SSIM_SIZE, FilterType::Triangle)
+                .to_luma8()
+                .into_raw(),
+        ),
+        _ => Fingerprint::Pixels(
+            img.resize_exact(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+                .to_luma8()
+                .into_raw(),
+        ),
+    };
+    // The fingerprint of each image as stored, followed by those of its
+    // variants. Rotating or mirroring an image leaves its colors unchanged.
+    let invariance = match options.method {
+        Verification::Color => &[],
+        _ => invariance,
+    };
+    let fingerprints: HashMap<usize, Vec<Fingerprint>> = involved
+        .into_par_iter()
+        .filter_map(|index| {
+            let img = decode::open(images.path(index), decode).ok()?;
+            let mut fingerprints = vec![fingerprint(&img)];
+            fingerprints.extend(
+                invariance::variants(&img, invariance)
+                    .iter()
+                    .map(fingerprint),
+            );
+            Some((index, fingerprints))
+        })
+        .collect();
+
+    let limit = match options.method {
+        Verification::Ssim => 1.0 - options.min_ssim,
+        _ => options.tolerance,
+    };
+    let mut scores = Scores::new();
+    let pairs = pairs
+        .into_iter()
+        .filter(|&(i, j)| {
+            let (Some(a), Some(b)) = (fingerprints.get(&i), fingerprints.get(&j)) else {
+                return false;
+            };
+            let closest = b
+                .iter()
+                .map(|variant| a[0].difference(variant))
+                .fold(f64::INFINITY, f64::min);
+            let accepted = closest <= limit;
+            if accepted && options.method == Verification::Ssim {
+                scores.insert((i, j), 1.0 - closest);
+            }
+            accepted
+        })
+        .collect();
+    (pairs, scores)
+}
+
+/// Mean structural similarity of the `size` x `size` grayscale images `a`
+/// and `b`, over overlapping windows. 1 means identical.
+fn ssim(a: &[u8], b: &[u8], size: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let pixels = (SSIM_WINDOW * SSIM_WINDOW) as f64;
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for top in (0..=size - SSIM_WINDOW).step_by(SSIM_STEP) {
+        for left in (0..=size - SSIM_WINDOW).step_by(SSIM_STEP) {
+            let window = (top..top + SSIM_WINDOW)
+                .flat_map(|y| (left..left + SSIM_WINDOW).map(move |x| y * size + x));
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for index in window {
+                let (x, y) = (a[index] as f64, b[index] as f64);
+                sum_a += x;
+                sum_b += y;
+                sum_aa += x * x;
+                sum_bb += y * y;
+                sum_ab += x * y;
+            }
+            let (mean_a, mean_b) = (sum_a / pixels, sum_b / pixels);
+            let var_a = sum_aa / pixels - mean_a * mean_a;
+            let var_b = sum_bb / pixels - mean_b * mean_b;
+            let covariance = sum_ab / pixels - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows.max(1) as f64
+}
+
+/// Histogram of the colors of `img` over [`HISTOGRAM_LEVELS`] levels per
+/// channel, with the counts normalized to sum to one.
+///
+/// Each pixel is shared between the levels around its value, so that a
+/// slight color shift moves a little weight rather than a whole bin.
+fn histogram(img: &image::DynamicImage) -> Vec<f64> {
+    let thumbnail = img
+        .resize_exact(HISTOGRAM_SIZE, HISTOGRAM_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    let mut bins = vec![0.0; HISTOGRAM_LEVELS.pow(3)];
+    let levels = |value: u8| {
+        let position = value as f64 * (HISTOGRAM_LEVELS - 1) as f64 / 255.0;
+        let low = (position as usize).min(HISTOGRAM_LEVELS - 2);
+        let weight = position - low as f64;
+        [(low, 1.0 - weight), (low + 1, weight)]
+    };
+    for pixel in thumbnail.pixels() {
+        let [r, g, b] = pixel.0.map(levels);
+        for (r, wr) in r {
+            for (g, wg) in g {
+                for (b, wb) in b {
+                    bins[(r * HISTOGRAM_LEVELS + g) * HISTOGRAM_LEVELS + b] += wr * wg * wb;
+                }
+            }
+        }
+    }
+    let total = (thumbnail.width() * thumbnail.height()).max(1) as f64;
+    bins.iter_mut().for_each(|bin| *bin /= total);
+    bins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+    use image::{GrayImage, Luma};
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn save(path: &Path, light: bool) -> ImageInfo {
+        let image = GrayImage::from_fn(64, 64, |x, _| {
+            if (x < 32) == light {
+                Luma([255])
+            } else {
+                Luma([0])
+            }
+        });
+        image.save(path).unwrap();
+        ImageInfo::at(path)
+    }
+
+    #[test]
+    fn test_pixel_verification_rejects_different_images() {
+        let dir = tempdir().unwrap();
+        let images = [
+            save(&dir.path().join("a.png"), true),
+            save(&dir.path().join("b.png"), true),
+            save(&dir.path().join("c.png"), false),
+        ];
+        let options = VerifyOptions {
+            method: Verification::Pixel,
+            tolerance: 0.05,
+            ..VerifyOptions::default()
+        };
+
+        let pairs = vec![(0, 1), (0, 2), (1, 2)];
+        let (verified, _) = verify_pairs(
+            &images[..],
+            pairs,
+            &options,
+            HashAlgorithm::Gradient,
+            8,
+            &DecodeOptions::default(),
+            &[],
+        );
+        assert_eq!(verified, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_ssim_verification_scores_accepted_pairs() {
+        let dir = tempdir().unwrap();
+        let images = [
+            save(&dir.path().join("a.png"), true),
+            save(&dir.path().join("b.png"), true),
+            save(&dir.path().join("c.png"), false),
+        ];
+        let options = VerifyOptions {
+            method: Verification::Ssim,
+            ..VerifyOptions::default()
+        };
+
+        let (verified, scores) = verify_pairs(
+            &images[..],
+            vec![(0, 1), (0, 2)],
+            &options,
+            HashAlgorithm::Gradient,
+            8,
+            &DecodeOptions::default(),
+            &[],
+        );
+        assert_eq!(verified, vec![(0, 1)]);
+        assert_eq!(scores.len(), 1);
+        assert!((scores[&(0, 1)] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_color_verification_rejects_recolored_image() {
+        let dir = tempdir().unwrap();
+        let images: Vec<ImageInfo> = [[200, 30, 30], [196, 32, 30], [30, 30, 200]]
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| {
+                let path = dir.path().join(format!("{}.png", i));
+                image::RgbImage::from_fn(64, 64, |x, _| {
+                    if x < 32 {
+                        image::Rgb(color)
+                    } else {
+                        image::Rgb([255, 255, 255])
+                    }
+                })
+                .save(&path)
+                .unwrap();
+                ImageInfo::at(path)
+            })
+            .collect();
+        let options = VerifyOptions {
+            method: Verification::Color,
+            tolerance: 0.05,
+            ..VerifyOptions::default()
+        };
+
+        let (verified, _) = verify_pairs(
+            &images[..],
+            vec![(0, 1), (0, 2)],
+            &options,
+            HashAlgorithm::Gradient,
+            8,
+            &DecodeOptions::default(),
+            &[],
+        );
+        assert_eq!(verified, vec![(0, 1)]);
+    }
+}
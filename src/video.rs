@@ -0,0 +1,184 @@
+use crate::archive;
+use crate::source;
+use image::{Delay, DynamicImage, Frame, Frames, ImageError, ImageResult, RgbImage};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+/// Extensions of the video containers whose frames are sampled.
+const EXTENSIONS: &[&str] = &[
+    "3gp", "avi", "m2ts", "m4v", "mkv", "mov", "mp4", "mts", "webm", "wmv",
+];
+
+/// Program decoding the videos, looked up on the `PATH`.
+const FFMPEG: &str = "ffmpeg";
+
+/// Width keyframes are scaled to before they are hashed.
+const KEYFRAME_WIDTH: u32 = 256;
+
+/// Whether `path` names a video, by its extension.
+pub fn is_video(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy();
+        EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    })
+}
+
+/// Decode the first frame of the video at `path` at full size, turned
+/// upright by the rotation stored in the container.
+pub fn first_frame(path: &Path) -> ImageResult<DynamicImage> {
+    let mut frames = FrameStream::spawn(path, &[], &["-frames:v", "1"])?;
+    match frames.next() {
+        Some(frame) => Ok(DynamicImage::ImageRgba8(frame?.into_buffer())),
+        None => Err(ImageError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no video frames",
+        ))),
+    }
+}
+
+/// The keyframes of the video at `path`, scaled down for hashing.
+///
+/// Only the frames the video was encoded with in full are decoded, which
+/// is fast and picks frames at scene changes and regular intervals.
+pub fn keyframes(path: &Path) -> ImageResult<Frames<'static>> {
+    let scale = format!("scale={}:-2", KEYFRAME_WIDTH);
+    let stream = FrameStream::spawn(
+        path,
+        &["-skip_frame", "nokey"],
+        &["-vf", &scale, "-vsync", "passthrough"],
+    )?;
+    Ok(Frames::new(Box::new(stream)))
+}
+
+/// Frames decoded by an `ffmpeg` process writing them to its output as
+/// binary PPM images, one after the other.
+struct FrameStream {
+    child: Child,
+    output: BufReader<ChildStdout>,
+}
+
+impl FrameStream {
+    fn spawn(
+        path: &Path,
+        input_options: &[&str],
+        output_options: &[&str],
+    ) -> ImageResult<FrameStream> {
+        if archive::is_member(path) || source::is_remote(path) {
+            return Err(ImageError::IoError(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "videos inside archives or remote locations are not decoded",
+            )));
+        }
+        let mut child = Command::new(FFMPEG)
+            .args(["-nostdin", "-v", "error"])
+            .args(input_options)
+            .arg("-i")
+            .arg(path)
+            .arg("-an")
+            .args(output_options)
+            .args(["-f", "image2pipe", "-c:v", "ppm", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("cannot run {}: {}", FFMPEG, err)))?;
+        let output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(FrameStream { child, output })
+    }
+}
+
+impl Iterator for FrameStream {
+    type Item = ImageResult<Frame>;
+
+    fn next(&mut self) -> Option<ImageResult<Frame>> {
+        let frame = read_ppm(&mut self.output).transpose()?;
+        Some(frame.map(|img| {
+            let img = DynamicImage::ImageRgb8(img).into_rgba8();
+            Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(0, 1))
+        }))
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        // Stop decoding frames that will not be read.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Read the next binary PPM image of `reader`, or `None` at its end.
+fn read_ppm(reader: &mut impl BufRead) -> ImageResult<Option<RgbImage>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let invalid = |message: &str| {
+        ImageError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            message.to_string(),
+        ))
+    };
+    let mut header = Vec::with_capacity(4);
+    for _ in 0..4 {
+        header.push(read_token(reader)?);
+    }
+    if header[0] != "P6" {
+        return Err(invalid("not a binary PPM frame"));
+    }
+    let number = |token: &str| token.parse::<u32>().map_err(|_| invalid("bad PPM header"));
+    let (width, height, max) = (
+        number(&header[1])?,
+        number(&header[2])?,
+        number(&header[3])?,
+    );
+    if max != 255 {
+        return Err(invalid("PPM frame with more than 8 bits per sample"));
+    }
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    reader.read_exact(&mut pixels)?;
+    RgbImage::from_raw(width, height, pixels)
+        .map(Some)
+        .ok_or_else(|| invalid("bad PPM frame size"))
+}
+
+/// Read a header token, consuming the single whitespace byte after it.
+fn read_token(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0u8];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b if b.is_ascii_whitespace() && token.is_empty() => continue,
+            b if b.is_ascii_whitespace() => return Ok(token),
+            b => token.push(b as char),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_ppm_frames_are_read_in_turn() {
+        let mut stream = b"P6\n2 1\n255\n".to_vec();
+        stream.extend([255, 0, 0, 0, 0, 255]);
+        stream.extend(b"P6 1 1 255\n");
+        stream.extend([7, 8, 9]);
+        let mut reader = Cursor::new(stream);
+
+        let first = read_ppm(&mut reader).unwrap().unwrap();
+        assert_eq!(first.dimensions(), (2, 1));
+        assert_eq!(first.get_pixel(1, 0).0, [0, 0, 255]);
+        let second = read_ppm(&mut reader).unwrap().unwrap();
+        assert_eq!(second.get_pixel(0, 0).0, [7, 8, 9]);
+        assert!(read_ppm(&mut reader).unwrap().is_none());
+
+        assert!(is_video(Path::new("clip.MOV")));
+        assert!(!is_video(Path::new("photo.jpg")));
+    }
+}
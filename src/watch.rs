@@ -0,0 +1,513 @@
+use crate::decode;
+use crate::deduplicate::DeduplicateOptions;
+use crate::errors::AppError;
+use crate::filter;
+use crate::hashing::PerceptualHasher;
+use crate::index::HashIndex;
+use crate::invariance;
+use crate::models::ImageInfo;
+use crate::scan;
+use crate::source;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Settings of `idar watch`.
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Time between two scans of the watched directory when it is polled,
+    /// and between two checks of a new file still being written.
+    pub interval: Duration,
+    /// Print alerts as newline-delimited JSON instead of text.
+    pub ndjson: bool,
+    /// Check new images against this index instead of the images already
+    /// in the directory.
+    pub index: Option<PathBuf>,
+}
+
+/// A new image found to duplicate known ones.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Alert {
+    pub path: PathBuf,
+    /// Known images within the threshold, nearest first.
+    pub matches: Vec<Match>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Match {
+    pub path: PathBuf,
+    pub distance: u32,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} duplicates", self.path.display())?;
+        for (i, found) in self.matches.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(
+                f,
+                "{}{} (distance {})",
+                separator,
+                found.path.display(),
+                found.distance
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Scans a directory for files that were not there before and checks them
+/// against the images known so far.
+///
+/// A new file is only hashed once its size is the same on two scans in a
+/// row, so that files still being copied in are not read half written.
+pub struct Watcher {
+    directory: PathBuf,
+    options: DeduplicateOptions,
+    hasher: Arc<dyn PerceptualHasher>,
+    threshold: u32,
+    known: Vec<ImageInfo>,
+    /// Files already handled, images or not.
+    seen: HashSet<PathBuf>,
+    /// New files waiting for their size to settle, with the size last seen.
+    pending: HashMap<PathBuf, u64>,
+}
+
+impl Watcher {
+    /// Watch `directory`, treating the files it holds now as already seen.
+    ///
+    /// New images are matched against `known`, or against the images in the
+    /// directory when it is `None`.
+    pub fn new(
+        directory: &Path,
+        known: Option<Vec<ImageInfo>>,
+        options: &DeduplicateOptions,
+    ) -> Result<Watcher, AppError> {
+        let (files, _) = source::for_location(directory)?.list(&options.scan)?;
+        let hasher = options.build_hasher();
+        let seen = files.iter().cloned().collect();
+        let known = match known {
+            Some(known) => known,
+            None => {
+                let (images, _) = filter::apply(files, &options.filter);
+                hash_images(&images, &*hasher, options)
+            }
+        };
+        Ok(Watcher {
+            directory: directory.to_path_buf(),
+            options: options.clone(),
+            hasher,
+            threshold: options.threshold(),
+            known,
+            seen,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Number of images new files are checked against.
+    pub fn known(&self) -> usize {
+        self.known.len()
+    }
+
+    /// Whether new files are waiting for their size to settle.
+    pub fn settling(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Scan the directory once, hashing the new files that are ready and
+    /// returning an alert for each one that duplicates a known image.
+    ///
+    /// Hashed images become known themselves, so a file arriving twice is
+    /// reported the second time. Files that disappear are forgotten.
+    pub fn poll(&mut self) -> Result<Vec<Alert>, AppError> {
+        let (files, _) = scan::collect_files(&self.directory, &self.options.scan)?;
+        let present: HashSet<&PathBuf> = files.iter().collect();
+        self.seen.retain(|path| present.contains(path));
+        self.pending.retain(|path, _| present.contains(path));
+        self.known.retain(|image| {
+            !image.path.starts_with(&self.directory) || present.contains(&image.path)
+        });
+
+        let mut ready = Vec::new();
+        for path in &files {
+            if self.seen.contains(path) {
+                continue;
+            }
+            let Some(size) = fs::metadata(path).ok().map(|metadata| metadata.len()) else {
+                continue;
+            };
+            if self.pending.insert(path.clone(), size) == Some(size) {
+                self.pending.remove(path);
+                self.seen.insert(path.clone());
+                ready.push(path.clone());
+            }
+        }
+
+        let (ready, _) = filter::apply(ready, &self.options.filter);
+        let mut alerts = Vec::new();
+        for image in hash_images(&ready, &*self.hasher, &self.options) {
+            let mut matches: Vec<Match> = self
+                .known
+                .iter()
+                .map(|known| Match {
+                    path: known.path.clone(),
+                    distance: std::iter::once(&known.hash)
+                        .chain(&known.variants)
+                        .map(|hash| {
+                            self.options
+                                .distance(image.hash.as_bytes(), hash.as_bytes())
+                        })
+                        .min()
+                        .unwrap_or(u32::MAX),
+                })
+                .filter(|found| found.distance < self.threshold)
+                .collect();
+            matches.sort_by(|a, b| {
+                a.distance
+                    .cmp(&b.distance)
+                    .then_with(|| a.path.cmp(&b.path))
+            });
+            if !matches.is_empty() {
+                alerts.push(Alert {
+                    path: image.path.clone(),
+                    matches,
+                });
+            }
+            self.known.push(image);
+        }
+        Ok(alerts)
+    }
+}
+
+/// Hash the images among `paths` with the variants allowed by `options`,
+/// skipping other files.
+fn hash_images(
+    paths: &[PathBuf],
+    hasher: &dyn PerceptualHasher,
+    options: &DeduplicateOptions,
+) -> Vec<ImageInfo> {
+    paths
+        .par_iter()
+        .filter_map(|path| {
+            let (img, dimensions) = decode::open_sized(path, &options.decode).ok()?;
+            Some(ImageInfo {
+                dimensions: Some(dimensions),
+                variants: invariance::hash_variants(hasher, &img, &options.invariance),
+                ..ImageInfo::new(path.clone(), hasher.hash_image(&img))
+            })
+        })
+        .collect()
+}
+
+/// How the watched directory is waited on between two scans.
+enum Trigger {
+    /// Scan every interval.
+    Interval,
+    /// Scan when inotify reports a file written or moved in, and every
+    /// interval while new files settle.
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Inotify),
+}
+
+impl Trigger {
+    /// Watch `directory`, and its subdirectories if `recursive`, with
+    /// inotify where available, or fall back to polling.
+    fn new(directory: &Path, recursive: bool) -> Trigger {
+        #[cfg(target_os = "linux")]
+        match inotify::Inotify::new(directory, recursive) {
+            Ok(inotify) => return Trigger::Inotify(inotify),
+            Err(err) => eprintln!(
+                "warning: cannot watch {:?} with inotify, polling it instead: {}",
+                directory, err
+            ),
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (directory, recursive);
+        Trigger::Interval
+    }
+
+    /// Wait until the directory is worth scanning again.
+    fn wait(&mut self, interval: Duration, settling: bool) -> Result<(), AppError> {
+        #[cfg(not(target_os = "linux"))]
+        let _ = settling;
+        match self {
+            Trigger::Interval => thread::sleep(interval),
+            #[cfg(target_os = "linux")]
+            Trigger::Inotify(inotify) => inotify.wait(interval, settling)?,
+        }
+        Ok(())
+    }
+
+    fn describe(&self, interval: Duration) -> String {
+        match self {
+            Trigger::Interval => format!("polling every {}s", interval.as_secs_f64()),
+            #[cfg(target_os = "linux")]
+            Trigger::Inotify(_) => "with inotify".to_string(),
+        }
+    }
+}
+
+/// Watch `directory` until interrupted, printing an alert whenever a new
+/// image duplicates a known one.
+///
+/// On Linux the directory is watched with inotify, and scanned again as soon
+/// as a file in it is written or moved in. Elsewhere, or when inotify cannot
+/// watch it, for instance on a network share or past the limit on watches,
+/// it is polled every `watch.interval`.
+///
+/// Progress messages go to stderr, so that with `watch.ndjson` stdout only
+/// holds alerts.
+pub fn run(
+    directory: &Path,
+    watch: &WatchOptions,
+    options: &DeduplicateOptions,
+) -> Result<(), AppError> {
+    let mut options = options.clone();
+    let known = match &watch.index {
+        Some(index_path) => {
+            let index = HashIndex::load(index_path)?;
+            options.algorithm = index.algorithm;
+            options.hash_size = index.hash_size;
+            options.channels = index.channels;
+            options.preprocessing = index.preprocessing;
+            options.invariance = index.invariance;
+            Some(index.images)
+        }
+        None => None,
+    };
+    let mut watcher = Watcher::new(directory, known, &options)?;
+    let mut trigger = Trigger::new(directory, options.scan.recursive);
+    eprintln!(
+        "Watching {:?} for new images {}, checking them against {} known images.",
+        directory,
+        trigger.describe(watch.interval),
+        watcher.known()
+    );
+
+    loop {
+        trigger.wait(watch.interval, watcher.settling())?;
+        for alert in watcher.poll()? {
+            if watch.ndjson {
+                println!("{}", serde_json::to_string(&alert)?);
+            } else {
+                println!("{}", alert);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    /// Events that may bring a new file, or a directory holding some.
+    const MASK: u32 = libc::IN_CREATE | libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_ONLYDIR;
+
+    /// Time without events after which a burst of them, such as a folder
+    /// being copied in, is taken to be over.
+    const QUIET: Duration = Duration::from_millis(200);
+
+    /// Watches on a directory and, if recursive, on all directories below it.
+    pub struct Inotify {
+        fd: OwnedFd,
+        recursive: bool,
+        /// Watched directories, by watch descriptor.
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl Inotify {
+        pub fn new(directory: &Path, recursive: bool) -> io::Result<Inotify> {
+            // SAFETY: no pointers are passed.
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut inotify = Inotify {
+                // SAFETY: `fd` was just opened and nothing else owns it.
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                recursive,
+                dirs: HashMap::new(),
+            };
+            inotify.add(directory)?;
+            Ok(inotify)
+        }
+
+        /// Watch `dir`, and the directories below it if recursive.
+        fn add(&mut self, dir: &Path) -> io::Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            // SAFETY: the descriptor is open and `path` is NUL-terminated.
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+            if wd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            self.dirs.insert(wd, dir.to_path_buf());
+            if self.recursive {
+                for entry in fs::read_dir(dir)?.flatten() {
+                    if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                        self.add(&entry.path())?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Wait until a file is written or moved in and events stop coming,
+        /// for at most `interval` after the first one. While new files
+        /// settle, wait no longer than `interval` at all.
+        pub fn wait(&mut self, interval: Duration, settling: bool) -> io::Result<()> {
+            if !self.next(settling.then_some(interval))? {
+                return Ok(());
+            }
+            let end = Instant::now() + interval;
+            loop {
+                let left = end.saturating_duration_since(Instant::now());
+                if left.is_zero() || !self.next(Some(left.min(QUIET)))? {
+                    return Ok(());
+                }
+            }
+        }
+
+        /// Wait for events for at most `timeout`, or forever, and handle
+        /// them. Returns whether any arrived.
+        fn next(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+            let mut poll = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = timeout.map_or(-1, |timeout| {
+                timeout.as_millis().min(i32::MAX as u128) as i32
+            });
+            // SAFETY: `poll` is a single valid entry.
+            match unsafe { libc::poll(&mut poll, 1, timeout) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    return match err.kind() {
+                        io::ErrorKind::Interrupted => Ok(false),
+                        _ => Err(err),
+                    };
+                }
+                0 => return Ok(false),
+                _ => {}
+            }
+            let mut buffer = [0u8; 4096];
+            loop {
+                // SAFETY: `buffer` is writable for its whole length.
+                let read = unsafe {
+                    libc::read(
+                        self.fd.as_raw_fd(),
+                        buffer.as_mut_ptr().cast(),
+                        buffer.len(),
+                    )
+                };
+                if read == -1 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(true);
+                    }
+                    return Err(err);
+                }
+                self.handle(&buffer[..read as usize]);
+            }
+        }
+
+        /// Watch the directories created or moved in among `events`, and
+        /// forget those no longer watched.
+        fn handle(&mut self, mut events: &[u8]) {
+            const HEADER: usize = mem::size_of::<libc::inotify_event>();
+            let mut added = Vec::new();
+            while events.len() >= HEADER {
+                // SAFETY: the kernel writes whole events, each a header
+                // followed by `len` bytes of name.
+                let event: libc::inotify_event = unsafe {
+                    events
+                        .as_ptr()
+                        .cast::<libc::inotify_event>()
+                        .read_unaligned()
+                };
+                let name = &events[HEADER..HEADER + event.len as usize];
+                events = &events[HEADER + event.len as usize..];
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.dirs.remove(&event.wd);
+                } else if self.recursive && event.mask & libc::IN_ISDIR != 0 {
+                    let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+                    if let Some(dir) = self.dirs.get(&event.wd) {
+                        added.push(dir.join(std::ffi::OsStr::from_bytes(name)));
+                    }
+                }
+            }
+            for dir in added {
+                // A directory gone again, or past the limit on watches, is
+                // still found by the scan, only later.
+                let _ = self.add(&dir);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+    use tempfile::tempdir;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_inotify_wakes_up_for_files_in_new_subdirectories() {
+        use std::time::Instant;
+
+        let dir = tempdir().unwrap();
+        let mut inotify = inotify::Inotify::new(dir.path(), true).unwrap();
+        let started = Instant::now();
+        inotify.wait(Duration::from_millis(50), true).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        fs::create_dir(dir.path().join("new")).unwrap();
+        inotify.wait(Duration::from_secs(10), true).unwrap();
+        fs::write(dir.path().join("new").join("a.png"), b"png").unwrap();
+        let started = Instant::now();
+        inotify.wait(Duration::from_secs(10), true).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_poll_reports_new_duplicates_once_settled() {
+        let dir = tempdir().unwrap();
+        let mut image = RgbImage::new(64, 64);
+        for x in 0..32 {
+            for y in 0..64 {
+                *image.get_pixel_mut(x, y) = image::Rgb([255, 255, 255]);
+            }
+        }
+        image.save(dir.path().join("original.png")).unwrap();
+
+        let mut watcher = Watcher::new(dir.path(), None, &DeduplicateOptions::default()).unwrap();
+        assert_eq!(watcher.known(), 1);
+        assert!(watcher.poll().unwrap().is_empty());
+
+        let copy = dir.path().join("copy.png");
+        image.save(&copy).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        let alerts = watcher.poll().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].path, copy);
+        assert_eq!(alerts[0].matches[0].path, dir.path().join("original.png"));
+        assert_eq!(alerts[0].matches[0].distance, 0);
+        assert!(watcher.poll().unwrap().is_empty());
+    }
+}
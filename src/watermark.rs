@@ -0,0 +1,190 @@
+use crate::content::ContentKind;
+use crate::decode::Colorspace;
+use crate::hashing::PerceptualHasher;
+use crate::models::{Dimensions, ImageInfo};
+use crate::store::{Hashes, ImageSource};
+use image::{DynamicImage, Rgba};
+use image_hasher::ImageHash;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Each masked corner spans one in this many of the columns and rows of
+/// an image: where logos, watermarks and burned-in dates are usually put.
+const CORNER: (u32, u32) = (4, 4);
+
+/// Hash `img` with its corners masked out, so that copies differing only
+/// by a logo, watermark or timestamp in a corner hash alike.
+///
+/// The corners are filled with the mean colour of the rest of the image,
+/// which makes them as flat as possible for the hasher.
+pub fn hash_masked(hasher: &dyn PerceptualHasher, img: &DynamicImage) -> ImageHash {
+    let mut masked = img.to_rgba8();
+    let (width, height) = masked.dimensions();
+    let (corner_width, corner_height) = (width / CORNER.0, height / CORNER.1);
+    let in_corner = |x: u32, y: u32| {
+        (x < corner_width || x >= width - corner_width)
+            && (y < corner_height || y >= height - corner_height)
+    };
+    let mut sum = [0u64; 4];
+    let mut count = 0u64;
+    for (x, y, pixel) in masked.enumerate_pixels() {
+        if !in_corner(x, y) {
+            for (total, &value) in sum.iter_mut().zip(&pixel.0) {
+                *total += value as u64;
+            }
+            count += 1;
+        }
+    }
+    let mean = Rgba(sum.map(|total| (total / count.max(1)) as u8));
+    for (x, y, pixel) in masked.enumerate_pixels_mut() {
+        if in_corner(x, y) {
+            *pixel = mean;
+        }
+    }
+    hasher.hash_image(&DynamicImage::ImageRgba8(masked))
+}
+
+/// The masked hashes of the images of a source that have one, so that
+/// matching finds the pairs that are close once their corners are masked.
+pub struct Masked<'a, S: ?Sized> {
+    images: &'a S,
+    /// Image of every entry.
+    entries: Vec<usize>,
+}
+
+impl<'a, S: ImageSource + ?Sized> Masked<'a, S> {
+    pub fn new(images: &'a S) -> Masked<'a, S> {
+        let entries = (0..images.len())
+            .filter(|&index| !images.masked(index).is_empty())
+            .collect();
+        Masked { images, entries }
+    }
+
+    /// The sorted pairs of images given by the pairs of matching entries,
+    /// leaving out those in `found`, which match without masking.
+    pub fn owner_pairs(
+        &self,
+        pairs: Vec<(usize, usize)>,
+        found: &HashSet<(usize, usize)>,
+    ) -> Vec<(usize, usize)> {
+        let mut owners: Vec<(usize, usize)> = pairs
+            .into_iter()
+            .map(|(i, j)| {
+                let (i, j) = (self.entries[i], self.entries[j]);
+                (i.min(j), i.max(j))
+            })
+            .filter(|pair| !found.contains(pair))
+            .collect();
+        owners.sort_unstable();
+        owners.dedup();
+        owners
+    }
+}
+
+impl<S: ImageSource + ?Sized> ImageSource for Masked<'_, S> {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn path(&self, index: usize) -> &Path {
+        self.images.path(self.entries[index])
+    }
+
+    fn hash_bytes(&self, index: usize) -> &[u8] {
+        self.images.masked(self.entries[index]).bytes(0)
+    }
+
+    fn root(&self, index: usize) -> Option<&Path> {
+        self.images.root(self.entries[index])
+    }
+
+    fn links(&self, index: usize) -> Vec<PathBuf> {
+        self.images.links(self.entries[index])
+    }
+
+    fn sidecars(&self, index: usize) -> Vec<PathBuf> {
+        self.images.sidecars(self.entries[index])
+    }
+
+    fn variants(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn segments(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn windows(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn frames(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn masked(&self, _index: usize) -> Hashes<'_> {
+        Hashes::default()
+    }
+
+    fn dimensions(&self, index: usize) -> Option<Dimensions> {
+        self.images.dimensions(self.entries[index])
+    }
+
+    fn colorspace(&self, index: usize) -> Option<Colorspace> {
+        self.images.colorspace(self.entries[index])
+    }
+
+    fn content(&self, index: usize) -> Option<ContentKind> {
+        self.images.content(self.entries[index])
+    }
+
+    fn columns(&self, index: usize) -> BTreeMap<String, String> {
+        self.images.columns(self.entries[index])
+    }
+
+    fn image(&self, index: usize) -> ImageInfo {
+        self.images.image(self.entries[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::{self, HashAlgorithm};
+    use crate::matching;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_copy_with_corner_logo_matches_once_masked() {
+        let hasher = hashing::build_hasher(HashAlgorithm::Gradient, 16);
+        let photo = RgbaImage::from_fn(320, 240, |x, y| {
+            let v = ((x / 20 * 37 + y / 20 * 91) % 200) as u8 + 30;
+            Rgba([v, v / 2, 255 - v, 255])
+        });
+        let mut stamped = photo.clone();
+        for y in 190..235 {
+            for x in 245..315 {
+                stamped.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        let info = |name: &str, img: RgbaImage| {
+            let img = DynamicImage::ImageRgba8(img);
+            ImageInfo {
+                masked: vec![hash_masked(&hasher, &img)],
+                ..ImageInfo::new(PathBuf::from(name), hasher.hash_image(&img))
+            }
+        };
+        let images = [info("photo.png", photo), info("stamped.png", stamped)];
+        let distance = |a: &ImageInfo, b: &ImageInfo| a.hash.dist(&b.hash);
+        assert!(distance(&images[0], &images[1]) > 4);
+
+        let masked = Masked::new(&images[..]);
+        let pairs = matching::candidate_pairs(&masked, 4);
+        assert_eq!(
+            masked.owner_pairs(pairs.clone(), &HashSet::new()),
+            vec![(0, 1)]
+        );
+        let found = HashSet::from([(0, 1)]);
+        assert!(masked.owner_pairs(pairs, &found).is_empty());
+    }
+}